@@ -2,7 +2,11 @@ mod repl;
 mod storage;
 
 pub use repl::*;
-pub use storage::{Cursor, Table};
+pub use storage::{
+    validate, validate_parallel, Cursor, FlushPolicy, KeyBucket, KeyComparator, KeyType,
+    NodeResult, Operation, OutputFormat, PageNum, PagerMetrics, Plan, Records, StorageEngine,
+    Table, ValidationReport, ValueType, DEFAULT_TABLE, PAGE_SIZE,
+};
 
 macro_rules! calculate_offsets {
     ($start:ident, $size:ident) => {{
@@ -16,8 +20,6 @@ pub(crate) use calculate_offsets;
 
 #[cfg(test)]
 mod test {
-    use super::*;
-
     #[test]
     fn calculate_offset() {
         let start = 0;
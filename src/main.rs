@@ -1,23 +1,122 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use btree_db::start_repl;
-use clap::Parser;
+use btree_db::{start_repl, OutputFormat, DEFAULT_MAX_LINE_LENGTH, DEFAULT_TABLE, IN_MEMORY_PATH};
+use clap::{ArgAction, Parser};
+use tracing::Level;
 
 #[derive(Parser)]
 #[command(version, about,long_about = None)]
 struct Cli {
-    /// Optional name to operate on
+    /// Table to open; created if it doesn't exist yet. Defaults to `db`
     name: Option<String>,
 
-    /// Optionally, sets a database file to use
+    /// Optionally, sets a database file to use; `:memory:` keeps the database in memory
     #[arg(short, long, value_name = "FILE")]
     file: Option<PathBuf>,
+
+    /// Format used to print records; `plain`, `json`, `tsv` or `table`
+    #[arg(short, long, value_name = "FORMAT", default_value = "plain")]
+    output: OutputFormat,
+
+    /// Create a new database file; refuses to touch a file that already holds data
+    #[arg(long)]
+    init: bool,
+
+    /// Longest input line accepted, in bytes; longer lines are rejected
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_LINE_LENGTH)]
+    max_line_length: usize,
+
+    /// Encrypts the database file with a key derived from the passphrase; prompts for the
+    /// passphrase if it is not given. Builds need the `encryption` feature
+    #[arg(long, value_name = "PASSPHRASE", num_args = 0..=1)]
+    key: Option<Option<String>>,
+
+    /// Prints log messages to stderr; `-v` for info, `-vv` for debug and `-vvv` for trace
+    /// messages. Takes precedence over `RUST_LOG`
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+}
+
+/// Sets up logging at the level requested by `verbose`; without it, `start_repl` falls back to
+/// `RUST_LOG`
+fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => return,
+        1 => Level::INFO,
+        2 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+/// Reads a passphrase from the terminal without echoing it; statements keep coming from stdin
+#[cfg(feature = "encryption")]
+fn prompt_passphrase() -> Result<String, String> {
+    rpassword::prompt_password("passphrase: ")
+        .map_err(|e| format!("failed to read passphrase; {e}"))
+}
+
+#[cfg(not(feature = "encryption"))]
+fn prompt_passphrase() -> Result<String, String> {
+    Err("encryption support is not enabled".to_string())
+}
+
+/// Checks that `path` can be opened; only `init` may create new database files and it never
+/// reuses a file with contents
+fn check_path(path: &Path, init: bool) -> Result<(), String> {
+    let len = match std::fs::metadata(path) {
+        Ok(metadata) => Some(metadata.len()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(format!("failed to open {}; {e}", path.display())),
+    };
+
+    match (len, init) {
+        (Some(len), true) if len > 0 => Err(format!(
+            "{} already exists; refusing to initialize it",
+            path.display()
+        )),
+        (None, false) => Err(format!(
+            "{} does not exist; use --init to create it",
+            path.display()
+        )),
+        _ => Ok(()),
+    }
 }
 
 fn main() {
     let cli = Cli::parse();
-    let name = cli.name.unwrap_or("db".into());
+    init_logging(cli.verbose);
+    let name = cli.name.unwrap_or(DEFAULT_TABLE.into());
     let path = cli.file.unwrap_or("/tmp/default.db".into());
+    if path.as_os_str() != IN_MEMORY_PATH {
+        if let Err(e) = check_path(&path, cli.init) {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+    }
+
+    let key = match cli.key {
+        Some(None) => match prompt_passphrase() {
+            Ok(key) => Some(key),
+            Err(e) => {
+                eprintln!("error: {e}");
+                std::process::exit(1);
+            }
+        },
+        Some(key) => key,
+        None => None,
+    };
 
-    start_repl(name, path)
+    start_repl(
+        name,
+        path,
+        key,
+        cli.output,
+        cli.max_line_length,
+        std::io::stdin().lock(),
+        std::io::stdout(),
+    )
 }
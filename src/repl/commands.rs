@@ -1,6 +1,123 @@
-use std::error::Error;
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::PathBuf,
+};
 
-use crate::storage::layout::*;
+use super::{Settings, SETTINGS};
+use crate::{
+    storage::{cursor::MAX_HISTOGRAM_BUCKETS, dump, layout::*, validate},
+    Cursor, KeyType, OutputFormat, Table, ValueType,
+};
+
+/// Syntax and description of every meta command; printed by `.help`
+const META_COMMANDS_HELP: &[(&str, &str)] = &[
+    (
+        ".backup <path>",
+        "Write a copy of the database file to <path>",
+    ),
+    (
+        ".clone [--switch] <path>",
+        "Flush and copy the database file to <path>; --switch continues on the copy",
+    ),
+    (
+        ".clear [confirm]",
+        "Remove every record of the current table; asks to confirm first",
+    ),
+    (
+        ".dump <path>",
+        "Write all records of the current table to <path>",
+    ),
+    (".exit", "Close the current session"),
+    (
+        ".get [key]",
+        "Print the value of a setting, or of all settings",
+    ),
+    (".help", "Print this help message"),
+    (
+        ".histogram <buckets>",
+        "Count the records in up to 1024 equal-width id ranges between the smallest and largest id",
+    ),
+    (".import <path>", "Insert all records from a dump at <path>"),
+    (
+        ".import csv [--strict] <path>",
+        "Insert `id,value` lines from <path>; --strict aborts on bad lines",
+    ),
+    (".layout", "Print page layout information"),
+    (
+        ".mode <plain|json|tsv|table>",
+        "Change the format used to print records",
+    ),
+    (
+        ".relink",
+        "Rebuild the leaf sibling chain of the current table",
+    ),
+    (
+        ".repair",
+        "Recalculate the free space pointers of every leaf of the current table",
+    ),
+    (
+        ".set <key> <value>",
+        "Change a setting; `.get` lists them. Autoflush off defers writes to .exit and .clone",
+    ),
+    (".stats", "Print page cache and leaf space statistics"),
+    (".tables", "List the tables stored in the database file"),
+    (".timer <on|off>", "Print how long each statement takes"),
+    (
+        ".validate",
+        "Check that the current table is sorted and intact",
+    ),
+    (".version", "Print the crate and on-disk format versions"),
+    (
+        "use <name> [text|int|blob] [signed|u128]",
+        "Switch to table <name>; creating it with the value and key type if needed",
+    ),
+];
+
+/// Syntax and description of every DSL statement; printed by `.help`
+const STATEMENTS_HELP: &[(&str, &str)] = &[
+    ("insert <id> <value>", "Insert a new record"),
+    (
+        "insert auto <value>",
+        "Insert a record under the largest id plus one; prints the id",
+    ),
+    (
+        "select [offset <m>] [limit <n>]",
+        "Print records in ascending identifier order",
+    ),
+    (
+        "select where value = <value>",
+        "Print records whose value equals <value>",
+    ),
+    ("select count", "Print the number of records"),
+    (
+        "select keys",
+        "Print the id of every record, without values",
+    ),
+    (
+        "select min|max",
+        "Print the record with the smallest or largest id",
+    ),
+    (
+        "update <id> <value>",
+        "Replace the value of an existing record",
+    ),
+    ("get <id>", "Print a single record"),
+    ("exists <id>", "Print whether a record exists"),
+    (
+        "delete <lo> [hi]",
+        "Remove the record <lo>, or all records in <lo>..=<hi>",
+    ),
+    (
+        "rekey <old> <new>",
+        "Move the record <old> to identifier <new>",
+    ),
+    (
+        "explain <statement>",
+        "Print the pages an insert, get, exists or select would visit",
+    ),
+];
 
 /// Commands that are not part of the database DSL.
 ///
@@ -11,35 +128,294 @@ pub enum MetaCommand {
     Exit,
     /// Prints out layout information
     Layout,
+    /// Changes the format used to print records
+    Mode(OutputFormat),
+    /// Switches to another table in the database file; creating it if needed. Tables created with
+    /// a value type validate inserted values; the key type can be changed while a table is empty
+    Use(String, Option<ValueType>, Option<KeyType>),
+    /// Lists the tables stored in the database file
+    Tables,
+    /// Checks that the keys of the current table are sorted and its cells are intact
+    Validate,
+    /// Prints the crate version and the on-disk format version of the file
+    Version,
+    /// Lists the available commands and statements
+    Help,
+    /// Prints page cache statistics
+    Stats,
+    /// Prints the number of records in each of the given number of equal-width key ranges
+    Histogram(u64),
+    /// Writes all records of the current table to a file
+    Dump(PathBuf),
+    /// Writes a copy of the database file to a new file
+    Backup(PathBuf),
+    /// Flushes the database file and copies it to a new file; `switch` continues the session on
+    /// the copy
+    Clone { path: PathBuf, switch: bool },
+    /// Removes every record of the current table; nothing is removed unless `confirmed`
+    Clear { confirmed: bool },
+    /// Inserts all records of a dump file into the current table
+    Import(PathBuf),
+    /// Inserts all records of a CSV file into the current table; `strict` aborts on malformed
+    /// lines instead of skipping them
+    ImportCsv { path: PathBuf, strict: bool },
+    /// Enables or disables printing the time taken by each statement
+    Timer(bool),
+    /// Rebuilds the sibling chain of the current table from its internal nodes
+    Relink,
+    /// Recalculates the free space pointers of every leaf of the current table from its cells
+    Repair,
+    /// Changes a setting of the session or the current table
+    Set(String, String),
+    /// Prints the value of a setting; all settings if no key is given
+    Get(Option<String>),
 }
 
 impl MetaCommand {
-    pub fn execute(&self) -> Result<(), Box<dyn Error>> {
+    /// Runs the command; anything it prints is written to `out`.
+    ///
+    /// [Exit](MetaCommand::Exit) does nothing; ending the session is up to the caller.
+    pub fn execute<W: Write>(
+        &self,
+        settings: &mut Settings,
+        table: &mut Table,
+        out: &mut W,
+    ) -> Result<(), Box<dyn Error>> {
         match self {
-            Self::Exit => {
-                // NOTE: This will not drop any objects created
-                std::process::exit(0);
-            }
+            Self::Exit => Ok(()),
             Self::Layout => {
-                println!("=== Common info ===");
-                println!("Page size: {}", PAGE_SIZE);
-                println!("Common header size: {}", PAGE_HEADERS_SIZE);
-                println!();
+                writeln!(out, "=== Common info ===")?;
+                writeln!(out, "Page size: {}", PAGE_SIZE)?;
+                writeln!(out, "Common header size: {}", PAGE_HEADERS_SIZE)?;
+                writeln!(out)?;
+
+                writeln!(out, "=== Internal page info ===")?;
+                writeln!(out, "Header size: {}", INTERNAL_HEADER_SIZE)?;
+                writeln!(out, "Space for keys: {}", INTERNAL_SPACE_FOR_CELLS)?;
+                writeln!(out, "Max keys: {}", INTERNAL_MAX_KEYS)?;
+                writeln!(out, "Key size: {}", INTERNAL_CELL_SIZE)?;
+                writeln!(out)?;
+
+                writeln!(out, "=== Leaf page info ===")?;
+                writeln!(out, "Header size: {}", LEAF_HEADER_SIZE)?;
+                writeln!(out, "Space for cells: {}", LEAF_SPACE_FOR_DATA)?;
+                writeln!(out, "Key cell size: {}", LEAF_KEY_CELL_SIZE)?;
+                writeln!(out, "Max inline content: {}", LEAF_MAX_INLINE_CONTENT)?;
+                writeln!(out)?;
+
+                writeln!(out, "=== Overflow page info ===")?;
+                writeln!(out, "Header size: {}", OVERFLOW_HEADER_SIZE)?;
+                writeln!(out, "Space for data: {}", OVERFLOW_SPACE_FOR_DATA)?;
 
-                println!("=== Internal page info ===");
-                println!("Header size: {}", INTERNAL_HEADER_SIZE);
-                println!("Space for keys: {}", INTERNAL_SPACE_FOR_CELLS);
-                println!("Max keys: {}", INTERNAL_MAX_KEYS);
-                println!("Key size: {}", INTERNAL_CELL_SIZE);
-                println!();
+                Ok(())
+            }
+            Self::Mode(new_format) => {
+                settings.format = *new_format;
+                Ok(())
+            }
+            Self::Timer(enabled) => {
+                settings.timer = *enabled;
+                Ok(())
+            }
+            Self::Use(name, schema, key_type) => {
+                let opened = match schema {
+                    Some(schema) => table.open_typed(name, *schema),
+                    None => table.open(name),
+                };
+                let opened = match key_type {
+                    Some(key_type) => opened.and_then(|_| table.set_key_type(*key_type)),
+                    None => opened,
+                };
+                if let Err(e) = opened {
+                    writeln!(out, "error: {e}")?;
+                }
+                Ok(())
+            }
+            Self::Tables => {
+                for name in table.tables() {
+                    writeln!(out, "{name}")?;
+                }
+                Ok(())
+            }
+            Self::Help => {
+                writeln!(out, "=== Meta commands ===")?;
+                for (syntax, desc) in META_COMMANDS_HELP {
+                    writeln!(out, "{syntax:<34}{desc}")?;
+                }
+                writeln!(out)?;
 
-                println!("=== Leaf page info ===");
-                println!("Header size: {}", LEAF_HEADER_SIZE);
-                println!("Space for cells: {}", LEAF_SPACE_FOR_DATA);
-                println!("Key cell size: {}", LEAF_KEY_CELL_SIZE);
+                writeln!(out, "=== Statements ===")?;
+                for (syntax, desc) in STATEMENTS_HELP {
+                    writeln!(out, "{syntax:<34}{desc}")?;
+                }
 
                 Ok(())
             }
+            Self::Dump(path) => {
+                let result = File::create(path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|f| dump::dump(table, BufWriter::new(f)));
+                match result {
+                    Ok(n) => writeln!(out, "dumped {n} records")?,
+                    Err(e) => writeln!(out, "error: {e}")?,
+                }
+                Ok(())
+            }
+            Self::Backup(path) => {
+                match table.backup_to(path) {
+                    Ok(n) => writeln!(out, "backed up {n} pages")?,
+                    Err(e) => writeln!(out, "error: {e}")?,
+                }
+                Ok(())
+            }
+            Self::Clone { path, switch } => {
+                if let Err(e) = table.flush() {
+                    writeln!(out, "error: {e}")?;
+                    return Ok(());
+                }
+                match table.fork_to(path) {
+                    Ok(fork) if *switch => {
+                        writeln!(
+                            out,
+                            "cloned {} pages; now using {}",
+                            fork.page_count(),
+                            path.display()
+                        )?;
+                        *table = fork;
+                    }
+                    Ok(fork) => writeln!(out, "cloned {} pages", fork.page_count())?,
+                    Err(e) => writeln!(out, "error: {e}")?,
+                }
+                Ok(())
+            }
+            Self::Clear { confirmed: false } => {
+                writeln!(
+                    out,
+                    "this removes every record of table `{}`; run `.clear confirm` to continue",
+                    table.name()
+                )?;
+                Ok(())
+            }
+            Self::Clear { confirmed: true } => {
+                if let Err(e) = table.clear() {
+                    writeln!(out, "error: {e}")?;
+                }
+                Ok(())
+            }
+            Self::Import(path) => {
+                let result = File::open(path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|f| dump::import(table, BufReader::new(f)));
+                match result {
+                    Ok(n) => writeln!(out, "imported {n} records")?,
+                    Err(e) => writeln!(out, "error: {e}")?,
+                }
+                Ok(())
+            }
+            Self::ImportCsv { path, strict } => {
+                let result = File::open(path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|f| dump::import_csv(table, BufReader::new(f), *strict));
+                match result {
+                    Ok(result) => {
+                        for e in result.skipped {
+                            writeln!(out, "skipped {e}")?;
+                        }
+                        writeln!(out, "imported {} records", result.imported)?;
+                    }
+                    Err(e) => writeln!(out, "error: {e}")?,
+                }
+                Ok(())
+            }
+            Self::Stats => {
+                let metrics = table.metrics();
+                writeln!(out, "Pages: {}", table.page_count())?;
+                writeln!(out, "Cache hits: {}", metrics.cache_hits)?;
+                writeln!(out, "Cache misses: {}", metrics.cache_misses)?;
+                writeln!(out, "Hit ratio: {:.2}", metrics.hit_ratio())?;
+                writeln!(out, "Pages read: {}", metrics.pages_read)?;
+                writeln!(out, "Pages written: {}", metrics.pages_written)?;
+                match Cursor::new(table).leaf_usage() {
+                    Ok(usage) => {
+                        writeln!(out, "Leaves: {}", usage.leaves)?;
+                        writeln!(out, "Leaf free bytes: {}", usage.free_bytes)?;
+                        writeln!(out, "Leaf fill factor: {:.2}", usage.fill_factor)?;
+                    }
+                    Err(e) => writeln!(out, "error: {e}")?,
+                }
+                Ok(())
+            }
+            Self::Histogram(buckets) => {
+                let key_type = table.key_type();
+                match Cursor::new(table).histogram(*buckets) {
+                    Ok(histogram) => {
+                        for bucket in histogram {
+                            writeln!(
+                                out,
+                                "{}..={}: {}",
+                                key_type.format(bucket.lo),
+                                key_type.format(bucket.hi),
+                                bucket.keys
+                            )?;
+                        }
+                    }
+                    Err(e) => writeln!(out, "error: {e}")?,
+                }
+                Ok(())
+            }
+            Self::Version => {
+                writeln!(out, "btree-db {}", env!("CARGO_PKG_VERSION"))?;
+                writeln!(
+                    out,
+                    "format version {} (current {})",
+                    table.format_version(),
+                    FORMAT_VERSION
+                )?;
+                Ok(())
+            }
+            Self::Set(key, value) => {
+                if let Err(e) = settings.set(table, key, value) {
+                    writeln!(out, "error: {e}")?;
+                }
+                Ok(())
+            }
+            Self::Get(Some(key)) => {
+                match settings.get(table, key) {
+                    Ok(value) => writeln!(out, "{value}")?,
+                    Err(e) => writeln!(out, "error: {e}")?,
+                }
+                Ok(())
+            }
+            Self::Get(None) => {
+                for key in SETTINGS {
+                    writeln!(out, "{key} = {}", settings.get(table, key)?)?;
+                }
+                Ok(())
+            }
+            Self::Relink => {
+                match table.rebuild_leaf_chain() {
+                    Ok(leaves) => writeln!(out, "relinked {leaves} leaves")?,
+                    Err(e) => writeln!(out, "error: {e}")?,
+                }
+                Ok(())
+            }
+            Self::Repair => {
+                match table.repair_free_space() {
+                    Ok(leaves) => writeln!(out, "repaired {leaves} leaves")?,
+                    Err(e) => writeln!(out, "error: {e}")?,
+                }
+                Ok(())
+            }
+            Self::Validate => {
+                let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+                match validate::validate_parallel(table, threads) {
+                    Ok(report) => {
+                        writeln!(out, "ok: {} keys in {} leaves", report.keys, report.leaves)?
+                    }
+                    Err(e) => writeln!(out, "error: {e}")?,
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -51,7 +427,91 @@ impl TryInto<MetaCommand> for &str {
         match self {
             ".exit" => Ok(MetaCommand::Exit),
             ".layout" => Ok(MetaCommand::Layout),
-            _ => Err(format!("unknown command `{self}`.")),
+            ".tables" => Ok(MetaCommand::Tables),
+            ".validate" => Ok(MetaCommand::Validate),
+            ".relink" => Ok(MetaCommand::Relink),
+            ".repair" => Ok(MetaCommand::Repair),
+            ".version" => Ok(MetaCommand::Version),
+            ".help" => Ok(MetaCommand::Help),
+            ".stats" => Ok(MetaCommand::Stats),
+            ".get" => Ok(MetaCommand::Get(None)),
+            ".clear" => Ok(MetaCommand::Clear { confirmed: false }),
+            _ => match self.split_once(' ') {
+                Some((".mode", format)) => Ok(MetaCommand::Mode(format.trim().parse()?)),
+                Some((".dump", path)) => Ok(MetaCommand::Dump(path.trim().into())),
+                Some((".clear", "confirm")) => Ok(MetaCommand::Clear { confirmed: true }),
+                Some((".backup", path)) => Ok(MetaCommand::Backup(path.trim().into())),
+                Some((".clone", path)) => match path.trim().strip_prefix("--switch ") {
+                    Some(path) => Ok(MetaCommand::Clone {
+                        path: path.trim().into(),
+                        switch: true,
+                    }),
+                    None => Ok(MetaCommand::Clone {
+                        path: path.trim().into(),
+                        switch: false,
+                    }),
+                },
+                Some((".import", args)) => match args.trim().split_once(' ') {
+                    Some(("csv", path)) => match path.trim().strip_prefix("--strict ") {
+                        Some(path) => Ok(MetaCommand::ImportCsv {
+                            path: path.trim().into(),
+                            strict: true,
+                        }),
+                        None => Ok(MetaCommand::ImportCsv {
+                            path: path.trim().into(),
+                            strict: false,
+                        }),
+                    },
+                    _ => Ok(MetaCommand::Import(args.trim().into())),
+                },
+                Some((".timer", value)) => match value.trim() {
+                    "on" => Ok(MetaCommand::Timer(true)),
+                    "off" => Ok(MetaCommand::Timer(false)),
+                    value => Err(format!("invalid timer setting `{value}`.")),
+                },
+                Some((".set", args)) => match args.split_whitespace().collect::<Vec<_>>()[..] {
+                    [key, value] => Ok(MetaCommand::Set(key.to_string(), value.to_string())),
+                    _ => Err("invalid syntax".to_string()),
+                },
+                Some((".get", key)) => Ok(MetaCommand::Get(Some(key.trim().to_string()))),
+                Some((".histogram", buckets)) => match buckets.trim().parse::<u64>() {
+                    Ok(buckets) if (1..=MAX_HISTOGRAM_BUCKETS).contains(&buckets) => {
+                        Ok(MetaCommand::Histogram(buckets))
+                    }
+                    _ => Err(format!(
+                        "invalid bucket count `{}`; expected 1 to {MAX_HISTOGRAM_BUCKETS}.",
+                        buckets.trim()
+                    )),
+                },
+                Some(("use", args)) => match args.split_whitespace().collect::<Vec<_>>()[..] {
+                    [name] => Ok(MetaCommand::Use(name.to_string(), None, None)),
+                    [name, key_type @ ("signed" | "unsigned")] => Ok(MetaCommand::Use(
+                        name.to_string(),
+                        None,
+                        Some(key_type.parse()?),
+                    )),
+                    [name, "u128"] => Ok(MetaCommand::Use(
+                        name.to_string(),
+                        None,
+                        Some(KeyType::U128),
+                    )),
+                    [name, schema] => Ok(MetaCommand::Use(
+                        name.to_string(),
+                        Some(schema.parse()?),
+                        None,
+                    )),
+                    [name, schema, key_type] => Ok(MetaCommand::Use(
+                        name.to_string(),
+                        Some(schema.parse()?),
+                        Some(key_type.parse()?),
+                    )),
+                    _ => Err("invalid syntax".to_string()),
+                },
+                _ if self.starts_with('.') => Err(format!(
+                    "unknown meta command `{self}`. Try `.help` for a list of commands."
+                )),
+                _ => Err(format!("unknown command `{self}`.")),
+            },
         }
     }
 }
@@ -1,46 +1,283 @@
 pub mod commands;
 
 pub use commands::MetaCommand;
-use std::{io::Write, path::PathBuf};
+use std::{
+    io::{BufRead, Read, Write},
+    path::PathBuf,
+    time::Instant,
+};
+use tracing_subscriber::EnvFilter;
 
-use crate::{storage::statement::Statement, Cursor, Table};
+use crate::{storage::statement::Statement, Cursor, OutputFormat, Table};
 
-/// Starts a database REPL session
-pub fn start_repl(name: String, path: PathBuf) {
-    let mut table = Table::new(path);
-    env_logger::init();
+/// Database file name used to request a table that only lives in memory
+pub const IN_MEMORY_PATH: &str = ":memory:";
 
-    loop {
-        // TODO: This needs to be at a better place
-        table.flush_contents();
-        print!("{name} > ");
+/// Longest input line accepted by default; 1 MiB
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 1024 * 1024;
+
+/// Settings of a REPL session that can be changed by meta commands
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Format used when printing records
+    pub format: OutputFormat,
+    /// Print the time taken by each statement
+    pub timer: bool,
+    /// Longest input line in bytes; longer lines are discarded without being stored
+    pub max_line_length: usize,
+    /// Write changes to disk after every statement and meta command that makes them; when off
+    /// only `.exit`, `.clone` and the end of input write them
+    pub autoflush: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::default(),
+            timer: false,
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+            autoflush: true,
+        }
+    }
+}
+
+/// Names of the settings that can be changed with `.set`
+pub const SETTINGS: &[&str] = &[
+    "autoflush",
+    "max_line_length",
+    "output",
+    "prefetch_window",
+    "timer",
+    "utf8_values",
+];
+
+impl Settings {
+    /// Changes setting `key` to `value`; settings stored by the table are changed on `table`
+    pub fn set(&mut self, table: &mut Table, key: &str, value: &str) -> Result<(), String> {
+        let number = |value: &str| {
+            value
+                .parse::<usize>()
+                .map_err(|_| format!("invalid number `{value}`."))
+        };
+
+        match key {
+            "autoflush" => self.autoflush = parse_switch(value)?,
+            "max_line_length" => self.max_line_length = number(value)?,
+            "output" => self.format = value.parse()?,
+            "prefetch_window" => table.set_prefetch_window(number(value)?),
+            "timer" => self.timer = parse_switch(value)?,
+            "utf8_values" => table.set_utf8_values(parse_switch(value)?),
+            _ => return Err(unknown_setting(key)),
+        }
+
+        Ok(())
+    }
+
+    /// Returns the value of setting `key`
+    pub fn get(&self, table: &Table, key: &str) -> Result<String, String> {
+        let switch = |on: bool| if on { "on" } else { "off" }.to_string();
+
+        match key {
+            "autoflush" => Ok(switch(self.autoflush)),
+            "max_line_length" => Ok(self.max_line_length.to_string()),
+            "output" => Ok(self.format.to_string()),
+            "prefetch_window" => Ok(table.prefetch_window().to_string()),
+            "timer" => Ok(switch(self.timer)),
+            "utf8_values" => Ok(switch(table.utf8_values())),
+            _ => Err(unknown_setting(key)),
+        }
+    }
+}
+
+/// Parses an `on`/`off` setting
+fn parse_switch(value: &str) -> Result<bool, String> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        value => Err(format!(
+            "invalid setting `{value}`; expected `on` or `off`."
+        )),
+    }
+}
+
+fn unknown_setting(key: &str) -> String {
+    format!(
+        "unknown setting `{key}`; expected one of {}.",
+        SETTINGS.join(", ")
+    )
+}
+
+/// A single line read from the REPL input
+enum Input {
+    Line(String),
+    /// The line was longer than the maximum length and was discarded
+    TooLong,
+    End,
+}
+
+/// Reads a line of at most `max_len` bytes from `reader`.
+///
+/// Longer lines are consumed in chunks and dropped so they never have to be held in memory.
+fn read_line<R: BufRead>(reader: &mut R, max_len: usize) -> std::io::Result<Input> {
+    let mut line = Vec::new();
+    (&mut *reader)
+        .take(max_len as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+    if line.is_empty() {
+        return Ok(Input::End);
+    }
+
+    if line.last() != Some(&b'\n') && line.len() > max_len {
+        loop {
+            let buf = reader.fill_buf()?;
+            match buf.iter().position(|b| *b == b'\n') {
+                Some(end) => {
+                    reader.consume(end + 1);
+                    break;
+                }
+                None if buf.is_empty() => break,
+                None => {
+                    let len = buf.len();
+                    reader.consume(len);
+                }
+            }
+        }
+        return Ok(Input::TooLong);
+    }
+
+    String::from_utf8(line)
+        .map(Input::Line)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
 
-        let mut input: String = String::new();
-        std::io::stdout()
-            .flush()
+/// Starts a database REPL session on table `name`; reading statements from `input` and writing
+/// prompts and results to `output`. Input lines longer than `max_line_length` bytes are rejected.
+///
+/// The pages of the file are encrypted with a key derived from `key` when it is set
+#[allow(clippy::too_many_arguments)]
+pub fn start_repl<R: BufRead, W: Write>(
+    name: String,
+    path: PathBuf,
+    key: Option<String>,
+    format: OutputFormat,
+    max_line_length: usize,
+    mut input: R,
+    mut output: W,
+) {
+    let mut settings = Settings {
+        format,
+        max_line_length,
+        ..Default::default()
+    };
+    let table = match (path.as_os_str() == IN_MEMORY_PATH, key) {
+        (true, None) => Ok(Table::in_memory()),
+        (true, Some(_)) => Err("in-memory databases can not be encrypted".to_string()),
+        (false, Some(key)) => Table::new_encrypted(path, &key),
+        (false, None) => Table::new(path),
+    };
+    let mut table = match table.and_then(|mut table| table.open(&name).map(|_| table)) {
+        Ok(table) => table,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+    };
+    // Sessions started from the same process share the subscriber of the first one
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .try_init();
+
+    loop {
+        // The flush interval of the table keeps running while the session waits for input
+        table.flush_if_due();
+        write!(output, "{} > ", table.name())
+            .and_then(|_| output.flush())
             .expect("failed to print to screen");
-        std::io::stdin()
-            .read_line(&mut input)
-            .expect("failed to read command");
-        let input = input.trim();
-
-        let result: Result<MetaCommand, _> = input.try_into();
-        if let Ok(command) = result {
-            command.execute().expect("failed to execute command");
+        let read = read_line(&mut input, settings.max_line_length).expect("failed to read command");
+        let line = match read {
+            Input::Line(line) => line,
+            Input::TooLong => {
+                writeln!(
+                    output,
+                    "error: input is longer than {} bytes",
+                    settings.max_line_length
+                )
+                .expect("failed to print to screen");
+                continue;
+            }
+            Input::End => {
+                // End of input; there is nothing left to execute
+                if let Err(e) = table.flush() {
+                    writeln!(output, "error: failed to flush; {e}")
+                        .expect("failed to print to screen");
+                }
+                writeln!(output).expect("failed to print to screen");
+                break;
+            }
+        };
+
+        // Statements may end in whitespace that belongs to a value
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.trim().is_empty() {
             continue;
         }
 
-        let result: Result<Statement, _> = input.try_into();
+        let result: Result<MetaCommand, _> = line.trim().try_into();
         match result {
+            Ok(command) => {
+                // `.exit` ends the session; nothing may be left in the cache even without autoflush
+                if matches!(command, MetaCommand::Exit) {
+                    if let Err(e) = table.flush() {
+                        writeln!(output, "error: failed to flush; {e}")
+                            .expect("failed to print to screen");
+                    }
+                }
+                command
+                    .execute(&mut settings, &mut table, &mut output)
+                    .expect("failed to execute command");
+                if matches!(command, MetaCommand::Exit) {
+                    break;
+                }
+                // Meta commands such as `.import` change the table just like statements do
+                if settings.autoflush && table.has_unflushed_changes() {
+                    if let Err(e) = table.flush() {
+                        writeln!(output, "error: failed to flush; {e}")
+                            .expect("failed to print to screen");
+                    }
+                }
+                continue;
+            }
+            // Only meta commands start with a dot; there is no statement to fall back to
+            Err(e) if line.trim_start().starts_with('.') => {
+                writeln!(output, "error: {e}").expect("failed to print to screen");
+                continue;
+            }
+            Err(_) => (),
+        }
+
+        match Statement::parse(line, table.key_type()) {
             Ok(s) => {
+                let start = Instant::now();
                 let mut cursor = Cursor::new(&mut table);
-                s.execute(&mut cursor);
+                s.execute(&mut cursor, settings.format, &mut output)
+                    .expect("failed to print to screen");
+                if settings.timer {
+                    writeln!(output, "time: {:?}", start.elapsed())
+                        .expect("failed to print to screen");
+                }
+                // Changes are written after every statement; a failed flush is retried after the
+                // next one
+                if settings.autoflush && table.has_unflushed_changes() {
+                    if let Err(e) = table.flush() {
+                        writeln!(output, "error: failed to flush; {e}")
+                            .expect("failed to print to screen");
+                    }
+                }
             }
-            Err(e) => println!("error: {}", e),
+            Err(e) => writeln!(output, "error: {}", e).expect("failed to print to screen"),
         }
 
-        std::io::stdout()
-            .flush()
-            .expect("failed to print to screen");
+        output.flush().expect("failed to print to screen");
     }
 }
@@ -0,0 +1,95 @@
+/// Number of bits in every filter; 8 KiB per filtered page
+pub const BLOOM_FILTER_BITS: usize = 1 << 16;
+
+/// Seeds of the hash functions; every key sets one bit per seed
+const SEEDS: [u64; 3] = [
+    0x9e37_79b9_7f4a_7c15,
+    0xc2b2_ae3d_27d4_eb4f,
+    0x1656_67b1_9e37_79f9,
+];
+
+/// Summary of a set of keys.
+///
+/// A filter never misses a key that was inserted into it, but may claim to hold keys that were
+/// never inserted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+}
+
+impl Default for BloomFilter {
+    fn default() -> Self {
+        Self {
+            bits: vec![0; BLOOM_FILTER_BITS / 64],
+        }
+    }
+}
+
+impl BloomFilter {
+    /// Adds `key` to the filter
+    pub fn insert(&mut self, key: u128) {
+        for bit in Self::bits_of(key) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Returns `false` if `key` was never inserted; `true` if it may have been
+    pub fn contains(&self, key: u128) -> bool {
+        Self::bits_of(key).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Adds every key of `other` to the filter
+    pub fn union(&mut self, other: &BloomFilter) {
+        for (bits, other) in self.bits.iter_mut().zip(&other.bits) {
+            *bits |= other;
+        }
+    }
+
+    fn bits_of(key: u128) -> impl Iterator<Item = usize> {
+        // The high half of wide keys is folded into the low half; multiplying it by an odd seed
+        // keeps it a bijection, and keys that fit into a u64 hash exactly as they always did
+        let key = key as u64 ^ ((key >> 64) as u64).wrapping_mul(SEEDS[0]);
+        SEEDS
+            .into_iter()
+            .map(move |seed| (splitmix64(key ^ seed) % BLOOM_FILTER_BITS as u64) as usize)
+    }
+}
+
+/// Scrambles the bits of `x`; see <https://prng.di.unimi.it/splitmix64.c>
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn never_misses_inserted_keys() {
+        let mut filter = BloomFilter::default();
+        for key in (0..5000).map(|k| k * 7) {
+            filter.insert(key);
+        }
+
+        assert!((0..5000).map(|k| k * 7).all(|key| filter.contains(key)));
+        let false_positives = (0..5000)
+            .map(|k| k * 7 + 1)
+            .filter(|key| filter.contains(*key))
+            .count();
+        assert!(false_positives < 50, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn union_holds_keys_of_both_filters() {
+        let (mut left, mut right) = (BloomFilter::default(), BloomFilter::default());
+        left.insert(1);
+        right.insert(2);
+        left.union(&right);
+
+        assert!(left.contains(1));
+        assert!(left.contains(2));
+    }
+}
@@ -1,47 +1,73 @@
 use core::panic;
-use std::{fmt::Display, mem::size_of, sync::Arc};
+use std::{cmp::Ordering, fmt::Display, mem::size_of, sync::Arc};
 
-use log::debug;
+use tracing::debug;
 
 use crate::{
     calculate_offsets,
     storage::layout::{
-        INTERNAL_CELL_SIZE, INTERNAL_KEY_POINTER_SIZE, INTERNAL_MAX_KEYS, INTERNAL_NUM_KEYS_OFFSET,
-        INTERNAL_RIGHT_MOST_CHILD_OFFSET, INTERNAL_RIGHT_MOST_CHILD_SIZE,
-        LEAF_FREE_SPACE_END_OFFSET, LEAF_FREE_SPACE_START_OFFSET, LEAF_KEY_INDENTIFIER_OFFSET,
+        internal_cell_size, internal_max_keys, leaf_key_cell_size, leaf_max_inline_content,
+        INTERNAL_KEY_POINTER_SIZE, INTERNAL_NUM_KEYS_OFFSET, INTERNAL_RIGHT_MOST_CHILD_OFFSET,
+        INTERNAL_RIGHT_MOST_CHILD_SIZE, KEY_SIZE, LEAF_FREE_SPACE_END_OFFSET,
+        LEAF_FREE_SPACE_START_OFFSET, LEAF_KEY_INDENTIFIER_OFFSET,
         LEAF_NEXT_SIBLING_POINTER_DEFAULT, LEAF_NEXT_SIBLING_POINTER_OFFSET, LEAF_NUM_KEYS_OFFSET,
-        PAGE_SIZE,
+        PAGE_SIZE, WIDE_KEY_SIZE,
     },
 };
 
 use super::{
-    cell::{Cell, LeafCell},
+    cell::{Cell, CellFlags, InternalCell, LeafCell},
     layout::{
-        INTERNAL_HEADER_SIZE, INTERNAL_KEY_OFFSET, INTERNAL_KEY_POINTER_OFFSET,
-        LEAF_CONTENT_LEN_SIZE, LEAF_HEADER_SIZE, LEAF_KEY_CELL_SIZE, LEAF_KEY_POINTER_OFFSET,
-        LEAF_OVERFLOW_POINTER_DEFAULT, LEAF_OVERFLOW_POINTER_OFFSET, PAGE_IS_ROOT_OFFSET,
-        PAGE_IS_ROOT_SIZE, PAGE_TYPE_OFFSET, PAGE_TYPE_SIZE,
+        INTERNAL_HEADER_SIZE, INTERNAL_KEY_OFFSET, INTERNAL_SPACE_FOR_CELLS,
+        LEAF_CELL_HAS_OVERFLOW_FLAG_OFFSET, LEAF_CELL_HAS_OVERFLOW_FLAG_SIZE, LEAF_HEADER_SIZE,
+        LEAF_KEY_POINTER_SIZE, LEAF_LEGACY_CONTENT_LEN_SIZE, LEAF_OVERFLOW_POINTER_DEFAULT,
+        LEAF_OVERFLOW_POINTER_OFFSET, LEAF_SPACE_FOR_DATA, PAGE_IS_ROOT_OFFSET, PAGE_IS_ROOT_SIZE,
+        PAGE_MAGIC_OFFSET, PAGE_MAGIC_SIZE, PAGE_TYPE_OFFSET, PAGE_TYPE_SIZE,
     },
-    page::{bool_to_u8, u8_to_bool, CachedPage, Page, PageType},
+    page::{bool_to_u8, check_magic, u8_to_bool, CachedPage, Page, PageNum, PageType},
+    schema::ValueType,
+    varint,
 };
 
 type Result<T> = std::result::Result<T, NodeResult>;
 
+/// Function used to order the keys of a tree
+pub type KeyComparator = fn(u128, u128) -> Ordering;
+
+/// Default key order; ascending numeric
+pub fn ascending(a: u128, b: u128) -> Ordering {
+    a.cmp(&b)
+}
+
 /// Possible result types that can be returned by [Node](Node) operations
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum NodeResult {
     /// Returned when a node is full and requires a split action to be performed
     IsFull,
     /// Returned when a node has an overflow.
     ///
     /// Returns the remaining content that needs to be written.
-    HasOverflow(Vec<u8>),
+    HasOverflow(#[allow(dead_code)] Vec<u8>),
     /// Returned when trying to read a node with invalid page content
     InvalidPage { desc: String },
     /// Returned when trying to insert a duplicate key
     DuplicateKey,
     /// Returned when the identifier given for an operation does not exist
     KeyDoesNotExist,
+    /// Returned when a value is larger than the maximum allowed value size
+    ValueTooLarge { size: usize, max: usize },
+    /// Returned when a value that has to be text is not valid UTF-8
+    InvalidUtf8,
+    /// Returned when a key does not fit into the key size of the tree
+    KeyTooLarge,
+    /// Returned when a value does not match the schema of the table
+    SchemaMismatch(ValueType),
+    /// Returned when a key does not fit into the operation log the table writes to
+    KeyNotLogged,
+    /// Returned when appending to a table whose largest key is the largest key of its key type
+    NoIdentifiersLeft,
+    /// Returned when a new page can't be written to the file
+    WriteFailed { desc: String },
 }
 
 impl Display for NodeResult {
@@ -52,12 +78,28 @@ impl Display for NodeResult {
             Self::InvalidPage { desc } => format!("invalid page; {desc}"),
             Self::DuplicateKey => "duplicate key".to_string(),
             Self::KeyDoesNotExist => "key does not exist".to_string(),
+            Self::ValueTooLarge { size, max } => {
+                format!("value of {size} bytes exceeds the maximum of {max} bytes")
+            }
+            Self::InvalidUtf8 => "value is not valid UTF-8".to_string(),
+            Self::KeyTooLarge => "key does not fit into the keys of the table".to_string(),
+            Self::SchemaMismatch(schema) => format!("value is not a valid {schema}"),
+            Self::KeyNotLogged => "key does not fit into the keys of the operation log".to_string(),
+            Self::NoIdentifiersLeft => "no identifiers left after the largest key".to_string(),
+            Self::WriteFailed { desc } => format!("failed to write page; {desc}"),
         };
 
         write!(f, "{}", msg)
     }
 }
 
+impl std::error::Error for NodeResult {}
+
+/// Returns the child pointer of an internal cell
+fn internal_pointer<T: Cell>(cell: &T) -> PageNum {
+    InternalCell::from_bytes(cell.get_content()).pointer()
+}
+
 // In-memory representation of a page.
 //
 // This structure is used to manipulate page contents in memory
@@ -66,6 +108,9 @@ pub struct Node {
     keys: u64,
     _type: PageType,
     buffer: Option<Page>,
+    compare: KeyComparator,
+    max_keys: u64,
+    key_size: usize,
 }
 
 impl Node {
@@ -77,64 +122,102 @@ impl Node {
             keys: 0,
             _type: PageType::Leaf,
             buffer: None,
+            compare: ascending,
+            max_keys: internal_max_keys(KEY_SIZE) as u64,
+            key_size: KEY_SIZE,
         };
 
+        check_magic(&obj.read_variable_data(PAGE_MAGIC_OFFSET, PAGE_MAGIC_SIZE, false))
+            .map_err(|desc| NodeResult::InvalidPage { desc })?;
         obj._type = obj.read_variable_data(PAGE_TYPE_OFFSET, PAGE_TYPE_SIZE, false)[0]
             .try_into()
             .map_err(|e| NodeResult::InvalidPage {
                 desc: format!("error while reading page type; {}", e),
             })?;
+        if obj._type == PageType::Overflow || obj._type == PageType::Catalog {
+            return Err(NodeResult::InvalidPage {
+                desc: format!("{:?} pages can not be loaded as nodes", obj._type),
+            });
+        }
         obj.keys = obj.num_cells();
 
         Ok(obj)
     }
 
-    pub fn find_cell_num(&self, key: u64) -> u64 {
-        let num_cells = self.num_cells();
-        let mut min_idx = 0;
-        let mut max_idx = self.num_cells();
+    /// Orders the keys of the node with `compare` instead of the default ascending order.
+    ///
+    /// Every node of a tree has to use the same order.
+    pub fn with_comparator(mut self, compare: KeyComparator) -> Self {
+        self.compare = compare;
+        self
+    }
 
-        match self._type {
-            PageType::Leaf => {
-                while min_idx != max_idx {
-                    let index = (min_idx + max_idx) / 2;
-                    let key_at_index = self.get_cell_key(self.calculate_cell_position(index), true);
+    /// Limits internal nodes to `max` cells instead of as many as fit in a page
+    pub fn with_max_keys(mut self, max: usize) -> Self {
+        self.max_keys = max.min(internal_max_keys(self.key_size)) as u64;
+        self
+    }
 
-                    if key == key_at_index {
-                        return index;
-                    } else if key < key_at_index {
-                        max_idx = index;
-                    } else {
-                        min_idx = index + 1;
-                    }
-                }
+    /// Reads and writes keys of `size` bytes instead of 8-byte keys.
+    ///
+    /// Every node of a tree has to use the same key size.
+    pub fn with_key_size(mut self, size: usize) -> Self {
+        self.key_size = size;
+        self.max_keys = self.max_keys.min(internal_max_keys(size) as u64);
+        self
+    }
+
+    pub fn find_cell_num(&self, key: u128) -> u64 {
+        #[cfg(test)]
+        test::FIND_CELL_NUM_CALLS.with(|calls| calls.set(calls.get() + 1));
 
-                min_idx
+        let mut min_idx = 0;
+        let mut max_idx = self.num_cells();
+
+        while min_idx != max_idx {
+            let index = (min_idx + max_idx) / 2;
+            match self.search_order(key, index) {
+                Ordering::Equal => return index,
+                Ordering::Less => max_idx = index,
+                Ordering::Greater => min_idx = index + 1,
             }
-            PageType::Internal => {
-                while min_idx != max_idx {
-                    let index = (min_idx + max_idx) / 2;
-                    let key_at_right = self.get_cell_key(self.calculate_cell_position(index), true);
+        }
 
-                    if key_at_right >= key {
-                        max_idx = index
-                    } else {
-                        min_idx = index + 1;
-                    }
-                }
+        // `num_cells` routes to the right-most child of internal nodes
+        min_idx
+    }
 
-                if min_idx >= num_cells {
-                    num_cells
-                } else {
-                    min_idx
-                }
+    /// Orders `key` against cell `index` while searching the node; `Equal` ends the search at the
+    /// cell.
+    ///
+    /// Leaves look for the cell holding `key`. Separators of internal nodes hold the largest key of
+    /// their child, so internal nodes look for the first separator that is not smaller than `key`
+    /// and never end the search early.
+    fn search_order(&self, key: u128, index: u64) -> Ordering {
+        let key_at_index = self.get_cell_key(self.calculate_cell_position(index), true);
+
+        match self._type {
+            PageType::Leaf => (self.compare)(key, key_at_index),
+            PageType::Internal => match (self.compare)(key_at_index, key) {
+                Ordering::Less => Ordering::Greater,
+                _ => Ordering::Less,
+            },
+            PageType::Overflow | PageType::Catalog => {
+                unreachable!("only tree pages are loaded as nodes")
             }
         }
     }
 
-    pub fn node_high_key(&self) -> u64 {
-        let cell_num = self.num_cells() - 1;
-        self.get_cell_key(self.calculate_cell_position(cell_num), false)
+    /// Returns the key stored in cell `num`
+    pub fn cell_key(&self, num: u64) -> u128 {
+        self.get_cell_key(self.calculate_cell_position(num), true)
+    }
+
+    /// Returns the key of the last cell; `None` if the node has no cells, like leaves emptied by a
+    /// range delete
+    pub fn node_high_key(&self) -> Option<u128> {
+        let cell_num = self.num_cells().checked_sub(1)?;
+        Some(self.get_cell_key(self.calculate_cell_position(cell_num), false))
     }
 
     pub fn node_type(&self) -> PageType {
@@ -148,33 +231,82 @@ impl Node {
             .unwrap()
     }
 
+    #[allow(dead_code)]
     pub fn set_is_root(&mut self, val: bool) {
-        self.write_all_bytes(vec![bool_to_u8(self.is_root())], PAGE_IS_ROOT_OFFSET);
+        self.write_all_bytes(vec![bool_to_u8(val)], PAGE_IS_ROOT_OFFSET);
     }
 
-    pub fn overflow_pointer(&self) -> Option<u64> {
+    #[allow(dead_code)]
+    pub fn overflow_pointer(&self) -> Option<PageNum> {
         if self._type == PageType::Internal {
             panic!("internal pages do not support overflows");
         } else {
             match self.read_u64_data(LEAF_OVERFLOW_POINTER_OFFSET, true) {
                 LEAF_OVERFLOW_POINTER_DEFAULT => None,
-                v => Some(v),
+                v => Some(PageNum(v)),
             }
         }
     }
 
-    pub fn next_sibling(&self) -> Option<u64> {
+    /// Returns whether the contents of leaf cell `num` are stored in overflow pages
+    pub fn cell_has_overflow(&self, num: u64) -> bool {
+        match self._type {
+            PageType::Leaf => self.cell_flags(num).contains(CellFlags::OVERFLOW),
+            _ => false,
+        }
+    }
+
+    /// Returns whether the contents of leaf cell `num` are compressed
+    pub fn cell_is_compressed(&self, num: u64) -> bool {
+        match self._type {
+            PageType::Leaf => self.cell_flags(num).contains(CellFlags::COMPRESSED),
+            _ => false,
+        }
+    }
+
+    /// Returns whether leaf cell `num` has been removed and only waits to be vacuumed
+    pub fn cell_is_deleted(&self, num: u64) -> bool {
+        match self._type {
+            PageType::Leaf => self.cell_flags(num).contains(CellFlags::DELETED),
+            _ => false,
+        }
+    }
+
+    /// Marks leaf cell `num` as removed without reclaiming its space
+    pub fn set_cell_deleted(&mut self, num: u64) {
+        let mut flags = self.cell_flags(num);
+        flags.set(CellFlags::DELETED, true);
+
+        let pos = self.calculate_cell_position(num) as usize;
+        self.write_all_bytes(
+            vec![flags.to_byte()],
+            pos + LEAF_CELL_HAS_OVERFLOW_FLAG_OFFSET,
+        );
+    }
+
+    fn cell_flags(&self, num: u64) -> CellFlags {
+        let pos = self.calculate_cell_position(num) as usize;
+        CellFlags::from_byte(
+            self.read_variable_data(
+                pos + LEAF_CELL_HAS_OVERFLOW_FLAG_OFFSET,
+                LEAF_CELL_HAS_OVERFLOW_FLAG_SIZE,
+                true,
+            )[0],
+        )
+    }
+
+    pub fn next_sibling(&self) -> Option<PageNum> {
         if self._type == PageType::Internal {
             None
         } else {
             match self.read_u64_data(LEAF_NEXT_SIBLING_POINTER_OFFSET, true) {
                 LEAF_NEXT_SIBLING_POINTER_DEFAULT => None,
-                v => Some(v),
+                v => Some(PageNum(v)),
             }
         }
     }
 
-    pub fn set_next_sibling(&mut self, pointer: u64) {
+    pub fn set_next_sibling(&mut self, pointer: PageNum) {
         self.write_all_bytes(
             pointer.to_be_bytes().to_vec(),
             LEAF_NEXT_SIBLING_POINTER_OFFSET,
@@ -185,60 +317,176 @@ impl Node {
         match self._type {
             PageType::Leaf => self.read_u64_data(LEAF_NUM_KEYS_OFFSET, true),
             PageType::Internal => self.read_u64_data(INTERNAL_NUM_KEYS_OFFSET, true),
+            PageType::Overflow | PageType::Catalog => {
+                unreachable!("only tree pages are loaded as nodes")
+            }
         }
     }
 
     pub fn insert_cell<T: Cell>(&mut self, cell: T) -> Result<()> {
-        if self.check_key_exists(cell.get_key()) {
+        if self.key_size < WIDE_KEY_SIZE && cell.get_key() >> (self.key_size * 8) != 0 {
+            return Err(NodeResult::KeyTooLarge);
+        }
+        // The position is searched for once and shared by the duplicate check and the insert
+        let cell_num = self.find_cell_num(cell.get_key());
+        if self.has_key_at(cell_num, cell.get_key()) {
             return Err(NodeResult::DuplicateKey);
         }
 
-        self.check_has_space(cell.get_key())?;
+        self.check_has_space()?;
+
+        // Like splits, inserts are written to a buffer first so a failed insert leaves the node
+        // unchanged. Inserts that are part of a larger buffered operation share its buffer
+        let owns_buffer = self.buffer.is_none();
+        if owns_buffer {
+            self.set_buffer();
+        }
 
         debug!("inserting new cell");
+        let res = match self._type {
+            PageType::Internal => self.insert_internal_cell(cell, cell_num),
+            PageType::Leaf => self.insert_leaf_cell(cell, cell_num),
+            PageType::Overflow | PageType::Catalog => {
+                unreachable!("only tree pages are loaded as nodes")
+            }
+        };
+
+        if owns_buffer {
+            match res {
+                Ok(()) => self.flush_buffer(),
+                Err(_) => self.buffer = None,
+            }
+        }
+        res
+    }
+
+    /// Removes the cell holding `key` from the node.
+    ///
+    /// Leaves reclaim the space of the cell's content. Internal nodes drop the separator together
+    /// with the pointer to the child it bounds; keys of that child are routed to the next child
+    /// afterwards.
+    pub fn remove_cell(&mut self, key: u128) -> Result<()> {
+        let cell_num = self.find_cell_num(key);
+        if !self.has_key_at(cell_num, key) {
+            return Err(NodeResult::KeyDoesNotExist);
+        }
+
+        debug!("removing cell {}; key {}", cell_num, key);
         match self._type {
-            PageType::Internal => self.insert_internal_cell(cell),
-            PageType::Leaf => self.insert_leaf_cell(cell),
+            PageType::Leaf => self.remove_leaf_cell(cell_num),
+            PageType::Internal => self.remove_internal_cell(cell_num),
+            PageType::Overflow | PageType::Catalog => {
+                unreachable!("only tree pages are loaded as nodes")
+            }
         }
     }
 
-    pub fn right_child(&self) -> Option<u64> {
+    /// Returns a copy of the page of the node; see [restore](Node::restore)
+    pub fn contents(&self) -> Page {
+        Page(
+            self.read_variable_data(0, PAGE_SIZE, false)[..]
+                .try_into()
+                .expect("failed to copy node contents"),
+        )
+    }
+
+    /// Replaces the page of the node with `contents`; undoes every change made since they were
+    /// copied with [contents](Node::contents)
+    pub fn restore(&mut self, contents: Page) {
+        self.write_all_bytes(contents[..].to_vec(), 0);
+        self.keys = self.num_cells();
+    }
+
+    pub fn right_child(&self) -> Option<PageNum> {
         match self._type {
             PageType::Leaf => None,
-            PageType::Internal => Some(self.read_u64_data(INTERNAL_RIGHT_MOST_CHILD_OFFSET, true)),
+            PageType::Internal => Some(PageNum(
+                self.read_u64_data(INTERNAL_RIGHT_MOST_CHILD_OFFSET, true),
+            )),
+            PageType::Overflow | PageType::Catalog => {
+                unreachable!("only tree pages are loaded as nodes")
+            }
         }
     }
 
-    pub fn read_cell_bytes(&self, num: u64) -> Vec<u8> {
+    /// Reads the content of cell `num`; the right child of internal nodes is read as cell
+    /// `num_cells`.
+    ///
+    /// Fails if the stored length of a leaf cell's content runs past the end of the page.
+    pub fn read_cell_bytes(&self, num: u64) -> Result<Vec<u8>> {
         let cell_pos = self.calculate_cell_position(num) as usize;
 
         match self._type {
             PageType::Internal => {
                 if num < self.num_cells() {
-                    self.read_variable_data(cell_pos, INTERNAL_CELL_SIZE, true)
+                    let mut vec = self.cell_key(num).to_be_bytes().to_vec();
+                    vec.append(&mut self.read_variable_data(
+                        cell_pos + self.key_pointer_offset(),
+                        INTERNAL_KEY_POINTER_SIZE,
+                        true,
+                    ));
+                    Ok(vec)
                 } else {
-                    let mut vec = self.node_high_key().to_be_bytes().to_vec();
+                    let mut vec = self
+                        .node_high_key()
+                        .unwrap_or_default()
+                        .to_be_bytes()
+                        .to_vec();
                     vec.append(&mut self.read_variable_data(
                         INTERNAL_RIGHT_MOST_CHILD_OFFSET,
                         INTERNAL_RIGHT_MOST_CHILD_SIZE,
                         true,
                     ));
-                    vec
+                    Ok(vec)
                 }
             }
             PageType::Leaf => {
-                let mut pointer = self.get_cell_key_pointer(cell_pos as u64, false) as usize;
-                let content_size = self.read_u64_data(pointer, true);
-                pointer += LEAF_CONTENT_LEN_SIZE;
+                let pointer = self.get_cell_key_pointer(cell_pos as u64, false) as usize;
+                let (content_size, len_size) =
+                    self.read_content_len(pointer, true)
+                        .ok_or(NodeResult::InvalidPage {
+                            desc: format!("content length of cell {num} is not a valid varint"),
+                        })?;
+                let start = pointer + len_size;
+                if content_size > (PAGE_SIZE - start) as u64 {
+                    return Err(NodeResult::InvalidPage {
+                        desc: format!("content of cell {num} runs past the end of the page"),
+                    });
+                }
 
-                self.read_variable_data(pointer, content_size as usize, true)
+                Ok(self.read_variable_data(start, content_size as usize, true))
+            }
+            PageType::Overflow | PageType::Catalog => {
+                unreachable!("only tree pages are loaded as nodes")
             }
         }
     }
 
     /// Splits the contents of the current node and inserts the split content into the passed in
     /// Node.
-    pub fn split<T: Cell>(&mut self, node: &mut Node, cell: T) -> Result<()> {
+    ///
+    /// The current node keeps the lower half of the keys. Returns the separator for the parent of
+    /// the two nodes; the largest key that is routed to the current node.
+    pub fn split<T: Cell>(&mut self, node: &mut Node, cell: T) -> Result<u128> {
+        self.split_at(node, cell, None)
+    }
+
+    /// Splits a leaf so that the new cell is the only cell of the passed in Node; the current
+    /// node keeps all of its keys. Used to leave space in leaves that are filled in key order.
+    ///
+    /// `cell` has to be ordered after every key of the current node.
+    pub fn split_off<T: Cell>(&mut self, node: &mut Node, cell: T) -> Result<u128> {
+        debug_assert_eq!(self.find_cell_num(cell.get_key()), self.num_cells());
+        self.split_at(node, cell, Some(self.num_cells()))
+    }
+
+    /// Splits the current node; leaves keep `left_count` cells, or half of them if not set
+    fn split_at<T: Cell>(
+        &mut self,
+        node: &mut Node,
+        cell: T,
+        left_count: Option<u64>,
+    ) -> Result<u128> {
         // Splits are a bit iffy; This enables us to recover from any errors that occur during
         // them. All writes during this operation are sent to the buffer which is then flushed
         // after a successful split
@@ -247,50 +495,79 @@ impl Node {
 
         let res = match self.node_type() {
             PageType::Internal => self.split_internal_node(node, cell),
-            PageType::Leaf => self.split_leaf_node(node, cell),
+            PageType::Leaf => self.split_leaf_node(node, cell, left_count),
+            PageType::Overflow | PageType::Catalog => {
+                unreachable!("only tree pages are loaded as nodes")
+            }
         };
 
-        if let Err(e) = res {
-            self.buffer = None;
-            node.buffer = None;
-            Err(e)
-        } else {
-            self.flush_buffer();
-            node.flush_buffer();
-
-            if let Some(sibling) = self.next_sibling() {
-                node.set_next_sibling(sibling);
+        match res {
+            Err(e) => {
+                self.buffer = None;
+                node.buffer = None;
+                Err(e)
             }
+            Ok(separator) => {
+                self.flush_buffer();
+                node.flush_buffer();
 
-            Ok(())
+                if let Some(sibling) = self.next_sibling() {
+                    node.set_next_sibling(sibling);
+                }
+
+                Ok(separator)
+            }
         }
     }
 
-    pub fn update<T: Cell>(&mut self, identifier: u64, cell: T) -> Result<()> {
-        if !self.check_key_exists(identifier) {
+    /// Replaces cell `identifier` with `cell`.
+    ///
+    /// Internal cells may change their key; leaf cells have to keep it. A leaf is left unchanged
+    /// if the new content doesn't fit.
+    pub fn update<T: Cell>(&mut self, identifier: u128, cell: T) -> Result<()> {
+        let cell_num = self.find_cell_num(identifier);
+        if !self.has_key_at(cell_num, identifier) {
             return Err(NodeResult::KeyDoesNotExist);
         }
 
-        let cell_num = self.find_cell_num(identifier);
         match self._type {
             PageType::Internal => {
-                let pointer_bytes = cell.get_content()[INTERNAL_KEY_POINTER_OFFSET
-                    ..INTERNAL_KEY_POINTER_SIZE + INTERNAL_KEY_POINTER_OFFSET]
-                    .to_vec();
+                let pointer_bytes = internal_pointer(&cell).to_be_bytes().to_vec();
 
                 if cell_num >= self.num_cells() {
                     self.write_all_bytes(pointer_bytes, INTERNAL_RIGHT_MOST_CHILD_OFFSET);
                 } else {
                     let pos = self.calculate_cell_position(cell_num) as usize;
-                    self.write_all_bytes(
-                        cell.get_key().to_be_bytes().to_vec(),
-                        pos + INTERNAL_KEY_OFFSET,
-                    );
-                    self.write_all_bytes(pointer_bytes, pos + INTERNAL_KEY_POINTER_OFFSET);
+                    self.write_all_bytes(self.key_bytes(cell.get_key()), pos + INTERNAL_KEY_OFFSET);
+                    self.write_all_bytes(pointer_bytes, pos + self.key_pointer_offset());
                 }
             }
             PageType::Leaf => {
-                todo!()
+                if cell.get_key() != identifier {
+                    return Err(NodeResult::InvalidPage {
+                        desc: format!(
+                            "leaf cell {identifier} can not be updated to key {}",
+                            cell.get_key()
+                        ),
+                    });
+                }
+
+                // The old content is removed before the new content is placed, so a value can
+                // grow into the space it frees; the page is left untouched if it doesn't fit
+                self.set_buffer();
+                let res = self
+                    .remove_leaf_cell(cell_num)
+                    .and_then(|_| self.insert_leaf_cell(cell, cell_num));
+                match res {
+                    Ok(()) => self.flush_buffer(),
+                    Err(e) => {
+                        self.buffer = None;
+                        return Err(e);
+                    }
+                }
+            }
+            PageType::Overflow | PageType::Catalog => {
+                unreachable!("only tree pages are loaded as nodes")
             }
         }
 
@@ -300,44 +577,261 @@ impl Node {
     /// Retrieve the cell position for an Internal node key or Leaf node key
     fn calculate_cell_position(&self, num: u64) -> u64 {
         match self._type {
-            PageType::Leaf => LEAF_HEADER_SIZE as u64 + (num * LEAF_KEY_CELL_SIZE as u64),
-            PageType::Internal => INTERNAL_HEADER_SIZE as u64 + (num * INTERNAL_CELL_SIZE as u64),
+            PageType::Leaf => LEAF_HEADER_SIZE as u64 + (num * self.cell_size() as u64),
+            PageType::Internal => INTERNAL_HEADER_SIZE as u64 + (num * self.cell_size() as u64),
+            PageType::Overflow | PageType::Catalog => {
+                unreachable!("only tree pages are loaded as nodes")
+            }
+        }
+    }
+
+    /// Returns the size of a key cell of a leaf or of a cell of an internal node
+    fn cell_size(&self) -> usize {
+        match self._type {
+            PageType::Leaf => leaf_key_cell_size(self.key_size),
+            PageType::Internal => internal_cell_size(self.key_size),
+            PageType::Overflow | PageType::Catalog => {
+                unreachable!("only tree pages are loaded as nodes")
+            }
+        }
+    }
+
+    /// Returns the offset of the content or child pointer within a cell
+    fn key_pointer_offset(&self) -> usize {
+        self.cell_size() - LEAF_KEY_POINTER_SIZE
+    }
+
+    /// Returns the largest value that can be stored inline in an empty leaf of the tree
+    pub fn max_inline_content(&self) -> usize {
+        leaf_max_inline_content(self.key_size)
+    }
+
+    /// Encodes `key` into the bytes stored in a cell
+    fn key_bytes(&self, key: u128) -> Vec<u8> {
+        key.to_be_bytes()[WIDE_KEY_SIZE - self.key_size..].to_vec()
+    }
+
+    /// Checks that the content of leaf cell `num` lies within the leaf content area
+    pub fn check_cell_content(&self, num: u64) -> Result<()> {
+        let pos = self.calculate_cell_position(num);
+        let pointer = self.get_cell_key_pointer(pos, true);
+        let free_space_end = self.read_u64_data(LEAF_FREE_SPACE_END_OFFSET, true);
+
+        if pointer < free_space_end || pointer >= PAGE_SIZE as u64 {
+            return Err(NodeResult::InvalidPage {
+                desc: format!("content pointer {pointer} of cell {num} is out of bounds"),
+            });
+        }
+
+        let (content_size, len_size) =
+            self.read_content_len(pointer as usize, true)
+                .ok_or(NodeResult::InvalidPage {
+                    desc: format!("content length of cell {num} is not a valid varint"),
+                })?;
+        if content_size > PAGE_SIZE as u64 - pointer - len_size as u64 {
+            return Err(NodeResult::InvalidPage {
+                desc: format!("content of cell {num} runs past the end of the page"),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Recalculates the free space pointers of a leaf from its cells
+    ///
+    /// Cell content is packed against the end of the page again, so the free space starts after
+    /// the last key cell and ends at the page size minus the content bytes of every cell.
+    pub fn recompute_free_space(&mut self) -> Result<()> {
+        if self._type != PageType::Leaf {
+            return Err(NodeResult::InvalidPage {
+                desc: "only leaf nodes track free space".into(),
+            });
+        }
+
+        let num_cells = self.num_cells();
+        if num_cells > (LEAF_SPACE_FOR_DATA / self.cell_size()) as u64 {
+            return Err(NodeResult::InvalidPage {
+                desc: format!("leaf claims {num_cells} cells"),
+            });
+        }
+        let free_space_start = self.calculate_cell_position(num_cells);
+
+        // The stored free space end cannot be trusted; content only has to lie past the key cells
+        let mut contents = Vec::with_capacity(num_cells as usize);
+        for num in 0..num_cells {
+            let pos = self.calculate_cell_position(num);
+            let pointer = self.get_cell_key_pointer(pos, true);
+            if pointer < free_space_start || pointer >= PAGE_SIZE as u64 {
+                return Err(NodeResult::InvalidPage {
+                    desc: format!("content pointer {pointer} of cell {num} is out of bounds"),
+                });
+            }
+
+            let (content_size, len_size) = self
+                .read_content_len(pointer as usize, true)
+                .filter(|(size, len)| *size <= PAGE_SIZE as u64 - pointer - *len as u64)
+                .ok_or(NodeResult::InvalidPage {
+                    desc: format!("content of cell {num} runs past the end of the page"),
+                })?;
+            contents.push(self.read_variable_data(
+                pointer as usize,
+                len_size + content_size as usize,
+                true,
+            ));
+        }
+
+        self.pack_contents(contents)
+    }
+
+    /// Rewrites the content length of every cell from the fixed size integer used before format
+    /// version 2 to a varint; the contents are packed against the end of the page again.
+    ///
+    /// Fails without changing the leaf if a stored length runs past the end of the page.
+    pub fn upgrade_content_lengths(&mut self) -> Result<()> {
+        if self._type != PageType::Leaf {
+            return Err(NodeResult::InvalidPage {
+                desc: "only leaf nodes store content lengths".into(),
+            });
+        }
+
+        let mut contents = Vec::with_capacity(self.num_cells() as usize);
+        for num in 0..self.num_cells() {
+            let pos = self.calculate_cell_position(num);
+            let pointer = self.get_cell_key_pointer(pos, true) as usize;
+            let start = pointer + LEAF_LEGACY_CONTENT_LEN_SIZE;
+            let size = Some(start)
+                .filter(|start| *start <= PAGE_SIZE)
+                .map(|_| self.read_u64_data(pointer, true) as usize)
+                .filter(|size| *size <= PAGE_SIZE - start)
+                .ok_or(NodeResult::InvalidPage {
+                    desc: format!("content of cell {num} runs past the end of the page"),
+                })?;
+
+            let mut content = varint::encode(size as u64);
+            content.append(&mut self.read_variable_data(start, size, true));
+            contents.push(content);
+        }
+
+        self.pack_contents(contents)
+    }
+
+    /// Writes the length prefixed content of every cell, in cell order, against the end of the
+    /// page and updates the free space pointers to match
+    fn pack_contents(&mut self, contents: Vec<Vec<u8>>) -> Result<()> {
+        let free_space_start = self.calculate_cell_position(self.num_cells());
+        let content_len = contents.iter().map(|c| c.len() as u64).sum::<u64>();
+        if content_len > PAGE_SIZE as u64 - free_space_start {
+            return Err(NodeResult::InvalidPage {
+                desc: "cell content does not fit into the leaf".into(),
+            });
+        }
+
+        let mut free_space_end = PAGE_SIZE as u64;
+        for (num, content) in contents.into_iter().enumerate() {
+            free_space_end -= content.len() as u64;
+            let pos = self.calculate_cell_position(num as u64) as usize;
+            self.write_all_bytes(
+                free_space_end.to_be_bytes().to_vec(),
+                pos + self.key_pointer_offset(),
+            );
+            self.write_all_bytes(content, free_space_end as usize);
+        }
+
+        self.write_all_bytes(
+            free_space_start.to_be_bytes().to_vec(),
+            LEAF_FREE_SPACE_START_OFFSET,
+        );
+        self.write_all_bytes(
+            free_space_end.to_be_bytes().to_vec(),
+            LEAF_FREE_SPACE_END_OFFSET,
+        );
+
+        Ok(())
+    }
+
+    /// Returns the number of unused bytes in the node
+    pub fn free_space(&self) -> usize {
+        match self._type {
+            PageType::Leaf => {
+                let start = self.read_u64_data(LEAF_FREE_SPACE_START_OFFSET, true);
+                let end = self.read_u64_data(LEAF_FREE_SPACE_END_OFFSET, true);
+                end.saturating_sub(start) as usize
+            }
+            PageType::Internal => INTERNAL_SPACE_FOR_CELLS
+                .saturating_sub(self.num_cells() as usize * self.cell_size()),
+            PageType::Overflow | PageType::Catalog => {
+                unreachable!("only tree pages are loaded as nodes")
+            }
         }
     }
 
-    fn check_key_exists(&self, key: u64) -> bool {
-        let pos = self.calculate_cell_position(self.find_cell_num(key));
+    /// Returns the fraction of the node's cell space that is in use
+    pub fn fill_factor(&self) -> f64 {
+        let space = match self._type {
+            PageType::Leaf => LEAF_SPACE_FOR_DATA,
+            _ => INTERNAL_SPACE_FOR_CELLS,
+        };
+
+        1.0 - self.free_space() as f64 / space as f64
+    }
+
+    pub fn check_key_exists(&self, key: u128) -> bool {
+        self.has_key_at(self.find_cell_num(key), key)
+    }
+
+    /// Checks whether cell `cell_num`, as returned by [find_cell_num](Node::find_cell_num),
+    /// holds `key`
+    pub fn has_key_at(&self, cell_num: u64, key: u128) -> bool {
+        cell_num < self.num_cells() && self.cell_key(cell_num) == key
+    }
 
-        self.get_cell_key(pos, false) == key
+    /// Checks whether a cell with `content_len` bytes of inline content fits into the node
+    /// without splitting it
+    pub fn has_space_for(&self, content_len: usize) -> bool {
+        if self.check_has_space().is_err() {
+            return false;
+        }
+
+        match self._type {
+            PageType::Leaf => {
+                let start = self.read_u64_data(LEAF_FREE_SPACE_START_OFFSET, true);
+                let end = self.read_u64_data(LEAF_FREE_SPACE_END_OFFSET, true);
+                end.checked_sub((varint::encoded_len(content_len as u64) + content_len) as u64)
+                    .is_some_and(|end| start + (self.cell_size() as u64) < end)
+            }
+            _ => true,
+        }
     }
 
     /// Checks if the particular node has space
     ///
     /// - Internal nodes: are checked against the maximum allowed number of keys. Ensuring the node
-    /// only stores N+1 key; The +1 being the right-most pointer.
+    ///   only stores N+1 key; The +1 being the right-most pointer.
     /// - Leaf nodes: are checked to ensure the node can store one more key entry and have left
-    /// over space; If only one key can be stored without it's data or part of it's data it has
-    /// filled up
-    fn check_has_space(&self, key: u64) -> Result<()> {
+    ///   over space; If only one key can be stored without it's data or part of it's data it has
+    ///   filled up
+    fn check_has_space(&self) -> Result<()> {
         match self._type {
             PageType::Leaf => {
                 let free_space = self.read_u64_data(LEAF_FREE_SPACE_END_OFFSET, true)
                     - self.read_u64_data(LEAF_FREE_SPACE_START_OFFSET, true);
 
-                match free_space as u64 {
-                    v if v <= LEAF_KEY_CELL_SIZE as u64
-                        || v - LEAF_KEY_CELL_SIZE as u64 <= LEAF_KEY_CELL_SIZE as u64 =>
-                    {
+                let cell_size = self.cell_size() as u64;
+                match free_space {
+                    v if v <= cell_size || v - cell_size <= cell_size => {
                         return Err(NodeResult::IsFull)
                     }
                     _ => (),
                 }
             }
             PageType::Internal => {
-                if self.num_cells() + 1 > INTERNAL_MAX_KEYS as u64 {
+                if self.num_cells() + 1 > self.max_keys {
                     return Err(NodeResult::IsFull);
                 }
             }
+            PageType::Overflow | PageType::Catalog => {
+                unreachable!("only tree pages are loaded as nodes")
+            }
         };
 
         Ok(())
@@ -349,27 +843,30 @@ impl Node {
         }
     }
 
-    fn get_cell_key(&self, pos: u64, buffered: bool) -> u64 {
+    fn get_cell_key(&self, pos: u64, buffered: bool) -> u128 {
         let start_pos = match self._type {
             PageType::Leaf => LEAF_KEY_INDENTIFIER_OFFSET + pos as usize,
             PageType::Internal => INTERNAL_KEY_OFFSET + pos as usize,
+            PageType::Overflow | PageType::Catalog => {
+                unreachable!("only tree pages are loaded as nodes")
+            }
         };
 
-        self.read_u64_data(start_pos, buffered)
+        let mut bytes = [0x0; WIDE_KEY_SIZE];
+        bytes[WIDE_KEY_SIZE - self.key_size..].clone_from_slice(&self.read_variable_data(
+            start_pos,
+            self.key_size,
+            buffered,
+        ));
+        u128::from_be_bytes(bytes)
     }
 
     fn get_cell_key_pointer(&self, pos: u64, buffered: bool) -> u64 {
-        let start_pos = match self._type {
-            PageType::Leaf => LEAF_KEY_POINTER_OFFSET + pos as usize,
-            PageType::Internal => INTERNAL_KEY_POINTER_OFFSET + pos as usize,
-        };
-
-        self.read_u64_data(start_pos, buffered)
+        self.read_u64_data(pos as usize + self.key_pointer_offset(), buffered)
     }
 
-    fn insert_internal_cell<T: Cell>(&mut self, cell: T) -> Result<()> {
+    fn insert_internal_cell<T: Cell>(&mut self, cell: T, cell_num: u64) -> Result<()> {
         let key = cell.get_key();
-        let cell_num = self.find_cell_num(key);
         let mut bytes: Vec<u8>;
 
         if cell_num >= self.num_cells() {
@@ -381,9 +878,7 @@ impl Node {
             );
 
             self.write_all_bytes(
-                cell.get_content()[INTERNAL_KEY_POINTER_OFFSET
-                    ..INTERNAL_KEY_POINTER_SIZE + INTERNAL_KEY_POINTER_OFFSET]
-                    .to_vec(),
+                internal_pointer(&cell).to_be_bytes().to_vec(),
                 INTERNAL_RIGHT_MOST_CHILD_OFFSET,
             );
 
@@ -391,17 +886,18 @@ impl Node {
                 return Ok(());
             }
 
-            bytes.append(&mut cell.get_key().to_be_bytes().to_vec());
+            bytes.append(&mut self.key_bytes(cell.get_key()));
             bytes.append(&mut right_child.to_be_bytes().to_vec());
         } else {
-            bytes = cell.get_content();
+            bytes = self.key_bytes(cell.get_key());
+            bytes.append(&mut internal_pointer(&cell).to_be_bytes().to_vec());
         }
 
         let pos = self.calculate_cell_position(cell_num) as usize;
         debug!("inserting new internal cell at {}; key {}", pos, key);
 
         let free_space_start = if self.num_cells() > 0 {
-            self.num_cells() as usize * INTERNAL_CELL_SIZE + INTERNAL_HEADER_SIZE
+            self.num_cells() as usize * self.cell_size() + INTERNAL_HEADER_SIZE
         } else {
             INTERNAL_HEADER_SIZE
         };
@@ -409,32 +905,40 @@ impl Node {
         if free_space_start != pos {
             // Move cells to the right
             let keys_after_pos = self.read_variable_data(pos, free_space_start - pos, true);
-            self.write_all_bytes(keys_after_pos, pos + INTERNAL_CELL_SIZE);
+            self.write_all_bytes(keys_after_pos, pos + self.cell_size());
         }
         self.write_all_bytes(bytes, pos);
 
         let num_cells = self.num_cells() + 1;
         self.write_all_bytes(num_cells.to_be_bytes().to_vec(), INTERNAL_NUM_KEYS_OFFSET);
 
-        debug!("key after insert: {}", self.read_u64_data(pos, true));
+        debug!("key after insert: {}", self.get_cell_key(pos as u64, true));
         debug!("has buffer: {:?}", self.buffer);
 
         Ok(())
     }
 
-    fn insert_leaf_cell<T: Cell>(&mut self, cell: T) -> Result<()> {
+    fn insert_leaf_cell<T: Cell>(&mut self, cell: T, cell_num: u64) -> Result<()> {
         let mut free_space_start = self.read_u64_data(LEAF_FREE_SPACE_START_OFFSET, true);
         let mut free_space_end = self.read_u64_data(LEAF_FREE_SPACE_END_OFFSET, true);
 
-        let key_pos = self.calculate_cell_position(self.find_cell_num(cell.get_key()));
+        let key_pos = self.calculate_cell_position(cell_num);
         let mut content = cell.get_content();
-        let mut content_bytes = Vec::new();
-        content_bytes.append(&mut content.len().to_be_bytes().to_vec());
+        if content.len() > self.max_inline_content() {
+            return Err(NodeResult::ValueTooLarge {
+                size: content.len(),
+                max: self.max_inline_content(),
+            });
+        }
+        let mut content_bytes = varint::encode(content.len() as u64);
         content_bytes.append(&mut content);
 
-        free_space_end -= content_bytes.len() as u64;
+        free_space_end = match free_space_end.checked_sub(content_bytes.len() as u64) {
+            Some(end) => end,
+            None => return Err(NodeResult::HasOverflow(Vec::with_capacity(0))),
+        };
 
-        if free_space_start + LEAF_KEY_CELL_SIZE as u64 >= free_space_end {
+        if free_space_start + self.cell_size() as u64 >= free_space_end {
             // TODO: Need to figure out how to handle overflow pages
             return Err(NodeResult::HasOverflow(Vec::with_capacity(0)));
         }
@@ -445,7 +949,8 @@ impl Node {
             cell.get_key()
         );
 
-        let mut key_bytes = cell.get_key_bytes();
+        let mut key_bytes = vec![cell.get_key_bytes()[LEAF_CELL_HAS_OVERFLOW_FLAG_OFFSET]];
+        key_bytes.append(&mut self.key_bytes(cell.get_key()));
         key_bytes.append(&mut free_space_end.to_be_bytes().to_vec());
 
         // Move key cells
@@ -455,11 +960,15 @@ impl Node {
                 (free_space_start - key_pos) as usize,
                 true,
             );
-            self.write_all_bytes(keys_after_cell, key_pos as usize + LEAF_KEY_CELL_SIZE);
+            self.write_all_bytes(keys_after_cell, key_pos as usize + self.cell_size());
         }
-        free_space_start += LEAF_KEY_CELL_SIZE as u64;
+        free_space_start += self.cell_size() as u64;
 
         self.write_all_bytes(key_bytes, key_pos as usize);
+        #[cfg(test)]
+        if test::FAIL_AFTER_KEY_CELL.with(|fail| fail.get()) {
+            return Err(NodeResult::HasOverflow(Vec::with_capacity(0)));
+        }
         self.write_all_bytes(content_bytes, free_space_end as usize);
 
         self.write_all_bytes(
@@ -480,6 +989,84 @@ impl Node {
         Ok(())
     }
 
+    fn remove_internal_cell(&mut self, cell_num: u64) -> Result<()> {
+        let num_cells = self.num_cells();
+        let pos = self.calculate_cell_position(cell_num) as usize;
+        let end = self.calculate_cell_position(num_cells) as usize;
+
+        // Move key cells
+        let next_pos = pos + self.cell_size();
+        if next_pos < end {
+            let cells_after = self.read_variable_data(next_pos, end - next_pos, true);
+            self.write_all_bytes(cells_after, pos);
+        }
+        self.write_all_bytes(
+            (num_cells - 1).to_be_bytes().to_vec(),
+            INTERNAL_NUM_KEYS_OFFSET,
+        );
+
+        Ok(())
+    }
+
+    fn remove_leaf_cell(&mut self, cell_num: u64) -> Result<()> {
+        let num_cells = self.num_cells();
+        let free_space_start = self.read_u64_data(LEAF_FREE_SPACE_START_OFFSET, true);
+        let free_space_end = self.read_u64_data(LEAF_FREE_SPACE_END_OFFSET, true);
+
+        let key_pos = self.calculate_cell_position(cell_num);
+        let pointer = self.get_cell_key_pointer(key_pos, true);
+        let (content_size, len_size) = self
+            .read_content_len(pointer as usize, true)
+            .expect("failed to read content length");
+        let content_len = len_size as u64 + content_size;
+
+        // Close the gap left by the content; everything stored below it moves up
+        if pointer > free_space_end {
+            let content_below = self.read_variable_data(
+                free_space_end as usize,
+                (pointer - free_space_end) as usize,
+                true,
+            );
+            self.write_all_bytes(content_below, (free_space_end + content_len) as usize);
+        }
+
+        // Move key cells
+        let next_pos = key_pos + self.cell_size() as u64;
+        if next_pos < free_space_start {
+            let keys_after_cell = self.read_variable_data(
+                next_pos as usize,
+                (free_space_start - next_pos) as usize,
+                true,
+            );
+            self.write_all_bytes(keys_after_cell, key_pos as usize);
+        }
+
+        for num in 0..num_cells - 1 {
+            let pos = self.calculate_cell_position(num);
+            let cell_pointer = self.get_cell_key_pointer(pos, true);
+            if cell_pointer < pointer {
+                self.write_all_bytes(
+                    (cell_pointer + content_len).to_be_bytes().to_vec(),
+                    pos as usize + self.key_pointer_offset(),
+                );
+            }
+        }
+
+        self.write_all_bytes(
+            (free_space_start - self.cell_size() as u64)
+                .to_be_bytes()
+                .to_vec(),
+            LEAF_FREE_SPACE_START_OFFSET,
+        );
+        self.write_all_bytes(
+            (free_space_end + content_len).to_be_bytes().to_vec(),
+            LEAF_FREE_SPACE_END_OFFSET,
+        );
+        self.write_all_bytes((num_cells - 1).to_be_bytes().to_vec(), LEAF_NUM_KEYS_OFFSET);
+
+        Ok(())
+    }
+
     /// Reads u64 numbers from the attached page.
     ///
     /// The `u64` number bytes are read in big-endian format
@@ -487,8 +1074,7 @@ impl Node {
         let size = size_of::<usize>();
         let (start, end) = calculate_offsets!(start, size);
 
-        if buffered && self.buffer.is_some() {
-            let buf = self.buffer.as_ref().expect("buffer should be set");
+        if let (true, Some(buf)) = (buffered, self.buffer.as_ref()) {
             u64::from_be_bytes(buf[start..end].try_into().expect("failed to read u64 data"))
         } else {
             let page = Arc::clone(&self.page.0);
@@ -508,8 +1094,7 @@ impl Node {
     fn read_variable_data(&self, start: usize, size: usize, buffered: bool) -> Vec<u8> {
         let (start, end) = calculate_offsets!(start, size);
 
-        if buffered && self.buffer.is_some() {
-            let buf = self.buffer.as_ref().expect("buffer should be set");
+        if let (true, Some(buf)) = (buffered, self.buffer.as_ref()) {
             buf[start..end].into()
         } else {
             let page = Arc::clone(&self.page.0);
@@ -530,17 +1115,66 @@ impl Node {
 
     /// Splits a full internal node
     ///
-    fn split_internal_node<T: Cell>(&mut self, node: &mut Node, cell: T) -> Result<()> {
-        todo!()
+    /// The middle cell is promoted to the parent; its child becomes the right child of the current
+    /// node.
+    fn split_internal_node<T: Cell>(&mut self, node: &mut Node, new_cell: T) -> Result<u128> {
+        let mut cells = (0..self.num_cells())
+            .map(|i| self.read_cell_bytes(i).map(InternalCell::from_bytes))
+            .collect::<Result<Vec<_>>>()?;
+        let mut right_child = self.right_child().expect("internal node has a right child");
+        let pointer = internal_pointer(&new_cell);
+
+        // Same placement as insert_internal_cell; a key past the last cell replaces the right child
+        let new_cell_num = self.find_cell_num(new_cell.get_key()) as usize;
+        if new_cell_num >= cells.len() {
+            cells.push(InternalCell::new(new_cell.get_key(), right_child));
+            right_child = pointer;
+        } else {
+            cells.insert(new_cell_num, InternalCell::new(new_cell.get_key(), pointer));
+        }
+
+        let mid = cells.len() / 2;
+        let promoted = &cells[mid];
+        self.write_internal_cells(&cells[..mid], promoted.pointer());
+        node.write_internal_cells(&cells[mid + 1..], right_child);
+
+        Ok(promoted.key())
+    }
+
+    /// Replaces all cells and the right child of an internal node
+    fn write_internal_cells(&mut self, cells: &[InternalCell], right_child: PageNum) {
+        for (i, cell) in cells.iter().enumerate() {
+            let pos = self.calculate_cell_position(i as u64) as usize;
+            self.write_all_bytes(self.key_bytes(cell.key()), pos + INTERNAL_KEY_OFFSET);
+            self.write_all_bytes(
+                cell.pointer().to_be_bytes().to_vec(),
+                pos + self.key_pointer_offset(),
+            );
+        }
+
+        self.write_all_bytes(
+            (cells.len() as u64).to_be_bytes().to_vec(),
+            INTERNAL_NUM_KEYS_OFFSET,
+        );
+        self.write_all_bytes(
+            right_child.to_be_bytes().to_vec(),
+            INTERNAL_RIGHT_MOST_CHILD_OFFSET,
+        );
     }
 
     /// Splits a full leaf node
     ///
-    fn split_leaf_node<T: Cell>(&mut self, node: &mut Node, new_cell: T) -> Result<()> {
+    fn split_leaf_node<T: Cell>(
+        &mut self,
+        node: &mut Node,
+        new_cell: T,
+        left_count: Option<u64>,
+    ) -> Result<u128> {
         let cells = self.num_cells() + 1;
+        let mut separator = 0;
         let new_cell_num = self.find_cell_num(new_cell.get_key());
-        let right_split_count = cells / 2;
-        let left_split_count = cells - right_split_count;
+        let left_split_count = left_count.unwrap_or(cells - cells / 2);
+        let right_split_count = cells - left_split_count;
         self.write_all_bytes(
             LEAF_HEADER_SIZE.to_be_bytes().to_vec(),
             LEAF_FREE_SPACE_START_OFFSET,
@@ -549,46 +1183,24 @@ impl Node {
         self.write_all_bytes(0_u64.to_be_bytes().to_vec(), LEAF_NUM_KEYS_OFFSET);
 
         for i in (0..cells).rev() {
-            let destination: &mut Self;
-            let mut cell: LeafCell = Default::default();
-
-            if i == new_cell_num {
-                let mut content = new_cell.get_key().to_be_bytes().to_vec();
-                content.append(&mut new_cell.get_content());
-                cell.from_bytes(content);
+            let cell = if i == new_cell_num {
+                let flags = CellFlags::from_byte(
+                    new_cell.get_key_bytes()[LEAF_CELL_HAS_OVERFLOW_FLAG_OFFSET],
+                );
+                LeafCell::with_flags(new_cell.get_key(), new_cell.get_content(), flags)
             } else if i > new_cell_num {
-                let pos = self.calculate_cell_position(i - 1);
-                let key = self.get_cell_key(pos, false);
-                let pointer = self.get_cell_key_pointer(pos, false) as usize;
-
-                let content_size = self.read_u64_data(pointer, false) as usize;
-                let mut content_bytes =
-                    self.read_variable_data(pointer + LEAF_CONTENT_LEN_SIZE, content_size, false);
-
-                let mut cell_bytes = key.to_be_bytes().to_vec();
-                cell_bytes.append(&mut content_bytes);
-                cell.from_bytes(cell_bytes);
+                self.read_leaf_cell(i - 1)
             } else {
-                let pos = self.calculate_cell_position(i);
-                let key = self.get_cell_key(pos, false);
-                let pointer = self.get_cell_key_pointer(pos, false) as usize;
-
-                let content_size = self.read_u64_data(pointer, false) as usize;
-                let mut content_bytes =
-                    self.read_variable_data(pointer + LEAF_CONTENT_LEN_SIZE, content_size, false);
+                self.read_leaf_cell(i)
+            };
 
-                let mut cell_bytes = key.to_be_bytes().to_vec();
-                cell_bytes.append(&mut content_bytes);
-                cell.from_bytes(cell_bytes);
+            if i == left_split_count - 1 {
+                separator = cell.get_key();
             }
+            let destination: &mut Self = if i >= left_split_count { node } else { self };
 
-            if i >= left_split_count {
-                destination = node;
-            } else {
-                destination = self;
-            }
-
-            destination.insert_leaf_cell(cell)?;
+            let cell_num = destination.find_cell_num(cell.get_key());
+            destination.insert_leaf_cell(cell, cell_num)?;
         }
 
         self.write_all_bytes(
@@ -600,7 +1212,35 @@ impl Node {
             LEAF_NUM_KEYS_OFFSET,
         );
 
-        Ok(())
+        Ok(separator)
+    }
+
+    /// Reads leaf cell `num` from the attached page; ignoring any buffered writes
+    fn read_leaf_cell(&self, num: u64) -> LeafCell {
+        let pos = self.calculate_cell_position(num);
+        let key = self.get_cell_key(pos, false);
+        let pointer = self.get_cell_key_pointer(pos, false) as usize;
+        let flags = CellFlags::from_byte(
+            self.read_variable_data(
+                pos as usize + LEAF_CELL_HAS_OVERFLOW_FLAG_OFFSET,
+                LEAF_CELL_HAS_OVERFLOW_FLAG_SIZE,
+                false,
+            )[0],
+        );
+
+        let (content_size, len_size) = self
+            .read_content_len(pointer, false)
+            .expect("failed to read content length");
+        let content = self.read_variable_data(pointer + len_size, content_size as usize, false);
+
+        LeafCell::with_flags(key, content, flags)
+    }
+
+    /// Reads the varint length of the leaf content at `pointer`; returns the length and the
+    /// number of bytes the varint takes up
+    fn read_content_len(&self, pointer: usize, buffered: bool) -> Option<(u64, usize)> {
+        let size = varint::MAX_SIZE.min(PAGE_SIZE.checked_sub(pointer)?);
+        varint::decode(&self.read_variable_data(pointer, size, buffered))
     }
 
     /// Writes data to the attached page
@@ -618,3 +1258,592 @@ impl Node {
         }
     }
 }
+
+/// Constructors and helpers for testing nodes without a pager or a file
+#[cfg(test)]
+impl Node {
+    /// Creates an empty leaf backed by a page that only lives in memory
+    pub fn new_leaf() -> Self {
+        let page = super::page::PageBuilder::default()
+            .kind(&PageType::Leaf)
+            .build();
+        Self::load(CachedPage::new(page)).expect("failed to load new leaf")
+    }
+
+    /// Creates an empty internal node backed by a page that only lives in memory
+    pub fn new_internal() -> Self {
+        let page = super::page::PageBuilder::default()
+            .kind(&PageType::Internal)
+            .build();
+        Self::load(CachedPage::new(page)).expect("failed to load new internal node")
+    }
+
+    /// Inserts leaf cells until one doesn't fit; returns the number of cells inserted
+    pub fn fill_leaf(&mut self, cells: impl IntoIterator<Item = (u128, Vec<u8>)>) -> Result<u64> {
+        let mut inserted = 0;
+        for (key, content) in cells {
+            match self.insert_cell(LeafCell::new(key, content, false)) {
+                Ok(()) => inserted += 1,
+                Err(NodeResult::IsFull) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(inserted)
+    }
+
+    /// Replaces the cells of an internal node; `cells` are `(separator, child)` pairs in key
+    /// order
+    pub fn set_internal_cells(&mut self, cells: &[(u128, PageNum)], right_child: PageNum) {
+        let cells = cells
+            .iter()
+            .map(|(key, child)| InternalCell::new(*key, *child))
+            .collect::<Vec<_>>();
+        self.write_internal_cells(&cells, right_child);
+    }
+
+    /// Returns the keys and contents of every cell of a leaf
+    pub fn leaf_cells(&self) -> Vec<(u128, Vec<u8>)> {
+        (0..self.num_cells())
+            .map(|i| {
+                let content = self.read_cell_bytes(i).expect("failed to read leaf cell");
+                (self.cell_key(i), content)
+            })
+            .collect()
+    }
+
+    /// Returns the separators and children of an internal node; the right child comes last with
+    /// the separator `None`
+    pub fn internal_cells(&self) -> Vec<(Option<u128>, PageNum)> {
+        (0..self.num_cells())
+            .map(|i| {
+                let bytes = self
+                    .read_cell_bytes(i)
+                    .expect("failed to read internal cell");
+                let cell = InternalCell::from_bytes(bytes);
+                (Some(cell.key()), cell.pointer())
+            })
+            .chain(self.right_child().map(|child| (None, child)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell as StdCell;
+
+    use super::*;
+    use crate::storage::layout::{
+        INTERNAL_CELL_SIZE, INTERNAL_MAX_KEYS, LEAF_KEY_CELL_SIZE, LEAF_MAX_INLINE_CONTENT,
+    };
+
+    thread_local! {
+        pub static FIND_CELL_NUM_CALLS: StdCell<u64> = const { StdCell::new(0) };
+        /// Fails leaf inserts after the key cell was written; before the content is
+        pub static FAIL_AFTER_KEY_CELL: StdCell<bool> = const { StdCell::new(false) };
+    }
+
+    /// Internal node with separators 10, 20 and 30; keys are routed to children 100 through 400
+    fn internal_node() -> Node {
+        let mut node = Node::new_internal();
+        let cells = [(10, 100), (20, 200), (30, 300)].map(|(key, child)| (key, PageNum(child)));
+        node.set_internal_cells(&cells, PageNum(400));
+        node
+    }
+
+    #[test]
+    fn emptied_nodes_have_no_high_key() {
+        let mut leaf = Node::new_leaf();
+        leaf.insert_cell(LeafCell::new(7, b"data".to_vec(), false))
+            .unwrap();
+        assert_eq!(leaf.node_high_key(), Some(7));
+        leaf.remove_cell(7).unwrap();
+        assert_eq!(leaf.node_high_key(), None);
+
+        let mut node = internal_node();
+        for key in [10, 20, 30] {
+            node.remove_cell(key).unwrap();
+        }
+        assert_eq!(node.node_high_key(), None);
+        // Only the right child is left
+        let cell = InternalCell::from_bytes(node.read_cell_bytes(0).unwrap());
+        assert_eq!(cell.pointer(), PageNum(400));
+    }
+
+    #[test]
+    fn internal_nodes_hold_max_keys() {
+        let mut node = Node::new_internal();
+
+        // The first insert only sets the right child; every later one adds a key
+        for i in 0..=INTERNAL_MAX_KEYS as u64 {
+            let cell = InternalCell::new(u128::from(i * 10), PageNum(i + 1));
+            node.insert_cell(cell).unwrap();
+        }
+        assert_eq!(node.num_cells(), INTERNAL_MAX_KEYS as u64);
+        assert_eq!(
+            node.free_space(),
+            INTERNAL_SPACE_FOR_CELLS % INTERNAL_CELL_SIZE
+        );
+
+        let before = node.page.0.read().unwrap()[..].to_vec();
+        let cell = InternalCell::new(INTERNAL_MAX_KEYS as u128 * 10 + 10, PageNum(0));
+        assert_eq!(node.insert_cell(cell), Err(NodeResult::IsFull));
+        assert_eq!(node.page.0.read().unwrap()[..], before[..]);
+
+        // Header and cells are intact
+        check_magic(&before[..]).unwrap();
+        assert_eq!(node.node_type(), PageType::Internal);
+        assert_eq!(
+            node.right_child(),
+            Some(PageNum(INTERNAL_MAX_KEYS as u64 + 1))
+        );
+        for i in 0..INTERNAL_MAX_KEYS as u64 {
+            let cell = InternalCell::from_bytes(node.read_cell_bytes(i).unwrap());
+            assert_eq!(
+                (cell.get_key(), cell.pointer()),
+                (u128::from((i + 1) * 10), PageNum(i + 1))
+            );
+        }
+    }
+
+    #[test]
+    fn internal_find_cell_num_routes_to_children() {
+        let node = internal_node();
+        let child = |key| {
+            let cell_num = node.find_cell_num(key);
+            (
+                cell_num,
+                InternalCell::from_bytes(node.read_cell_bytes(cell_num).unwrap())
+                    .pointer()
+                    .0,
+            )
+        };
+
+        // Below the first separator
+        assert_eq!(child(0), (0, 100));
+        assert_eq!(child(9), (0, 100));
+        // Separators hold the largest key of their child
+        assert_eq!(child(10), (0, 100));
+        assert_eq!(child(20), (1, 200));
+        assert_eq!(child(30), (2, 300));
+        // Between separators
+        assert_eq!(child(11), (1, 200));
+        assert_eq!(child(29), (2, 300));
+        // Above the last separator
+        assert_eq!(child(31), (3, 400));
+        assert_eq!(child(u128::MAX), (3, 400));
+    }
+
+    /// Search of [Node::find_cell_num] before leaves and internal nodes shared their loop
+    fn reference_find_cell_num(node: &Node, key: u128) -> u64 {
+        let (mut min_idx, mut max_idx) = (0, node.num_cells());
+        match node.node_type() {
+            PageType::Leaf => {
+                while min_idx != max_idx {
+                    let index = (min_idx + max_idx) / 2;
+                    match (node.compare)(key, node.cell_key(index)) {
+                        Ordering::Equal => return index,
+                        Ordering::Less => max_idx = index,
+                        Ordering::Greater => min_idx = index + 1,
+                    }
+                }
+            }
+            _ => {
+                while min_idx != max_idx {
+                    let index = (min_idx + max_idx) / 2;
+                    if (node.compare)(node.cell_key(index), key) != Ordering::Less {
+                        max_idx = index
+                    } else {
+                        min_idx = index + 1;
+                    }
+                }
+            }
+        }
+        min_idx
+    }
+
+    #[test]
+    fn find_cell_num_matches_reference_search() {
+        fn descending(a: u128, b: u128) -> Ordering {
+            b.cmp(&a)
+        }
+
+        // Deterministic pseudo random keys; small ranges make neighbouring and equal keys likely
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move |range: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % range
+        };
+
+        for round in 0..200 {
+            let compare: KeyComparator = if round % 2 == 0 {
+                ascending
+            } else {
+                descending
+            };
+            let range = [50, 1_000, u64::MAX][round % 3];
+            // Keys shifted past 64 bits need nodes with wide keys
+            let shift = round % 5 * 16;
+            let key_size = if shift == 0 { KEY_SIZE } else { WIDE_KEY_SIZE };
+            let mut keys = (0..next(120))
+                .map(|_| u128::from(next(range)) << shift)
+                .collect::<Vec<_>>();
+            keys.sort_by(|a, b| compare(*a, *b));
+            keys.dedup();
+
+            let mut leaf = Node::new_leaf()
+                .with_comparator(compare)
+                .with_key_size(key_size);
+            for key in keys.iter().take(100) {
+                leaf.insert_cell(LeafCell::new(*key, vec![0x1], false))
+                    .unwrap();
+            }
+            let mut internal = Node::new_internal()
+                .with_comparator(compare)
+                .with_key_size(key_size);
+            let cells = keys
+                .iter()
+                .map(|key| (*key, PageNum(*key as u64)))
+                .collect::<Vec<_>>();
+            internal.set_internal_cells(&cells, PageNum(0));
+
+            let probes = keys
+                .iter()
+                .flat_map(|key| [key.wrapping_sub(1), *key, key.wrapping_add(1)])
+                .chain((0..50).map(|_| u128::from(next(range))))
+                .chain([0, u128::MAX]);
+            for key in probes {
+                for node in [&leaf, &internal] {
+                    assert_eq!(
+                        node.find_cell_num(key),
+                        reference_find_cell_num(node, key),
+                        "{:?} node, key {key}, keys {keys:?}",
+                        node.node_type()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn small_values_pack_densely() {
+        let mut node = Node::new_leaf();
+        let mut cells = 0;
+        while node
+            .insert_cell(LeafCell::new(cells.into(), b"abc".to_vec(), false))
+            .is_ok()
+        {
+            cells += 1;
+        }
+
+        // A fixed eight byte length prefix only fits this many cells
+        let fixed = (LEAF_SPACE_FOR_DATA / (LEAF_KEY_CELL_SIZE + size_of::<u64>() + 3)) as u64;
+        assert!(cells > fixed, "{cells} cells; {fixed} with fixed lengths");
+        assert!((0..cells).all(|i| node.read_cell_bytes(i).unwrap() == b"abc"));
+        node.check_cell_content(cells - 1).unwrap();
+    }
+
+    type Cells = Vec<(u128, Vec<u8>)>;
+
+    /// Splits a full leaf holding keys 10, 20, 30, ... by inserting `key`; returns the keys and
+    /// values of both halves and the separator
+    fn split_full_leaf(key: u128) -> (Cells, Cells, u128) {
+        let mut left = Node::new_leaf();
+        left.fill_leaf((1..).map(|i| (i * 10, vec![i as u8; 100])))
+            .unwrap();
+        left.set_next_sibling(PageNum(99));
+        let mut right = Node::new_leaf();
+        let separator = left
+            .split(&mut right, LeafCell::new(key, b"new".to_vec(), false))
+            .unwrap();
+        // The new node takes over the old sibling; linking the split node to it is up to the caller
+        assert_eq!(right.next_sibling(), Some(PageNum(99)));
+
+        for node in [&left, &right] {
+            let high_key = node.leaf_cells().last().map(|(key, _)| *key);
+            assert_eq!(node.node_high_key(), high_key);
+        }
+        (left.leaf_cells(), right.leaf_cells(), separator)
+    }
+
+    #[test]
+    fn splits_full_leaves_around_new_keys() {
+        let full = split_full_leaf(u128::MAX);
+        let max_key = full.0.len() as u128 * 10 + full.1.len() as u128 * 10 - 10;
+
+        for key in [5, max_key / 2 + 5, max_key + 10] {
+            let (left, right, separator) = split_full_leaf(key);
+            let cells = left.len() + right.len();
+            assert_eq!(left.len(), cells - cells / 2, "new key {key}");
+            assert_eq!(separator, left.last().unwrap().0);
+            assert!(right.iter().all(|(k, _)| *k > separator));
+
+            // Every key ends up exactly once, in order and with its own value
+            let all = left.iter().chain(&right).collect::<Vec<_>>();
+            assert!(all.windows(2).all(|w| w[0].0 < w[1].0), "new key {key}");
+            let mut expected = (1..cells as u128)
+                .map(|i| (i * 10, vec![i as u8; 100]))
+                .collect::<Vec<_>>();
+            let at = expected.partition_point(|(k, _)| *k < key);
+            expected.insert(at, (key, b"new".to_vec()));
+            assert_eq!(
+                all.into_iter().cloned().collect::<Vec<_>>(),
+                expected,
+                "new key {key}"
+            );
+        }
+
+        // The largest key ends up in the right half as its high key
+        let (_, right, _) = split_full_leaf(max_key + 10);
+        assert_eq!(right.last().unwrap(), &(max_key + 10, b"new".to_vec()));
+    }
+
+    #[test]
+    fn splits_filled_leaf_in_half() {
+        let mut left = Node::new_leaf();
+        let count = left
+            .fill_leaf((0..).map(|i| (i * 2, vec![0x7; 100])))
+            .unwrap();
+        assert_eq!(left.num_cells(), count);
+
+        let mut right = Node::new_leaf();
+        let separator = left
+            .split(&mut right, LeafCell::new(1, vec![0x8; 100], false))
+            .unwrap();
+
+        let (left, right) = (left.leaf_cells(), right.leaf_cells());
+        assert_eq!(left.len() + right.len(), count as usize + 1);
+        assert!(left.len().abs_diff(right.len()) <= 1);
+        assert_eq!(left[..2], [(0, vec![0x7; 100]), (1, vec![0x8; 100])]);
+        assert_eq!(separator, left.last().unwrap().0);
+        assert_eq!(right[0].0, separator + 2);
+    }
+
+    #[test]
+    fn splits_full_internal_node() {
+        let mut left = Node::new_internal();
+        let cells = (1..=INTERNAL_MAX_KEYS as u64)
+            .map(|i| (u128::from(i * 10), PageNum(i)))
+            .collect::<Vec<_>>();
+        left.set_internal_cells(&cells, PageNum(0));
+
+        let mut right = Node::new_internal();
+        let separator = left
+            .split(&mut right, InternalCell::new(15, PageNum(1000)))
+            .unwrap();
+
+        // The promoted separator moves to the parent; its child becomes the right child of the
+        // split node
+        let (left, right) = (left.internal_cells(), right.internal_cells());
+        assert_eq!(
+            left[..3],
+            [
+                (Some(10), PageNum(1)),
+                (Some(15), PageNum(1000)),
+                (Some(20), PageNum(2))
+            ]
+        );
+        assert_eq!(left.last().unwrap().0, None);
+        assert!(right
+            .iter()
+            .flat_map(|(key, _)| key)
+            .all(|key| *key > separator));
+        assert_eq!(right.last().unwrap(), &(None, PageNum(0)));
+        // Every child is still referenced exactly once
+        assert_eq!(left.len() + right.len(), INTERNAL_MAX_KEYS + 2);
+    }
+
+    #[test]
+    fn removes_leaf_cells() {
+        let filled = || {
+            let mut node = Node::new_leaf();
+            for key in 1..=5 {
+                let content = format!("value {key}").repeat(key as usize);
+                node.insert_cell(LeafCell::new(key, content.into_bytes(), false))
+                    .unwrap();
+            }
+            node
+        };
+        let free_space = filled().free_space();
+
+        // First, middle and last cell
+        for removed in [1, 3, 5] {
+            let mut node = filled();
+            node.remove_cell(removed).unwrap();
+
+            assert_eq!(node.num_cells(), 4);
+            let content_len = format!("value {removed}").len() * removed as usize;
+            assert_eq!(
+                node.free_space(),
+                free_space + LEAF_KEY_CELL_SIZE + 1 + content_len
+            );
+            let remaining = (1..=5).filter(|key| *key != removed).collect::<Vec<u128>>();
+            for (i, key) in remaining.iter().enumerate() {
+                assert_eq!(node.cell_key(i as u64), *key);
+                assert_eq!(
+                    node.read_cell_bytes(i as u64).unwrap(),
+                    format!("value {key}").repeat(*key as usize).into_bytes()
+                );
+                node.check_cell_content(i as u64).unwrap();
+            }
+            assert_eq!(node.remove_cell(removed), Err(NodeResult::KeyDoesNotExist));
+        }
+    }
+
+    #[test]
+    fn corrupt_content_lengths_are_errors() {
+        let mut node = Node::new_leaf();
+        for key in 0..5 {
+            node.insert_cell(LeafCell::new(key, b"abc".to_vec(), false))
+                .unwrap();
+        }
+        let pointer = node.get_cell_key_pointer(node.calculate_cell_position(4), true) as usize;
+
+        // Longer than the page and not a varint at all
+        for length in [
+            varint::encode(PAGE_SIZE as u64 * 4),
+            vec![0xFF; varint::MAX_SIZE],
+        ] {
+            node.write_all_bytes(length, pointer);
+            assert!(matches!(
+                node.read_cell_bytes(4),
+                Err(NodeResult::InvalidPage { .. })
+            ));
+            assert!(node.check_cell_content(4).is_err());
+        }
+        assert_eq!(node.read_cell_bytes(0).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn removes_internal_cells() {
+        let children = |node: &Node| {
+            (0..=node.num_cells())
+                .map(|i| {
+                    let cell = InternalCell::from_bytes(node.read_cell_bytes(i).unwrap());
+                    (cell.get_key(), cell.pointer().0)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut node = internal_node();
+        node.remove_cell(20).unwrap();
+        assert_eq!(node.num_cells(), 2);
+        assert_eq!(
+            node.free_space(),
+            INTERNAL_SPACE_FOR_CELLS - 2 * INTERNAL_CELL_SIZE
+        );
+        // Keys of the removed child are routed to the next one
+        assert_eq!(node.find_cell_num(15), 1);
+        assert_eq!(children(&node)[..2], [(10, 100), (30, 300)]);
+        assert_eq!(node.right_child(), Some(PageNum(400)));
+
+        node.remove_cell(30).unwrap();
+        node.remove_cell(10).unwrap();
+        assert_eq!(node.num_cells(), 0);
+        assert_eq!(node.right_child(), Some(PageNum(400)));
+        assert_eq!(node.remove_cell(40), Err(NodeResult::KeyDoesNotExist));
+    }
+
+    #[test]
+    fn reports_free_space() {
+        let mut node = Node::new_leaf();
+        assert_eq!(node.free_space(), LEAF_SPACE_FOR_DATA);
+        assert_eq!(node.fill_factor(), 0.0);
+
+        node.insert_cell(LeafCell::new(1, vec![0x42; 100], false))
+            .unwrap();
+        assert!(node.free_space() < LEAF_SPACE_FOR_DATA - 100);
+        assert!(node.fill_factor() > 0.0 && node.fill_factor() < 1.0);
+
+        let node = internal_node();
+        assert_eq!(
+            node.free_space(),
+            INTERNAL_SPACE_FOR_CELLS - 3 * INTERNAL_CELL_SIZE
+        );
+    }
+
+    #[test]
+    fn failed_insert_leaves_node_unchanged() {
+        let mut node = Node::new_leaf();
+        for key in [0, 2, 4] {
+            node.insert_cell(LeafCell::new(key, vec![0x42; 1000], false))
+                .unwrap();
+        }
+        let num_cells = node.num_cells();
+        let free_space = node.free_space();
+        let before = node.read_variable_data(0, PAGE_SIZE, false);
+
+        assert!(matches!(
+            node.insert_cell(LeafCell::new(10, vec![0x42; 1000], false)),
+            Err(NodeResult::HasOverflow(_))
+        ));
+        assert_eq!(node.num_cells(), num_cells);
+        assert_eq!(node.free_space(), free_space);
+        assert_eq!(node.read_variable_data(0, PAGE_SIZE, false), before);
+        assert!(node.buffer.is_none());
+
+        // Fails once the later key cells have been moved and the new one was written
+        FAIL_AFTER_KEY_CELL.with(|fail| fail.set(true));
+        let res = node.insert_cell(LeafCell::new(1, vec![0x42; 10], false));
+        FAIL_AFTER_KEY_CELL.with(|fail| fail.set(false));
+        assert!(matches!(res, Err(NodeResult::HasOverflow(_))));
+        assert_eq!(node.page.0.read().unwrap()[..], before[..]);
+        assert!(node.buffer.is_none());
+
+        // The node is still usable afterwards
+        node.insert_cell(LeafCell::new(10, vec![0x42; 100], false))
+            .unwrap();
+        assert_eq!(node.num_cells(), num_cells + 1);
+    }
+
+    #[test]
+    fn insert_searches_for_cell_once() {
+        let mut node = Node::new_leaf();
+        for key in [5, 1, 9, 3] {
+            node.insert_cell(LeafCell::new(key, b"data".to_vec(), false))
+                .unwrap();
+        }
+
+        FIND_CELL_NUM_CALLS.with(|calls| calls.set(0));
+        node.insert_cell(LeafCell::new(4, b"data".to_vec(), false))
+            .unwrap();
+        assert_eq!(FIND_CELL_NUM_CALLS.with(|calls| calls.get()), 1);
+
+        FIND_CELL_NUM_CALLS.with(|calls| calls.set(0));
+        assert!(matches!(
+            node.insert_cell(LeafCell::new(4, b"data".to_vec(), false)),
+            Err(NodeResult::DuplicateKey)
+        ));
+        assert_eq!(FIND_CELL_NUM_CALLS.with(|calls| calls.get()), 1);
+
+        let keys = (0..node.num_cells())
+            .map(|i| node.cell_key(i))
+            .collect::<Vec<u128>>();
+        assert_eq!(keys, vec![1, 3, 4, 5, 9]);
+    }
+
+    #[test]
+    fn leaf_rejects_content_larger_than_page() {
+        let mut node = Node::new_leaf();
+        let content = vec![0x42; LEAF_MAX_INLINE_CONTENT + 1];
+        assert_eq!(
+            node.insert_cell(LeafCell::new(1, content, false)),
+            Err(NodeResult::ValueTooLarge {
+                size: LEAF_MAX_INLINE_CONTENT + 1,
+                max: LEAF_MAX_INLINE_CONTENT
+            })
+        );
+
+        // Content that would fit an empty leaf overflows a partially filled one
+        node.insert_cell(LeafCell::new(1, vec![0x42; 100], false))
+            .unwrap();
+        let content = vec![0x42; LEAF_MAX_INLINE_CONTENT];
+        assert!(matches!(
+            node.insert_cell(LeafCell::new(2, content, false)),
+            Err(NodeResult::HasOverflow(_))
+        ));
+        assert_eq!(node.num_cells(), 1);
+    }
+}
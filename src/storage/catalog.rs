@@ -0,0 +1,265 @@
+use std::sync::Arc;
+
+use crate::calculate_offsets;
+
+use super::{
+    layout::{
+        CATALOG_ENTRY_SIZE, CATALOG_FORMAT_VERSION_OFFSET, CATALOG_FORMAT_VERSION_SIZE,
+        CATALOG_HEADER_SIZE, CATALOG_MAX_TABLES, CATALOG_NAME_OFFSET, CATALOG_NAME_SIZE,
+        CATALOG_NUM_PAGES_OFFSET, CATALOG_NUM_PAGES_SIZE, CATALOG_NUM_TABLES_OFFSET,
+        CATALOG_NUM_TABLES_SIZE, CATALOG_ROOT_OFFSET, CATALOG_ROOT_SIZE, CATALOG_SCHEMAS_OFFSET,
+        CATALOG_SIGNED_KEYS_BIT, CATALOG_WIDE_KEYS_BIT, PAGE_TYPE_OFFSET,
+    },
+    page::{check_magic, CachedPage, PageNum, PageType},
+    schema::{KeyType, ValueType},
+};
+
+/// Bits of the schema byte of a table that hold its key type
+const KEY_TYPE_BITS: u8 = CATALOG_SIGNED_KEYS_BIT | CATALOG_WIDE_KEYS_BIT;
+
+/// Mapping of table names to the root page of their B+-Tree.
+///
+/// The catalog is stored in the first page of a database file. Every table in the file has an
+/// entry; tables are never renamed and their root page never moves.
+pub struct Catalog {
+    page: CachedPage,
+}
+
+impl Catalog {
+    /// Wraps an existing catalog page.
+    ///
+    /// Fails if the page is not a catalog page or stores more tables than fit into it. Catalogs
+    /// written before format version 3 could hold more tables; they can't be upgraded.
+    pub fn load(page: CachedPage) -> Result<Self, String> {
+        let handle = page.0.read().expect("failed to retrieve read lock on page");
+        check_magic(&handle[..])?;
+        let kind = handle[PAGE_TYPE_OFFSET];
+        drop(handle);
+
+        match PageType::try_from(kind)? {
+            PageType::Catalog => (),
+            kind => return Err(format!("expected a catalog page, found {:?}", kind)),
+        }
+
+        let catalog = Self { page };
+        match catalog.num_tables() {
+            num_tables if num_tables > CATALOG_MAX_TABLES => Err(format!(
+                "catalog holds {num_tables} tables; at most {CATALOG_MAX_TABLES} fit"
+            )),
+            _ => Ok(catalog),
+        }
+    }
+
+    /// Returns the root page of table `name`
+    pub fn find(&self, name: &str) -> Option<PageNum> {
+        self.tables()
+            .into_iter()
+            .find(|(table, _)| table == name)
+            .map(|(_, root)| root)
+    }
+
+    /// Returns the type of the values stored in table `name`; `None` if the table has no schema
+    pub fn schema(&self, name: &str) -> Option<ValueType> {
+        let index = self.index_of(name)?;
+        let page = Arc::clone(&self.page.0);
+        let handle = page.read().expect("failed to retrieve read lock on page");
+        ValueType::try_from(handle[CATALOG_SCHEMAS_OFFSET + index] & !KEY_TYPE_BITS).ok()
+    }
+
+    /// Returns the type of the keys of table `name`; unsigned unless set otherwise.
+    ///
+    /// Fails if the schema byte of the table has more than one key type bit set.
+    pub fn key_type(&self, name: &str) -> Result<KeyType, String> {
+        let Some(index) = self.index_of(name) else {
+            return Ok(KeyType::default());
+        };
+
+        let page = Arc::clone(&self.page.0);
+        let handle = page.read().expect("failed to retrieve read lock on page");
+        match handle[CATALOG_SCHEMAS_OFFSET + index] & KEY_TYPE_BITS {
+            0 => Ok(KeyType::Unsigned),
+            CATALOG_SIGNED_KEYS_BIT => Ok(KeyType::Signed),
+            CATALOG_WIDE_KEYS_BIT => Ok(KeyType::U128),
+            bits => Err(format!(
+                "table `{name}` has unknown key type bits {bits:#x}"
+            )),
+        }
+    }
+
+    /// Stores the type of the keys of table `name`
+    pub fn set_key_type(&mut self, name: &str, key_type: KeyType) -> Result<(), String> {
+        let index = self
+            .index_of(name)
+            .ok_or_else(|| format!("table `{name}` does not exist"))?;
+
+        let page = Arc::clone(&self.page.0);
+        let mut handle = page.write().expect("failed to retrieve write lock on page");
+        let bits = match key_type {
+            KeyType::Unsigned => 0,
+            KeyType::Signed => CATALOG_SIGNED_KEYS_BIT,
+            KeyType::U128 => CATALOG_WIDE_KEYS_BIT,
+        };
+        handle[CATALOG_SCHEMAS_OFFSET + index] =
+            handle[CATALOG_SCHEMAS_OFFSET + index] & !KEY_TYPE_BITS | bits;
+        Ok(())
+    }
+
+    /// Stores the type of the values of table `name`
+    pub fn set_schema(&mut self, name: &str, schema: ValueType) -> Result<(), String> {
+        let index = self
+            .index_of(name)
+            .ok_or_else(|| format!("table `{name}` does not exist"))?;
+
+        let page = Arc::clone(&self.page.0);
+        let mut handle = page.write().expect("failed to retrieve write lock on page");
+        let key_type = handle[CATALOG_SCHEMAS_OFFSET + index] & KEY_TYPE_BITS;
+        handle[CATALOG_SCHEMAS_OFFSET + index] = u8::from(schema) | key_type;
+        Ok(())
+    }
+
+    /// Returns all tables stored in the catalog along with their root page
+    pub fn tables(&self) -> Vec<(String, PageNum)> {
+        let page = Arc::clone(&self.page.0);
+        let handle = page.read().expect("failed to retrieve read lock on page");
+
+        (0..self.num_tables())
+            .map(|i| {
+                let entry = CATALOG_HEADER_SIZE + i * CATALOG_ENTRY_SIZE;
+
+                let (start, end) = calculate_offsets!(CATALOG_NAME_OFFSET, CATALOG_NAME_SIZE);
+                let name = handle[entry + start..entry + end]
+                    .iter()
+                    .take_while(|b| **b != 0)
+                    .copied()
+                    .collect::<Vec<u8>>();
+
+                let (start, end) = calculate_offsets!(CATALOG_ROOT_OFFSET, CATALOG_ROOT_SIZE);
+                let root = PageNum::from_be_bytes(
+                    handle[entry + start..entry + end]
+                        .try_into()
+                        .expect("failed to read catalog root"),
+                );
+
+                (String::from_utf8_lossy(&name).to_string(), root)
+            })
+            .collect()
+    }
+
+    /// Adds a new table entry to the catalog
+    pub fn add(&mut self, name: &str, root: PageNum) -> Result<(), String> {
+        validate_name(name)?;
+        if self.find(name).is_some() {
+            return Err(format!("table `{name}` already exists"));
+        }
+
+        let num_tables = self.num_tables();
+        if num_tables >= CATALOG_MAX_TABLES {
+            return Err("catalog is full".to_string());
+        }
+
+        let page = Arc::clone(&self.page.0);
+        let mut handle = page.write().expect("failed to retrieve write lock on page");
+        let entry = CATALOG_HEADER_SIZE + num_tables * CATALOG_ENTRY_SIZE;
+
+        let mut name_bytes = [0x0; CATALOG_NAME_SIZE];
+        name_bytes[..name.len()].clone_from_slice(name.as_bytes());
+        let (start, end) = calculate_offsets!(CATALOG_NAME_OFFSET, CATALOG_NAME_SIZE);
+        handle[entry + start..entry + end].clone_from_slice(&name_bytes);
+
+        let (start, end) = calculate_offsets!(CATALOG_ROOT_OFFSET, CATALOG_ROOT_SIZE);
+        handle[entry + start..entry + end].clone_from_slice(&root.to_be_bytes());
+
+        let (start, end) = calculate_offsets!(CATALOG_NUM_TABLES_OFFSET, CATALOG_NUM_TABLES_SIZE);
+        handle[start..end].clone_from_slice(&(num_tables as u64 + 1).to_be_bytes());
+
+        Ok(())
+    }
+
+    /// Returns the on-disk format version the file was created with
+    pub fn format_version(&self) -> u64 {
+        let page = Arc::clone(&self.page.0);
+        let handle = page.read().expect("failed to retrieve read lock on page");
+
+        let (start, end) =
+            calculate_offsets!(CATALOG_FORMAT_VERSION_OFFSET, CATALOG_FORMAT_VERSION_SIZE);
+        u64::from_be_bytes(
+            handle[start..end]
+                .try_into()
+                .expect("failed to read format version"),
+        )
+    }
+
+    /// Stores the on-disk format version of the file; only changed once the file was upgraded
+    pub fn set_format_version(&mut self, version: u64) {
+        let page = Arc::clone(&self.page.0);
+        let mut handle = page.write().expect("failed to retrieve write lock on page");
+
+        let (start, end) =
+            calculate_offsets!(CATALOG_FORMAT_VERSION_OFFSET, CATALOG_FORMAT_VERSION_SIZE);
+        handle[start..end].clone_from_slice(&version.to_be_bytes());
+    }
+
+    /// Returns the number of pages allocated in the file when it was last flushed; `None` if it
+    /// was never stored
+    pub fn num_pages(&self) -> Option<u64> {
+        let page = Arc::clone(&self.page.0);
+        let handle = page.read().expect("failed to retrieve read lock on page");
+
+        let (start, end) = calculate_offsets!(CATALOG_NUM_PAGES_OFFSET, CATALOG_NUM_PAGES_SIZE);
+        match u64::from_be_bytes(
+            handle[start..end]
+                .try_into()
+                .expect("failed to read page count"),
+        ) {
+            0 => None,
+            num_pages => Some(num_pages),
+        }
+    }
+
+    /// Stores the number of pages allocated in the file
+    pub fn set_num_pages(&mut self, num_pages: u64) {
+        let page = Arc::clone(&self.page.0);
+        let mut handle = page.write().expect("failed to retrieve write lock on page");
+
+        let (start, end) = calculate_offsets!(CATALOG_NUM_PAGES_OFFSET, CATALOG_NUM_PAGES_SIZE);
+        handle[start..end].clone_from_slice(&num_pages.to_be_bytes());
+    }
+
+    fn num_tables(&self) -> usize {
+        let page = Arc::clone(&self.page.0);
+        let handle = page.read().expect("failed to retrieve read lock on page");
+
+        let (start, end) = calculate_offsets!(CATALOG_NUM_TABLES_OFFSET, CATALOG_NUM_TABLES_SIZE);
+        u64::from_be_bytes(
+            handle[start..end]
+                .try_into()
+                .expect("failed to read catalog size"),
+        ) as usize
+    }
+
+    fn index_of(&self, name: &str) -> Option<usize> {
+        self.tables().iter().position(|(table, _)| table == name)
+    }
+}
+
+/// Returns whether `page` is a catalog page; fails if it is not a page at all
+pub fn is_catalog(page: &CachedPage) -> Result<bool, String> {
+    let handle = page.0.read().expect("failed to retrieve read lock on page");
+    check_magic(&handle[..])?;
+    Ok(PageType::try_from(handle[PAGE_TYPE_OFFSET])? == PageType::Catalog)
+}
+
+/// Checks that a table name can be stored in the catalog
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > CATALOG_NAME_SIZE {
+        return Err(format!(
+            "table names must be between 1 and {CATALOG_NAME_SIZE} bytes long"
+        ));
+    }
+
+    if name.contains(|c: char| c.is_whitespace() || c == '\0') {
+        return Err("table names can not contain whitespace".to_string());
+    }
+
+    Ok(())
+}
@@ -1,78 +1,154 @@
+use std::ops::BitOr;
+
 use crate::calculate_offsets;
 
 use super::{
     layout::{
-        INTERNAL_CELL_SIZE, INTERNAL_KEY_POINTER_SIZE, INTERNAL_KEY_SIZE,
-        LEAF_CELL_HAS_OVERFLOW_FLAG_OFFSET, LEAF_CELL_HAS_OVERFLOW_FLAG_SIZE,
-        LEAF_KEY_IDENTIFIER_SIZE, LEAF_KEY_INDENTIFIER_OFFSET,
+        internal_cell_size, INTERNAL_KEY_POINTER_SIZE, LEAF_CELL_COMPRESSED_BIT,
+        LEAF_CELL_DELETED_BIT, LEAF_CELL_HAS_OVERFLOW_FLAG_OFFSET,
+        LEAF_CELL_HAS_OVERFLOW_FLAG_SIZE, LEAF_CELL_OVERFLOW_BIT, LEAF_KEY_INDENTIFIER_OFFSET,
+        WIDE_KEY_SIZE,
     },
-    page::bool_to_u8,
+    page::PageNum,
 };
 
+/// Flags stored in the flag byte of a leaf cell
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CellFlags(u8);
+
+impl CellFlags {
+    /// The content of the cell continues in overflow pages
+    pub const OVERFLOW: Self = Self(LEAF_CELL_OVERFLOW_BIT);
+    /// The content of the cell is compressed
+    pub const COMPRESSED: Self = Self(LEAF_CELL_COMPRESSED_BIT);
+    /// The cell has been removed but its space has not been reclaimed yet
+    pub const DELETED: Self = Self(LEAF_CELL_DELETED_BIT);
+
+    /// Reads the flags from a stored flag byte
+    pub fn from_byte(byte: u8) -> Self {
+        // The overflow bit predates the other flags and is stored inverted
+        Self(byte ^ LEAF_CELL_OVERFLOW_BIT)
+    }
+
+    /// Returns the flag byte to store
+    pub fn to_byte(self) -> u8 {
+        self.0 ^ LEAF_CELL_OVERFLOW_BIT
+    }
+
+    /// Returns whether all flags in `other` are set
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Sets or clears all flags in `other`
+    pub fn set(&mut self, other: Self, value: bool) {
+        match value {
+            true => self.0 |= other.0,
+            false => self.0 &= !other.0,
+        }
+    }
+}
+
+impl BitOr for CellFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LeafCell {
-    overflow: bool,
-    identifier: u64,
+    flags: CellFlags,
+    identifier: u128,
     content: Vec<u8>,
 }
 
 #[derive(Debug, Clone)]
 pub struct InternalCell {
-    key: u64,
-    pointer: [u8; 8],
+    key: u128,
+    pointer: PageNum,
 }
 
+/// Cell of a tree node.
+///
+/// The serialized form of a cell always holds 16-byte keys; nodes store them with the key size of
+/// their tree.
 pub trait Cell {
-    fn get_key(&self) -> u64;
+    fn get_key(&self) -> u128;
 
     fn get_key_bytes(&self) -> Vec<u8>;
 
     fn get_content(&self) -> Vec<u8>;
 
-    fn from_bytes(&mut self, c: Vec<u8>);
+    /// Creates a cell from its serialized form
+    fn from_bytes(c: Vec<u8>) -> Self
+    where
+        Self: Sized;
 }
 
 impl InternalCell {
-    pub fn new(key: u64, pointer: [u8; 8]) -> Self {
+    pub fn new(key: u128, pointer: PageNum) -> Self {
         Self { key, pointer }
     }
 
-    pub fn key(&self) -> u64 {
+    pub fn key(&self) -> u128 {
         self.key
     }
 
-    pub fn pointer(&self) -> u64 {
-        u64::from_be_bytes(self.pointer)
+    pub fn pointer(&self) -> PageNum {
+        self.pointer
     }
 }
 
 impl LeafCell {
-    pub fn new(id: u64, content: Vec<u8>, overflow: bool) -> Self {
+    pub fn new(id: u128, content: Vec<u8>, overflow: bool) -> Self {
+        let mut flags = CellFlags::default();
+        flags.set(CellFlags::OVERFLOW, overflow);
+
+        Self::with_flags(id, content, flags)
+    }
+
+    /// Creates a leaf cell with all of its flags
+    pub fn with_flags(id: u128, content: Vec<u8>, flags: CellFlags) -> Self {
         Self {
             identifier: id,
             content,
-            overflow,
+            flags,
         }
     }
 
+    /// Marks the contents of the cell as compressed
+    pub fn compressed(mut self, compressed: bool) -> Self {
+        self.flags.set(CellFlags::COMPRESSED, compressed);
+        self
+    }
+
+    /// Returns the flags stored with the cell
+    pub fn flags(&self) -> CellFlags {
+        self.flags
+    }
+
     /// Returns the size of the cells contents; excluding the flags and identifier
+    #[allow(dead_code)]
     pub fn content_size(&self) -> usize {
         self.content.len()
     }
 
     /// Returns whether the cell has an overflow
+    #[allow(dead_code)]
     pub fn has_overflow(&self) -> bool {
-        self.overflow
+        self.flags.contains(CellFlags::OVERFLOW)
     }
 
     /// Returns the indentifier of a leaf cell
-    pub fn identifier(&self) -> u64 {
+    pub fn identifier(&self) -> u128 {
         self.identifier
     }
 }
 
 impl Cell for InternalCell {
-    fn get_key(&self) -> u64 {
+    fn get_key(&self) -> u128 {
         self.key()
     }
 
@@ -81,43 +157,46 @@ impl Cell for InternalCell {
     }
 
     fn get_content(&self) -> Vec<u8> {
-        let mut out = [0x00; INTERNAL_CELL_SIZE];
+        let mut out = [0x00; internal_cell_size(WIDE_KEY_SIZE)];
 
-        out[0..INTERNAL_KEY_SIZE].clone_from_slice(self.key.to_be_bytes().as_ref());
-        out[INTERNAL_KEY_SIZE..INTERNAL_KEY_SIZE + INTERNAL_KEY_POINTER_SIZE]
-            .clone_from_slice(self.pointer.as_ref());
+        out[0..WIDE_KEY_SIZE].clone_from_slice(self.key.to_be_bytes().as_ref());
+        out[WIDE_KEY_SIZE..WIDE_KEY_SIZE + INTERNAL_KEY_POINTER_SIZE]
+            .clone_from_slice(&self.pointer.to_be_bytes());
 
         out.to_vec()
     }
 
-    fn from_bytes(&mut self, c: Vec<u8>) {
-        self.key = u64::from_be_bytes(
-            c[0..INTERNAL_KEY_SIZE]
-                .try_into()
-                .expect("failed to read internal cell key data"),
-        );
-        self.pointer = c[INTERNAL_KEY_SIZE..INTERNAL_KEY_SIZE + INTERNAL_KEY_POINTER_SIZE]
-            .try_into()
-            .expect("failed to read internal cell key pointer data");
+    fn from_bytes(c: Vec<u8>) -> Self {
+        Self {
+            key: u128::from_be_bytes(
+                c[0..WIDE_KEY_SIZE]
+                    .try_into()
+                    .expect("failed to read internal cell key data"),
+            ),
+            pointer: PageNum::from_be_bytes(
+                c[WIDE_KEY_SIZE..WIDE_KEY_SIZE + INTERNAL_KEY_POINTER_SIZE]
+                    .try_into()
+                    .expect("failed to read internal cell key pointer data"),
+            ),
+        }
     }
 }
 
 impl Cell for LeafCell {
-    fn get_key(&self) -> u64 {
+    fn get_key(&self) -> u128 {
         self.identifier()
     }
 
     fn get_key_bytes(&self) -> Vec<u8> {
-        let mut out = [0x00; LEAF_CELL_HAS_OVERFLOW_FLAG_SIZE + LEAF_KEY_IDENTIFIER_SIZE];
+        let mut out = [0x00; LEAF_CELL_HAS_OVERFLOW_FLAG_SIZE + WIDE_KEY_SIZE];
 
         let (start, end) = calculate_offsets!(
             LEAF_CELL_HAS_OVERFLOW_FLAG_OFFSET,
             LEAF_CELL_HAS_OVERFLOW_FLAG_SIZE
         );
-        out[start..end].clone_from_slice(&[bool_to_u8(self.overflow)]);
+        out[start..end].clone_from_slice(&[self.flags.to_byte()]);
 
-        let (start, end) =
-            calculate_offsets!(LEAF_KEY_INDENTIFIER_OFFSET, LEAF_KEY_IDENTIFIER_SIZE);
+        let (start, end) = calculate_offsets!(LEAF_KEY_INDENTIFIER_OFFSET, WIDE_KEY_SIZE);
         out[start..end].clone_from_slice(self.identifier.to_be_bytes().as_ref());
 
         out.to_vec()
@@ -127,28 +206,56 @@ impl Cell for LeafCell {
         self.content.clone()
     }
 
-    fn from_bytes(&mut self, c: Vec<u8>) {
-        self.overflow = false;
-        self.identifier = u64::from_be_bytes(c[0..LEAF_KEY_IDENTIFIER_SIZE].try_into().unwrap());
-        self.content = c[LEAF_KEY_IDENTIFIER_SIZE..].to_vec();
+    fn from_bytes(c: Vec<u8>) -> Self {
+        Self::new(
+            u128::from_be_bytes(c[0..WIDE_KEY_SIZE].try_into().unwrap()),
+            c[WIDE_KEY_SIZE..].to_vec(),
+            false,
+        )
     }
 }
 
-impl Default for InternalCell {
-    fn default() -> Self {
-        Self {
-            key: u64::MAX,
-            pointer: u64::MAX.to_be_bytes(),
-        }
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn max_key_round_trips() {
+        let cell = InternalCell::new(u128::MAX, PageNum(7));
+        let cell = InternalCell::from_bytes(cell.get_content());
+        assert_eq!(cell.key(), u128::MAX);
+        assert_eq!(cell.pointer(), PageNum(7));
+
+        let mut bytes = u128::MAX.to_be_bytes().to_vec();
+        bytes.extend_from_slice(b"data");
+        let cell = LeafCell::from_bytes(bytes);
+        assert_eq!(cell.identifier(), u128::MAX);
+        assert_eq!(cell.get_content(), b"data".to_vec());
     }
-}
 
-impl Default for LeafCell {
-    fn default() -> Self {
-        Self {
-            overflow: false,
-            identifier: u64::MAX,
-            content: Vec::with_capacity(0),
-        }
+    #[test]
+    fn flags_round_trip() {
+        let flags = CellFlags::OVERFLOW | CellFlags::DELETED;
+        let cell = LeafCell::with_flags(1, Vec::new(), flags).compressed(true);
+
+        let byte = cell.get_key_bytes()[LEAF_CELL_HAS_OVERFLOW_FLAG_OFFSET];
+        let read = CellFlags::from_byte(byte);
+        assert_eq!(read, flags | CellFlags::COMPRESSED);
+        assert!(read.contains(CellFlags::OVERFLOW));
+        assert!(read.contains(CellFlags::COMPRESSED));
+        assert!(read.contains(CellFlags::DELETED));
+
+        let mut flags = read;
+        flags.set(CellFlags::DELETED, false);
+        assert!(!flags.contains(CellFlags::DELETED));
+        assert!(flags.contains(CellFlags::OVERFLOW | CellFlags::COMPRESSED));
+    }
+
+    #[test]
+    fn overflow_bit_is_stored_inverted() {
+        // Cells written before the flag byte held more than the overflow bit must read the same
+        assert_eq!(CellFlags::from_byte(0x1), CellFlags::default());
+        assert_eq!(CellFlags::from_byte(0x0), CellFlags::OVERFLOW);
+        assert_eq!(LeafCell::new(1, Vec::new(), false).flags().to_byte(), 0x1);
     }
 }
@@ -0,0 +1,37 @@
+/// Compresses `content`; returns `None` if compression is unavailable or doesn't make the
+/// content smaller.
+#[cfg(feature = "compression")]
+pub fn compress(content: &[u8]) -> Option<Vec<u8>> {
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content).ok()?;
+    let compressed = encoder.finish().ok()?;
+
+    (compressed.len() < content.len()).then_some(compressed)
+}
+
+#[cfg(not(feature = "compression"))]
+pub fn compress(_content: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+/// Restores content compressed by [compress](compress)
+#[cfg(feature = "compression")]
+pub fn decompress(content: &[u8]) -> Result<Vec<u8>, String> {
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    let mut out = Vec::new();
+    ZlibDecoder::new(content)
+        .read_to_end(&mut out)
+        .map_err(|e| format!("failed to decompress content; {e}"))?;
+
+    Ok(out)
+}
+
+#[cfg(not(feature = "compression"))]
+pub fn decompress(_content: &[u8]) -> Result<Vec<u8>, String> {
+    Err("content is compressed but compression support is not enabled".to_string())
+}
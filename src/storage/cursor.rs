@@ -1,13 +1,29 @@
-use log::debug;
+use std::cmp::Ordering;
+
+use tracing::{debug, instrument, warn};
+
+use crate::calculate_offsets;
 
 use super::{
     btree::{Node, NodeResult},
-    cell::{Cell, InternalCell, LeafCell},
-    layout::LEAF_KEY_POINTER_SIZE,
-    page::PageType,
+    cell::{Cell, CellFlags, InternalCell, LeafCell},
+    compression,
+    layout::{
+        leaf_key_cell_size, CATALOG_PAGE_NUM, LEAF_KEY_POINTER_SIZE, LEAF_OVERFLOW_LEN_OFFSET,
+        LEAF_OVERFLOW_LEN_SIZE, LEAF_OVERFLOW_PAGE_OFFSET, LEAF_OVERFLOW_PAGE_SIZE,
+        LEAF_SPACE_FOR_DATA,
+    },
+    oplog::Operation,
+    overflow,
+    page::{CachedPage, PageNum, PageType},
+    pager::NewPage,
+    schema::{KeyType, ValueType},
     table::Table,
 };
 
+/// Most ranges returned by [histogram](Cursor::histogram)
+pub const MAX_HISTOGRAM_BUCKETS: u64 = 1024;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum CursorState {
     AtEnd,
@@ -15,22 +31,106 @@ pub enum CursorState {
     InProgress,
 }
 
+/// Space usage of the leaves of a table
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LeafUsage {
+    /// Number of leaves
+    pub leaves: u64,
+    /// Unused bytes summed over all leaves
+    pub free_bytes: u64,
+    /// Average fraction of the leaf space that is in use
+    pub fill_factor: f64,
+}
+
+/// Keys in a range of identifiers; returned by [histogram](Cursor::histogram)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyBucket {
+    /// Smallest identifier of the range
+    pub lo: u128,
+    /// Largest identifier of the range
+    pub hi: u128,
+    /// Number of records stored under an identifier in the range
+    pub keys: u64,
+}
+
+/// Pages an operation would visit; returned by the `explain_*` methods of [Cursor](Cursor)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Plan {
+    /// Page number and type of every visited page, in the order they are visited
+    pub pages: Vec<(PageNum, PageType)>,
+    /// Whether the leaf the operation ends at would be split
+    pub split: bool,
+}
+
+/// Iterator over the records of a table in ascending identifier order; created by
+/// [iter](Cursor::iter)
+pub struct Records<'c, 'a> {
+    cursor: &'c mut Cursor<'a>,
+}
+
+impl Iterator for Records<'_, '_> {
+    type Item = Result<(u128, Vec<u8>), NodeResult>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cursor = &mut self.cursor;
+        if cursor._state == CursorState::AtStart {
+            while cursor.node.node_type() != PageType::Leaf {
+                if let Err(e) = cursor.load_child(0) {
+                    cursor._state = CursorState::AtEnd;
+                    return Some(Err(e));
+                }
+            }
+            cursor._state = CursorState::InProgress;
+        }
+
+        while cursor._state != CursorState::AtEnd {
+            let cell_num = cursor.cell_num;
+            let record = (cell_num < cursor.node.num_cells()
+                && !cursor.node.cell_is_deleted(cell_num))
+            .then(|| {
+                cursor
+                    .read_value(cell_num)
+                    .map(|value| (cursor.node.cell_key(cell_num), value))
+            });
+
+            if let Err(e) = cursor.advance() {
+                cursor._state = CursorState::AtEnd;
+                return Some(Err(e));
+            }
+            if record.is_some() {
+                return record;
+            }
+        }
+
+        None
+    }
+}
+
 /// Traversal mechanism for a tree structure.
 ///
 /// This type provides the functionality to retrieve, add and remove data from a Table.
+///
+/// A single cursor can drive any number of operations; every operation starts by moving the
+/// cursor back to the root of the tree, see [reset](Cursor::reset).
 pub struct Cursor<'a> {
     table: &'a mut Table,
     cell_num: u64,
     node: Node,
     _state: CursorState,
     // Stores the parent cell number and page number for easy traversal
-    page_breadcrumb: Vec<(u64, u64)>,
+    page_breadcrumb: Vec<(u64, PageNum)>,
+    // Number of sibling pointers followed since the last reset
+    siblings_followed: u64,
+    // Fill factor at which leaves stop taking keys appended after their last key
+    fill_factor: Option<f64>,
 }
 
 impl<'a> Cursor<'a> {
     /// Create a new cursor object for a Table
     pub fn new(table: &'a mut Table) -> Self {
-        let node = Node::load(table.root_page()).expect("failed to load root node");
+        table.flush_if_due();
+        let root = table.root_page();
+        let node = table.load_node(root).expect("failed to load root node");
 
         let _state = match node.num_cells() {
             0 => CursorState::AtEnd,
@@ -43,36 +143,537 @@ impl<'a> Cursor<'a> {
             cell_num: 0,
             node,
             _state,
+            siblings_followed: 0,
+            fill_factor: None,
         }
     }
 
+    /// Sets the fraction of leaf space used before keys appended after the last key of a leaf
+    /// are moved to a new leaf.
+    ///
+    /// Leaves filled in key order are otherwise packed until they are full and then split in
+    /// half. Inserts in the middle of a leaf are not affected.
+    pub fn with_fill_factor(mut self, fill_factor: Option<f64>) -> Self {
+        self.fill_factor = fill_factor;
+        self
+    }
+
+    /// Moves the cursor back to the root of the tree.
+    ///
+    /// The breadcrumb keeps its allocation, so resetting is only as expensive as looking up the
+    /// root page in the page cache.
+    pub fn reset(&mut self) {
+        let root = self.table.root_page();
+        self.node = self.load_node(root).expect("failed to load root node");
+        self._state = match self.node.num_cells() {
+            0 => CursorState::AtEnd,
+            _ => CursorState::AtStart,
+        };
+        self.cell_num = 0;
+        self.siblings_followed = 0;
+        self.page_breadcrumb.clear();
+        self.page_breadcrumb.push((0, self.table.root));
+    }
+
     /// Inserts a new record into the table
     ///
-    pub fn insert(&mut self, identifier: u64, content: Vec<u8>) -> Result<(), String> {
-        match self.node.node_type() {
-            PageType::Leaf => {
-                let cell = LeafCell::new(identifier, content.clone(), false);
-                let result = self.node.insert_cell(cell);
-                match result {
-                    Ok(_) => Ok(()),
-                    Err(NodeResult::IsFull) => self.split(identifier, content),
-                    Err(e) => Err(e.to_string()),
+    #[instrument(level = "debug", skip(self, identifier, content), fields(key = identifier))]
+    pub fn insert(&mut self, identifier: u128, content: Vec<u8>) -> Result<(), NodeResult> {
+        self.table.check_value(&content)?;
+
+        self.reset();
+        while self.node.node_type() != PageType::Leaf {
+            self.find_node(identifier)?;
+        }
+
+        self.insert_into_leaf(identifier, content)
+    }
+
+    /// Returns the value stored under `identifier`; inserting `default` first if there is none.
+    ///
+    /// Both the lookup and the insert happen after a single descent of the tree.
+    pub fn get_or_insert(
+        &mut self,
+        identifier: u128,
+        default: Vec<u8>,
+    ) -> Result<Vec<u8>, NodeResult> {
+        self.reset();
+        while self.node.node_type() != PageType::Leaf {
+            self.find_node(identifier)?;
+        }
+
+        let cell_num = self.node.find_cell_num(identifier);
+        if self.node.has_key_at(cell_num, identifier) && !self.node.cell_is_deleted(cell_num) {
+            return self.read_value(cell_num);
+        }
+
+        self.table.check_value(&default)?;
+        self.insert_into_leaf(identifier, default.clone())?;
+        Ok(default)
+    }
+
+    /// Inserts a new record under the largest identifier in the table plus one; returns the
+    /// assigned identifier. The first record of an empty table is stored under `1`.
+    pub fn append(&mut self, content: Vec<u8>) -> Result<u128, NodeResult> {
+        let identifier = match self.last()? {
+            Some((key, _)) if key >= self.table.key_type().max_key() => {
+                return Err(NodeResult::NoIdentifiersLeft)
+            }
+            Some((key, _)) => key + 1,
+            None => 1,
+        };
+
+        self.insert(identifier, content)?;
+        Ok(identifier)
+    }
+
+    /// Inserts a new record into the current node; the cursor has to be at the leaf that holds
+    /// `identifier`
+    fn insert_into_leaf(&mut self, identifier: u128, content: Vec<u8>) -> Result<(), NodeResult> {
+        self.table.check_logged_key(identifier)?;
+
+        // A removed record may still be waiting for a vacuum; the new record takes its place
+        let cell_num = self.node.find_cell_num(identifier);
+        if self.node.has_key_at(cell_num, identifier) && self.node.cell_is_deleted(cell_num) {
+            self.node.remove_cell(identifier)?;
+        }
+
+        // Checked before any overflow pages are allocated so a duplicate doesn't leave behind an
+        // orphaned chain
+        if self.node.check_key_exists(identifier) {
+            return Err(NodeResult::DuplicateKey);
+        }
+
+        // Every internal node on the way to the leaf gains the key; including nodes that are
+        // about to be split, whose filters are copied to the new pages
+        let internal = self.page_breadcrumb.len() - 1;
+        let pages = self.page_breadcrumb[..internal]
+            .iter()
+            .map(|(_, page)| *page);
+        self.table.add_to_bloom_filters(pages, identifier);
+
+        let logged = self.table.logs_operations().then(|| content.clone());
+        let (content, compressed) = self.compress_content(content);
+        let chain_start = self.table.page_count();
+        let (content, overflow) = self.spill_content(content)?;
+        let chain = overflow.then(|| (chain_start, self.table.page_count()));
+        let cell = LeafCell::new(identifier, content.clone(), overflow).compressed(compressed);
+        let flags = cell.flags();
+        let result = match self.appends_to_packed_leaf(cell_num) {
+            true => Err(NodeResult::IsFull),
+            false => self.node.insert_cell(cell),
+        };
+        let result = match result {
+            Err(NodeResult::IsFull) | Err(NodeResult::HasOverflow(_)) => {
+                // None of the pages touched by a split may be flushed before the parents link
+                // the new pages
+                let commit_lock = self.table.commit_lock();
+                let _guard = commit_lock.lock().expect("failed to acquire commit lock");
+                self.split(identifier, content, flags)
+            }
+            result => result,
+        };
+        if let Err(e) = result {
+            if let Some(chain) = chain {
+                self.release_chain(chain);
+            }
+            return Err(e);
+        }
+
+        if let Some(content) = logged {
+            self.table
+                .log_operation(&Operation::Insert(identifier, content));
+        }
+        self.table.record_mutation();
+        Ok(())
+    }
+
+    /// Replaces the value of the record stored under `identifier`.
+    ///
+    /// The value is replaced in place if it fits in the leaf; otherwise the leaf is split like it
+    /// would be for an insert. Large values are compressed and spilled to overflow pages the same
+    /// way inserted values are.
+    #[instrument(level = "debug", skip(self, identifier, content), fields(key = identifier))]
+    pub fn update(&mut self, identifier: u128, content: Vec<u8>) -> Result<(), NodeResult> {
+        self.table.check_value(&content)?;
+        self.table.check_logged_key(identifier)?;
+
+        self.reset();
+        while self.node.node_type() != PageType::Leaf {
+            self.find_node(identifier)?;
+        }
+
+        let cell_num = self.node.find_cell_num(identifier);
+        if !self.node.has_key_at(cell_num, identifier) || self.node.cell_is_deleted(cell_num) {
+            return Err(NodeResult::KeyDoesNotExist);
+        }
+
+        let logged = self.table.logs_operations().then(|| content.clone());
+        let (content, compressed) = self.compress_content(content);
+        let chain_start = self.table.page_count();
+        let (content, overflow) = self.spill_content(content)?;
+        let chain = overflow.then(|| (chain_start, self.table.page_count()));
+        let cell = LeafCell::new(identifier, content.clone(), overflow).compressed(compressed);
+        let flags = cell.flags();
+        let result = match self.node.update(identifier, cell) {
+            Err(NodeResult::IsFull) | Err(NodeResult::HasOverflow(_)) => {
+                // The new value doesn't fit next to the other cells of the leaf; the record is
+                // placed by a split instead
+                let commit_lock = self.table.commit_lock();
+                let _guard = commit_lock.lock().expect("failed to acquire commit lock");
+                self.split_update(identifier, content, flags)
+            }
+            result => result.map(|_| ()),
+        };
+        if let Err(e) = result {
+            if let Some(chain) = chain {
+                self.release_chain(chain);
+            }
+            return Err(e);
+        }
+
+        if let Some(content) = logged {
+            self.table
+                .log_operation(&Operation::Delete(identifier, identifier));
+            self.table
+                .log_operation(&Operation::Insert(identifier, content));
+        }
+        self.table.record_mutation();
+        Ok(())
+    }
+
+    /// Replaces record `identifier` of the current leaf by removing it and splitting the leaf
+    /// around the new cell; the leaf is put back as it was if the split fails.
+    fn split_update(
+        &mut self,
+        identifier: u128,
+        content: Vec<u8>,
+        flags: CellFlags,
+    ) -> Result<(), NodeResult> {
+        let (leaf_page, leaf) = (self.current_page(), self.node.contents());
+        let result = self
+            .node
+            .remove_cell(identifier)
+            .and_then(|_| self.split(identifier, content, flags));
+        if result.is_err() {
+            let node = self
+                .table
+                .get_page(leaf_page)
+                .map(|page| self.load_node(page));
+            match node {
+                Some(Ok(mut node)) => node.restore(leaf),
+                _ => warn!("leaf {leaf_page} of a failed update can not be restored"),
+            }
+        }
+        result
+    }
+
+    /// Retrieves the record stored under `identifier`; `None` if there is none or its value can't
+    /// be read.
+    ///
+    /// The cursor descends directly to the leaf that would hold the key instead of scanning the
+    /// leaf chain.
+    pub fn get(&mut self, identifier: u128) -> Option<Vec<u8>> {
+        if !self.descend_filtered(identifier) {
+            return None;
+        }
+
+        let cell_num = self.node.find_cell_num(identifier);
+        if !self.node.has_key_at(cell_num, identifier) || self.node.cell_is_deleted(cell_num) {
+            return None;
+        }
+
+        self.read_value(cell_num)
+            .inspect_err(|e| warn!("failed to read value of key {identifier}; {e}"))
+            .ok()
+    }
+
+    /// Returns the values stored under each of `keys`, in the order of `keys`.
+    ///
+    /// The keys are looked up in ascending order after a single descent; the cursor follows the
+    /// leaf chain from one key to the next instead of descending from the root for every key.
+    pub fn get_many(&mut self, keys: &[u128]) -> Vec<Option<Vec<u8>>> {
+        let compare = self.table.comparator();
+        let mut order = (0..keys.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| compare(keys[*a], keys[*b]));
+
+        let mut values = vec![None; keys.len()];
+        let Some(first) = order.first() else {
+            return values;
+        };
+        self.reset();
+        while self.node.node_type() != PageType::Leaf {
+            if let Err(e) = self.find_node(keys[*first]) {
+                warn!("failed to descend to key {}; {e}", keys[*first]);
+                return values;
+            }
+        }
+
+        for i in order {
+            let key = keys[i];
+            loop {
+                let num_cells = self.node.num_cells();
+                if num_cells > 0
+                    && compare(key, self.node.cell_key(num_cells - 1)) != Ordering::Greater
+                {
+                    break;
+                }
+                let Some(sibling) = self.node.next_sibling() else {
+                    break;
+                };
+                if self.load_sibling(sibling).is_err() {
+                    // The chain is broken; the key can still be reached from the root
+                    self.reset();
+                    while self.node.node_type() != PageType::Leaf {
+                        if let Err(e) = self.find_node(key) {
+                            warn!("failed to descend to key {key}; {e}");
+                            return values;
+                        }
+                    }
+                    break;
                 }
             }
-            PageType::Internal => {
-                self.find_node(identifier);
-                self.insert(identifier, content)
+
+            let cell_num = self.node.find_cell_num(key);
+            if self.node.has_key_at(cell_num, key) && !self.node.cell_is_deleted(cell_num) {
+                values[i] = self
+                    .read_value(cell_num)
+                    .inspect_err(|e| warn!("failed to read value of key {key}; {e}"))
+                    .ok();
+            }
+        }
+
+        values
+    }
+
+    /// Returns the type of the values stored in the linked table
+    pub fn schema(&self) -> Option<ValueType> {
+        self.table.schema()
+    }
+
+    /// Returns the type of the keys of the linked table
+    pub fn key_type(&self) -> KeyType {
+        self.table.key_type()
+    }
+
+    /// Checks whether a record is stored under `identifier` without reading its value
+    pub fn contains_key(&mut self, identifier: u128) -> bool {
+        if !self.descend_filtered(identifier) {
+            return false;
+        }
+
+        let cell_num = self.node.find_cell_num(identifier);
+        self.node.has_key_at(cell_num, identifier) && !self.node.cell_is_deleted(cell_num)
+    }
+
+    /// Removes the record stored under `identifier`.
+    ///
+    /// The record is only marked as deleted; its space is reclaimed by [vacuum](Cursor::vacuum).
+    pub fn remove(&mut self, identifier: u128) -> Result<(), NodeResult> {
+        self.table.check_logged_key(identifier)?;
+
+        self.reset();
+        while self.node.node_type() != PageType::Leaf {
+            self.find_node(identifier)?;
+        }
+
+        let cell_num = self.node.find_cell_num(identifier);
+        if !self.node.has_key_at(cell_num, identifier) || self.node.cell_is_deleted(cell_num) {
+            return Err(NodeResult::KeyDoesNotExist);
+        }
+
+        self.node.set_cell_deleted(cell_num);
+        self.table
+            .log_operation(&Operation::Delete(identifier, identifier));
+        self.table.record_mutation();
+        Ok(())
+    }
+
+    /// Moves the record stored under `old` to `new`; fails if `old` doesn't exist or `new`
+    /// already does
+    pub fn rekey(&mut self, old: u128, new: u128) -> Result<(), String> {
+        let value = self
+            .get(old)
+            .ok_or_else(|| format!("key {old} does not exist"))?;
+        if old == new {
+            return Ok(());
+        }
+        // Everything that can be checked up front is, so the record is rarely removed in vain
+        if new > self.table.key_type().max_key() {
+            return Err(NodeResult::KeyTooLarge.to_string());
+        }
+        if self.contains_key(new) {
+            return Err(format!("key {new} already exists"));
+        }
+
+        self.remove(old).map_err(|e| e.to_string())?;
+        if let Err(e) = self.insert(new, value.clone()) {
+            // The record is put back under its old key; it took the place of its own tombstone
+            self.insert(old, value)
+                .map_err(|restore| format!("{e}; failed to restore key {old}; {restore}"))?;
+            return Err(e.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of records in the table
+    pub fn count(&mut self) -> Result<u64, NodeResult> {
+        let mut count = 0;
+        self.walk_leaves(|node| {
+            count += (0..node.num_cells())
+                .filter(|&i| !node.cell_is_deleted(i))
+                .count() as u64;
+            Ok(())
+        })?;
+
+        Ok(count)
+    }
+
+    /// Returns the identifiers of all records in ascending order.
+    ///
+    /// Only the key cells of the leaves are read; values, and any overflow pages they span, are
+    /// never loaded.
+    pub fn keys(&mut self) -> Result<Vec<u128>, NodeResult> {
+        let mut keys = Vec::new();
+        self.walk_leaves(|node| {
+            keys.extend(
+                (0..node.num_cells())
+                    .filter(|&i| !node.cell_is_deleted(i))
+                    .map(|i| node.cell_key(i)),
+            );
+            Ok(())
+        })?;
+
+        Ok(keys)
+    }
+
+    /// Counts the records in each of `buckets` equal-width ranges between the smallest and the
+    /// largest identifier; empty for an empty table.
+    ///
+    /// Ranges never hold less than a single identifier, so fewer ranges are returned if there are
+    /// fewer distinct identifiers between the bounds than `buckets`. At most
+    /// [MAX_HISTOGRAM_BUCKETS] ranges are returned. Tables ordered by a custom comparator may store
+    /// identifiers outside of the bounds; those are not counted.
+    pub fn histogram(&mut self, buckets: u64) -> Result<Vec<KeyBucket>, NodeResult> {
+        let (Some((first, _)), Some((last, _))) = (self.first()?, self.last()?) else {
+            return Ok(Vec::new());
+        };
+        // Tables ordered by a custom comparator may store their largest identifier first
+        let (lo, hi) = (first.min(last), first.max(last));
+        // There are `span + 1` identifiers between the bounds; more than fit into a u128 if the
+        // table holds both the smallest and the largest one
+        let span = hi - lo;
+        let buckets = (buckets.min(MAX_HISTOGRAM_BUCKETS) as u128).clamp(1, span.saturating_add(1));
+
+        // Bucket `i` starts `i * (span + 1) / buckets` identifiers after `lo`, rounded up. The
+        // width only wraps around for a single bucket, which starts at `lo`
+        let (mut width, mut rest) = (span / buckets, span % buckets + 1);
+        if rest == buckets {
+            (width, rest) = (width.wrapping_add(1), 0);
+        }
+        let start = |i: u128| lo + i.wrapping_mul(width) + (i * rest).div_ceil(buckets);
+        let mut histogram = (0..buckets)
+            .map(|i| KeyBucket {
+                lo: start(i),
+                hi: match i + 1 < buckets {
+                    true => start(i + 1) - 1,
+                    false => hi,
+                },
+                keys: 0,
+            })
+            .collect::<Vec<_>>();
+
+        self.walk_leaves(|node| {
+            for i in (0..node.num_cells()).filter(|&i| !node.cell_is_deleted(i)) {
+                let key = node.cell_key(i);
+                if !(lo..=hi).contains(&key) {
+                    continue;
+                }
+                let bucket = histogram.partition_point(|bucket| bucket.lo <= key) - 1;
+                histogram[bucket].keys += 1;
+            }
+            Ok(())
+        })?;
+
+        Ok(histogram)
+    }
+
+    /// Returns how much of the space in the leaves of the table is used
+    pub fn leaf_usage(&mut self) -> Result<LeafUsage, NodeResult> {
+        let mut usage = LeafUsage::default();
+        let mut fill = 0.0;
+        self.walk_leaves(|node| {
+            usage.leaves += 1;
+            usage.free_bytes += node.free_space() as u64;
+            fill += node.fill_factor();
+            Ok(())
+        })?;
+
+        usage.fill_factor = fill / usage.leaves as f64;
+        Ok(usage)
+    }
+
+    /// Reclaims the space of removed records; returns the number of reclaimed records.
+    ///
+    /// Overflow pages of removed values are not reclaimed.
+    pub fn vacuum(&mut self) -> Result<u64, NodeResult> {
+        let mut reclaimed = 0;
+        self.walk_leaves(|node| {
+            let deleted = (0..node.num_cells())
+                .filter(|&i| node.cell_is_deleted(i))
+                .map(|i| node.cell_key(i))
+                .collect::<Vec<_>>();
+            for key in deleted {
+                node.remove_cell(key)?;
+                reclaimed += 1;
             }
+            Ok(())
+        })?;
+
+        if reclaimed > 0 {
+            self.table.record_mutation();
         }
+        Ok(reclaimed)
     }
 
     /// Selects all records from the linked table.
     ///
-    pub fn select(&mut self) -> Vec<String> {
+    /// Records are returned as `(identifier, value)` pairs in ascending identifier order. Fails if
+    /// the leaf chain is broken or loops back on itself.
+    pub fn select(&mut self) -> Result<Vec<(u128, Vec<u8>)>, NodeResult> {
+        self.select_limit(0, None)
+    }
+
+    /// Selects the records whose value matches `pred`, in ascending identifier order.
+    ///
+    /// Values are not indexed; every record is read while the cursor walks the leaf chain, but
+    /// only matching records are kept.
+    pub fn select_filter(
+        &mut self,
+        pred: impl Fn(&[u8]) -> bool,
+    ) -> Result<Vec<(u128, Vec<u8>)>, NodeResult> {
+        self.reset();
+        self.iter()
+            .filter(|record| record.as_ref().map_or(true, |(_, value)| pred(value)))
+            .collect()
+    }
+
+    /// Selects up to `limit` records after skipping the first `offset` records.
+    ///
+    /// The leaf chain walk stops as soon as enough records have been read; skipped records are
+    /// not read.
+    pub fn select_limit(
+        &mut self,
+        offset: u64,
+        limit: Option<u64>,
+    ) -> Result<Vec<(u128, Vec<u8>)>, NodeResult> {
         let mut data = Vec::new();
+        let mut skipped = 0;
+        self.reset();
         while self.node.node_type() != PageType::Leaf {
             debug!("searching for leaf node");
-            self.find_node(0);
+            self.load_child(0)?;
         }
 
         while self._state != CursorState::AtEnd {
@@ -80,138 +681,1764 @@ impl<'a> Cursor<'a> {
                 self._state = CursorState::InProgress;
             }
 
-            data.push(String::from_utf8(self.node.read_cell_bytes(self.cell_num)).unwrap());
-            self.advance();
+            if limit.is_some_and(|n| data.len() as u64 >= n) {
+                break;
+            }
+
+            // Leaves can be left empty by deletes
+            if self.cell_num < self.node.num_cells() && !self.node.cell_is_deleted(self.cell_num) {
+                if skipped < offset {
+                    skipped += 1;
+                } else {
+                    let key = self.node.cell_key(self.cell_num);
+                    data.push((key, self.read_value(self.cell_num)?));
+                }
+            }
+            self.advance()?;
         }
 
-        data
+        Ok(data)
     }
 
-    fn advance(&mut self) {
-        self.cell_num += 1;
-        if self.node.num_cells() <= self.cell_num {
-            debug!("cursor at the end; sibling {:?}", self.node.next_sibling());
-            if let Some(sibling) = self.node.next_sibling() {
-                self.node = Node::load(
-                    self.table
-                        .get_page(sibling)
-                        .expect("sibling does not exist"),
-                )
-                .expect("failed to load next sibling");
-                self.cell_num = 0;
-            } else {
-                self._state = CursorState::AtEnd;
-            }
+    /// Returns the pages visited while looking up `identifier`; nothing is modified
+    pub fn explain_lookup(&mut self, identifier: u128) -> Result<Plan, NodeResult> {
+        self.reset();
+        while self.node.node_type() != PageType::Leaf {
+            self.find_node(identifier)?;
         }
+
+        Ok(Plan {
+            pages: self.descended_pages(),
+            split: false,
+        })
     }
 
-    fn find_node(&mut self, identifier: u64) {
+    /// Returns the pages visited while inserting `content` under `identifier` and whether the
+    /// insert would split the leaf; nothing is modified
+    pub fn explain_insert(
+        &mut self,
+        identifier: u128,
+        content: Vec<u8>,
+    ) -> Result<Plan, NodeResult> {
+        let mut plan = self.explain_lookup(identifier)?;
         let cell_num = self.node.find_cell_num(identifier);
-        let key_data = self.node.read_cell_bytes(cell_num);
-        let mut cell = InternalCell::default();
-        cell.from_bytes(key_data);
-        debug!("loading found page: {}", cell.pointer());
-        self.page_breadcrumb.push((cell_num, cell.pointer()));
-        self.node = Node::load(self.table.get_page(cell.pointer()).unwrap()).unwrap();
-        debug!("current breadcrumbs: {:?}", self.page_breadcrumb);
+        if self.node.has_key_at(cell_num, identifier) && !self.node.cell_is_deleted(cell_num) {
+            return Err(NodeResult::DuplicateKey);
+        }
+
+        let (content, _) = self.compress_content(content);
+        let inline = match content.len() <= self.table.max_inline_value() {
+            true => content.len(),
+            false => LEAF_OVERFLOW_LEN_SIZE + LEAF_OVERFLOW_PAGE_SIZE,
+        };
+        plan.split = !self.node.has_space_for(inline);
+        Ok(plan)
     }
 
-    fn split(&mut self, identifier: u64, content: Vec<u8>) -> Result<(), String> {
-        debug!("splitting current node: {:?}", self.page_breadcrumb.last());
-        let (new_page, page) = self.table.create_page(&self.node.node_type());
-        let mut new_node =
-            Node::load(page).map_err(|e| format!("failed to split node: {}", e.to_string()))?;
-        let old_max = self.node.node_high_key();
+    /// Returns the pages visited while scanning every record; nothing is modified
+    pub fn explain_scan(&mut self) -> Result<Plan, NodeResult> {
+        self.reset();
+        while self.node.node_type() != PageType::Leaf {
+            self.load_child(0)?;
+        }
 
-        match self.node.node_type() {
-            PageType::Leaf => {
-                let cell = LeafCell::new(identifier, content.clone(), false);
-                self.node
-                    .split(&mut new_node, cell)
-                    .map_err(|e| format!("failed to split leaf node; {}", e))?;
+        let mut pages = self.descended_pages();
+        while let Some(sibling) = self.node.next_sibling() {
+            self.load_sibling(sibling)?;
+            pages.push((sibling, PageType::Leaf));
+        }
+
+        Ok(Plan {
+            pages,
+            split: false,
+        })
+    }
+
+    /// Positions the cursor at the first record with an identifier `>= key`; [iter](Cursor::iter)
+    /// continues from there
+    pub fn seek(&mut self, key: u128) -> Result<(), NodeResult> {
+        self.reset();
+        if self._state == CursorState::AtEnd {
+            return Ok(());
+        }
+
+        while self.node.node_type() != PageType::Leaf {
+            self.find_node(key)?;
+        }
+        self.cell_num = self.node.find_cell_num(key);
+        self._state = CursorState::InProgress;
+        Ok(())
+    }
+
+    /// Iterates over the records after the position of the cursor; starting at the first record
+    /// unless the cursor was positioned with [seek](Cursor::seek)
+    pub fn iter(&mut self) -> Records<'_, 'a> {
+        Records { cursor: self }
+    }
+
+    /// Returns the record with the smallest identifier; `None` if the table is empty
+    pub fn first(&mut self) -> Result<Option<(u128, Vec<u8>)>, NodeResult> {
+        Ok(self.select_limit(0, Some(1))?.pop())
+    }
+
+    /// Returns the record with the largest identifier; `None` if the table is empty.
+    ///
+    /// The cursor follows the right most child of every internal node to the last leaf. Leaves
+    /// left without records by deletes are skipped by moving back up to the closest subtree on
+    /// their left; leaves are only linked to their right sibling.
+    pub fn last(&mut self) -> Result<Option<(u128, Vec<u8>)>, NodeResult> {
+        self.reset();
+        loop {
+            while self.node.node_type() != PageType::Leaf {
+                self.load_child(self.node.num_cells())?;
             }
-            PageType::Internal => {
-                let cell = InternalCell::new(
-                    identifier,
-                    content[..LEAF_KEY_POINTER_SIZE].try_into().unwrap(),
-                );
-                self.node
-                    .split(&mut new_node, cell)
-                    .map_err(|e| format!("failed to split internal node; {}", e))?;
+
+            let last = (0..self.node.num_cells())
+                .rev()
+                .find(|&i| !self.node.cell_is_deleted(i));
+            if let Some(cell_num) = last {
+                let key = self.node.cell_key(cell_num);
+                return Ok(Some((key, self.read_value(cell_num)?)));
             }
-        };
 
-        self.node.set_next_sibling(new_page);
-        if self.node.is_root() {
-            debug!("split node was root; creating new root");
-            let (old_num, _) = self.table.create_new_root();
-            self.node = Node::load(self.table.root_page()).unwrap();
-            debug!(
-                "inserting old root as cell key {} for split page {}",
-                old_max, old_num
-            );
-            self.node
-                .insert_cell(InternalCell::new(1, old_num.to_be_bytes()))
-                .expect("failed to insert key into new internal node");
-            debug!(
-                "inserting new page as cell key {} for split page {}",
-                old_max, new_page
-            );
-            self.node
-                .insert_cell(InternalCell::new(old_max, new_page.to_be_bytes()))
-                .expect("failed to insert right most key in internal node");
-        } else {
-            debug!("split node was child; updating page pointers");
-            let (cell_num, cur_page) = self.page_breadcrumb.pop().expect("current page is unknown");
-            let (_, parent_page) = self
-                .page_breadcrumb
-                .last()
-                .expect("parent page not present");
+            // Children left of the first one belong to the subtree left of the parent
+            loop {
+                let (child, _) = self.page_breadcrumb.pop().expect("current page is unknown");
+                let Some(&(_, parent)) = self.page_breadcrumb.last() else {
+                    return Ok(None);
+                };
+                if child > 0 {
+                    let page = self.table.get_page(parent).ok_or(NodeResult::InvalidPage {
+                        desc: format!("parent page {parent} does not exist"),
+                    })?;
+                    self.node = self.load_node(page)?;
+                    self.load_child(child - 1)?;
+                    break;
+                }
+            }
+        }
+    }
 
-            let max_key = self.node.node_high_key();
-            let new_page_max = new_node.node_high_key();
+    /// Removes all records with identifiers in `lo..=hi`; returns the number of removed records.
+    ///
+    /// The cursor descends to the leaf holding `lo` and follows the sibling chain from there.
+    /// Leaves that end up empty stay in the tree as nodes are not merged yet, and the overflow
+    /// pages of removed values are not reclaimed.
+    pub fn delete_range(&mut self, lo: u128, hi: u128) -> Result<u64, NodeResult> {
+        let mut removed = 0;
+        let compare = self.table.comparator();
+        if compare(lo, hi) == Ordering::Greater {
+            return Ok(removed);
+        }
+        // Upper bounds are clamped to the keys of the log instead
+        self.table.check_logged_key(lo)?;
 
-            self.node = Node::load(
-                self.table
-                    .get_page(*parent_page)
-                    .expect("expected parent page to exist"),
-            )
-            .expect("failed to retrieve parent page");
+        self.reset();
+        while self.node.node_type() != PageType::Leaf {
+            self.find_node(lo)?;
+        }
 
-            let key_data = self.node.read_cell_bytes(cell_num);
-            let mut cell = InternalCell::default();
-            cell.from_bytes(key_data);
+        let mut cell_num = self.node.find_cell_num(lo);
+        'leaves: loop {
+            while cell_num < self.node.num_cells() {
+                let key = self.node.cell_key(cell_num);
+                if compare(key, hi) == Ordering::Greater {
+                    break 'leaves;
+                }
 
-            if cur_page != self.node.right_child().unwrap() {
-                debug!(
-                    "updating old cell key {} to {} for page {}",
-                    cell.get_key(),
-                    max_key,
-                    cell.pointer(),
-                );
-                // TODO: Handle parent node overflow
-                self.node
-                    .update(
-                        cell.get_key(),
-                        InternalCell::new(max_key, cur_page.to_be_bytes()),
-                    )
-                    .map_err(|e| format!("failed to update parent node pointer; {e}"))?;
+                if !self.node.cell_is_deleted(cell_num) {
+                    removed += 1;
+                }
+                self.node.remove_cell(key)?;
             }
-            debug!(
-                "inserting new cell key {} for split page {}",
-                new_page_max, new_page
-            );
 
-            let cell = InternalCell::new(new_page_max, new_page.to_be_bytes());
-            match self.node.insert_cell(cell) {
-                Ok(()) => (),
-                Err(NodeResult::IsFull) => {
-                    return self.split(new_page_max, new_page.to_be_bytes().to_vec());
+            match self.node.next_sibling() {
+                Some(sibling) => {
+                    self.load_sibling(sibling)?;
+                    cell_num = 0;
                 }
-                Err(e) => return Err(format!("failed to split parent node: {}", e)),
+                None => break,
             }
         }
 
-        Ok(())
+        if removed > 0 {
+            self.table.log_operation(&Operation::Delete(lo, hi));
+            self.table.record_mutation();
+        }
+        Ok(removed)
+    }
+
+    /// Compresses values at or above the tables compression threshold.
+    ///
+    /// Returns the content to store and whether it was compressed.
+    fn compress_content(&self, content: Vec<u8>) -> (Vec<u8>, bool) {
+        match self.table.compression_threshold() {
+            Some(threshold) if content.len() >= threshold => {
+                match compression::compress(&content) {
+                    Some(compressed) => {
+                        debug!("compressed {} bytes to {}", content.len(), compressed.len());
+                        (compressed, true)
+                    }
+                    None => (content, false),
+                }
+            }
+            _ => (content, false),
+        }
+    }
+
+    /// Moves values larger than the tables inline threshold into overflow pages.
+    ///
+    /// Returns the content to store inline and whether it overflowed.
+    fn spill_content(&mut self, content: Vec<u8>) -> Result<(Vec<u8>, bool), NodeResult> {
+        if content.len() <= self.table.max_inline_value() {
+            return Ok((content, false));
+        }
+
+        let chain_start = self.table.page_count();
+        let first_page = match overflow::write_chain(self.table, &content) {
+            Ok(first_page) => first_page,
+            Err(e) => {
+                let chain_end = self.table.page_count();
+                if chain_end > chain_start {
+                    self.release_chain((chain_start, chain_end));
+                }
+                return Err(NodeResult::WriteFailed {
+                    desc: e.to_string(),
+                });
+            }
+        };
+        debug!(
+            "spilled {} bytes to overflow page {}",
+            content.len(),
+            first_page
+        );
+
+        let mut inline = (content.len() as u64).to_be_bytes().to_vec();
+        inline.append(&mut first_page.to_be_bytes().to_vec());
+        Ok((inline, true))
+    }
+
+    /// Frees the overflow chain of a write that failed; `chain` is the range of pages it was
+    /// written to.
+    ///
+    /// The chain is only freed while it is still at the end of the file. If a split allocated
+    /// pages after it, the chain stays behind as unused pages.
+    fn release_chain(&mut self, (first, end): (u64, u64)) {
+        if self.table.page_count() != end {
+            warn!("overflow pages {first}..{end} of a failed write can not be freed");
+            return;
+        }
+
+        if let Err(e) = self.table.release_pages(first) {
+            warn!("failed to free overflow pages of a failed write; {e}");
+        }
+    }
+
+    /// Frees page `new_page` of a split that failed before anything pointed at it; like
+    /// [release_chain](Cursor::release_chain) only while it is the last page of the file
+    fn release_new_page(&mut self, new_page: PageNum) {
+        if self.table.page_count() != new_page.0 + 1 {
+            warn!("page {new_page} of a failed split can not be freed");
+            return;
+        }
+
+        if let Err(e) = self.table.release_pages(new_page.0) {
+            warn!("failed to free page of a failed split; {e}");
+        }
+    }
+
+    /// Reads the value of cell `cell_num` in the current node; following overflow pages and
+    /// decompressing if needed
+    fn read_value(&mut self, cell_num: u64) -> Result<Vec<u8>, NodeResult> {
+        let mut bytes = self.node.read_cell_bytes(cell_num)?;
+        if self.node.cell_has_overflow(cell_num) {
+            let (start, end) = calculate_offsets!(LEAF_OVERFLOW_LEN_OFFSET, LEAF_OVERFLOW_LEN_SIZE);
+            let len = u64::from_be_bytes(bytes[start..end].try_into().unwrap()) as usize;
+            let (start, end) =
+                calculate_offsets!(LEAF_OVERFLOW_PAGE_OFFSET, LEAF_OVERFLOW_PAGE_SIZE);
+            let first_page = PageNum::from_be_bytes(bytes[start..end].try_into().unwrap());
+
+            bytes = overflow::read_chain(self.table, first_page, len).map_err(|e| {
+                NodeResult::InvalidPage {
+                    desc: format!("failed to read overflow content; {e}"),
+                }
+            })?;
+        }
+
+        if self.node.cell_is_compressed(cell_num) {
+            bytes = compression::decompress(&bytes).map_err(|e| NodeResult::InvalidPage {
+                desc: format!("failed to read compressed content; {e}"),
+            })?;
+        }
+
+        Ok(bytes)
+    }
+
+    fn advance(&mut self) -> Result<(), NodeResult> {
+        self.cell_num += 1;
+        if self.node.num_cells() <= self.cell_num {
+            debug!("cursor at the end; sibling {:?}", self.node.next_sibling());
+            if let Some(sibling) = self.node.next_sibling() {
+                self.prefetch_siblings(sibling);
+                self.load_sibling(sibling)?;
+                self.cell_num = 0;
+            } else {
+                self._state = CursorState::AtEnd;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Calls `visit` on every leaf from left to right
+    fn walk_leaves(
+        &mut self,
+        mut visit: impl FnMut(&mut Node) -> Result<(), NodeResult>,
+    ) -> Result<(), NodeResult> {
+        self.reset();
+        while self.node.node_type() != PageType::Leaf {
+            self.load_child(0)?;
+        }
+
+        loop {
+            visit(&mut self.node)?;
+            match self.node.next_sibling() {
+                Some(sibling) => self.load_sibling(sibling)?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Moves the cursor to leaf `sibling` of the current leaf
+    fn load_sibling(&mut self, sibling: PageNum) -> Result<(), NodeResult> {
+        // Every page can be visited at most once; anything longer has to be a cycle
+        self.siblings_followed += 1;
+        if self.siblings_followed >= self.table.page_count() {
+            return Err(NodeResult::InvalidPage {
+                desc: format!("sibling chain loops back at leaf {sibling}"),
+            });
+        }
+
+        let page = self
+            .table
+            .get_page(sibling)
+            .ok_or(NodeResult::InvalidPage {
+                desc: format!("sibling {sibling} does not exist"),
+            })?;
+        let node = self.load_node(page)?;
+        if node.node_type() != PageType::Leaf {
+            return Err(NodeResult::InvalidPage {
+                desc: format!("sibling {sibling} is not a leaf"),
+            });
+        }
+
+        self.node = node;
+        Ok(())
+    }
+
+    /// Reads the next leaves of the chain, starting at `first`, into the cache.
+    ///
+    /// Leaves are read in batches of the tables prefetch window; nothing is read while the
+    /// current batch is still cached.
+    fn prefetch_siblings(&mut self, first: PageNum) {
+        let window = self.table.prefetch_window();
+        if window <= 1 || self.table.is_cached(first) {
+            return;
+        }
+
+        let mut next = Some(first);
+        for _ in 0..window {
+            let Some(page) = next.and_then(|num| self.table.prefetch_page(num)) else {
+                break;
+            };
+            next = Node::load(page).ok().and_then(|node| node.next_sibling());
+        }
+    }
+
+    /// Checks whether a key inserted at `cell_num` of the current leaf is appended after its last
+    /// key while the leaf has reached the fill factor
+    fn appends_to_packed_leaf(&self, cell_num: u64) -> bool {
+        let num_cells = self.node.num_cells();
+        self.fill_factor.is_some_and(|fill_factor| {
+            num_cells > 0 && cell_num == num_cells && self.node.fill_factor() >= fill_factor
+        })
+    }
+
+    /// Returns the pages from the root down to the current leaf
+    fn descended_pages(&self) -> Vec<(PageNum, PageType)> {
+        let mut pages = self
+            .page_breadcrumb
+            .iter()
+            .map(|(_, page)| (*page, PageType::Internal))
+            .collect::<Vec<_>>();
+        if let Some(leaf) = pages.last_mut() {
+            leaf.1 = PageType::Leaf;
+        }
+
+        pages
+    }
+
+    /// Descends to the leaf that would hold `identifier`; returns `false` without reaching it if
+    /// the bloom filter of an internal node rules the key out
+    fn descend_filtered(&mut self, identifier: u128) -> bool {
+        self.reset();
+        while self.node.node_type() != PageType::Leaf {
+            if !self.table.may_contain(self.current_page(), identifier) {
+                return false;
+            }
+            if let Err(e) = self.find_node(identifier) {
+                warn!("failed to descend to key {identifier}; {e}");
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Returns the page number of the current node
+    fn current_page(&self) -> PageNum {
+        self.page_breadcrumb
+            .last()
+            .map_or(self.table.root, |(_, page)| *page)
+    }
+
+    /// Loads a node of the table; see [Table::load_node]
+    fn load_node(&self, page: CachedPage) -> Result<Node, NodeResult> {
+        self.table.load_node(page)
+    }
+
+    #[instrument(
+        level = "debug",
+        skip(self, identifier),
+        fields(key = identifier, page = %self.current_page())
+    )]
+    fn find_node(&mut self, identifier: u128) -> Result<(), NodeResult> {
+        let cell_num = self.node.find_cell_num(identifier);
+        self.load_child(cell_num)
+    }
+
+    /// Moves the cursor to the child referenced by cell `cell_num` of the current node.
+    ///
+    /// Fails once the descent is longer than the number of pages of the table; a path that long
+    /// has to visit some page twice, so the child pointers form a cycle.
+    fn load_child(&mut self, cell_num: u64) -> Result<(), NodeResult> {
+        if self.page_breadcrumb.len() as u64 >= self.table.page_count() {
+            return Err(NodeResult::InvalidPage {
+                desc: format!(
+                    "child pointers loop back below page {}",
+                    self.current_page()
+                ),
+            });
+        }
+
+        let key_data = self.node.read_cell_bytes(cell_num)?;
+        let cell = InternalCell::from_bytes(key_data);
+        debug!("loading found page: {}", cell.pointer());
+        let page = self
+            .table
+            .get_page(cell.pointer())
+            .ok_or(NodeResult::InvalidPage {
+                desc: format!("child page {} does not exist", cell.pointer()),
+            })?;
+        self.node = self.load_node(page)?;
+        self.page_breadcrumb.push((cell_num, cell.pointer()));
+        debug!("current breadcrumbs: {:?}", self.page_breadcrumb);
+        Ok(())
+    }
+
+    /// Splits the current node while inserting a new cell.
+    ///
+    /// `flags` are the flags of a new leaf cell; they are ignored when splitting internal nodes.
+    /// The caller holds the [commit lock](Table::commit_lock) until the split returns, so a flush
+    /// never writes the split pages before their parent links them.
+    #[instrument(
+        level = "debug",
+        skip(self, identifier, content, flags),
+        fields(key = identifier, page = %self.current_page())
+    )]
+    fn split(
+        &mut self,
+        identifier: u128,
+        content: Vec<u8>,
+        flags: CellFlags,
+    ) -> Result<(), NodeResult> {
+        debug!("splitting current node: {:?}", self.page_breadcrumb.last());
+        debug_assert!(
+            self.table.commit_lock().try_lock().is_err(),
+            "splits have to hold the commit lock"
+        );
+        // The root is split in place; it is put back together if the new root can't be created
+        let root_contents = self.node.is_root().then(|| self.node.contents());
+        let NewPage {
+            num: new_page,
+            page,
+        } = self
+            .table
+            .create_page(&self.node.node_type())
+            .map_err(|e| NodeResult::WriteFailed {
+                desc: e.to_string(),
+            })?;
+        let mut new_node = match self.load_node(page) {
+            Ok(node) => node,
+            Err(e) => {
+                self.release_new_page(new_page);
+                return Err(e);
+            }
+        };
+
+        // Every key routed to the split page is at most the separator; the rest moved to the new
+        // page. Nodes are left unchanged by a failed split.
+        let split = match self.node.node_type() {
+            PageType::Leaf => {
+                let cell = LeafCell::with_flags(identifier, content.clone(), flags);
+                let appended = self.fill_factor.is_some()
+                    && self.node.num_cells() > 0
+                    && self.node.find_cell_num(identifier) == self.node.num_cells();
+                match appended {
+                    true => self.node.split_off(&mut new_node, cell),
+                    false => self.node.split(&mut new_node, cell),
+                }
+                .inspect_err(|e| {
+                    debug!("failed to split leaf node; {e}");
+                })
+            }
+            PageType::Internal => {
+                let cell = InternalCell::new(
+                    identifier,
+                    PageNum::from_be_bytes(content[..LEAF_KEY_POINTER_SIZE].try_into().unwrap()),
+                );
+                self.node.split(&mut new_node, cell).inspect_err(|e| {
+                    debug!("failed to split internal node; {e}");
+                })
+            }
+            PageType::Overflow | PageType::Catalog => {
+                unreachable!("only tree pages are loaded as nodes")
+            }
+        };
+        let separator = match split {
+            Ok(separator) => separator,
+            Err(e) => {
+                self.release_new_page(new_page);
+                return Err(e);
+            }
+        };
+
+        // Only leaves are chained; internal nodes store their right child in the same spot
+        let is_internal = self.node.node_type() == PageType::Internal;
+        if !is_internal {
+            self.node.set_next_sibling(new_page);
+        }
+        if let Some(contents) = root_contents {
+            debug!(
+                "split node was root; creating new root with separator {} for page {}",
+                separator, new_page
+            );
+            let split_page = match self.table.create_new_root(separator, new_page) {
+                Ok(split) => split.old_root_num,
+                Err(desc) => {
+                    // Nothing points at the new page yet; its records are still in the copy
+                    debug!("failed to create new root; {desc}");
+                    self.node.restore(contents);
+                    self.release_new_page(new_page);
+                    return Err(NodeResult::InvalidPage { desc });
+                }
+            };
+            // The filter of the root page still covers every key of the node that was split
+            if is_internal {
+                self.table.copy_bloom_filter(self.table.root, new_page);
+            }
+            let root = self.table.root_page();
+            self.node = self.load_node(root)?;
+            if cfg!(debug_assertions) {
+                self.check_split(separator, split_page, new_page, true);
+            }
+        } else {
+            if is_internal {
+                self.table.copy_bloom_filter(self.current_page(), new_page);
+            }
+            debug!("split node was child; updating page pointers");
+            let (cell_num, cur_page) = self.page_breadcrumb.pop().expect("current page is unknown");
+            let (_, parent_page) = *self
+                .page_breadcrumb
+                .last()
+                .expect("parent page not present");
+
+            let page = self
+                .table
+                .get_page(parent_page)
+                .ok_or(NodeResult::InvalidPage {
+                    desc: format!("parent page {parent_page} does not exist"),
+                })?;
+            self.node = self.load_node(page)?;
+            let right_child = self.node.right_child().ok_or(NodeResult::InvalidPage {
+                desc: format!("parent page {parent_page} is not an internal node"),
+            })?;
+
+            // The split page is now bounded by the separator; the new page takes over whatever bound
+            // the split page had before. Inserting a key past the last cell replaces the right
+            // child, so the right child case only needs the insert.
+            let (key, pointer) = if cur_page == right_child {
+                (separator, new_page)
+            } else {
+                let cell = InternalCell::from_bytes(self.node.read_cell_bytes(cell_num)?);
+                debug!(
+                    "updating cell key {} to point to split page {}",
+                    cell.get_key(),
+                    new_page,
+                );
+                self.node
+                    .update(cell.get_key(), InternalCell::new(cell.get_key(), new_page))
+                    .inspect_err(|e| debug!("failed to update parent node pointer; {e}"))?;
+                (separator, cur_page)
+            };
+            debug!("inserting new cell key {} for page {}", key, pointer);
+
+            let cell = InternalCell::new(key, pointer);
+            // A parent that splits as well may end up holding only one of the two pages
+            let parent_split = match self.node.insert_cell(cell) {
+                Ok(()) => false,
+                Err(NodeResult::IsFull) => {
+                    self.split(key, pointer.to_be_bytes().to_vec(), CellFlags::default())?;
+                    true
+                }
+                Err(e) => {
+                    debug!("failed to split parent node; {e}");
+                    return Err(e);
+                }
+            };
+            if cfg!(debug_assertions) {
+                self.check_split(separator, cur_page, new_page, !parent_split);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the pages involved in a split; only called in debug builds so split bugs surface
+    /// where they happen instead of as garbled records later on.
+    ///
+    /// Both halves of the split must stay within their cell limits and on their side of the
+    /// separator. Internal nodes must keep their keys in order and point at distinct, allocated
+    /// pages. With `check_parent`, the current node has to be the parent of both halves.
+    fn check_split(
+        &mut self,
+        separator: u128,
+        split_page: PageNum,
+        new_page: PageNum,
+        check_parent: bool,
+    ) {
+        let compare = self.table.comparator();
+        for (page_num, above_separator) in [(split_page, false), (new_page, true)] {
+            let page = self
+                .table
+                .get_page(page_num)
+                .unwrap_or_else(|| panic!("split page {page_num} does not exist"));
+            let node = self.load_node(page).expect("failed to load split page");
+            self.check_node(page_num, &node);
+
+            for i in 0..node.num_cells() {
+                let key = node.cell_key(i);
+                let side = compare(key, separator) == Ordering::Greater;
+                assert_eq!(
+                    side, above_separator,
+                    "key {key} of page {page_num} is on the wrong side of separator {separator}"
+                );
+            }
+        }
+
+        let parent_page = self.current_page();
+        let page = self
+            .table
+            .get_page(parent_page)
+            .expect("parent page does not exist");
+        let parent = self.load_node(page).expect("failed to load parent page");
+        let children = self.check_node(parent_page, &parent);
+        if check_parent {
+            for page_num in [split_page, new_page] {
+                assert!(
+                    children.contains(&page_num),
+                    "parent {parent_page} does not point at split page {page_num}"
+                );
+            }
+        }
+    }
+
+    /// Checks the cell count of a node, and the key order and child pointers of an internal node;
+    /// returns the children of an internal node
+    fn check_node(&mut self, page_num: PageNum, node: &Node) -> Vec<PageNum> {
+        let num_cells = node.num_cells();
+        if node.node_type() == PageType::Leaf {
+            assert!(
+                num_cells
+                    <= (LEAF_SPACE_FOR_DATA / leaf_key_cell_size(self.table.key_size())) as u64,
+                "leaf {page_num} claims {num_cells} cells"
+            );
+            return Vec::new();
+        }
+
+        assert!(
+            num_cells as usize <= self.table.max_internal_keys(),
+            "internal node {page_num} holds {num_cells} keys"
+        );
+        let compare = self.table.comparator();
+        for i in 1..num_cells {
+            let (prev, key) = (node.cell_key(i - 1), node.cell_key(i));
+            assert_eq!(
+                compare(prev, key),
+                Ordering::Less,
+                "internal node {page_num} has key {key} after key {prev}"
+            );
+        }
+
+        // The right child is stored past the last cell
+        let mut children = Vec::with_capacity(num_cells as usize + 1);
+        for i in 0..=num_cells {
+            let bytes = node
+                .read_cell_bytes(i)
+                .expect("failed to read internal cell");
+            let child = InternalCell::from_bytes(bytes).pointer();
+            assert!(
+                child != PageNum(CATALOG_PAGE_NUM) && child.0 < self.table.page_count(),
+                "internal node {page_num} points at unallocated page {child}"
+            );
+            assert!(
+                !children.contains(&child),
+                "internal node {page_num} points at page {child} twice"
+            );
+            children.push(child);
+        }
+        children
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        Arc, Mutex,
+    };
+
+    use assert_fs::NamedTempFile;
+    use tracing::{span, Subscriber};
+    use tracing_subscriber::{
+        layer::{Context, SubscriberExt},
+        registry::LookupSpan,
+        Layer,
+    };
+
+    use super::*;
+    use crate::storage::{
+        layout::{PAGE_SIZE, PAGE_TYPE_OFFSET},
+        validate,
+    };
+
+    #[test]
+    fn inline_threshold_overflows_values() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        table.set_max_inline_value(Some(16));
+        let value = vec![0x42; 100];
+
+        let mut cursor = Cursor::new(&mut table);
+        cursor.insert(1, value.clone()).unwrap();
+        cursor.insert(2, b"small".to_vec()).unwrap();
+        assert!(cursor.node.cell_has_overflow(0));
+        assert!(!cursor.node.cell_has_overflow(1));
+
+        let page = table
+            .get_page(PageNum(2))
+            .expect("overflow page should exist");
+        let kind = page.0.read().unwrap()[PAGE_TYPE_OFFSET];
+        assert_eq!(PageType::try_from(kind), Ok(PageType::Overflow));
+
+        assert_eq!(Cursor::new(&mut table).get(1), Some(value));
+        assert_eq!(Cursor::new(&mut table).get(2), Some(b"small".to_vec()));
+    }
+
+    #[test]
+    fn large_values_span_overflow_pages() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let value = (0..PAGE_SIZE * 3).map(|i| i as u8).collect::<Vec<u8>>();
+
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        Cursor::new(&mut table).insert(7, value.clone()).unwrap();
+        table.flush().unwrap();
+
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        assert_eq!(Cursor::new(&mut table).get(7), Some(value));
+    }
+
+    #[test]
+    fn failed_insert_frees_overflow_chain() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        // Scrambled bytes so the value still spills when it is compressed
+        let value = (0..PAGE_SIZE as u64 * 3)
+            .map(|i| (i.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 56) as u8)
+            .collect::<Vec<u8>>();
+
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        Cursor::new(&mut table).insert(1, value.clone()).unwrap();
+        table.flush().unwrap();
+        let pages = table.page_count();
+
+        // The key is only rejected once the value has been spilled
+        let key = u128::from(u64::MAX) + 1;
+        assert_eq!(
+            Cursor::new(&mut table).insert(key, value.clone()),
+            Err(NodeResult::KeyTooLarge)
+        );
+        assert_eq!(table.page_count(), pages);
+
+        table.flush().unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        assert_eq!(table.page_count(), pages);
+        assert_eq!(Cursor::new(&mut table).get(1), Some(value));
+    }
+
+    #[test]
+    fn deletes_key_range() {
+        let mut table = Table::in_memory();
+        for i in 0..100 {
+            Cursor::new(&mut table)
+                .insert(i, format!("{i}data").into_bytes())
+                .unwrap();
+        }
+
+        assert_eq!(Cursor::new(&mut table).delete_range(10, 20), Ok(11));
+        assert_eq!(Cursor::new(&mut table).delete_range(10, 20), Ok(0));
+
+        let rows = Cursor::new(&mut table).select().unwrap();
+        assert_eq!(rows.len(), 89);
+        assert_eq!(rows[9], (9, b"9data".to_vec()));
+        assert_eq!(rows[10], (21, b"21data".to_vec()));
+        assert_eq!(Cursor::new(&mut table).get(15), None);
+
+        // Freed space is reused by later inserts
+        Cursor::new(&mut table)
+            .insert(15, b"back".to_vec())
+            .unwrap();
+        assert_eq!(Cursor::new(&mut table).get(15), Some(b"back".to_vec()));
+        assert_eq!(Cursor::new(&mut table).get(99), Some(b"99data".to_vec()));
+    }
+
+    #[test]
+    fn deletes_ranges_spanning_leaves() {
+        let mut table = Table::in_memory();
+        for i in 0..2000 {
+            Cursor::new(&mut table)
+                .insert(i, format!("{i}data").into_bytes())
+                .unwrap();
+        }
+
+        // Leaves in the middle of the range are left without cells
+        assert_eq!(Cursor::new(&mut table).delete_range(100, 1899), Ok(1800));
+        let keys = Cursor::new(&mut table)
+            .select()
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, (0..100).chain(1900..2000).collect::<Vec<_>>());
+        validate(&mut table).unwrap();
+
+        assert_eq!(Cursor::new(&mut table).delete_range(0, 2000), Ok(200));
+        assert_eq!(Cursor::new(&mut table).select(), Ok(vec![]));
+        assert_eq!(Cursor::new(&mut table).get(1000), None);
+
+        for i in (0..2000).step_by(7) {
+            Cursor::new(&mut table)
+                .insert(i, format!("{i}back").into_bytes())
+                .unwrap();
+        }
+        assert_eq!(Cursor::new(&mut table).count(), Ok(286));
+        assert_eq!(
+            Cursor::new(&mut table).get(1001),
+            Some(b"1001back".to_vec())
+        );
+        validate(&mut table).unwrap();
+    }
+
+    #[test]
+    fn failed_rekey_keeps_record() {
+        let mut table = Table::in_memory();
+        let mut cursor = Cursor::new(&mut table);
+        cursor.insert(3, b"three".to_vec()).unwrap();
+        cursor.insert(5, b"five".to_vec()).unwrap();
+
+        assert!(cursor.rekey(3, 5).is_err());
+        assert_eq!(
+            cursor.rekey(3, u128::from(u64::MAX) + 1),
+            Err(NodeResult::KeyTooLarge.to_string())
+        );
+        assert_eq!(
+            cursor.select(),
+            Ok(vec![(3, b"three".to_vec()), (5, b"five".to_vec())])
+        );
+
+        cursor.rekey(3, 7).unwrap();
+        assert_eq!(cursor.get(3), None);
+        assert_eq!(cursor.get(7), Some(b"three".to_vec()));
+    }
+
+    #[test]
+    fn removed_records_wait_for_vacuum() {
+        let mut table = Table::in_memory();
+        let value = vec![0x42; 64];
+        let mut cursor = Cursor::new(&mut table);
+        for i in 0..40 {
+            cursor.insert(i, value.clone()).unwrap();
+        }
+        let pages = table.page_count();
+
+        let mut cursor = Cursor::new(&mut table);
+        cursor.remove(7).unwrap();
+        assert_eq!(cursor.remove(7), Err(NodeResult::KeyDoesNotExist));
+        assert_eq!(cursor.get(7), None);
+        assert_eq!(cursor.count(), Ok(39));
+        assert!(cursor.select().unwrap().iter().all(|(key, _)| *key != 7));
+
+        // Removed keys can be inserted again before a vacuum
+        cursor.insert(7, b"back".to_vec()).unwrap();
+        assert_eq!(cursor.get(7), Some(b"back".to_vec()));
+
+        (0..40).for_each(|i| cursor.remove(i).unwrap());
+        assert_eq!(cursor.count(), Ok(0));
+        assert_eq!(cursor.vacuum(), Ok(40));
+        assert_eq!(cursor.vacuum(), Ok(0));
+
+        // The same amount of data fits into the reclaimed space without new pages
+        for i in 40..80 {
+            cursor.insert(i, value.clone()).unwrap();
+        }
+        assert_eq!(cursor.count(), Ok(40));
+        assert_eq!(table.page_count(), pages);
+    }
+
+    #[test]
+    fn get_or_insert_keeps_existing_values() {
+        let mut table = Table::in_memory();
+        for i in 0..500 {
+            table.put(i * 2, format!("{i}data").as_bytes()).unwrap();
+        }
+
+        let mut cursor = Cursor::new(&mut table);
+        assert_eq!(
+            cursor.get_or_insert(7, b"first".to_vec()),
+            Ok(b"first".to_vec())
+        );
+        assert_eq!(
+            cursor.get_or_insert(7, b"second".to_vec()),
+            Ok(b"first".to_vec())
+        );
+        assert_eq!(
+            cursor.get_or_insert(8, b"other".to_vec()),
+            Ok(b"4data".to_vec())
+        );
+        assert_eq!(cursor.get(7), Some(b"first".to_vec()));
+        assert_eq!(cursor.count(), Ok(501));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "twice")]
+    fn splits_check_parent_pointers() {
+        let mut table = Table::in_memory();
+        for i in 0..1000 {
+            table.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+
+        // Leave the first cell of the root pointing at the page of the second one, as if a split
+        // had not updated it
+        let mut root = Node::load(table.root_page()).unwrap();
+        assert!(root.num_cells() >= 2);
+        let second = InternalCell::from_bytes(root.read_cell_bytes(1).unwrap());
+        let key = root.cell_key(0);
+        root.update(key, InternalCell::new(key, second.pointer()))
+            .unwrap();
+
+        // Keys past the end only ever split the right most leaf
+        for i in 1000..2000 {
+            table.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "does not point at split page")]
+    fn splits_check_that_the_parent_holds_both_halves() {
+        let mut table = Table::in_memory();
+        for i in 0..1000 {
+            table.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+
+        // Drop the second cell of the root; its leaf is still allocated, but the root no longer
+        // points at it, as if a split had never linked the new page
+        let mut root = Node::load(table.root_page()).unwrap();
+        assert!(root.num_cells() >= 2);
+        let separator = root.cell_key(0);
+        let left = InternalCell::from_bytes(root.read_cell_bytes(0).unwrap()).pointer();
+        let orphan = InternalCell::from_bytes(root.read_cell_bytes(1).unwrap()).pointer();
+        root.remove_cell(root.cell_key(1)).unwrap();
+
+        let mut cursor = Cursor::new(&mut table);
+        cursor.check_split(separator, left, orphan, true);
+    }
+
+    #[test]
+    fn splits_with_unreadable_parents_return_errors() {
+        let mut table = Table::in_memory();
+        for i in 0..1000 {
+            table.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+        let leaf = {
+            let mut cursor = Cursor::new(&mut table);
+            cursor.find_node(1000).unwrap();
+            cursor.current_page()
+        };
+
+        // A parent that can't be read and one that is not an internal node
+        for parent in [PageNum(999_999), leaf] {
+            let commit_lock = table.commit_lock();
+            let _guard = commit_lock.lock().unwrap();
+            let mut cursor = Cursor::new(&mut table);
+            cursor.find_node(1000).unwrap();
+            cursor.page_breadcrumb[0].1 = parent;
+            let result = cursor.split(1000, b"1000data".to_vec(), CellFlags::default());
+            assert!(matches!(result, Err(NodeResult::InvalidPage { .. })));
+        }
+    }
+
+    #[test]
+    fn failed_root_splits_keep_every_record() {
+        let mut table = Table::in_memory();
+        for i in 0..10 {
+            table.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+        let (root, pages) = (table.root, table.page_count());
+        let contents = Node::load(table.root_page()).unwrap().contents();
+
+        let commit_lock = table.commit_lock();
+        let guard = commit_lock.lock().unwrap();
+        let mut cursor = Cursor::new(&mut table);
+        // The cursor holds the root node already; the new root can only be created for the page
+        // the table points at, which is gone now
+        cursor.table.root = PageNum(999);
+        let result = cursor.split(10, b"10data".to_vec(), CellFlags::default());
+        assert!(matches!(result, Err(NodeResult::InvalidPage { .. })));
+        drop(guard);
+
+        table.root = root;
+        assert_eq!(table.page_count(), pages);
+        let restored = Node::load(table.root_page()).unwrap().contents();
+        assert!(restored.0 == contents.0, "root was not restored");
+        let records = Cursor::new(&mut table).select().unwrap();
+        assert_eq!(records.len(), 10);
+        table.put(10, b"10data").unwrap();
+        assert_eq!(table.get_bytes(10), Some(b"10data".to_vec()));
+    }
+
+    #[test]
+    fn failed_update_splits_keep_the_old_record() {
+        let mut table = Table::in_memory();
+        for i in 0..10 {
+            table.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+        let root = table.root;
+
+        let commit_lock = table.commit_lock();
+        let guard = commit_lock.lock().unwrap();
+        let mut cursor = Cursor::new(&mut table);
+        // The new root of the split can't be created; see failed_root_splits_keep_every_record
+        cursor.table.root = PageNum(999);
+        let result = cursor.split_update(5, b"new".to_vec(), CellFlags::default());
+        assert!(matches!(result, Err(NodeResult::InvalidPage { .. })));
+        drop(guard);
+
+        table.root = root;
+        assert_eq!(table.get_bytes(5), Some(b"5data".to_vec()));
+        assert_eq!(Cursor::new(&mut table).count(), Ok(10));
+    }
+
+    #[test]
+    fn flushes_never_see_half_finished_splits() {
+        let mut table = Table::in_memory();
+        for i in 0..1000 {
+            table.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+        let root = table.root_page();
+        let last = Node::load(root.clone()).unwrap().right_child().unwrap();
+        let leaf = table.get_page(last).unwrap();
+
+        // Reads the two pages the way a flush does; a split of the last leaf links the new leaf
+        // as its sibling before the root gains a pointer to it
+        let commit_lock = table.commit_lock();
+        let done = Arc::new(AtomicBool::new(false));
+        let (started, wait) = std::sync::mpsc::channel();
+        let flusher = {
+            let done = Arc::clone(&done);
+            std::thread::spawn(move || loop {
+                let guard = commit_lock.lock().unwrap();
+                let root = Node::load(root.clone()).unwrap();
+                let sibling = Node::load(leaf.clone()).unwrap().next_sibling();
+                let children = (0..=root.num_cells())
+                    .map(|i| InternalCell::from_bytes(root.read_cell_bytes(i).unwrap()))
+                    .map(|cell| cell.pointer())
+                    .collect::<Vec<_>>();
+                if let Some(sibling) = sibling {
+                    assert!(children.contains(&sibling), "page {sibling} is not linked");
+                }
+                drop(guard);
+
+                // The inserts start once the first flush went through
+                let _ = started.send(());
+                if done.load(AtomicOrdering::Relaxed) {
+                    break;
+                }
+            })
+        };
+
+        wait.recv().unwrap();
+        let mut key = 1000;
+        while Node::load(table.get_page(last).unwrap())
+            .unwrap()
+            .next_sibling()
+            .is_none()
+        {
+            table.put(key, format!("{key}data").as_bytes()).unwrap();
+            key += 1;
+        }
+        done.store(true, AtomicOrdering::Relaxed);
+        flusher.join().unwrap();
+    }
+
+    #[test]
+    fn updates_values_in_place_or_by_splitting() {
+        let mut table = Table::in_memory();
+        for i in 0..500 {
+            table.put(i, vec![i as u8; 50].as_slice()).unwrap();
+        }
+        let pages = table.page_count();
+
+        let mut cursor = Cursor::new(&mut table);
+        cursor.update(250, b"short".to_vec()).unwrap();
+        assert_eq!(cursor.get(250), Some(b"short".to_vec()));
+
+        // Growing every value of a leaf can't be done in place
+        for i in 100..200 {
+            cursor.update(i, vec![0xAB; 400]).unwrap();
+        }
+        cursor.update(300, vec![0xCD; 3 * PAGE_SIZE]).unwrap();
+        assert_eq!(cursor.get(300), Some(vec![0xCD; 3 * PAGE_SIZE]));
+        assert_eq!(cursor.get(150), Some(vec![0xAB; 400]));
+        assert_eq!(cursor.get(99), Some(vec![99; 50]));
+        assert_eq!(cursor.count(), Ok(500));
+
+        assert_eq!(
+            cursor.update(500, b"missing".to_vec()),
+            Err(NodeResult::KeyDoesNotExist)
+        );
+        cursor.remove(7).unwrap();
+        assert_eq!(
+            cursor.update(7, b"removed".to_vec()),
+            Err(NodeResult::KeyDoesNotExist)
+        );
+
+        assert_eq!(cursor.count(), Ok(499));
+        assert!(table.page_count() > pages);
+        validate(&mut table).unwrap();
+    }
+
+    #[test]
+    fn first_and_last_records() {
+        let mut table = Table::in_memory();
+        let mut cursor = Cursor::new(&mut table);
+        assert_eq!(cursor.first(), Ok(None));
+        assert_eq!(cursor.last(), Ok(None));
+
+        for i in (0..2000).rev() {
+            cursor
+                .insert(i * 3 + 5, format!("{i}").into_bytes())
+                .unwrap();
+        }
+        assert!(tree_height(&mut table) > 1);
+
+        let mut cursor = Cursor::new(&mut table);
+        assert_eq!(cursor.first(), Ok(Some((5, b"0".to_vec()))));
+        assert_eq!(cursor.last(), Ok(Some((1999 * 3 + 5, b"1999".to_vec()))));
+
+        cursor.remove(5).unwrap();
+        cursor.delete_range(1900 * 3 + 5, 1999 * 3 + 5).unwrap();
+        assert_eq!(cursor.first(), Ok(Some((8, b"1".to_vec()))));
+        assert_eq!(cursor.last(), Ok(Some((1899 * 3 + 5, b"1899".to_vec()))));
+
+        // Every leaf right of the first one is left without records
+        cursor.delete_range(2 * 3 + 5, 1899 * 3 + 5).unwrap();
+        assert_eq!(cursor.last(), Ok(Some((3 + 5, b"1".to_vec()))));
+        cursor.delete_range(0, 3 + 5).unwrap();
+        assert_eq!(cursor.last(), Ok(None));
+    }
+
+    #[test]
+    fn seek_then_iterate() {
+        let mut table = Table::in_memory();
+        let mut cursor = Cursor::new(&mut table);
+        for i in 0..3000 {
+            cursor.insert(i * 2, format!("{i}").into_bytes()).unwrap();
+        }
+
+        // Odd keys are not stored; iteration starts at the next key
+        cursor.seek(3001).unwrap();
+        let keys = cursor
+            .iter()
+            .map(|record| record.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, (3002..6000).step_by(2).collect::<Vec<_>>());
+
+        cursor.seek(6000).unwrap();
+        assert!(cursor.iter().next().is_none());
+
+        cursor.reset();
+        assert_eq!(cursor.iter().count(), 3000);
+    }
+
+    #[test]
+    fn explain_predicts_splits() {
+        let mut table = Table::in_memory();
+        let value = vec![0xA; 100];
+        let mut splits = 0;
+        for i in 0..200 {
+            let pages = table.page_count();
+            let plan = Cursor::new(&mut table)
+                .explain_insert(i, value.clone())
+                .unwrap();
+            assert_eq!(table.page_count(), pages);
+
+            Cursor::new(&mut table).insert(i, value.clone()).unwrap();
+            assert_eq!(plan.split, table.page_count() > pages, "insert {i}");
+            splits += plan.split as u64;
+        }
+        assert!(splits > 0);
+
+        let mut cursor = Cursor::new(&mut table);
+        assert_eq!(
+            cursor.explain_insert(0, value.clone()),
+            Err(NodeResult::DuplicateKey)
+        );
+        let plan = cursor.explain_lookup(150).unwrap();
+        assert_eq!(plan.pages.first(), Some(&(table.root, PageType::Internal)));
+        assert_eq!(plan.pages.len() as u64, tree_height(&mut table));
+    }
+
+    #[test]
+    fn get_many_matches_get() {
+        let mut table = Table::in_memory();
+        let mut cursor = Cursor::new(&mut table);
+        for i in 0..3000 {
+            cursor
+                .insert(i * 2, format!("{i}data").into_bytes())
+                .unwrap();
+        }
+        cursor.remove(1000).unwrap();
+
+        let keys = [5998, 4, 1000, 3, 2500, 4, 0, 7001, 1234, 5000];
+        let expected = keys.iter().map(|key| cursor.get(*key)).collect::<Vec<_>>();
+        assert_eq!(cursor.get_many(&keys), expected);
+        assert_eq!(expected.iter().filter(|value| value.is_none()).count(), 3);
+        assert!(cursor.get_many(&[]).is_empty());
+    }
+
+    #[test]
+    fn corrupt_values_are_errors() {
+        let mut table = Table::in_memory();
+        table.put(1, b"abc").unwrap();
+        table.put(2, b"def").unwrap();
+
+        // The first value is stored at the end of the page; give it an impossible length
+        let page = table.root_page();
+        page.0.write().unwrap()[PAGE_SIZE - 4..PAGE_SIZE - 2].clone_from_slice(&[0xFF, 0x7F]);
+
+        let mut cursor = Cursor::new(&mut table);
+        assert!(matches!(
+            cursor.select(),
+            Err(NodeResult::InvalidPage { .. })
+        ));
+        assert_eq!(cursor.get(1), None);
+        assert_eq!(cursor.get(2), Some(b"def".to_vec()));
+    }
+
+    #[test]
+    fn cyclic_child_pointers_are_errors() {
+        let mut table = Table::in_memory();
+        for i in 0..500 {
+            table.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+
+        // Point the first child of the root back at the root
+        let mut root = Node::load(table.root_page()).unwrap();
+        let key = root.cell_key(0);
+        root.update(key, InternalCell::new(key, table.root))
+            .unwrap();
+
+        let mut cursor = Cursor::new(&mut table);
+        assert!(matches!(
+            cursor.select(),
+            Err(NodeResult::InvalidPage { .. })
+        ));
+        assert!(cursor.insert(0, b"again".to_vec()).is_err());
+        assert_eq!(cursor.get(0), None);
+        assert_eq!(cursor.get(499), Some(b"499data".to_vec()));
+        assert!(validate::validate(&mut table).is_err());
+    }
+
+    #[test]
+    fn histogram_counts_skewed_keys() {
+        let mut table = Table::in_memory();
+        assert_eq!(Cursor::new(&mut table).histogram(4), Ok(Vec::new()));
+
+        for i in (0..90).chain(900..910) {
+            table.put(i, b"data").unwrap();
+        }
+        Cursor::new(&mut table).remove(905).unwrap();
+
+        let histogram = Cursor::new(&mut table).histogram(10).unwrap();
+        assert_eq!(histogram.len(), 10);
+        assert_eq!(
+            histogram[0],
+            KeyBucket {
+                lo: 0,
+                hi: 90,
+                keys: 90
+            }
+        );
+        assert_eq!(
+            histogram[9],
+            KeyBucket {
+                lo: 819,
+                hi: 909,
+                keys: 9
+            }
+        );
+        assert!(histogram[1..9].iter().all(|bucket| bucket.keys == 0));
+        assert!(histogram.windows(2).all(|w| w[0].hi + 1 == w[1].lo));
+
+        // Never more buckets than identifiers between the bounds
+        let mut table = Table::in_memory();
+        for i in 5..8 {
+            table.put(i, b"data").unwrap();
+        }
+        let histogram = Cursor::new(&mut table).histogram(10).unwrap();
+        let expected = (5..8).map(|i| KeyBucket {
+            lo: i,
+            hi: i,
+            keys: 1,
+        });
+        assert!(histogram.into_iter().eq(expected));
+
+        table.put(u64::MAX.into(), b"data").unwrap();
+        let histogram = Cursor::new(&mut table).histogram(2).unwrap();
+        assert_eq!(histogram[1].hi, u64::MAX.into());
+        assert_eq!(histogram[0].keys + histogram[1].keys, 4);
+
+        let histogram = Cursor::new(&mut table).histogram(u64::MAX).unwrap();
+        assert_eq!(histogram.len() as u64, MAX_HISTOGRAM_BUCKETS);
+        assert_eq!(histogram.iter().map(|bucket| bucket.keys).sum::<u64>(), 4);
+    }
+
+    #[test]
+    fn histogram_skips_keys_outside_the_bounds() {
+        fn by_last_digit(a: u128, b: u128) -> Ordering {
+            (a % 10, a).cmp(&(b % 10, b))
+        }
+        let mut table = Table::in_memory();
+        table.set_comparator(by_last_digit);
+        for key in [10, 3, 25, 51] {
+            table.put(key, b"data").unwrap();
+        }
+
+        // 10 is stored first and 25 last; 3 and 51 are outside of the ranges between them
+        let histogram = Cursor::new(&mut table).histogram(2).unwrap();
+        let keys = histogram
+            .iter()
+            .map(|bucket| bucket.keys)
+            .collect::<Vec<_>>();
+        assert_eq!((histogram[0].lo, histogram[1].hi), (10, 25));
+        assert_eq!(keys, [1, 1]);
+    }
+
+    #[test]
+    fn cursor_is_reusable() {
+        let mut table = Table::in_memory();
+        let mut cursor = Cursor::new(&mut table);
+
+        for i in (0..100).rev() {
+            cursor.insert(i, format!("{i}data").into_bytes()).unwrap();
+        }
+        let rows = cursor.select().unwrap();
+        assert_eq!(rows.len(), 100);
+        assert_eq!(rows[42], (42, b"42data".to_vec()));
+
+        // Operations after a select still start from the root
+        assert_eq!(cursor.get(7), Some(b"7data".to_vec()));
+        cursor.insert(100, b"100data".to_vec()).unwrap();
+        assert_eq!(cursor.select().unwrap().len(), 101);
+    }
+
+    #[test]
+    fn appends_after_the_largest_key() {
+        let mut table = Table::in_memory();
+        let mut cursor = Cursor::new(&mut table);
+        assert_eq!(cursor.append(b"first".to_vec()), Ok(1));
+        cursor.insert(41, b"data".to_vec()).unwrap();
+        assert_eq!(cursor.append(b"next".to_vec()), Ok(42));
+
+        let max = u128::from(u64::MAX);
+        cursor.insert(max, b"max".to_vec()).unwrap();
+        assert_eq!(
+            cursor.append(b"past max".to_vec()),
+            Err(NodeResult::NoIdentifiersLeft)
+        );
+        assert_eq!(cursor.count(), Ok(4));
+    }
+
+    #[test]
+    fn errors_are_structured() {
+        let mut table = Table::in_memory();
+        table.set_max_inline_value(Some(16));
+        let mut cursor = Cursor::new(&mut table);
+
+        cursor.insert(1, b"data".to_vec()).unwrap();
+        assert_eq!(
+            cursor.insert(1, b"data".to_vec()),
+            Err(NodeResult::DuplicateKey)
+        );
+        assert_eq!(
+            cursor.insert(1, vec![0x42; 100]),
+            Err(NodeResult::DuplicateKey)
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_values() {
+        let mut table = Table::in_memory();
+        table.set_max_value_size(64);
+        let pages = table.page_count();
+
+        let mut cursor = Cursor::new(&mut table);
+        assert_eq!(
+            cursor.insert(1, vec![0x42; 65]),
+            Err(NodeResult::ValueTooLarge { size: 65, max: 64 })
+        );
+        cursor.insert(1, vec![0x42; 64]).unwrap();
+        assert_eq!(cursor.select().unwrap().len(), 1);
+        assert_eq!(table.page_count(), pages);
+    }
+
+    #[test]
+    fn sibling_chain_covers_every_key() {
+        let mut table = Table::in_memory();
+
+        // Pseudo random insertion order so splits happen all over the chain
+        let mut keys = Vec::new();
+        let mut state: u64 = 0x2545F4914F6CDD1D;
+        for _ in 0..3000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let key = u128::from(state % 100_000);
+            if Cursor::new(&mut table)
+                .insert(key, format!("{key}").into_bytes())
+                .is_ok()
+            {
+                keys.push(key);
+            }
+        }
+        keys.sort();
+
+        // Descend to the left-most leaf and only follow sibling pointers from there
+        let mut node = Node::load(table.root_page()).unwrap();
+        while node.node_type() == PageType::Internal {
+            let cell = InternalCell::from_bytes(node.read_cell_bytes(0).unwrap());
+            node = Node::load(table.get_page(cell.pointer()).unwrap()).unwrap();
+        }
+
+        let mut chain = Vec::new();
+        let mut leaves = 0;
+        loop {
+            chain.extend((0..node.num_cells()).map(|i| node.cell_key(i)));
+            leaves += 1;
+            match node.next_sibling() {
+                Some(sibling) => node = Node::load(table.get_page(sibling).unwrap()).unwrap(),
+                None => break,
+            }
+        }
+
+        let leaf_pages = (0..table.page_count())
+            .map(PageNum)
+            .filter(|num| {
+                let page = table.get_page(*num).unwrap();
+                let kind = page.0.read().unwrap()[PAGE_TYPE_OFFSET];
+                PageType::try_from(kind) == Ok(PageType::Leaf)
+            })
+            .count();
+        assert_eq!(leaves, leaf_pages);
+
+        assert_eq!(chain, keys);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compresses_large_values() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let value = "compressible ".repeat(800).into_bytes();
+        assert!(value.len() > 10_000);
+
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        table.set_compression_threshold(Some(1024));
+        let pages = table.page_count();
+
+        let mut cursor = Cursor::new(&mut table);
+        cursor.insert(1, value.clone()).unwrap();
+        cursor.insert(2, b"small".to_vec()).unwrap();
+        assert!(cursor.node.cell_is_compressed(0));
+        assert!(!cursor.node.cell_is_compressed(1));
+        assert!(!cursor.node.cell_has_overflow(0));
+        assert!(cursor.node.read_cell_bytes(0).unwrap().len() < value.len() / 20);
+
+        // The compressed value fits in the leaf; no overflow pages were needed
+        assert_eq!(table.page_count(), pages);
+        table.flush().unwrap();
+
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        assert_eq!(Cursor::new(&mut table).get(1), Some(value));
+        assert_eq!(Cursor::new(&mut table).get(2), Some(b"small".to_vec()));
+    }
+
+    #[test]
+    fn prefetching_reduces_cache_misses() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        for i in 0..3000 {
+            Cursor::new(&mut table)
+                .insert(i, format!("{i}data").into_bytes())
+                .unwrap();
+        }
+        table.flush().unwrap();
+
+        let leaves = validate(&mut table).unwrap().leaves;
+        assert!(leaves > 16);
+
+        // Only the misses of the scan itself count; opening the table reads the same pages
+        let scan_misses = |table: &mut Table| {
+            let before = table.metrics().cache_misses;
+            let rows = Cursor::new(table).select().unwrap();
+            (rows, table.metrics().cache_misses - before)
+        };
+
+        let mut cold = Table::new(file.path().to_path_buf()).unwrap();
+        let (expected, cold_misses) = scan_misses(&mut cold);
+        assert_eq!(expected.len(), 3000);
+        // Every leaf is a miss without prefetching
+        assert!(cold_misses >= leaves);
+
+        let mut prefetched = Table::new(file.path().to_path_buf()).unwrap();
+        prefetched.set_prefetch_window(8);
+        let (rows, prefetched_misses) = scan_misses(&mut prefetched);
+        assert_eq!(rows, expected);
+        // Only the first leaf of every batch is a miss; the descent to the first leaf misses the
+        // same pages as before
+        assert!(prefetched_misses <= cold_misses - leaves + leaves.div_ceil(8));
+    }
+
+    #[test]
+    fn max_key_is_a_valid_key() {
+        let mut table = Table::in_memory();
+        let mut cursor = Cursor::new(&mut table);
+        for i in 0..300 {
+            cursor.insert(i, format!("{i}data").into_bytes()).unwrap();
+        }
+        let max = u128::from(u64::MAX);
+        cursor.insert(max, b"max".to_vec()).unwrap();
+
+        assert_eq!(cursor.get(max), Some(b"max".to_vec()));
+        assert_eq!(
+            cursor.select().unwrap().last(),
+            Some(&(max, b"max".to_vec()))
+        );
+        assert_eq!(
+            cursor.insert(max, b"again".to_vec()),
+            Err(NodeResult::DuplicateKey)
+        );
+        // Keys of 8 byte tables end at the largest u64
+        assert_eq!(
+            cursor.insert(max + 1, b"wide".to_vec()),
+            Err(NodeResult::KeyTooLarge)
+        );
+    }
+
+    #[test]
+    fn root_split_keeps_low_keys() {
+        let mut table = Table::in_memory();
+        let mut cursor = Cursor::new(&mut table);
+        let value = vec![0x42; 500];
+        for i in [1, 0] {
+            cursor.insert(i, value.clone()).unwrap();
+        }
+        let mut i = 2;
+        while table.page_count() < 4 {
+            Cursor::new(&mut table).insert(i, value.clone()).unwrap();
+            i += 1;
+        }
+
+        let mut cursor = Cursor::new(&mut table);
+        assert_eq!(cursor.get(0), Some(value.clone()));
+        assert_eq!(cursor.get(1), Some(value.clone()));
+        assert_eq!(cursor.get(i - 1), Some(value.clone()));
+        let keys = cursor
+            .select()
+            .unwrap()
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, (0..i).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn custom_key_order() {
+        let mut table = Table::in_memory();
+        table.set_comparator(|a, b| b.cmp(&a));
+
+        let mut cursor = Cursor::new(&mut table);
+        for i in 0..2000 {
+            cursor.insert(i, format!("{i}data").into_bytes()).unwrap();
+        }
+        assert_eq!(cursor.get(1234), Some(b"1234data".to_vec()));
+        assert_eq!(cursor.delete_range(1999, 1990), Ok(10));
+
+        let keys = cursor
+            .select()
+            .unwrap()
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, (0..1990).rev().collect::<Vec<_>>());
+        assert!(table.page_count() > 4);
+        assert_eq!(validate::validate(&mut table).unwrap().keys, 1990);
+    }
+
+    /// Returns the number of levels of the tree, counting the leaves
+    fn tree_height(table: &mut Table) -> u64 {
+        let mut node = Node::load(table.root_page()).unwrap();
+        let mut height = 1;
+        while node.node_type() == PageType::Internal {
+            let cell = InternalCell::from_bytes(node.read_cell_bytes(0).unwrap());
+            node = Node::load(table.get_page(cell.pointer()).unwrap()).unwrap();
+            height += 1;
+        }
+
+        height
+    }
+
+    #[test]
+    fn cascading_internal_splits() {
+        let value = vec![0x42; 1000];
+        for ordered in [true, false] {
+            let mut table = Table::in_memory();
+            table.set_max_internal_keys(Some(3));
+
+            let mut keys = Vec::new();
+            let mut state: u64 = 0x2545F4914F6CDD1D;
+            for i in 0..500 {
+                let key = if ordered {
+                    i
+                } else {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    u128::from(state % 100_000)
+                };
+                if Cursor::new(&mut table).insert(key, value.clone()).is_ok() {
+                    keys.push(key);
+                }
+                // Check after every insert so a split that loses a pointer is caught right away
+                assert!(validate::validate(&mut table).is_ok());
+            }
+            keys.sort();
+
+            // Splits went through at least two levels of internal nodes
+            assert!(tree_height(&mut table) >= 4);
+
+            let mut cursor = Cursor::new(&mut table);
+            for key in keys.iter() {
+                assert_eq!(cursor.get(*key).as_ref(), Some(&value), "key {key}");
+            }
+            let selected = cursor
+                .select()
+                .unwrap()
+                .into_iter()
+                .map(|(k, _)| k)
+                .collect::<Vec<_>>();
+            assert_eq!(selected, keys);
+        }
+    }
+
+    #[test]
+    fn select_detects_sibling_cycles() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        for i in 0..10 {
+            table.put(i, b"data").unwrap();
+        }
+
+        // Point the only leaf back at itself
+        let root = table.root;
+        Node::load(table.root_page())
+            .unwrap()
+            .set_next_sibling(root);
+        table.flush().unwrap();
+
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        let mut cursor = Cursor::new(&mut table);
+        assert_eq!(
+            cursor.select(),
+            Err(NodeResult::InvalidPage {
+                desc: format!("sibling chain loops back at leaf {root}")
+            })
+        );
+        assert!(cursor.select_limit(5, Some(2)).is_ok());
+        assert!(cursor.delete_range(0, 100).is_err());
+    }
+
+    /// Name of a span and the name of its parent
+    type SpanName = (&'static str, Option<&'static str>);
+
+    /// Records the name of every new span along with the name of its parent
+    #[derive(Clone, Default)]
+    struct SpanRecorder(Arc<Mutex<Vec<SpanName>>>);
+
+    impl<S: Subscriber + for<'s> LookupSpan<'s>> Layer<S> for SpanRecorder {
+        fn on_new_span(&self, _: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+            let span = ctx.span(id).expect("new span is registered");
+            let parent = span.parent().map(|parent| parent.name());
+            self.0.lock().unwrap().push((span.name(), parent));
+        }
+    }
+
+    #[test]
+    fn splits_are_traced_within_inserts() {
+        let recorder = SpanRecorder::default();
+        let subscriber = tracing_subscriber::registry().with(recorder.clone());
+
+        let mut table = Table::in_memory();
+        tracing::subscriber::with_default(subscriber, || {
+            let mut cursor = Cursor::new(&mut table);
+            for i in 0..300 {
+                cursor.insert(i, vec![0x42; 64]).unwrap();
+            }
+        });
+
+        let spans = recorder.0.lock().unwrap();
+        assert!(spans.contains(&("insert", None)));
+        assert!(spans.contains(&("split", Some("insert"))));
+        assert!(spans.contains(&("find_node", Some("insert"))));
     }
 }
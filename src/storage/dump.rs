@@ -0,0 +1,260 @@
+use std::io::{BufRead, ErrorKind, Read, Write};
+
+use super::{
+    cursor::Cursor,
+    layout::{KEY_SIZE, WIDE_KEY_SIZE},
+    table::Table,
+};
+
+/// Header identifying a dump file and the version of its record format; version 2 stores 16-byte
+/// keys
+const DUMP_HEADER: &[u8] = b"btree-db dump 2\n";
+/// Header of dumps written with 8-byte keys; they can still be imported
+const DUMP_HEADER_V1: &[u8] = b"btree-db dump 1\n";
+
+/// Writes every record of the current table to `out`.
+///
+/// Records are written as a big-endian `u128` key and `u64` value length followed by the value
+/// bytes, so dumps don't depend on the page format. Returns the number of records written.
+pub fn dump<W: Write>(table: &mut Table, mut out: W) -> Result<u64, String> {
+    let rows = Cursor::new(table).select().map_err(|e| e.to_string())?;
+
+    out.write_all(DUMP_HEADER).map_err(|e| e.to_string())?;
+    for (key, value) in rows.iter() {
+        out.write_all(&key.to_be_bytes())
+            .and_then(|_| out.write_all(&(value.len() as u64).to_be_bytes()))
+            .and_then(|_| out.write_all(value))
+            .map_err(|e| format!("failed to write record {key}; {e}"))?;
+    }
+    out.flush().map_err(|e| e.to_string())?;
+
+    Ok(rows.len() as u64)
+}
+
+/// Inserts every record of a dump created by [dump](dump) into the current table.
+///
+/// Returns the number of records imported; importing stops at the first record that can't be
+/// inserted.
+pub fn import<R: Read>(table: &mut Table, mut input: R) -> Result<u64, String> {
+    let mut header = [0x0; DUMP_HEADER.len()];
+    input
+        .read_exact(&mut header)
+        .map_err(|_| "not a dump file".to_string())?;
+    let key_size = match &header[..] {
+        DUMP_HEADER => WIDE_KEY_SIZE,
+        DUMP_HEADER_V1 => KEY_SIZE,
+        _ => return Err("not a dump file".to_string()),
+    };
+
+    let max_value_size = table.max_value_size() as u64;
+    let mut cursor = Cursor::new(table);
+    let mut imported = 0;
+    loop {
+        let mut key = [0x0; WIDE_KEY_SIZE];
+        match input.read_exact(&mut key[WIDE_KEY_SIZE - key_size..]) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.to_string()),
+        }
+        let key = u128::from_be_bytes(key);
+
+        let mut len = [0x0; 8];
+        input
+            .read_exact(&mut len)
+            .map_err(|_| format!("record {key} is truncated"))?;
+        let len = u64::from_be_bytes(len);
+        if len > max_value_size {
+            return Err(format!(
+                "record {key} is larger than the maximum value size"
+            ));
+        }
+
+        let mut value = vec![0x0; len as usize];
+        input
+            .read_exact(&mut value)
+            .map_err(|_| format!("record {key} is truncated"))?;
+
+        cursor
+            .insert(key, value)
+            .map_err(|e| format!("failed to import record {key}; {e}"))?;
+        imported += 1;
+    }
+
+    Ok(imported)
+}
+
+/// Outcome of a CSV import
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvImport {
+    /// Number of records inserted
+    pub imported: u64,
+    /// Description of every malformed line that was skipped
+    pub skipped: Vec<String>,
+}
+
+/// Inserts the records of a CSV file with one `id,value` record per line into the current table.
+///
+/// Identifiers are parsed according to the key type of the table. Values are everything after the
+/// first comma and are encoded according to the schema of the table. Malformed lines are skipped
+/// and reported unless `strict` is set, in which case nothing is imported. Blank lines are
+/// ignored.
+pub fn import_csv<R: BufRead>(
+    table: &mut Table,
+    input: R,
+    strict: bool,
+) -> Result<CsvImport, String> {
+    let schema = table.schema();
+    let key_type = table.key_type();
+    let mut rows = Vec::new();
+    let mut skipped = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let row = match line.split_once(',') {
+            Some((id, value)) => key_type
+                .parse(id.trim())
+                .map_err(|_| format!("line {}: invalid identifier `{}`", i + 1, id.trim()))
+                .and_then(|id| {
+                    let value = match schema {
+                        Some(schema) => schema.encode(value),
+                        None => Ok(value.as_bytes().to_vec()),
+                    };
+                    value
+                        .map(|value| (id, value))
+                        .map_err(|e| format!("line {}: {e}", i + 1))
+                }),
+            None => Err(format!("line {}: expected `id,value`", i + 1)),
+        };
+        match row {
+            Ok(row) => rows.push(row),
+            Err(e) if strict => return Err(e),
+            Err(e) => skipped.push(e),
+        }
+    }
+
+    let imported = table
+        .bulk_insert(rows, None)
+        .map_err(|e| format!("failed to import records; {e}"))?;
+    Ok(CsvImport { imported, skipped })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::schema::{self, KeyType, ValueType};
+
+    #[test]
+    fn dump_round_trip() {
+        let mut table = Table::in_memory();
+        for i in 0..500 {
+            let value = match i % 3 {
+                0 => format!("{i}\twith\ttabs\n").into_bytes(),
+                1 => vec![0xFF; i as usize],
+                _ => Vec::new(),
+            };
+            Cursor::new(&mut table).insert(i, value).unwrap();
+        }
+
+        let mut out = Vec::new();
+        assert_eq!(dump(&mut table, &mut out), Ok(500));
+
+        let mut imported = Table::in_memory();
+        assert_eq!(import(&mut imported, out.as_slice()), Ok(500));
+        assert_eq!(
+            Cursor::new(&mut imported).select().unwrap(),
+            Cursor::new(&mut table).select().unwrap()
+        );
+    }
+
+    #[test]
+    fn imports_dumps_with_narrow_keys() {
+        let mut dump = DUMP_HEADER_V1.to_vec();
+        dump.extend_from_slice(&7u64.to_be_bytes());
+        dump.extend_from_slice(&4u64.to_be_bytes());
+        dump.extend_from_slice(b"data");
+
+        let mut table = Table::in_memory();
+        assert_eq!(import(&mut table, dump.as_slice()), Ok(1));
+        assert_eq!(
+            Cursor::new(&mut table).select().unwrap(),
+            vec![(7, b"data".to_vec())]
+        );
+    }
+
+    #[test]
+    fn imports_csv() {
+        let csv = "1,one\n2,two, with a comma\r\n\nthree,3\n4\n5,\n";
+
+        let mut table = Table::in_memory();
+        let result = import_csv(&mut table, csv.as_bytes(), false).unwrap();
+        assert_eq!(result.imported, 3);
+        assert_eq!(
+            result.skipped,
+            vec![
+                "line 4: invalid identifier `three`".to_string(),
+                "line 5: expected `id,value`".to_string()
+            ]
+        );
+        assert_eq!(
+            Cursor::new(&mut table).select().unwrap(),
+            vec![
+                (1, b"one".to_vec()),
+                (2, b"two, with a comma".to_vec()),
+                (5, Vec::new())
+            ]
+        );
+
+        let mut table = Table::in_memory();
+        assert_eq!(
+            import_csv(&mut table, csv.as_bytes(), true),
+            Err("line 4: invalid identifier `three`".to_string())
+        );
+        assert_eq!(Cursor::new(&mut table).count(), Ok(0));
+
+        // Values of typed tables are encoded like inserted ones
+        let mut table = Table::in_memory();
+        table.open_typed("numbers", ValueType::Int).unwrap();
+        let result = import_csv(&mut table, "1, -42\n2,two\n".as_bytes(), false).unwrap();
+        assert_eq!(result.imported, 1);
+        assert_eq!(
+            result.skipped,
+            vec!["line 2: `two` is not an int".to_string()]
+        );
+        assert_eq!(table.get_bytes(1), Some((-42i64).to_be_bytes().to_vec()));
+
+        // Keys of signed tables are encoded like parsed ones
+        let mut table = Table::in_memory();
+        table.open("signed").unwrap();
+        table.set_key_type(KeyType::Signed).unwrap();
+        let result = import_csv(&mut table, "5,five\n-3,neg\n".as_bytes(), true).unwrap();
+        assert_eq!(result.imported, 2);
+        assert_eq!(
+            Cursor::new(&mut table).select().unwrap(),
+            vec![
+                (schema::encode_signed(-3).into(), b"neg".to_vec()),
+                (schema::encode_signed(5).into(), b"five".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn import_rejects_invalid_dumps() {
+        let mut table = Table::in_memory();
+        assert!(import(&mut table, &b"key\tvalue\n"[..]).is_err());
+
+        let mut out = Vec::new();
+        Cursor::new(&mut table).insert(1, b"data".to_vec()).unwrap();
+        dump(&mut table, &mut out).unwrap();
+        out.pop();
+
+        let mut imported = Table::in_memory();
+        assert_eq!(
+            import(&mut imported, out.as_slice()),
+            Err("record 1 is truncated".to_string())
+        );
+    }
+}
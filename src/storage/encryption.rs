@@ -0,0 +1,199 @@
+#[cfg(feature = "encryption")]
+use super::layout::{
+    ENCRYPTED_PAGE_SIZE, ENCRYPTION_CHECK_OFFSET, ENCRYPTION_CHECK_SIZE, ENCRYPTION_NONCE_SIZE,
+    ENCRYPTION_SALT_OFFSET, ENCRYPTION_SALT_SIZE,
+};
+use super::layout::{
+    ENCRYPTION_HEADER_SIZE, ENCRYPTION_MAGIC, ENCRYPTION_MAGIC_OFFSET, ENCRYPTION_MAGIC_SIZE,
+    PAGE_SIZE,
+};
+use super::page::PageNum;
+use crate::calculate_offsets;
+
+/// Number of PBKDF2 rounds used to derive a key from a passphrase
+#[cfg(feature = "encryption")]
+const KEY_DERIVATION_ROUNDS: u32 = 100_000;
+
+/// Returns whether `header` is the start of an encrypted file
+pub fn is_encrypted(header: &[u8]) -> bool {
+    let (start, end) = calculate_offsets!(ENCRYPTION_MAGIC_OFFSET, ENCRYPTION_MAGIC_SIZE);
+    header.get(start..end) == Some(&ENCRYPTION_MAGIC.to_be_bytes()[..])
+}
+
+/// Encrypts and decrypts the pages of a file with AES-256-GCM.
+///
+/// The key is derived from a passphrase and the salt stored in the plaintext header of the file.
+/// Pages are bound to their page number, so an encrypted page can't be moved to another place in
+/// the file without being noticed.
+#[cfg(feature = "encryption")]
+#[derive(Clone)]
+pub struct PageCipher {
+    cipher: aes_gcm::Aes256Gcm,
+    header: [u8; ENCRYPTION_HEADER_SIZE],
+}
+
+#[cfg(feature = "encryption")]
+impl PageCipher {
+    /// Derives the key of a new file from `passphrase` and a new random salt; the
+    /// [header](PageCipher::header) has to be stored at the start of the file
+    pub fn create(passphrase: &str) -> Result<Self, String> {
+        use aes_gcm::aead::{rand_core::RngCore, OsRng};
+
+        let mut salt = [0x0; ENCRYPTION_SALT_SIZE];
+        OsRng
+            .try_fill_bytes(&mut salt)
+            .map_err(|e| format!("failed to generate salt; {e}"))?;
+        let mut cipher = Self::derive(passphrase, &salt);
+
+        let (start, end) = calculate_offsets!(ENCRYPTION_MAGIC_OFFSET, ENCRYPTION_MAGIC_SIZE);
+        cipher.header[start..end].clone_from_slice(&ENCRYPTION_MAGIC.to_be_bytes());
+        let (start, end) = calculate_offsets!(ENCRYPTION_SALT_OFFSET, ENCRYPTION_SALT_SIZE);
+        cipher.header[start..end].clone_from_slice(&salt);
+        let check = cipher.seal(&ENCRYPTION_MAGIC.to_be_bytes(), &[])?;
+        let (start, end) = calculate_offsets!(ENCRYPTION_CHECK_OFFSET, ENCRYPTION_CHECK_SIZE);
+        cipher.header[start..end].clone_from_slice(&check);
+
+        Ok(cipher)
+    }
+
+    /// Derives the key of an existing file from `passphrase` and the `header` of the file; fails
+    /// if the passphrase is not the one the file was created with
+    pub fn unlock(passphrase: &str, header: &[u8]) -> Result<Self, String> {
+        if header.len() < ENCRYPTION_HEADER_SIZE || !is_encrypted(header) {
+            return Err("file is not encrypted".to_string());
+        }
+
+        let (start, end) = calculate_offsets!(ENCRYPTION_SALT_OFFSET, ENCRYPTION_SALT_SIZE);
+        let mut cipher = Self::derive(passphrase, &header[start..end]);
+        cipher
+            .header
+            .clone_from_slice(&header[..ENCRYPTION_HEADER_SIZE]);
+        let (start, end) = calculate_offsets!(ENCRYPTION_CHECK_OFFSET, ENCRYPTION_CHECK_SIZE);
+        match cipher.open(&header[start..end], &[]) {
+            Ok(check) if check == ENCRYPTION_MAGIC.to_be_bytes() => Ok(cipher),
+            _ => Err("invalid key".to_string()),
+        }
+    }
+
+    /// Returns the plaintext header identifying the file and holding the salt of the key
+    pub fn header(&self) -> &[u8; ENCRYPTION_HEADER_SIZE] {
+        &self.header
+    }
+
+    /// Encrypts page `num`; the result is [ENCRYPTED_PAGE_SIZE] bytes long
+    pub fn encrypt(&self, num: PageNum, page: &[u8; PAGE_SIZE]) -> Result<Vec<u8>, String> {
+        self.seal(page, &num.to_be_bytes())
+    }
+
+    /// Restores page `num` encrypted by [encrypt](PageCipher::encrypt)
+    pub fn decrypt(&self, num: PageNum, bytes: &[u8]) -> Result<[u8; PAGE_SIZE], String> {
+        if bytes.len() != ENCRYPTED_PAGE_SIZE {
+            return Err(format!("encrypted page {num} is truncated"));
+        }
+
+        self.open(bytes, &num.to_be_bytes())?
+            .try_into()
+            .map_err(|_| format!("encrypted page {num} has an invalid size"))
+    }
+
+    fn derive(passphrase: &str, salt: &[u8]) -> Self {
+        use aes_gcm::KeyInit;
+
+        let mut key = [0x0; 32];
+        pbkdf2::pbkdf2_hmac::<sha2::Sha256>(
+            passphrase.as_bytes(),
+            salt,
+            KEY_DERIVATION_ROUNDS,
+            &mut key,
+        );
+
+        Self {
+            cipher: aes_gcm::Aes256Gcm::new(&key.into()),
+            header: [0x0; ENCRYPTION_HEADER_SIZE],
+        }
+    }
+
+    /// Encrypts `content` with a new random nonce; returns the nonce followed by the ciphertext
+    /// and the tag
+    fn seal(&self, content: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+        use aes_gcm::{
+            aead::{Aead, AeadCore, OsRng, Payload},
+            Aes256Gcm,
+        };
+
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let sealed = self
+            .cipher
+            .encrypt(&nonce, Payload { msg: content, aad })
+            .map_err(|_| "failed to encrypt content".to_string())?;
+
+        Ok([nonce.as_slice(), &sealed].concat())
+    }
+
+    fn open(&self, bytes: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+        use aes_gcm::aead::{Aead, Payload};
+
+        let (nonce, sealed) = bytes.split_at(ENCRYPTION_NONCE_SIZE);
+        self.cipher
+            .decrypt(nonce.into(), Payload { msg: sealed, aad })
+            .map_err(|_| {
+                "failed to decrypt content; the key is invalid or the content is corrupt"
+                    .to_string()
+            })
+    }
+}
+
+/// Placeholder for builds without encryption support; can never be created
+#[cfg(not(feature = "encryption"))]
+#[derive(Clone)]
+pub struct PageCipher(std::convert::Infallible);
+
+#[cfg(not(feature = "encryption"))]
+impl PageCipher {
+    pub fn create(_passphrase: &str) -> Result<Self, String> {
+        Err("encryption support is not enabled".to_string())
+    }
+
+    pub fn unlock(_passphrase: &str, _header: &[u8]) -> Result<Self, String> {
+        Err("file is encrypted but encryption support is not enabled".to_string())
+    }
+
+    pub fn header(&self) -> &[u8; ENCRYPTION_HEADER_SIZE] {
+        match self.0 {}
+    }
+
+    pub fn encrypt(&self, _num: PageNum, _page: &[u8; PAGE_SIZE]) -> Result<Vec<u8>, String> {
+        match self.0 {}
+    }
+
+    pub fn decrypt(&self, _num: PageNum, _bytes: &[u8]) -> Result<[u8; PAGE_SIZE], String> {
+        match self.0 {}
+    }
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pages_are_bound_to_their_key_and_number() {
+        let cipher = PageCipher::create("secret").unwrap();
+        let header = *cipher.header();
+        assert!(is_encrypted(&header));
+
+        let mut page = [0x0; PAGE_SIZE];
+        page[..5].clone_from_slice(b"hello");
+        let bytes = cipher.encrypt(PageNum(3), &page).unwrap();
+        assert_eq!(bytes.len(), ENCRYPTED_PAGE_SIZE);
+        assert!(!bytes.windows(5).any(|w| w == b"hello"));
+        assert_eq!(cipher.decrypt(PageNum(3), &bytes).unwrap(), page);
+        assert!(cipher.decrypt(PageNum(4), &bytes).is_err());
+
+        let unlocked = PageCipher::unlock("secret", &header).unwrap();
+        assert_eq!(unlocked.decrypt(PageNum(3), &bytes).unwrap(), page);
+        assert_eq!(
+            PageCipher::unlock("guess", &header).err(),
+            Some("invalid key".to_string())
+        );
+    }
+}
@@ -5,8 +5,11 @@ use std::mem::size_of;
 pub const PAGE_SIZE: usize = 4096;
 
 // Page headers
-pub const PAGE_MAGIC: usize = 0xFEBA;
-pub const PAGE_MAGIC_SIZE: usize = size_of::<usize>();
+// Signature at the start of every page; the low bytes hold the page format version
+pub const PAGE_MAGIC: u64 = 0xB7EE_DB00_0000_0001;
+// Signature of pages written before the page format was versioned; still readable
+pub const LEGACY_PAGE_MAGIC: u64 = 0xFEBA;
+pub const PAGE_MAGIC_SIZE: usize = size_of::<u64>();
 pub const PAGE_MAGIC_OFFSET: usize = 0;
 
 pub const PAGE_TYPE_SIZE: usize = size_of::<u8>();
@@ -17,6 +20,11 @@ pub const PAGE_IS_ROOT_OFFSET: usize = PAGE_TYPE_OFFSET + PAGE_TYPE_SIZE;
 
 pub const PAGE_HEADERS_SIZE: usize = PAGE_MAGIC_SIZE + PAGE_TYPE_SIZE + PAGE_IS_ROOT_SIZE;
 
+// Keys of tree cells; tables with wide keys store 16-byte keys in place of 8-byte ones while every
+// other field of a cell keeps its size. The cell sizes below are the ones of 8-byte keys
+pub const KEY_SIZE: usize = size_of::<u64>();
+pub const WIDE_KEY_SIZE: usize = size_of::<u128>();
+
 // Internal node headers
 pub const INTERNAL_NUM_KEYS_SIZE: usize = size_of::<u64>();
 pub const INTERNAL_NUM_KEYS_OFFSET: usize = PAGE_HEADERS_SIZE;
@@ -29,15 +37,25 @@ pub const INTERNAL_HEADER_SIZE: usize =
     PAGE_HEADERS_SIZE + INTERNAL_NUM_KEYS_SIZE + INTERNAL_RIGHT_MOST_CHILD_SIZE;
 
 // Internal node body
-pub const INTERNAL_KEY_SIZE: usize = size_of::<usize>();
+pub const INTERNAL_KEY_SIZE: usize = KEY_SIZE;
 pub const INTERNAL_KEY_OFFSET: usize = 0;
 pub const INTERNAL_KEY_POINTER_SIZE: usize = size_of::<usize>();
 pub const INTERNAL_KEY_POINTER_OFFSET: usize = INTERNAL_KEY_OFFSET + INTERNAL_KEY_SIZE;
 
-pub const INTERNAL_CELL_SIZE: usize = INTERNAL_NUM_KEYS_SIZE + INTERNAL_KEY_POINTER_SIZE;
+pub const INTERNAL_CELL_SIZE: usize = internal_cell_size(INTERNAL_KEY_SIZE);
 
 pub const INTERNAL_SPACE_FOR_CELLS: usize = PAGE_SIZE - INTERNAL_HEADER_SIZE;
-pub const INTERNAL_MAX_KEYS: usize = INTERNAL_SPACE_FOR_CELLS / INTERNAL_CELL_SIZE;
+pub const INTERNAL_MAX_KEYS: usize = internal_max_keys(INTERNAL_KEY_SIZE);
+
+/// Size of a cell of an internal node with `key_size` byte keys
+pub const fn internal_cell_size(key_size: usize) -> usize {
+    key_size + INTERNAL_KEY_POINTER_SIZE
+}
+
+/// Number of cells that fit into an internal node with `key_size` byte keys
+pub const fn internal_max_keys(key_size: usize) -> usize {
+    INTERNAL_SPACE_FOR_CELLS / internal_cell_size(key_size)
+}
 
 // Leaf node headers
 pub const LEAF_OVERFLOW_POINTER_SIZE: usize = size_of::<u64>();
@@ -71,17 +89,123 @@ pub const LEAF_NEXT_SIBLING_POINTER_DEFAULT: u64 = u64::MAX;
 
 pub const LEAF_CELL_HAS_OVERFLOW_FLAG_SIZE: usize = size_of::<u8>();
 pub const LEAF_CELL_HAS_OVERFLOW_FLAG_OFFSET: usize = 0;
-pub const LEAF_KEY_IDENTIFIER_SIZE: usize = size_of::<u64>();
+// Bits of the flag byte; the overflow bit is stored inverted (`0` means the cell has overflow)
+pub const LEAF_CELL_OVERFLOW_BIT: u8 = 0x1;
+pub const LEAF_CELL_COMPRESSED_BIT: u8 = 0x2;
+pub const LEAF_CELL_DELETED_BIT: u8 = 0x4;
+pub const LEAF_KEY_IDENTIFIER_SIZE: usize = KEY_SIZE;
 pub const LEAF_KEY_INDENTIFIER_OFFSET: usize =
     LEAF_CELL_HAS_OVERFLOW_FLAG_OFFSET + LEAF_CELL_HAS_OVERFLOW_FLAG_SIZE;
 pub const LEAF_KEY_POINTER_SIZE: usize = size_of::<u64>();
 pub const LEAF_KEY_POINTER_OFFSET: usize = LEAF_KEY_INDENTIFIER_OFFSET + LEAF_KEY_IDENTIFIER_SIZE;
 
-pub const LEAF_KEY_CELL_SIZE: usize =
-    LEAF_CELL_HAS_OVERFLOW_FLAG_SIZE + LEAF_KEY_IDENTIFIER_SIZE + LEAF_KEY_POINTER_SIZE;
+pub const LEAF_KEY_CELL_SIZE: usize = leaf_key_cell_size(LEAF_KEY_IDENTIFIER_SIZE);
 
-pub const LEAF_CONTENT_LEN_SIZE: usize = size_of::<usize>();
-pub const LEAF_CONTENT_LEN_OFFSET: usize = 0;
-pub const LEAF_CONTENT_START_OFFSET: usize = LEAF_CONTENT_LEN_OFFSET + LEAF_CONTENT_LEN_SIZE;
+/// Size of a key cell of a leaf with `key_size` byte keys
+pub const fn leaf_key_cell_size(key_size: usize) -> usize {
+    LEAF_CELL_HAS_OVERFLOW_FLAG_SIZE + key_size + LEAF_KEY_POINTER_SIZE
+}
+
+// Leaf cell content; a LEB128 varint length followed by the content. Inline content is shorter
+// than 2^14 bytes, so the length takes up at most two bytes
+pub const LEAF_CONTENT_LEN_MAX_SIZE: usize = 2;
+// Files before format version 2 store the length as a fixed size integer instead
+pub const LEAF_LEGACY_CONTENT_LEN_SIZE: usize = size_of::<u64>();
 
 pub const LEAF_SPACE_FOR_DATA: usize = PAGE_SIZE - LEAF_HEADER_SIZE;
+
+/// Largest value that can be stored inline in an empty leaf; anything bigger has to overflow
+pub const LEAF_MAX_INLINE_CONTENT: usize = leaf_max_inline_content(LEAF_KEY_IDENTIFIER_SIZE);
+
+/// Largest value that can be stored inline in an empty leaf with `key_size` byte keys
+pub const fn leaf_max_inline_content(key_size: usize) -> usize {
+    LEAF_SPACE_FOR_DATA - leaf_key_cell_size(key_size) - LEAF_CONTENT_LEN_MAX_SIZE - 1
+}
+
+// Leaf cell overflow content; Stored inline in place of the value when a cell overflows
+pub const LEAF_OVERFLOW_LEN_SIZE: usize = size_of::<u64>();
+pub const LEAF_OVERFLOW_LEN_OFFSET: usize = 0;
+pub const LEAF_OVERFLOW_PAGE_SIZE: usize = size_of::<u64>();
+pub const LEAF_OVERFLOW_PAGE_OFFSET: usize = LEAF_OVERFLOW_LEN_OFFSET + LEAF_OVERFLOW_LEN_SIZE;
+pub const LEAF_OVERFLOW_CONTENT_SIZE: usize = LEAF_OVERFLOW_LEN_SIZE + LEAF_OVERFLOW_PAGE_SIZE;
+
+// Overflow page headers
+pub const OVERFLOW_NEXT_POINTER_SIZE: usize = size_of::<u64>();
+pub const OVERFLOW_NEXT_POINTER_OFFSET: usize = PAGE_HEADERS_SIZE;
+pub const OVERFLOW_NEXT_POINTER_DEFAULT: u64 = u64::MAX;
+
+pub const OVERFLOW_CONTENT_SIZE_SIZE: usize = size_of::<u64>();
+pub const OVERFLOW_CONTENT_SIZE_OFFSET: usize =
+    OVERFLOW_NEXT_POINTER_OFFSET + OVERFLOW_NEXT_POINTER_SIZE;
+
+pub const OVERFLOW_HEADER_SIZE: usize =
+    PAGE_HEADERS_SIZE + OVERFLOW_NEXT_POINTER_SIZE + OVERFLOW_CONTENT_SIZE_SIZE;
+
+// Overflow page body
+pub const OVERFLOW_SPACE_FOR_DATA: usize = PAGE_SIZE - OVERFLOW_HEADER_SIZE;
+
+// Catalog page headers
+pub const CATALOG_PAGE_NUM: u64 = 0;
+
+// Version of the on-disk format; files without a catalog page are version 0. Version 2 stores
+// leaf content lengths as varints; version 3 stores the schemas of tables in the catalog, version 4
+// the signed key bit in them and version 5 the wide key bit. Files of older versions are upgraded
+// when opened
+pub const FORMAT_VERSION: u64 = 5;
+pub const CATALOG_FORMAT_VERSION_SIZE: usize = size_of::<u64>();
+pub const CATALOG_FORMAT_VERSION_OFFSET: usize = PAGE_HEADERS_SIZE;
+
+pub const CATALOG_NUM_TABLES_SIZE: usize = size_of::<u64>();
+pub const CATALOG_NUM_TABLES_OFFSET: usize =
+    CATALOG_FORMAT_VERSION_OFFSET + CATALOG_FORMAT_VERSION_SIZE;
+
+pub const CATALOG_HEADER_SIZE: usize =
+    PAGE_HEADERS_SIZE + CATALOG_FORMAT_VERSION_SIZE + CATALOG_NUM_TABLES_SIZE;
+
+// Catalog page body
+pub const CATALOG_NAME_SIZE: usize = 32;
+pub const CATALOG_NAME_OFFSET: usize = 0;
+pub const CATALOG_ROOT_SIZE: usize = size_of::<u64>();
+pub const CATALOG_ROOT_OFFSET: usize = CATALOG_NAME_OFFSET + CATALOG_NAME_SIZE;
+
+pub const CATALOG_ENTRY_SIZE: usize = CATALOG_NAME_SIZE + CATALOG_ROOT_SIZE;
+
+// Number of pages allocated in the file; kept at the end of the page so catalogs written before it
+// existed keep their layout. `0` when it has not been stored yet
+pub const CATALOG_NUM_PAGES_SIZE: usize = size_of::<u64>();
+pub const CATALOG_NUM_PAGES_OFFSET: usize = PAGE_SIZE - CATALOG_NUM_PAGES_SIZE;
+
+// Value type of every table; one byte per entry stored in front of the page count. `0` for tables
+// without a schema
+pub const CATALOG_SCHEMA_SIZE: usize = size_of::<u8>();
+// Set in the schema byte of tables with signed keys
+pub const CATALOG_SIGNED_KEYS_BIT: u8 = 0x80;
+// Set in the schema byte of tables with 16-byte keys
+pub const CATALOG_WIDE_KEYS_BIT: u8 = 0x40;
+
+pub const CATALOG_MAX_TABLES: usize =
+    (CATALOG_NUM_PAGES_OFFSET - CATALOG_HEADER_SIZE) / (CATALOG_ENTRY_SIZE + CATALOG_SCHEMA_SIZE);
+pub const CATALOG_SCHEMAS_OFFSET: usize =
+    CATALOG_NUM_PAGES_OFFSET - CATALOG_MAX_TABLES * CATALOG_SCHEMA_SIZE;
+
+// Encrypted files; a plaintext header followed by the pages. Every page is stored with the nonce
+// it was encrypted with in front of it and the authentication tag after it
+pub const ENCRYPTION_MAGIC: u64 = 0xB7EE_DB00_E4C0_0001;
+pub const ENCRYPTION_MAGIC_SIZE: usize = size_of::<u64>();
+pub const ENCRYPTION_MAGIC_OFFSET: usize = 0;
+
+pub const ENCRYPTION_SALT_SIZE: usize = 16;
+pub const ENCRYPTION_SALT_OFFSET: usize = ENCRYPTION_MAGIC_OFFSET + ENCRYPTION_MAGIC_SIZE;
+
+pub const ENCRYPTION_NONCE_SIZE: usize = 12;
+pub const ENCRYPTION_TAG_SIZE: usize = 16;
+
+// The magic encrypted with the key of the file; used to check the passphrase when opening it
+pub const ENCRYPTION_CHECK_SIZE: usize =
+    ENCRYPTION_NONCE_SIZE + ENCRYPTION_MAGIC_SIZE + ENCRYPTION_TAG_SIZE;
+pub const ENCRYPTION_CHECK_OFFSET: usize = ENCRYPTION_SALT_OFFSET + ENCRYPTION_SALT_SIZE;
+
+pub const ENCRYPTION_HEADER_SIZE: usize =
+    ENCRYPTION_MAGIC_SIZE + ENCRYPTION_SALT_SIZE + ENCRYPTION_CHECK_SIZE;
+
+pub const ENCRYPTED_PAGE_SIZE: usize = ENCRYPTION_NONCE_SIZE + PAGE_SIZE + ENCRYPTION_TAG_SIZE;
@@ -1,18 +1,40 @@
+pub mod bloom;
 pub mod btree;
+pub mod catalog;
 pub mod cell;
+pub mod compression;
 pub mod cursor;
+pub mod dump;
+pub mod encryption;
 pub(crate) mod layout;
+pub mod oplog;
+pub mod overflow;
 pub(crate) mod page;
 pub mod pager;
+#[cfg(feature = "raw-pages")]
+pub mod raw;
+pub mod schema;
 pub mod statement;
 pub mod table;
+pub mod validate;
+pub(crate) mod varint;
 
-pub use cursor::Cursor;
+pub use btree::{KeyComparator, NodeResult};
+pub use cursor::{Cursor, KeyBucket, Plan, Records};
+pub use layout::PAGE_SIZE;
+pub use oplog::Operation;
+pub use page::PageNum;
+pub use pager::PagerMetrics;
+pub use schema::{KeyType, ValueType};
+pub use statement::OutputFormat;
 use std::error::Error;
-pub use table::Table;
+pub use table::{FlushPolicy, Table, DEFAULT_TABLE};
+pub use validate::{validate, validate_parallel, ValidationReport};
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
+/// Record level operations on a store; errors of the underlying nodes, such as
+/// [NodeResult](NodeResult), are returned as boxed errors
 pub trait StorageEngine {
     /// Inserts a new record
     ///
@@ -20,7 +42,7 @@ pub trait StorageEngine {
     ///
     /// - `identifier`: Unique identifier for the record.
     /// - `value`: Byte array of data to store.
-    fn insert(&mut self, identifier: u64, value: Vec<u8>) -> Result<()>;
+    fn insert(&mut self, identifier: u128, value: Vec<u8>) -> Result<()>;
 
     /// Updates an existing record
     ///
@@ -28,19 +50,19 @@ pub trait StorageEngine {
     ///
     /// - `identifier`: Unique identifier for the record.
     /// - `value`: Updated byte array of data to store.
-    fn update(&mut self, identifier: u64, value: Vec<u8>) -> Result<()>;
+    fn update(&mut self, identifier: u128, value: Vec<u8>) -> Result<()>;
 
     /// Removes an existing record
     ///
     /// # Params
     ///
     /// - `identifier`: Unique identifier for the record.
-    fn remove(&mut self, identifier: u64) -> Result<()>;
+    fn remove(&mut self, identifier: u128) -> Result<()>;
 
     /// Retrieves an existing record
     ///
     /// # Params
     ///
     /// - `identifier`: Unique identifier for the record.
-    fn get(self, identifier: u64) -> Result<Vec<u8>>;
+    fn get(&mut self, identifier: u128) -> Result<Vec<u8>>;
 }
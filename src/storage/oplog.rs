@@ -0,0 +1,224 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Read, Write},
+    mem::size_of,
+    path::Path,
+};
+
+use super::layout::{KEY_SIZE, WIDE_KEY_SIZE};
+
+/// Header identifying an operation log and the version of its record format; version 2 stores
+/// 16-byte keys
+const OPLOG_HEADER: &[u8] = b"btree-db oplog 2\n";
+/// Header of logs written with 8-byte keys; they can still be read and appended to
+const OPLOG_HEADER_V1: &[u8] = b"btree-db oplog 1\n";
+
+const OP_INSERT: u8 = 0x1;
+const OP_DELETE: u8 = 0x2;
+
+/// Mutation recorded in an operation log
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    /// A record was inserted
+    Insert(u128, Vec<u8>),
+    /// All records with identifiers in the inclusive range were removed
+    Delete(u128, u128),
+}
+
+/// Append-only log of the mutations applied to a table.
+///
+/// Records are a single op type byte followed by big-endian fields: the key and a `u64` value
+/// length followed by the value bytes for inserts, the lower and upper bound for deletes. Keys
+/// take up 16 bytes, or 8 bytes in logs of the first version.
+pub struct OpLog {
+    file: File,
+    key_size: usize,
+}
+
+impl OpLog {
+    /// Opens the log at `path` for appending; creating it if it doesn't exist yet
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("failed to open operation log; {e}"))?;
+
+        let len = file.metadata().map_err(|e| e.to_string())?.len();
+        let key_size = if len == 0 {
+            file.write_all(OPLOG_HEADER).map_err(|e| e.to_string())?;
+            WIDE_KEY_SIZE
+        } else {
+            read_header(&mut file)?
+        };
+
+        Ok(Self { file, key_size })
+    }
+
+    /// Appends `op` to the log
+    pub fn append(&mut self, op: &Operation) -> Result<(), String> {
+        let mut record = Vec::new();
+        match op {
+            Operation::Insert(key, value) => {
+                record.push(OP_INSERT);
+                record.append(&mut self.key_bytes(*key)?);
+                record.extend_from_slice(&(value.len() as u64).to_be_bytes());
+                record.extend_from_slice(value);
+            }
+            Operation::Delete(lo, hi) => {
+                record.push(OP_DELETE);
+                record.append(&mut self.key_bytes(*lo)?);
+                // Logs with 8-byte keys can't hold larger bounds; no record lies past them anyway
+                let hi = match self.key_size {
+                    KEY_SIZE => (*hi).min(u64::MAX.into()),
+                    _ => *hi,
+                };
+                record.append(&mut self.key_bytes(hi)?);
+            }
+        }
+
+        // Records are written in one go so a failed write never leaves half a record behind
+        // another one
+        self.file
+            .write_all(&record)
+            .map_err(|e| format!("failed to append to operation log; {e}"))
+    }
+
+    /// Returns whether `key` fits into the keys of the log
+    pub fn holds_key(&self, key: u128) -> bool {
+        self.key_size != KEY_SIZE || key <= u64::MAX.into()
+    }
+
+    /// Encodes `key` with the key size of the log
+    fn key_bytes(&self, key: u128) -> Result<Vec<u8>, String> {
+        match self.key_size {
+            KEY_SIZE => u64::try_from(key)
+                .map(|key| key.to_be_bytes().to_vec())
+                .map_err(|_| format!("key {key} does not fit into the operation log")),
+            _ => Ok(key.to_be_bytes().to_vec()),
+        }
+    }
+}
+
+/// Reads the header of a log; returns the size of the keys in its records
+fn read_header<R: Read>(input: &mut R) -> Result<usize, String> {
+    let mut header = [0x0; OPLOG_HEADER.len()];
+    input
+        .read_exact(&mut header)
+        .map_err(|_| "not an operation log".to_string())?;
+    match &header[..] {
+        OPLOG_HEADER => Ok(WIDE_KEY_SIZE),
+        OPLOG_HEADER_V1 => Ok(KEY_SIZE),
+        _ => Err("not an operation log".to_string()),
+    }
+}
+
+/// Reads every operation stored in a log created by [OpLog](OpLog)
+pub fn read<R: Read>(mut input: R) -> Result<Vec<Operation>, String> {
+    let key_size = read_header(&mut input)?;
+
+    let mut ops = Vec::new();
+    loop {
+        let mut kind = [0x0; 1];
+        match input.read_exact(&mut kind) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.to_string()),
+        }
+
+        let n = ops.len();
+        let first = read_field(&mut input, n, key_size)?;
+        let op = match kind[0] {
+            OP_INSERT => {
+                let len = read_field(&mut input, n, size_of::<u64>())?;
+                let mut value = vec![0x0; len as usize];
+                input
+                    .read_exact(&mut value)
+                    .map_err(|_| format!("operation {n} is truncated"))?;
+                Operation::Insert(first, value)
+            }
+            OP_DELETE => Operation::Delete(first, read_field(&mut input, n, key_size)?),
+            kind => return Err(format!("operation {n} has unknown type {kind}")),
+        };
+        ops.push(op);
+    }
+
+    Ok(ops)
+}
+
+/// Reads a big-endian field of `size` bytes of operation `n`
+fn read_field<R: Read>(input: &mut R, n: usize, size: usize) -> Result<u128, String> {
+    let mut bytes = [0x0; WIDE_KEY_SIZE];
+    input
+        .read_exact(&mut bytes[WIDE_KEY_SIZE - size..])
+        .map_err(|_| format!("operation {n} is truncated"))?;
+    Ok(u128::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use assert_fs::NamedTempFile;
+
+    use super::*;
+
+    #[test]
+    fn log_round_trip() {
+        let file = NamedTempFile::new("temp.log").unwrap();
+        let ops = vec![
+            Operation::Insert(1, b"one".to_vec()),
+            Operation::Insert(2, Vec::new()),
+            Operation::Delete(0, 1),
+        ];
+
+        let mut log = OpLog::open(file.path()).unwrap();
+        ops.iter().for_each(|op| log.append(op).unwrap());
+        drop(log);
+        // Reopening must not write a second header
+        OpLog::open(file.path()).unwrap();
+
+        let logged = read(File::open(file.path()).unwrap()).unwrap();
+        assert_eq!(logged, ops);
+    }
+
+    #[test]
+    fn read_rejects_invalid_logs() {
+        assert!(read(&b"key\tvalue\n"[..]).is_err());
+
+        let mut log = OPLOG_HEADER.to_vec();
+        log.push(OP_INSERT);
+        log.extend_from_slice(&1u128.to_be_bytes());
+        assert_eq!(
+            read(log.as_slice()),
+            Err("operation 0 is truncated".to_string())
+        );
+    }
+
+    #[test]
+    fn appends_to_logs_with_narrow_keys() {
+        let file = NamedTempFile::new("temp.log").unwrap();
+        let mut log = OPLOG_HEADER_V1.to_vec();
+        log.push(OP_DELETE);
+        log.extend_from_slice(&1u64.to_be_bytes());
+        log.extend_from_slice(&2u64.to_be_bytes());
+        std::fs::write(file.path(), log).unwrap();
+
+        let mut log = OpLog::open(file.path()).unwrap();
+        log.append(&Operation::Insert(3, b"three".to_vec()))
+            .unwrap();
+        log.append(&Operation::Delete(0, u128::MAX)).unwrap();
+        assert!(!log.holds_key(1 << 64));
+        assert!(log.append(&Operation::Insert(1 << 64, Vec::new())).is_err());
+        drop(log);
+
+        let logged = read(File::open(file.path()).unwrap()).unwrap();
+        assert_eq!(
+            logged,
+            vec![
+                Operation::Delete(1, 2),
+                Operation::Insert(3, b"three".to_vec()),
+                Operation::Delete(0, u64::MAX.into()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use crate::calculate_offsets;
+
+use super::{
+    layout::{
+        OVERFLOW_CONTENT_SIZE_OFFSET, OVERFLOW_CONTENT_SIZE_SIZE, OVERFLOW_HEADER_SIZE,
+        OVERFLOW_NEXT_POINTER_DEFAULT, OVERFLOW_NEXT_POINTER_OFFSET, OVERFLOW_NEXT_POINTER_SIZE,
+        OVERFLOW_SPACE_FOR_DATA, PAGE_TYPE_OFFSET,
+    },
+    page::{PageNum, PageType},
+    table::Table,
+};
+
+/// Writes `content` into a newly allocated chain of overflow pages.
+///
+/// Returns the page number of the first page in the chain. Fails if a page of the chain can't be
+/// allocated; the pages allocated before it are left behind.
+pub fn write_chain(table: &mut Table, content: &[u8]) -> std::io::Result<PageNum> {
+    let chunks = content
+        .chunks(OVERFLOW_SPACE_FOR_DATA)
+        .collect::<Vec<&[u8]>>();
+    let pages = chunks
+        .iter()
+        .map(|_| table.create_page(&PageType::Overflow))
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        let next = pages
+            .get(i + 1)
+            .map(|page| page.num)
+            .unwrap_or(PageNum(OVERFLOW_NEXT_POINTER_DEFAULT));
+        let page = Arc::clone(&pages[i].page.0);
+        let mut handle = page.write().expect("failed to retrieve write lock on page");
+
+        let (start, end) =
+            calculate_offsets!(OVERFLOW_NEXT_POINTER_OFFSET, OVERFLOW_NEXT_POINTER_SIZE);
+        handle[start..end].clone_from_slice(&next.to_be_bytes());
+
+        let (start, end) =
+            calculate_offsets!(OVERFLOW_CONTENT_SIZE_OFFSET, OVERFLOW_CONTENT_SIZE_SIZE);
+        handle[start..end].clone_from_slice(&(chunk.len() as u64).to_be_bytes());
+
+        let size = chunk.len();
+        let (start, end) = calculate_offsets!(OVERFLOW_HEADER_SIZE, size);
+        handle[start..end].clone_from_slice(chunk);
+    }
+
+    Ok(pages
+        .first()
+        .map(|page| page.num)
+        .unwrap_or(PageNum(OVERFLOW_NEXT_POINTER_DEFAULT)))
+}
+
+/// Reads `len` bytes from the overflow chain starting at page `first`.
+pub fn read_chain(table: &mut Table, first: PageNum, len: usize) -> Result<Vec<u8>, String> {
+    let mut content = Vec::with_capacity(len);
+    let mut next = first;
+
+    while content.len() < len {
+        if next == PageNum(OVERFLOW_NEXT_POINTER_DEFAULT) {
+            return Err(format!(
+                "overflow chain ended after {} of {len} bytes",
+                content.len()
+            ));
+        }
+
+        let page = table
+            .get_page(next)
+            .ok_or(format!("overflow page {next} does not exist"))?;
+        let handle = page.0.read().expect("failed to retrieve read lock on page");
+        if PageType::try_from(handle[PAGE_TYPE_OFFSET])? != PageType::Overflow {
+            return Err(format!("page {next} is not an overflow page"));
+        }
+
+        let (start, end) =
+            calculate_offsets!(OVERFLOW_NEXT_POINTER_OFFSET, OVERFLOW_NEXT_POINTER_SIZE);
+        next = PageNum::from_be_bytes(handle[start..end].try_into().unwrap());
+
+        let (start, end) =
+            calculate_offsets!(OVERFLOW_CONTENT_SIZE_OFFSET, OVERFLOW_CONTENT_SIZE_SIZE);
+        let size = u64::from_be_bytes(handle[start..end].try_into().unwrap()) as usize;
+        let size = size.min(OVERFLOW_SPACE_FOR_DATA);
+
+        let (start, end) = calculate_offsets!(OVERFLOW_HEADER_SIZE, size);
+        content.extend_from_slice(&handle[start..end]);
+    }
+
+    content.truncate(len);
+    Ok(content)
+}
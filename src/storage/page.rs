@@ -3,14 +3,41 @@ use std::sync::{Arc, RwLock};
 use crate::calculate_offsets;
 
 use super::layout::{
+    CATALOG_FORMAT_VERSION_OFFSET, CATALOG_FORMAT_VERSION_SIZE, FORMAT_VERSION,
     LEAF_FREE_SPACE_END_OFFSET, LEAF_FREE_SPACE_END_SIZE, LEAF_FREE_SPACE_START_OFFSET,
     LEAF_FREE_SPACE_START_SIZE, LEAF_HEADER_SIZE, LEAF_NEXT_SIBLING_POINTER_DEFAULT,
     LEAF_NEXT_SIBLING_POINTER_OFFSET, LEAF_NEXT_SIBLING_POINTER_SIZE,
     LEAF_OVERFLOW_POINTER_DEFAULT, LEAF_OVERFLOW_POINTER_OFFSET, LEAF_OVERFLOW_POINTER_SIZE,
-    PAGE_IS_ROOT_OFFSET, PAGE_IS_ROOT_SIZE, PAGE_MAGIC, PAGE_MAGIC_OFFSET, PAGE_MAGIC_SIZE,
-    PAGE_SIZE, PAGE_TYPE_OFFSET, PAGE_TYPE_SIZE,
+    LEGACY_PAGE_MAGIC, OVERFLOW_NEXT_POINTER_DEFAULT, OVERFLOW_NEXT_POINTER_OFFSET,
+    OVERFLOW_NEXT_POINTER_SIZE, PAGE_IS_ROOT_OFFSET, PAGE_IS_ROOT_SIZE, PAGE_MAGIC,
+    PAGE_MAGIC_OFFSET, PAGE_MAGIC_SIZE, PAGE_SIZE, PAGE_TYPE_OFFSET, PAGE_TYPE_SIZE,
 };
 
+/// Number of a page; the position of the page in the file in units of pages.
+///
+/// Page numbers are never record keys; keeping them apart lets the compiler catch a key passed
+/// where a page reference is expected and the other way around.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PageNum(pub u64);
+
+impl PageNum {
+    /// Returns the on-disk representation of the page number
+    pub fn to_be_bytes(self) -> [u8; 8] {
+        self.0.to_be_bytes()
+    }
+
+    /// Reads a page number stored by [to_be_bytes](PageNum::to_be_bytes)
+    pub fn from_be_bytes(bytes: [u8; 8]) -> Self {
+        Self(u64::from_be_bytes(bytes))
+    }
+}
+
+impl std::fmt::Display for PageNum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 /// On-disk structure for storing and organizing records
 #[derive(Debug, Clone)]
 pub struct Page(pub [u8; PAGE_SIZE]);
@@ -47,21 +74,27 @@ where
 
 /// Type of page.
 ///
-/// A page can be one of two types:
+/// A page can be one of four types:
 ///
 /// - `Internal`: An internal node within the B+-Tree structure. It acts as an index for the B+-Tree
 /// - `Leaf`: An external node within the B+-Tree structure. These pages store the actual data
+/// - `Overflow`: A page outside of the B+-Tree structure that stores values too large for a leaf
+/// - `Catalog`: The first page of a file; maps table names to the root page of their B+-Tree
 #[derive(Debug, Clone, PartialEq)]
 pub enum PageType {
     Internal,
     Leaf,
+    Overflow,
+    Catalog,
 }
 
-impl Into<u8> for &PageType {
-    fn into(self) -> u8 {
-        match self {
+impl From<&PageType> for u8 {
+    fn from(value: &PageType) -> Self {
+        match value {
             PageType::Leaf => 0xA,
             PageType::Internal => 0xB,
+            PageType::Overflow => 0xC,
+            PageType::Catalog => 0xD,
         }
     }
 }
@@ -73,6 +106,8 @@ impl TryFrom<u8> for PageType {
         match value {
             0xA => Ok(PageType::Leaf),
             0xB => Ok(PageType::Internal),
+            0xC => Ok(PageType::Overflow),
+            0xD => Ok(PageType::Catalog),
             v => Err(format!("unknown type: {:#x}", v)),
         }
     }
@@ -87,20 +122,11 @@ pub struct PageBuilder {
 
 impl PageBuilder {
     pub fn content(mut self, c: [u8; PAGE_SIZE]) -> Result<Self, String> {
-        let (start, end) = calculate_offsets!(PAGE_MAGIC_OFFSET, PAGE_MAGIC_SIZE);
-        let magic = usize::from_be_bytes(
-            c[start..end]
-                .try_into()
-                .expect("failed to read page magic data"),
-        );
+        check_magic(&c)?;
 
-        if magic != PAGE_MAGIC {
-            Err("content is not a valid page".to_string())
-        } else {
-            self.inner = c;
-            self.content_set = true;
-            Ok(self)
-        }
+        self.inner = c;
+        self.content_set = true;
+        Ok(self)
     }
 
     pub fn kind(mut self, _type: &PageType) -> Self {
@@ -111,6 +137,7 @@ impl PageBuilder {
         self
     }
 
+    #[allow(clippy::wrong_self_convention)]
     pub fn is_root(mut self, is_root: bool) -> Self {
         let (start, end) = calculate_offsets!(PAGE_IS_ROOT_OFFSET, PAGE_IS_ROOT_SIZE);
 
@@ -137,10 +164,29 @@ impl PageBuilder {
             );
             self.inner[start..end]
                 .clone_from_slice(&LEAF_NEXT_SIBLING_POINTER_DEFAULT.to_be_bytes());
+        }
 
+        if self._type == PageType::Leaf {
+            // Content copied from elsewhere may never have had its overflow pointer written; page
+            // 0 is never an overflow page, so a zeroed pointer can only mean there is none
             let (start, end) =
                 calculate_offsets!(LEAF_OVERFLOW_POINTER_OFFSET, LEAF_OVERFLOW_POINTER_SIZE);
-            self.inner[start..end].clone_from_slice(&LEAF_OVERFLOW_POINTER_DEFAULT.to_be_bytes());
+            if !self.content_set || self.inner[start..end] == [0x0; LEAF_OVERFLOW_POINTER_SIZE] {
+                self.inner[start..end]
+                    .clone_from_slice(&LEAF_OVERFLOW_POINTER_DEFAULT.to_be_bytes());
+            }
+        }
+
+        if self._type == PageType::Overflow && !self.content_set {
+            let (start, end) =
+                calculate_offsets!(OVERFLOW_NEXT_POINTER_OFFSET, OVERFLOW_NEXT_POINTER_SIZE);
+            self.inner[start..end].clone_from_slice(&OVERFLOW_NEXT_POINTER_DEFAULT.to_be_bytes());
+        }
+
+        if self._type == PageType::Catalog && !self.content_set {
+            let (start, end) =
+                calculate_offsets!(CATALOG_FORMAT_VERSION_OFFSET, CATALOG_FORMAT_VERSION_SIZE);
+            self.inner[start..end].clone_from_slice(&FORMAT_VERSION.to_be_bytes());
         }
 
         Page(self.inner)
@@ -149,15 +195,31 @@ impl PageBuilder {
 
 impl Default for PageBuilder {
     fn default() -> Self {
-        let builder = PageBuilder {
+        PageBuilder {
             inner: [0x0; PAGE_SIZE],
             _type: PageType::Leaf,
             content_set: false,
         }
         .kind(&PageType::Internal)
-        .is_root(false);
+        .is_root(false)
+    }
+}
 
-        builder
+/// Checks that `page` starts with the page magic; pages written with the legacy magic are
+/// accepted as well
+pub fn check_magic(page: &[u8]) -> Result<(), String> {
+    let (start, end) = calculate_offsets!(PAGE_MAGIC_OFFSET, PAGE_MAGIC_SIZE);
+    let magic = u64::from_be_bytes(
+        page[start..end]
+            .try_into()
+            .expect("failed to read page magic data"),
+    );
+
+    match magic {
+        PAGE_MAGIC | LEGACY_PAGE_MAGIC => Ok(()),
+        magic => Err(format!(
+            "content is not a valid page; unknown magic {magic:#x}"
+        )),
     }
 }
 
@@ -178,3 +240,70 @@ pub fn u8_to_bool(v: u8) -> Result<bool, String> {
         _ => Err("value is not a boolean".to_string()),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn page_types_round_trip() {
+        for kind in [
+            PageType::Leaf,
+            PageType::Internal,
+            PageType::Overflow,
+            PageType::Catalog,
+        ] {
+            assert_eq!(PageType::try_from(u8::from(&kind)), Ok(kind));
+        }
+
+        let byte: u8 = (&PageType::Leaf).into();
+        assert_eq!(byte, 0xA);
+        assert_eq!(
+            PageType::try_from(0xFF),
+            Err("unknown type: 0xff".to_string())
+        );
+    }
+
+    #[test]
+    fn leaves_built_from_content_have_no_overflow() {
+        use crate::storage::btree::Node;
+
+        // A leaf whose header was only partly written; the overflow pointer is still zeroed
+        let mut content = [0x0; PAGE_SIZE];
+        let (start, end) = calculate_offsets!(PAGE_MAGIC_OFFSET, PAGE_MAGIC_SIZE);
+        content[start..end].clone_from_slice(&PAGE_MAGIC.to_be_bytes());
+        let page = PageBuilder::default()
+            .content(content)
+            .unwrap()
+            .kind(&PageType::Leaf)
+            .build();
+        let node = Node::load(CachedPage::new(page.clone())).unwrap();
+        assert_eq!(node.overflow_pointer(), None);
+
+        // Copies keep whatever pointer the original page stores
+        let (start, end) =
+            calculate_offsets!(LEAF_OVERFLOW_POINTER_OFFSET, LEAF_OVERFLOW_POINTER_SIZE);
+        let mut content = page.0;
+        content[start..end].clone_from_slice(&7u64.to_be_bytes());
+        let copy = PageBuilder::default()
+            .content(content)
+            .unwrap()
+            .kind(&PageType::Leaf)
+            .build();
+        let node = Node::load(CachedPage::new(copy)).unwrap();
+        assert_eq!(node.overflow_pointer(), Some(PageNum(7)));
+
+        let page = PageBuilder::default().kind(&PageType::Leaf).build();
+        let node = Node::load(CachedPage::new(page)).unwrap();
+        assert_eq!(node.overflow_pointer(), None);
+    }
+
+    #[test]
+    fn page_nums_round_trip() {
+        let num = PageNum(7);
+        assert_eq!(num.to_be_bytes(), 7u64.to_be_bytes());
+        assert_eq!(PageNum::from_be_bytes(num.to_be_bytes()), num);
+        assert_eq!(num.to_string(), "7");
+        assert!(PageNum(1) < PageNum(2));
+    }
+}
@@ -1,155 +1,995 @@
 use std::{
+    borrow::Cow,
     collections::HashMap,
     fs::{File, OpenOptions},
     io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
-    path::PathBuf,
-    sync::Arc,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
 };
 
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+use tracing::{instrument, warn};
+
 use crate::storage::{layout::PAGE_SIZE, page::PageBuilder};
 
 use super::{
-    layout::{PAGE_TYPE_OFFSET, PAGE_TYPE_SIZE},
-    page::{CachedPage, Page, PageType},
+    encryption::{self, PageCipher},
+    layout::{
+        ENCRYPTED_PAGE_SIZE, ENCRYPTION_HEADER_SIZE, ENCRYPTION_MAGIC_SIZE, PAGE_TYPE_OFFSET,
+        PAGE_TYPE_SIZE,
+    },
+    page::{CachedPage, Page, PageNum, PageType},
 };
 
+/// Counters describing how the pager cache is used
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PagerMetrics {
+    /// Requested pages that were already cached
+    pub cache_hits: u64,
+    /// Requested pages that were not cached
+    pub cache_misses: u64,
+    /// Pages read from disk
+    pub pages_read: u64,
+    /// Pages written to disk
+    pub pages_written: u64,
+}
+
+impl PagerMetrics {
+    /// Returns the fraction of page requests served from the cache
+    pub fn hit_ratio(&self) -> f64 {
+        match self.cache_hits + self.cache_misses {
+            0 => 0.0,
+            total => self.cache_hits as f64 / total as f64,
+        }
+    }
+}
+
+/// A page appended by [Pager::new_page](Pager::new_page)
+#[derive(Debug, Clone)]
+pub struct NewPage {
+    /// Page number of the new page
+    pub num: PageNum,
+    pub page: CachedPage,
+}
+
+/// Result of [Pager::new_root](Pager::new_root); the root keeps its page number while its old
+/// contents move to a new page
+#[derive(Debug, Clone)]
+pub struct RootSplit {
+    /// Page number the old root contents were moved to; not the page number of the root
+    pub old_root_num: PageNum,
+    /// The page holding the old root contents
+    pub page: CachedPage,
+}
+
+/// Pages a pager has handed over to a snapshot; their contents from when the snapshot was taken
+type SharedPages = Mutex<HashMap<PageNum, CachedPage>>;
+
+/// A snapshot taken from a pager
+struct SnapshotHandle {
+    pages: Weak<SharedPages>,
+    // Number of pages when the snapshot was taken; pages allocated later are never needed
+    num_pages: u64,
+}
+
+/// Where a snapshot finds the pages it has not requested yet
+struct SnapshotSource {
+    // Pages handed over by the pager the snapshot was taken from, followed by the pages handed
+    // over to that pager if it is a snapshot itself
+    pages: Vec<Arc<SharedPages>>,
+    // File of the first pager of the chain; pages that were never handed over are unchanged in it
+    file: Option<File>,
+}
+
 pub struct Pager {
     num_pages: u64,
-    root_page: u64,
-    cache: HashMap<u64, CachedPage>,
-    out: File,
+    metrics: PagerMetrics,
+    // Cached pages along with the version they were cached in
+    cache: HashMap<PageNum, (CachedPage, u64)>,
+    // Bumped whenever a snapshot is taken; cached pages of older versions may be shared with a
+    // snapshot and are copied before they are handed out again
+    version: u64,
+    snapshots: Vec<SnapshotHandle>,
+    // Set for snapshots; `None` for pagers opened on a file or created in memory
+    source: Option<SnapshotSource>,
+    // On-disk file backing the pager; `None` when pages only live in memory
+    out: Option<File>,
+    // Read-only map of `out`; pages are copied out of it instead of being read from the file
+    #[cfg(feature = "mmap")]
+    map: Option<Mmap>,
+    // Held while changes spanning multiple pages are made so they are never flushed halfway
+    commit_lock: Arc<Mutex<()>>,
+    // Whether the on-disk file is encrypted; pages of encrypted files can only be read and written
+    // with a cipher
+    encrypted: bool,
+    cipher: Option<PageCipher>,
 }
 
 impl Pager {
+    /// Opens the on-disk file at `path`.
+    ///
+    /// Encrypted files are left untouched; the pager is [locked](Pager::is_locked) and has no
+    /// pages.
     pub fn new(path: PathBuf) -> Self {
-        let out = OpenOptions::new()
+        let mut out = Self::open_file(path);
+        let mut magic = [0x0; ENCRYPTION_MAGIC_SIZE];
+        let is_encrypted = out.read_exact(&mut magic).is_ok() && encryption::is_encrypted(&magic);
+        if is_encrypted {
+            let mut pager = Self::init(0, Some(out));
+            pager.encrypted = true;
+            return pager;
+        }
+
+        Self::with_file(out, None)
+    }
+
+    /// Opens the on-disk file at `path` whose pages are encrypted with a key derived from
+    /// `passphrase`; empty files are set up for encryption.
+    ///
+    /// Only the header identifying the file and holding the salt of the key is stored in
+    /// plaintext.
+    pub fn new_encrypted(path: PathBuf, passphrase: &str) -> Result<Self, String> {
+        let mut out = Self::open_file(path);
+        let file_len = out
+            .metadata()
+            .map_err(|e| format!("failed to retrieve file metadata; {e}"))?
+            .len();
+
+        let cipher = if file_len == 0 {
+            let cipher = PageCipher::create(passphrase)?;
+            out.write_all(cipher.header())
+                .map_err(|e| format!("failed to write encryption header; {e}"))?;
+            cipher
+        } else {
+            let mut header = [0x0; ENCRYPTION_HEADER_SIZE];
+            out.read_exact(&mut header)
+                .map_err(|_| "file is not encrypted".to_string())?;
+            PageCipher::unlock(passphrase, &header)?
+        };
+
+        Ok(Self::with_file(out, Some(cipher)))
+    }
+
+    fn open_file(path: PathBuf) -> File {
+        OpenOptions::new()
             .create(true)
+            .truncate(false)
             .read(true)
             .write(true)
             .open(path)
-            .expect("failed to open pager on-disk file");
+            .expect("failed to open pager on-disk file")
+    }
+
+    fn with_file(out: File, cipher: Option<PageCipher>) -> Self {
+        let encrypted = cipher.is_some();
+        let (start, page_size) = Self::page_layout(encrypted);
         let file_len = out
             .metadata()
             .expect("failed to retrieve pager on-disk metadata")
             .len();
-        let num_pages = file_len / PAGE_SIZE as u64;
+        let pages_len = file_len.saturating_sub(start);
+        let num_pages = pages_len / page_size;
 
-        let mut obj = Self {
+        // A partially written trailing page can't be read back; drop it so the file only holds
+        // whole pages
+        if !pages_len.is_multiple_of(page_size) {
+            warn!(
+                "file length {} is not a multiple of the page size; truncating to {} pages",
+                file_len, num_pages
+            );
+            out.set_len(start + num_pages * page_size)
+                .expect("failed to truncate partial page");
+        }
+
+        let mut pager = Self::init(num_pages, Some(out));
+        pager.encrypted = encrypted;
+        pager.cipher = cipher;
+        pager
+    }
+
+    /// Creates a pager that keeps all pages in memory; nothing is ever written to disk
+    pub fn in_memory() -> Self {
+        Self::init(0, None)
+    }
+
+    fn init(num_pages: u64, out: Option<File>) -> Self {
+        Self {
             num_pages,
-            root_page: 0,
+            metrics: PagerMetrics::default(),
             cache: HashMap::new(),
+            version: 0,
+            snapshots: Vec::new(),
+            source: None,
             out,
-        };
+            #[cfg(feature = "mmap")]
+            map: None,
+            commit_lock: Arc::default(),
+            encrypted: false,
+            cipher: None,
+        }
+    }
 
-        if num_pages == 0 {
-            let (root_page, _) = obj.new_page(PageType::Leaf, true);
-            obj.root_page = root_page;
+    /// Returns the offset of the first page and the size of every page in the on-disk file
+    fn page_layout(encrypted: bool) -> (u64, u64) {
+        if encrypted {
+            (ENCRYPTION_HEADER_SIZE as u64, ENCRYPTED_PAGE_SIZE as u64)
+        } else {
+            (0, PAGE_SIZE as u64)
         }
+    }
 
-        obj
+    /// Returns the offset of page `num` in the on-disk file
+    fn page_offset(&self, num: PageNum) -> Option<u64> {
+        let (start, page_size) = Self::page_layout(self.encrypted);
+        num.0.checked_mul(page_size)?.checked_add(start)
     }
 
-    fn file_len(&self) -> u64 {
-        self.out
-            .metadata()
-            .expect("failed to retrieve pager on-disk metadata")
-            .len()
+    /// Returns the range of bytes page `num` occupies in the on-disk file.
+    ///
+    /// Fails if the end of the page does not fit in a file offset.
+    fn page_bounds(&self, num: PageNum) -> Result<(u64, u64), String> {
+        let (_, page_size) = Self::page_layout(self.encrypted);
+        self.page_offset(num)
+            .and_then(|offset| Some((offset, offset.checked_add(page_size)?)))
+            .ok_or(format!("page {num} is out of range"))
+    }
+
+    /// Returns the bytes of page `num` as they are stored on disk
+    fn encode_page<'a>(
+        cipher: Option<&PageCipher>,
+        num: PageNum,
+        page: &'a [u8; PAGE_SIZE],
+    ) -> std::io::Result<Cow<'a, [u8]>> {
+        match cipher {
+            Some(cipher) => cipher
+                .encrypt(num, page)
+                .map(Cow::Owned)
+                .map_err(std::io::Error::other),
+            None => Ok(Cow::Borrowed(page)),
+        }
+    }
+
+    /// Returns whether the on-disk file is encrypted
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// Returns whether the on-disk file is encrypted and the pager has no key to read it
+    pub fn is_locked(&self) -> bool {
+        self.encrypted && self.cipher.is_none()
     }
 
-    fn read_page(&self, offset: u64) -> [u8; PAGE_SIZE] {
-        let mut buf: [u8; PAGE_SIZE] = [0; PAGE_SIZE];
-        let mut reader = BufReader::new(&self.out);
+    /// Returns the lock to hold while changing several pages that have to reach the disk together.
+    ///
+    /// [flush_cache](Pager::flush_cache) waits for the lock before writing any page.
+    pub fn commit_lock(&self) -> Arc<Mutex<()>> {
+        Arc::clone(&self.commit_lock)
+    }
+
+    /// Returns whether the pager is backed by an on-disk file
+    pub fn is_in_memory(&self) -> bool {
+        self.out.is_none()
+    }
+
+    /// Returns the number of pages allocated; including pages that have not been flushed yet
+    pub fn num_pages(&self) -> u64 {
+        self.num_pages
+    }
+
+    /// Overrides the number of allocated pages derived from the file length; new pages are
+    /// allocated after the first `num_pages` pages
+    pub fn set_num_pages(&mut self, num_pages: u64) {
+        self.num_pages = num_pages;
+    }
+
+    /// Returns the cache usage counters collected since the pager was opened
+    pub fn metrics(&self) -> PagerMetrics {
+        self.metrics
+    }
+
+    /// Returns the size of the on-disk file in bytes
+    pub fn file_len(&self) -> u64 {
+        self.out.as_ref().map_or(0, |out| {
+            out.metadata()
+                .expect("failed to retrieve pager on-disk metadata")
+                .len()
+        })
+    }
+
+    /// Reads pages through a read-only memory map of the on-disk file instead of reading them
+    /// from the file one at a time.
+    ///
+    /// Writes still go through the file; the map is refreshed after every flush. The file must
+    /// not be modified by anything else while it is mapped.
+    #[cfg(feature = "mmap")]
+    pub fn set_mmap(&mut self, enabled: bool) -> Result<(), String> {
+        self.map = None;
+        if enabled {
+            let out = self
+                .out
+                .as_ref()
+                .ok_or("in-memory pagers can not be mapped".to_string())?;
+            // SAFETY: `Mmap::map` is unsafe because the mapped bytes change, or disappear, when the
+            // file is modified while the map is alive. Within this process every change goes
+            // through `out` from methods that take `&mut self`, and `read_page` copies a page out
+            // of the map before returning; no slice of the map is alive while the file changes.
+            // Writes show up in the map since it shares the file's pages, pages past its end are
+            // read from the file, and `truncate`/`release_pages` remap after shrinking the file.
+            // Changes made by other processes are not covered; the file must not be shared while
+            // it is mapped, which is why mapping is opt-in.
+            let map = unsafe { Mmap::map(out) }.map_err(|e| format!("failed to map file; {e}"))?;
+            self.map = Some(map);
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether pages are read through a memory map
+    pub fn is_mapped(&self) -> bool {
+        #[cfg(feature = "mmap")]
+        return self.map.is_some();
+        #[cfg(not(feature = "mmap"))]
+        false
+    }
+
+    /// Reads page `num` stored between `offset` and `end`; `None` if the page can't be decrypted
+    fn read_page(&self, num: PageNum, (offset, end): (u64, u64)) -> Option<[u8; PAGE_SIZE]> {
+        let mut buf = vec![0; (end - offset) as usize];
+
+        #[cfg(feature = "mmap")]
+        let mapped = self
+            .map
+            .as_ref()
+            .and_then(|map| map.get(offset as usize..end as usize));
+        #[cfg(not(feature = "mmap"))]
+        let mapped: Option<&[u8]> = None;
 
-        reader
-            .seek(SeekFrom::Start(offset))
-            .expect("failed to read at offset");
+        match mapped {
+            Some(bytes) => buf.clone_from_slice(bytes),
+            None => {
+                let out = self
+                    .out
+                    .as_ref()
+                    .or(self.source.as_ref().and_then(|s| s.file.as_ref()))
+                    .expect("in-memory pagers do not have on-disk pages");
+                let mut reader = BufReader::new(out);
 
-        reader
-            .read_exact(&mut buf)
-            .expect("failed to read page data");
+                reader
+                    .seek(SeekFrom::Start(offset))
+                    .expect("failed to read at offset");
 
-        buf
+                reader
+                    .read_exact(&mut buf)
+                    .expect("failed to read page data");
+            }
+        }
+
+        if !self.encrypted {
+            return buf.try_into().ok();
+        }
+        match self.cipher.as_ref()?.decrypt(num, &buf) {
+            Ok(page) => Some(page),
+            Err(e) => {
+                warn!("failed to read page {num}; {e}");
+                None
+            }
+        }
     }
 
-    fn cache_page(&mut self, index: u64, page: Page) -> CachedPage {
+    fn cache_page(&mut self, index: PageNum, page: Page) -> CachedPage {
         let cached_page = CachedPage::new(page);
-        let copy = CachedPage(Arc::clone(&cached_page.0));
-        self.cache.insert(index, cached_page);
+        let copy = cached_page.clone();
+        self.cache.insert(index, (cached_page, self.version));
         copy
     }
 
-    pub fn root_page(&self) -> u64 {
-        self.root_page
+    pub fn new_page(&mut self, kind: PageType, is_root: bool) -> std::io::Result<NewPage> {
+        let builder = PageBuilder::default().kind(&kind).is_root(is_root);
+        self.allocate_page(builder.build())
     }
 
-    pub fn new_page(&mut self, kind: PageType, is_root: bool) -> (u64, CachedPage) {
-        let builder = PageBuilder::default().kind(&kind).is_root(is_root);
+    /// Appends `page` to the pager; returns it along with its page number.
+    ///
+    /// The page is written to disk right away so the file covers every allocated page; the page
+    /// can be read back even if it is dropped from the cache before the next flush. Nothing is
+    /// allocated if the write fails.
+    fn allocate_page(&mut self, page: Page) -> std::io::Result<NewPage> {
+        let num = PageNum(self.num_pages);
+
+        if let Some(mut out) = self.out.as_ref() {
+            let offset = self
+                .page_offset(num)
+                .ok_or(std::io::Error::other(format!("page {num} is out of range")))?;
+            let bytes = Self::encode_page(self.cipher.as_ref(), num, &page.0)?;
+            out.seek(SeekFrom::Start(offset))?;
+            out.write_all(&bytes)?;
+            self.metrics.pages_written += 1;
+        }
 
-        let num = self.num_pages;
         self.num_pages += 1;
-        (num, self.cache_page(num, builder.build()))
+        Ok(NewPage {
+            num,
+            page: self.cache_page(num, page),
+        })
     }
 
-    /// Creates a new root internal node and returns the old roots new page number
+    /// Moves the contents of page `root` to a new page and replaces them with the page returned by
+    /// `build`; returns the page the old root contents were moved to.
     ///
-    /// NOTE: The caller is responsible for recreating any links required in order to have a valid
-    /// B+ Tree
-    pub fn new_root(&mut self) -> (u64, CachedPage) {
-        let root_arc = self.get_page(self.root_page).unwrap().0;
-        let mut root_handle = root_arc.write().unwrap();
+    /// `build` is handed the new page number of the old root so the new root can be completely
+    /// linked before it replaces the old one; the root page is swapped in a single write. The root
+    /// keeps its page number so anything referencing the tree stays valid.
+    ///
+    /// Fails without allocating anything if page `root` does not exist or is not a tree page.
+    pub fn new_root(
+        &mut self,
+        root: PageNum,
+        build: impl FnOnce(PageNum) -> Page,
+    ) -> Result<RootSplit, String> {
+        let root_arc = self
+            .get_page(root)
+            .ok_or(format!("root page {root} does not exist"))?
+            .0;
+        let mut root_handle = root_arc
+            .write()
+            .map_err(|_| format!("failed to lock root page {root}"))?;
         let kind: PageType = root_handle[PAGE_TYPE_OFFSET..PAGE_TYPE_OFFSET + PAGE_TYPE_SIZE][0]
             .try_into()
-            .unwrap();
-
-        let new_root = PageBuilder::default()
-            .is_root(true)
-            .kind(&PageType::Internal)
-            .build();
+            .map_err(|e| format!("root page {root}: {e}"))?;
+        if !matches!(kind, PageType::Leaf | PageType::Internal) {
+            return Err(format!("root page {root} is not a tree page"));
+        }
 
-        let num = self.num_pages;
-        self.num_pages += 1;
         let left_node = PageBuilder::default()
-            .content(root_handle[..].try_into().unwrap())
-            .unwrap()
+            .content(root_handle.0)
+            .map_err(|e| format!("root page {root}: {e}"))?
             .is_root(false)
             .kind(&kind)
             .build();
+        let NewPage { num, page } = self
+            .allocate_page(left_node)
+            .map_err(|e| format!("failed to write page; {e}"))?;
 
+        let new_root = build(num);
         root_handle[..].clone_from_slice(&new_root[..]);
-        (num, self.cache_page(num, left_node))
+        Ok(RootSplit {
+            old_root_num: num,
+            page,
+        })
+    }
+
+    pub fn get_page(&mut self, num: PageNum) -> Option<CachedPage> {
+        if self.cache.contains_key(&num) {
+            self.metrics.cache_hits += 1;
+        } else {
+            self.metrics.cache_misses += 1;
+        }
+
+        self.load_page(num)
+    }
+
+    /// Reads page `num` into the cache ahead of it being requested.
+    ///
+    /// Prefetching isn't counted as a cache hit or miss.
+    pub fn prefetch(&mut self, num: PageNum) -> Option<CachedPage> {
+        self.load_page(num)
+    }
+
+    /// Returns whether page `num` is in the cache
+    pub fn is_cached(&self, num: PageNum) -> bool {
+        self.cache.contains_key(&num)
+    }
+
+    fn load_page(&mut self, num: PageNum) -> Option<CachedPage> {
+        if let Some((cached_page, version)) = self.cache.get(&num) {
+            if *version == self.version {
+                return Some(cached_page.clone());
+            }
+
+            // Cached before the last snapshot was taken; a page the snapshot still shares is
+            // copied so changes to it don't reach the snapshot
+            let cached_page = cached_page.clone();
+            if Arc::strong_count(&cached_page.0) == 1 {
+                self.cache.insert(num, (cached_page.clone(), self.version));
+                return Some(cached_page);
+            }
+            let page = cached_page
+                .0
+                .read()
+                .expect("failed to retrieve read handle on page")
+                .clone();
+            return Some(self.cache_page(num, page));
+        }
+
+        let page = match self.source.is_some() {
+            true => self.read_snapshot_page(num)?,
+            false => Page(self.read_file_page(num)?),
+        };
+        self.hand_over(num, &page);
+        Some(self.cache_page(num, page))
     }
 
-    pub fn get_page(&mut self, num: u64) -> Option<CachedPage> {
-        let offset = num * PAGE_SIZE as u64;
-        if offset > self.file_len() {
+    /// Reads page `num` from the on-disk file; the page has to be fully present on disk
+    fn read_file_page(&mut self, num: PageNum) -> Option<[u8; PAGE_SIZE]> {
+        let bounds = match self.page_bounds(num) {
+            Ok(bounds) => bounds,
+            Err(e) => {
+                warn!("failed to load page; {e}");
+                return None;
+            }
+        };
+        if self.is_in_memory() || bounds.1 > self.file_len() {
             return None;
         }
 
-        if let Some(cached_page) = self.cache.get(&num) {
-            Some(CachedPage(Arc::clone(&cached_page.0)))
-        } else {
-            let page = Page(self.read_page(offset));
-            Some(self.cache_page(num, page))
+        let page = self.read_page(num, bounds)?;
+        self.metrics.pages_read += 1;
+        Some(page)
+    }
+
+    /// Reads page `num` of a snapshot as it was when the snapshot was taken
+    fn read_snapshot_page(&self, num: PageNum) -> Option<Page> {
+        let source = self.source.as_ref()?;
+        // Pages are handed over before they change on disk; holding the locks until the page is
+        // read keeps a page from being handed over and written in between
+        let handed_over = source
+            .pages
+            .iter()
+            .map(|pages| pages.lock().expect("failed to lock snapshot pages"))
+            .collect::<Vec<_>>();
+        if let Some(page) = handed_over.iter().find_map(|pages| pages.get(&num)) {
+            return Some(
+                page.0
+                    .read()
+                    .expect("failed to retrieve read handle on page")
+                    .clone(),
+            );
+        }
+
+        let file = source.file.as_ref()?;
+        let bounds = self.page_bounds(num).ok()?;
+        let file_len = file.metadata().map(|m| m.len()).unwrap_or(0);
+        if bounds.1 > file_len {
+            return None;
+        }
+        self.read_page(num, bounds).map(Page)
+    }
+
+    /// Hands `page`, the contents of page `num` before this pager changes it, over to every
+    /// snapshot that does not have the page yet
+    fn hand_over(&mut self, num: PageNum, page: &Page) {
+        self.snapshots.retain(|s| s.pages.strong_count() > 0);
+        for snapshot in self.snapshots.iter().filter(|s| num.0 < s.num_pages) {
+            if let Some(pages) = snapshot.pages.upgrade() {
+                pages
+                    .lock()
+                    .expect("failed to lock snapshot pages")
+                    .entry(num)
+                    .or_insert_with(|| CachedPage::new(page.clone()));
+            }
+        }
+    }
+
+    /// Hands the on-disk pages past the first `num_pages` pages over to the snapshots that need
+    /// them before the file is shrunk
+    fn hand_over_tail(&mut self, num_pages: u64) {
+        self.snapshots.retain(|s| s.pages.strong_count() > 0);
+        let needed = self.snapshots.iter().map(|s| s.num_pages).max();
+        let end = needed.unwrap_or(0).min(self.num_pages);
+        // Cached pages were handed over when they were cached
+        for num in (num_pages..end).map(PageNum) {
+            if self.cache.contains_key(&num) {
+                continue;
+            }
+            if let Some(page) = self.read_file_page(num) {
+                self.hand_over(num, &Page(page));
+            }
         }
     }
 
-    pub fn flush_cache(&mut self) {
-        let mut writer = BufWriter::new(&self.out);
+    /// Returns an in-memory pager that keeps the pages as they are now.
+    ///
+    /// The snapshot is taken while holding the [commit lock](Pager::commit_lock), so it never
+    /// contains half of a split. Later changes to this pager are not visible in the snapshot and
+    /// the other way around.
+    ///
+    /// Nothing is copied up front; cached pages are shared and every other page is read from the
+    /// file when the snapshot requests it. The version of the pager is bumped, so either side
+    /// copies a shared page the first time it requests it. Pages this pager reads afterwards are
+    /// handed over to the snapshot before they can change. Pages handed out before the snapshot
+    /// was taken have to be requested again before they are changed.
+    pub fn snapshot(&mut self) -> Result<Pager, String> {
+        let commit_lock = Arc::clone(&self.commit_lock);
+        let _guard = commit_lock.lock().expect("failed to acquire commit lock");
+
+        let shared = self
+            .cache
+            .iter()
+            .map(|(num, (page, _))| (*num, page.clone()))
+            .collect::<HashMap<_, _>>();
+        let shared = Arc::new(Mutex::new(shared));
+        let (mut pages, file) = match self.source.as_ref() {
+            Some(source) => (source.pages.clone(), source.file.as_ref()),
+            None => (Vec::new(), self.out.as_ref()),
+        };
+        pages.insert(0, Arc::clone(&shared));
+        let file = file
+            .map(File::try_clone)
+            .transpose()
+            .map_err(|e| format!("failed to open file for snapshot; {e}"))?;
+
+        self.version += 1;
+        self.snapshots.retain(|s| s.pages.strong_count() > 0);
+        self.snapshots.push(SnapshotHandle {
+            pages: Arc::downgrade(&shared),
+            num_pages: self.num_pages,
+        });
+
+        let mut snapshot = Pager::init(self.num_pages, None);
+        snapshot.encrypted = self.encrypted;
+        snapshot.cipher = self.cipher.clone();
+        snapshot.source = Some(SnapshotSource { pages, file });
+        Ok(snapshot)
+    }
+
+    /// Copies every page to a new file at `path`; returns the number of pages copied.
+    ///
+    /// The copy is taken while holding the [commit lock](Pager::commit_lock) and includes changes
+    /// that have not been flushed yet, so it is consistent even while the pager is in use. Pages of
+    /// encrypted files are copied with the same key. `path` must not exist yet.
+    pub fn backup_to(&mut self, path: &Path) -> Result<u64, String> {
+        if self.is_locked() {
+            return Err("file is encrypted; a key is required to back it up".to_string());
+        }
+        let commit_lock = Arc::clone(&self.commit_lock);
+        let _guard = commit_lock.lock().expect("failed to acquire commit lock");
 
-        for (page_num, page) in self.cache.iter() {
-            let offset = page_num * PAGE_SIZE as u64;
-            writer
-                .seek(SeekFrom::Start(offset))
-                .expect("failed to flush cached pages");
+        let out = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|e| format!("failed to create {}; {e}", path.display()))?;
+        let mut writer = BufWriter::new(&out);
+        let write_err = |e: std::io::Error| format!("failed to write backup; {e}");
 
+        if let Some(cipher) = self.cipher.as_ref() {
+            writer.write_all(cipher.header()).map_err(write_err)?;
+        }
+        for num in (0..self.num_pages).map(PageNum) {
+            let page = self
+                .load_page(num)
+                .ok_or(format!("page {num} can not be read"))?;
             let bytes = page
                 .0
                 .read()
                 .expect("failed to retrieve read handle on page")
                 .0;
-            writer
-                .write_all(&bytes)
-                .expect("failed to write updated page content");
+            let bytes = Self::encode_page(self.cipher.as_ref(), num, &bytes).map_err(write_err)?;
+            writer.write_all(&bytes).map_err(write_err)?;
+        }
+        writer.flush().map_err(write_err)?;
+        drop(writer);
+        out.sync_all().map_err(write_err)?;
+
+        Ok(self.num_pages)
+    }
+
+    /// Opens the file at `path`, written by [backup_to](Pager::backup_to), with the key of this
+    /// pager
+    pub fn open_backup(&self, path: &Path) -> Result<Self, String> {
+        let out = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| format!("failed to open {}; {e}", path.display()))?;
+
+        Ok(Self::with_file(out, self.cipher.clone()))
+    }
+
+    /// Drops every page past the first `num_pages` pages and shrinks the on-disk file to fit.
+    ///
+    /// The cache is flushed and emptied first, so pages handed out before the truncation no
+    /// longer reach the file; they have to be requested again. In-memory pagers only drop the
+    /// truncated pages since the cache is all they have.
+    pub fn truncate(&mut self, num_pages: u64) -> Result<(), String> {
+        if self.is_locked() {
+            return Err("file is encrypted; a key is required to change it".to_string());
+        }
+        if self.is_in_memory() {
+            self.cache.retain(|num, _| num.0 < num_pages);
+            self.num_pages = num_pages;
+            return Ok(());
+        }
+
+        self.hand_over_tail(num_pages);
+        self.flush_cache()
+            .map_err(|e| format!("failed to flush pages; {e}"))?;
+        let commit_lock = Arc::clone(&self.commit_lock);
+        let _guard = commit_lock.lock().expect("failed to acquire commit lock");
+
+        let len = self
+            .page_offset(PageNum(num_pages))
+            .ok_or("page offset is out of range".to_string())?;
+        if let Some(out) = self.out.as_ref() {
+            out.set_len(len)
+                .map_err(|e| format!("failed to truncate file; {e}"))?;
+        }
+        self.cache.clear();
+        self.num_pages = num_pages;
+
+        #[cfg(feature = "mmap")]
+        if self.is_mapped() {
+            self.set_mmap(true)?;
+        }
+
+        Ok(())
+    }
+
+    /// Drops the pages allocated after the first `num_pages` pages; nothing may reference them.
+    ///
+    /// Unlike [truncate](Pager::truncate) the rest of the cache is left alone, so it can undo
+    /// allocations that turned out to be unused halfway through a change.
+    pub fn release_pages(&mut self, num_pages: u64) -> Result<(), String> {
+        if num_pages >= self.num_pages {
+            return Ok(());
+        }
+
+        self.hand_over_tail(num_pages);
+        self.cache.retain(|num, _| num.0 < num_pages);
+        self.num_pages = num_pages;
+        let len = self
+            .page_offset(PageNum(num_pages))
+            .ok_or("page offset is out of range".to_string())?;
+        if let Some(out) = self.out.as_ref().filter(|_| self.file_len() > len) {
+            out.set_len(len)
+                .map_err(|e| format!("failed to truncate file; {e}"))?;
+        }
+
+        #[cfg(feature = "mmap")]
+        if self.is_mapped() {
+            self.set_mmap(true)?;
         }
+
+        Ok(())
+    }
+
+    /// Writes every cached page to the on-disk file; in-memory pagers have nothing to write.
+    ///
+    /// Pages are never dropped from the cache, so a failed flush can simply be retried.
+    #[instrument(level = "debug", skip(self), fields(pages = self.cache.len()))]
+    pub fn flush_cache(&mut self) -> std::io::Result<()> {
+        let Some(out) = self.out.as_ref() else {
+            return Ok(());
+        };
+        let commit_lock = Arc::clone(&self.commit_lock);
+        let _guard = commit_lock.lock().expect("failed to acquire commit lock");
+        let mut writer = BufWriter::new(out);
+
+        for (page_num, (page, _)) in self.cache.iter() {
+            let offset = self
+                .page_offset(*page_num)
+                .ok_or(std::io::Error::other(format!(
+                    "page {page_num} is out of range"
+                )))?;
+            writer.seek(SeekFrom::Start(offset))?;
+
+            let bytes = page
+                .0
+                .read()
+                .expect("failed to retrieve read handle on page")
+                .0;
+            writer.write_all(&Self::encode_page(self.cipher.as_ref(), *page_num, &bytes)?)?;
+            self.metrics.pages_written += 1;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        #[cfg(feature = "mmap")]
+        if self.is_mapped() {
+            self.set_mmap(true).map_err(std::io::Error::other)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_fs::NamedTempFile;
+
+    use super::*;
+
+    #[test]
+    fn truncates_partial_page() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        std::fs::write(file.path(), vec![0x0; PAGE_SIZE + 10]).unwrap();
+
+        let pager = Pager::new(file.path().to_path_buf());
+        assert_eq!(pager.num_pages(), 1);
+        assert_eq!(pager.file_len(), PAGE_SIZE as u64);
+    }
+
+    #[test]
+    fn counts_cache_hits() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        std::fs::write(file.path(), PageBuilder::default().build().0).unwrap();
+
+        let mut pager = Pager::new(file.path().to_path_buf());
+        pager.get_page(PageNum(0)).unwrap();
+        pager.get_page(PageNum(0)).unwrap();
+
+        let metrics = pager.metrics();
+        assert_eq!(metrics.cache_misses, 1);
+        assert_eq!(metrics.cache_hits, 1);
+        assert_eq!(metrics.pages_read, 1);
+        assert_eq!(metrics.hit_ratio(), 0.5);
+
+        pager.flush_cache().unwrap();
+        assert_eq!(pager.metrics().pages_written, 1);
+    }
+
+    #[test]
+    fn failed_flushes_return_errors() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut pager = Pager::new(file.path().to_path_buf());
+        pager.new_page(PageType::Leaf, true).unwrap();
+
+        // Writes through a read-only handle fail like writes to a full disk would
+        pager.out = Some(File::open(file.path()).unwrap());
+        assert!(pager.flush_cache().is_err());
+        assert!(pager.is_cached(PageNum(0)));
+    }
+
+    #[test]
+    fn failed_allocations_return_errors() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut pager = Pager::new(file.path().to_path_buf());
+        pager.new_page(PageType::Leaf, true).unwrap();
+
+        pager.out = Some(File::open(file.path()).unwrap());
+        assert!(pager.new_page(PageType::Leaf, false).is_err());
+        assert_eq!(pager.num_pages(), 1);
+        assert!(!pager.is_cached(PageNum(1)));
+    }
+
+    #[test]
+    fn missing_pages_are_not_read() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        std::fs::write(file.path(), PageBuilder::default().build().0).unwrap();
+
+        let mut pager = Pager::new(file.path().to_path_buf());
+        assert!(pager.get_page(PageNum(0)).is_some());
+        assert!(pager.get_page(PageNum(1)).is_none());
+        assert!(pager.get_page(PageNum(42)).is_none());
+        assert!(pager.get_page(PageNum(u64::MAX)).is_none());
+        // The page starts at a valid offset but ends past the largest one
+        let last = PageNum(u64::MAX / PAGE_SIZE as u64);
+        assert!(pager.get_page(last).is_none());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mapped_reads_match_file_reads() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let pages = (0..4u8)
+            .flat_map(|i| {
+                let mut page = PageBuilder::default().build();
+                page.0[PAGE_SIZE - 1] = i;
+                page.0
+            })
+            .collect::<Vec<u8>>();
+        std::fs::write(file.path(), pages).unwrap();
+
+        let mut pager = Pager::new(file.path().to_path_buf());
+        pager.set_mmap(true).unwrap();
+        assert!(pager.is_mapped());
+        for i in 0..4 {
+            let page = pager.get_page(PageNum(i)).unwrap();
+            assert_eq!(page.0.read().unwrap().0[PAGE_SIZE - 1], i as u8);
+        }
+        assert!(pager.get_page(PageNum(4)).is_none());
+
+        // Pages written after mapping are readable once flushed
+        pager.new_page(PageType::Leaf, false).unwrap();
+        pager.flush_cache().unwrap();
+        pager.cache.clear();
+        assert!(pager.get_page(PageNum(4)).is_some());
+    }
+
+    #[test]
+    fn new_pages_are_readable_before_a_flush() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut pager = Pager::new(file.path().to_path_buf());
+        let first = pager.new_page(PageType::Leaf, true).unwrap().num;
+        let second = pager.new_page(PageType::Overflow, false).unwrap().num;
+        assert_eq!(pager.file_len(), 2 * PAGE_SIZE as u64);
+
+        // A pager without the cached copies has to find the pages on disk
+        let mut reopened = Pager::new(file.path().to_path_buf());
+        assert_eq!(reopened.num_pages(), 2);
+        for (num, kind) in [(first, PageType::Leaf), (second, PageType::Overflow)] {
+            let page = reopened
+                .get_page(num)
+                .expect("allocated page should be on disk");
+            let kind_byte = page.0.read().unwrap()[PAGE_TYPE_OFFSET];
+            assert_eq!(PageType::try_from(kind_byte), Ok(kind));
+        }
+    }
+
+    #[test]
+    fn flush_waits_for_commits() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut pager = Pager::new(file.path().to_path_buf());
+        let first = pager.new_page(PageType::Leaf, false).unwrap().page;
+        let second = pager.new_page(PageType::Leaf, false).unwrap().page;
+
+        let commit_lock = pager.commit_lock();
+        let (started, wait) = std::sync::mpsc::channel();
+        let writer = std::thread::spawn(move || {
+            let _guard = commit_lock.lock().unwrap();
+            started.send(()).unwrap();
+
+            first.0.write().unwrap().0[PAGE_SIZE - 1] = 0x1;
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            second.0.write().unwrap().0[PAGE_SIZE - 1] = 0x2;
+        });
+
+        // Flushing while the pages are changed has to write both changes or neither
+        wait.recv().unwrap();
+        pager.flush_cache().unwrap();
+        writer.join().unwrap();
+
+        let bytes = std::fs::read(file.path()).unwrap();
+        assert_eq!(bytes[PAGE_SIZE - 1], 0x1);
+        assert_eq!(bytes[2 * PAGE_SIZE - 1], 0x2);
+    }
+
+    #[test]
+    fn snapshots_copy_pages_once_they_change() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let pages = (0..4u8)
+            .flat_map(|i| {
+                let mut page = PageBuilder::default().build();
+                page.0[PAGE_SIZE - 1] = i;
+                page.0
+            })
+            .collect::<Vec<u8>>();
+        std::fs::write(file.path(), pages).unwrap();
+        let last_byte = |page: CachedPage| page.0.read().unwrap().0[PAGE_SIZE - 1];
+
+        let mut pager = Pager::new(file.path().to_path_buf());
+        pager.get_page(PageNum(0)).unwrap();
+        let mut snapshot = pager.snapshot().unwrap();
+        // Nothing is copied or read up front
+        assert!(!snapshot.is_cached(PageNum(0)));
+        assert_eq!(snapshot.metrics().pages_read, 0);
+
+        // A shared page, a page read after the snapshot and a page the snapshot already read
+        assert_eq!(last_byte(snapshot.get_page(PageNum(2)).unwrap()), 2);
+        for num in 0..3 {
+            let page = pager.get_page(PageNum(num)).unwrap();
+            page.0.write().unwrap().0[PAGE_SIZE - 1] = 0xFF;
+        }
+        pager.flush_cache().unwrap();
+        for num in 0..4 {
+            assert_eq!(
+                last_byte(snapshot.get_page(PageNum(num)).unwrap()),
+                num as u8
+            );
+        }
+
+        // Pages dropped from the file are handed over first
+        drop(snapshot);
+        let mut second = pager.snapshot().unwrap();
+        pager.truncate(1).unwrap();
+        pager.new_page(PageType::Leaf, false).unwrap();
+        pager.flush_cache().unwrap();
+        assert_eq!(last_byte(second.get_page(PageNum(1)).unwrap()), 0xFF);
+        assert_eq!(last_byte(second.get_page(PageNum(3)).unwrap()), 3);
+
+        // Changes to a snapshot stay in the snapshot
+        second.get_page(PageNum(0)).unwrap().0.write().unwrap().0[PAGE_SIZE - 1] = 0x42;
+        assert_eq!(last_byte(pager.get_page(PageNum(0)).unwrap()), 0xFF);
     }
 }
@@ -0,0 +1,73 @@
+//! Direct access to the pages of a database file for inspection and repair tools.
+//!
+//! Nothing written through this module is checked; a page with the wrong contents can leave the
+//! tree unreadable. Prefer the [Cursor](super::Cursor) and [Table] methods for anything else.
+//! Only built with the `raw-pages` feature.
+use super::{layout::PAGE_SIZE, page::PageNum, table::Table};
+
+impl Table {
+    /// Returns a copy of the contents of page `num`; `None` if the page does not exist.
+    ///
+    /// Changes that haven't been flushed yet are included.
+    pub fn read_raw_page(&mut self, num: PageNum) -> Option<[u8; PAGE_SIZE]> {
+        let page = self.get_page(num)?;
+        let handle = page.0.read().expect("failed to retrieve read lock on page");
+        Some(handle.0)
+    }
+
+    /// Replaces the contents of page `num` with `bytes`.
+    ///
+    /// The page is changed in the cache and written to disk by the next flush like any other
+    /// change. Only existing pages can be written. Bloom filters are rebuilt; the schema and key
+    /// type of the current table are only reloaded when it is opened again.
+    pub fn write_raw_page(&mut self, num: PageNum, bytes: &[u8; PAGE_SIZE]) -> Result<(), String> {
+        let page = self
+            .get_page(num)
+            .ok_or(format!("page {num} does not exist"))?;
+        page.0
+            .write()
+            .expect("failed to retrieve write lock on page")
+            .0
+            .clone_from_slice(bytes);
+        self.record_mutation();
+
+        if self.has_bloom_filters() {
+            self.set_bloom_filters(true)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_fs::NamedTempFile;
+
+    use super::*;
+    use crate::storage::table::DEFAULT_TABLE;
+
+    #[test]
+    fn raw_writes_are_flushed() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().into()).unwrap();
+        table.put(1, b"hello").unwrap();
+        table.put(2, b"world").unwrap();
+
+        let root = table.root;
+        let mut page = table.read_raw_page(root).unwrap();
+        let at = page.windows(5).position(|w| w == b"hello").unwrap();
+        page[at] = b'j';
+        table.write_raw_page(root, &page).unwrap();
+        assert_eq!(table.read_raw_page(root), Some(page));
+        assert_eq!(table.get_bytes(1), Some(b"jello".to_vec()));
+
+        let missing = PageNum(table.page_count());
+        assert_eq!(table.read_raw_page(missing), None);
+        assert!(table.write_raw_page(missing, &page).is_err());
+        table.flush().unwrap();
+
+        let mut table = Table::new(file.path().into()).unwrap();
+        table.open(DEFAULT_TABLE).unwrap();
+        assert_eq!(table.get_bytes(1), Some(b"jello".to_vec()));
+        assert_eq!(table.get_bytes(2), Some(b"world".to_vec()));
+    }
+}
@@ -0,0 +1,213 @@
+use std::{fmt::Display, str::FromStr};
+
+use super::layout::{KEY_SIZE, WIDE_KEY_SIZE};
+
+/// Type of the values stored in a table with a schema
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    /// UTF-8 text
+    Text,
+    /// Signed 64-bit integer; stored as 8 big-endian bytes
+    Int,
+    /// Arbitrary bytes
+    Blob,
+}
+
+impl ValueType {
+    /// Validates `value` and encodes it into the bytes to store
+    pub fn encode(&self, value: &str) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Text | Self::Blob => Ok(value.as_bytes().to_vec()),
+            Self::Int => value
+                .trim()
+                .parse::<i64>()
+                .map(|n| n.to_be_bytes().to_vec())
+                .map_err(|_| format!("`{value}` is not an int")),
+        }
+    }
+
+    /// Returns whether `bytes` are a stored value of this type
+    pub fn accepts(&self, bytes: &[u8]) -> bool {
+        match self {
+            Self::Text => std::str::from_utf8(bytes).is_ok(),
+            Self::Int => bytes.len() == size_of::<i64>(),
+            Self::Blob => true,
+        }
+    }
+
+    /// Turns stored bytes back into the value that was inserted
+    pub fn decode(&self, bytes: &[u8]) -> Vec<u8> {
+        match (self, <[u8; 8]>::try_from(bytes)) {
+            (Self::Int, Ok(n)) => i64::from_be_bytes(n).to_string().into_bytes(),
+            _ => bytes.to_vec(),
+        }
+    }
+}
+
+/// Bit flipped in signed keys; moves negative keys in front of positive ones in unsigned order
+const SIGN_BIT: u64 = 1 << 63;
+
+/// Type of the keys of a table.
+///
+/// The tree always orders keys as unsigned integers; signed keys are stored with their sign bit
+/// flipped so they keep their signed order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyType {
+    /// Unsigned 64-bit integer
+    #[default]
+    Unsigned,
+    /// Signed 64-bit integer
+    Signed,
+    /// Unsigned 128-bit integer; stored as 16 bytes, so fewer keys fit into a page
+    U128,
+}
+
+impl KeyType {
+    /// Parses a key written by a user into the key stored in the tree
+    pub fn parse(&self, key: &str) -> Result<u128, String> {
+        let invalid = |_| format!("invalid identifier `{key}`.");
+        match self {
+            Self::Unsigned => key.parse::<u64>().map(u128::from).map_err(invalid),
+            Self::Signed => key
+                .parse::<i64>()
+                .map(|key| encode_signed(key).into())
+                .map_err(invalid),
+            Self::U128 => key.parse::<u128>().map_err(invalid),
+        }
+    }
+
+    /// Formats a key stored in the tree the way it was written by the user
+    pub fn format(&self, key: u128) -> String {
+        match self {
+            Self::Unsigned | Self::U128 => key.to_string(),
+            Self::Signed => decode_signed(key as u64).to_string(),
+        }
+    }
+
+    /// Returns the number of bytes a key takes up in the cells of the tree
+    pub fn key_size(&self) -> usize {
+        match self {
+            Self::Unsigned | Self::Signed => KEY_SIZE,
+            Self::U128 => WIDE_KEY_SIZE,
+        }
+    }
+
+    /// Returns the largest key that can be stored in the tree
+    pub fn max_key(&self) -> u128 {
+        match self {
+            Self::Unsigned | Self::Signed => u64::MAX.into(),
+            Self::U128 => u128::MAX,
+        }
+    }
+}
+
+/// Returns the key stored in the tree for the signed key `key`
+pub fn encode_signed(key: i64) -> u64 {
+    key as u64 ^ SIGN_BIT
+}
+
+/// Restores a signed key encoded by [encode_signed]
+pub fn decode_signed(key: u64) -> i64 {
+    (key ^ SIGN_BIT) as i64
+}
+
+impl FromStr for KeyType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unsigned" => Ok(Self::Unsigned),
+            "signed" => Ok(Self::Signed),
+            "u128" => Ok(Self::U128),
+            _ => Err(format!("unknown key type `{s}`.")),
+        }
+    }
+}
+
+impl Display for KeyType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Unsigned => "unsigned",
+            Self::Signed => "signed",
+            Self::U128 => "u128",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl From<ValueType> for u8 {
+    fn from(value: ValueType) -> Self {
+        match value {
+            ValueType::Text => 0x1,
+            ValueType::Int => 0x2,
+            ValueType::Blob => 0x3,
+        }
+    }
+}
+
+impl TryFrom<u8> for ValueType {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x1 => Ok(Self::Text),
+            0x2 => Ok(Self::Int),
+            0x3 => Ok(Self::Blob),
+            v => Err(format!("unknown value type: {:#x}", v)),
+        }
+    }
+}
+
+impl FromStr for ValueType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "int" => Ok(Self::Int),
+            "blob" => Ok(Self::Blob),
+            _ => Err(format!("unknown value type `{s}`.")),
+        }
+    }
+}
+
+impl Display for ValueType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Text => "text",
+            Self::Int => "int",
+            Self::Blob => "blob",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ints_round_trip() {
+        let bytes = ValueType::Int.encode(" -42").unwrap();
+        assert_eq!(bytes, (-42i64).to_be_bytes());
+        assert_eq!(ValueType::Int.decode(&bytes), b"-42");
+
+        assert!(ValueType::Int.encode("foo").is_err());
+        assert!(ValueType::Int.encode("").is_err());
+        assert_eq!(ValueType::Text.encode("foo"), Ok(b"foo".to_vec()));
+    }
+
+    #[test]
+    fn signed_keys_keep_their_order() {
+        let keys = [i64::MIN, -5, -1, 0, 5, i64::MAX];
+        let encoded = keys.map(encode_signed);
+        assert!(encoded.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(encoded.map(decode_signed), keys);
+
+        assert_eq!(KeyType::Signed.parse("-5"), Ok(encode_signed(-5).into()));
+        assert_eq!(KeyType::Signed.format(encode_signed(-5).into()), "-5");
+        assert!(KeyType::Unsigned.parse("-5").is_err());
+    }
+}
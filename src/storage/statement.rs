@@ -1,53 +1,642 @@
-use super::cursor::Cursor;
+use std::{fmt::Display, io::Write, str::FromStr};
+
+use super::{
+    cursor::Cursor,
+    schema::{KeyType, ValueType},
+};
 
 /// Database commands/statements
 #[derive(Debug, Clone)]
 pub enum Statement {
-    Select,
-    Insert(u64, String),
+    /// Retrieves records in ascending identifier order; optionally skipping and limiting them
+    Select {
+        offset: u64,
+        limit: Option<u64>,
+    },
+    /// Retrieves the records whose value equals the given value
+    SelectWhere(String),
+    /// Counts the records in the table
+    Count,
+    /// Retrieves the identifiers of all records in ascending order, without their values
+    Keys,
+    /// Retrieves the record with the smallest identifier
+    Min,
+    /// Retrieves the record with the largest identifier
+    Max,
+    Insert(u128, String),
+    /// Replaces the value of an existing record
+    Update(u128, String),
+    /// Inserts a record under the largest identifier plus one
+    InsertAuto(String),
+    /// Retrieves the value of a single record
+    Get(u128),
+    /// Checks whether a record exists
+    Exists(u128),
+    /// Removes all records with identifiers in the inclusive range
+    Delete(u128, u128),
+    /// Moves a record to a new identifier
+    Rekey(u128, u128),
+    /// Prints the pages a statement would visit without executing it
+    Explain(Box<Statement>),
+}
+
+/// Format used when printing records
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    /// Prints the raw value of each record on its own line
+    #[default]
+    Plain,
+    /// Prints one JSON object per record; non UTF-8 values are base64 encoded
+    Json,
+    /// Prints the key and the escaped value of each record separated by a tab
+    Tsv,
+    /// Prints records as a table with aligned columns and a header; values are escaped
+    Table,
 }
 
 impl Statement {
-    pub fn execute(&self, cursor: &mut Cursor) {
+    /// Runs the statement; anything it prints is written to `out`
+    pub fn execute<W: Write>(
+        &self,
+        cursor: &mut Cursor,
+        format: OutputFormat,
+        out: &mut W,
+    ) -> std::io::Result<()> {
         match self {
-            Self::Select => {
-                cursor.select().iter().for_each(|s| {
-                    println!("{}", s);
+            Self::Select { offset, limit } => match cursor.select_limit(*offset, *limit) {
+                Ok(rows) => {
+                    for line in format.render(&display_rows(cursor, rows)) {
+                        writeln!(out, "{line}")?;
+                    }
+                }
+                Err(e) => writeln!(out, "error: {e}")?,
+            },
+            Self::SelectWhere(target) => {
+                let rows = encode(cursor.schema(), target).and_then(|target| {
+                    cursor
+                        .select_filter(|value| value == target)
+                        .map_err(|e| e.to_string())
                 });
+                match rows {
+                    Ok(rows) => {
+                        for line in format.render(&display_rows(cursor, rows)) {
+                            writeln!(out, "{line}")?;
+                        }
+                    }
+                    Err(e) => writeln!(out, "error: {e}")?,
+                }
+            }
+            Self::Count => match cursor.count() {
+                Ok(count) => writeln!(out, "{count}")?,
+                Err(e) => writeln!(out, "error: {e}")?,
+            },
+            Self::Keys => match cursor.keys() {
+                Ok(keys) => {
+                    for key in keys {
+                        writeln!(out, "{}", cursor.key_type().format(key))?;
+                    }
+                }
+                Err(e) => writeln!(out, "error: {e}")?,
+            },
+            Self::Min | Self::Max => {
+                let row = match self {
+                    Self::Min => cursor.first(),
+                    _ => cursor.last(),
+                };
+                match row {
+                    Ok(Some(row)) => {
+                        for line in format.render(&display_rows(cursor, vec![row])) {
+                            writeln!(out, "{line}")?;
+                        }
+                    }
+                    Ok(None) => writeln!(out, "not found")?,
+                    Err(e) => writeln!(out, "error: {e}")?,
+                }
             }
-            Self::Insert(id, content) => match cursor.insert(*id, content.as_bytes().to_vec()) {
-                Err(e) => println!("error: {e}"),
-                _ => (),
+            Self::Insert(id, content) => match encode(cursor.schema(), content) {
+                Ok(value) => {
+                    if let Err(e) = cursor.insert(*id, value) {
+                        writeln!(out, "error: {e}")?;
+                    }
+                }
+                Err(e) => writeln!(out, "error: {e}")?,
+            },
+            Self::Update(id, content) => match encode(cursor.schema(), content) {
+                Ok(value) => {
+                    if let Err(e) = cursor.update(*id, value) {
+                        writeln!(out, "error: {e}")?;
+                    }
+                }
+                Err(e) => writeln!(out, "error: {e}")?,
+            },
+            Self::InsertAuto(content) => match encode(cursor.schema(), content) {
+                Ok(value) => match cursor.append(value) {
+                    Ok(id) => writeln!(out, "{}", cursor.key_type().format(id))?,
+                    Err(e) => writeln!(out, "error: {e}")?,
+                },
+                Err(e) => writeln!(out, "error: {e}")?,
+            },
+            Self::Get(id) => match cursor.get(*id) {
+                Some(value) => {
+                    for line in format.render(&display_rows(cursor, vec![(*id, value)])) {
+                        writeln!(out, "{line}")?;
+                    }
+                }
+                None => writeln!(out, "not found")?,
+            },
+            Self::Exists(id) => writeln!(out, "{}", cursor.contains_key(*id))?,
+            Self::Delete(lo, hi) => match cursor.delete_range(*lo, *hi) {
+                Ok(removed) => writeln!(out, "deleted {removed}")?,
+                Err(e) => writeln!(out, "error: {e}")?,
+            },
+            Self::Rekey(old, new) => {
+                if let Err(e) = cursor.rekey(*old, *new) {
+                    writeln!(out, "error: {e}")?;
+                }
+            }
+            Self::Explain(statement) => {
+                let plan = match statement.as_ref() {
+                    Self::Insert(id, content) => {
+                        encode(cursor.schema(), content).and_then(|value| {
+                            cursor.explain_insert(*id, value).map_err(|e| e.to_string())
+                        })
+                    }
+                    Self::Get(id) | Self::Exists(id) => {
+                        cursor.explain_lookup(*id).map_err(|e| e.to_string())
+                    }
+                    Self::Select { .. } | Self::Count | Self::Keys => {
+                        cursor.explain_scan().map_err(|e| e.to_string())
+                    }
+                    _ => unreachable!("only explainable statements are parsed"),
+                };
+
+                match plan {
+                    Ok(plan) => {
+                        for (page, kind) in plan.pages {
+                            writeln!(out, "page {page} ({})", format!("{kind:?}").to_lowercase())?;
+                        }
+                        if matches!(statement.as_ref(), Self::Insert(..)) {
+                            writeln!(out, "split: {}", if plan.split { "yes" } else { "no" })?;
+                        }
+                    }
+                    Err(e) => writeln!(out, "error: {e}")?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Validates and encodes a value according to the schema of the table
+fn encode(schema: Option<ValueType>, value: &str) -> Result<Vec<u8>, String> {
+    match schema {
+        Some(schema) => schema.encode(value),
+        None => Ok(value.as_bytes().to_vec()),
+    }
+}
+
+/// Formats stored bytes according to the schema of the table
+fn decode(schema: Option<ValueType>, value: &[u8]) -> Vec<u8> {
+    schema.map_or_else(|| value.to_vec(), |schema| schema.decode(value))
+}
+
+/// Formats stored records according to the key type and schema of the table
+fn display_rows(cursor: &Cursor, rows: Vec<(u128, Vec<u8>)>) -> Vec<(String, Vec<u8>)> {
+    rows.into_iter()
+        .map(|(key, value)| {
+            (
+                cursor.key_type().format(key),
+                decode(cursor.schema(), &value),
+            )
+        })
+        .collect()
+}
+
+impl OutputFormat {
+    /// Renders a single record; [Table](OutputFormat::Table) rows are not aligned, see
+    /// [render](OutputFormat::render)
+    pub fn row(&self, key: impl Display, value: &[u8]) -> String {
+        match self {
+            Self::Plain => String::from_utf8_lossy(value).to_string(),
+            Self::Tsv => format!("{key}\t{}", escape_value(value)),
+            Self::Table => format!("{key} | {}", escape_value(value)),
+            Self::Json => match escape(value, Escape::Json) {
+                Some(s) => format!("{{\"key\":{key},\"value\":\"{s}\"}}"),
+                None => format!(
+                    "{{\"key\":{key},\"value\":\"{}\",\"encoding\":\"base64\"}}",
+                    base64_encode(value)
+                ),
             },
         }
     }
+
+    /// Renders `rows`; one line per record, plus a header for [Table](OutputFormat::Table)
+    pub fn render<K: Display>(&self, rows: &[(K, Vec<u8>)]) -> Vec<String> {
+        if *self != Self::Table || rows.is_empty() {
+            return rows
+                .iter()
+                .map(|(key, value)| self.row(key, value))
+                .collect();
+        }
+
+        let rows = rows
+            .iter()
+            .map(|(key, value)| (key.to_string(), escape_value(value)))
+            .collect::<Vec<_>>();
+        let key_width = rows.iter().map(|(key, _)| key.len()).fold(3, usize::max);
+        let value_width = rows
+            .iter()
+            .map(|(_, value)| value.chars().count())
+            .fold(5, usize::max);
+
+        let mut lines = vec![
+            format!("{:>key_width$} | value", "key"),
+            format!("{}-+-{}", "-".repeat(key_width), "-".repeat(value_width)),
+        ];
+        lines.extend(
+            rows.iter()
+                .map(|(key, value)| format!("{key:>key_width$} | {value}")),
+        );
+        lines
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(Self::Plain),
+            "json" => Ok(Self::Json),
+            "tsv" => Ok(Self::Tsv),
+            "table" => Ok(Self::Table),
+            _ => Err(format!("unknown output format `{s}`.")),
+        }
+    }
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Plain => "plain",
+            Self::Json => "json",
+            Self::Tsv => "tsv",
+            Self::Table => "table",
+        };
+
+        write!(f, "{}", name)
+    }
 }
 
 impl TryInto<Statement> for &str {
     type Error = String;
 
     fn try_into(self) -> Result<Statement, Self::Error> {
-        let value = self.trim();
+        Statement::parse(self, KeyType::default())
+    }
+}
 
-        if value == "select" {
-            Ok(Statement::Select)
-        } else if value.starts_with("insert") {
-            let data = value.split(' ').collect::<Vec<&str>>();
-            if data.len() < 3 {
+impl Statement {
+    /// Parses a statement on a table whose keys are of type `key_type`
+    pub fn parse(line: &str, key_type: KeyType) -> Result<Statement, String> {
+        // Values may end in whitespace; only the line terminator is stripped from them
+        let line = line.trim_start().trim_end_matches(['\r', '\n']);
+        let value = line.trim_end();
+
+        if let Some(statement) = line.strip_prefix("explain ") {
+            let statement = Statement::parse(statement, key_type)?;
+            match statement {
+                Statement::Insert(..)
+                | Statement::Get(_)
+                | Statement::Exists(_)
+                | Statement::Select { .. }
+                | Statement::Count
+                | Statement::Keys => Ok(Statement::Explain(Box::new(statement))),
+                _ => Err("only insert, get, exists and select can be explained".to_string()),
+            }
+        } else if let Some(target) = line.strip_prefix("select where value = ") {
+            // Compared against stored values; kept verbatim like inserted values
+            Ok(Statement::SelectWhere(target.to_string()))
+        } else if value == "select" || value.starts_with("select ") {
+            parse_select(value)
+        } else if value == "insert" || value.starts_with("insert ") {
+            // Everything after the identifier is the value; kept verbatim, whitespace included
+            let data = line.splitn(3, ' ').collect::<Vec<&str>>();
+            let [_, id, content] = data[..] else {
                 return Err("invalid syntax".to_string());
+            };
+            if id == "auto" {
+                return Ok(Statement::InsertAuto(content.to_string()));
             }
+            Ok(Statement::Insert(key_type.parse(id)?, content.to_string()))
+        } else if value == "update" || value.starts_with("update ") {
+            // Values are kept verbatim like inserted values
+            let data = line.splitn(3, ' ').collect::<Vec<&str>>();
+            let [_, id, content] = data[..] else {
+                return Err("invalid syntax".to_string());
+            };
+            Ok(Statement::Update(key_type.parse(id)?, content.to_string()))
+        } else if value.starts_with("get ") {
+            Ok(Statement::Get(parse_identifier(value, key_type)?))
+        } else if value.starts_with("exists ") {
+            Ok(Statement::Exists(parse_identifier(value, key_type)?))
+        } else if value.starts_with("delete ") {
+            let data = value.split_whitespace().collect::<Vec<&str>>();
+            let (lo, hi) = match data[..] {
+                [_, id] => (id, id),
+                [_, lo, hi] => (lo, hi),
+                _ => return Err("invalid syntax".to_string()),
+            };
 
-            let id = data[1].parse::<u64>().unwrap();
-            let content = data
-                .iter()
-                .skip(2)
-                .map(|s| String::from(*s))
-                .collect::<Vec<String>>();
-            let content = content.join(" ");
+            Ok(Statement::Delete(key_type.parse(lo)?, key_type.parse(hi)?))
+        } else if value.starts_with("rekey ") {
+            let data = value.split_whitespace().collect::<Vec<&str>>();
+            let [_, old, new] = data[..] else {
+                return Err("invalid syntax".to_string());
+            };
 
-            Ok(Statement::Insert(id, content))
+            Ok(Statement::Rekey(key_type.parse(old)?, key_type.parse(new)?))
         } else {
             Err(format!("unknown command `{value}`."))
         }
     }
 }
+
+/// Parses a `select [offset <m>] [limit <n>]` or `select count|keys|min|max` statement
+fn parse_select(value: &str) -> Result<Statement, String> {
+    let mut offset = 0;
+    let mut limit = None;
+
+    let mut data = value.split_whitespace().skip(1).peekable();
+    let aggregate = match data.peek() {
+        Some(&"count") => Some(Statement::Count),
+        Some(&"keys") => Some(Statement::Keys),
+        Some(&"min") => Some(Statement::Min),
+        Some(&"max") => Some(Statement::Max),
+        _ => None,
+    };
+    if let Some(statement) = aggregate {
+        data.next();
+        return match data.next() {
+            None => Ok(statement),
+            Some(_) => Err("invalid syntax".to_string()),
+        };
+    }
+    if data.peek() == Some(&"offset") {
+        data.next();
+        offset = parse_count(data.next())?;
+    }
+    if data.peek() == Some(&"limit") {
+        data.next();
+        limit = Some(parse_count(data.next())?);
+    }
+    if data.next().is_some() {
+        return Err("invalid syntax".to_string());
+    }
+
+    Ok(Statement::Select { offset, limit })
+}
+
+fn parse_count(value: Option<&str>) -> Result<u64, String> {
+    let value = value.ok_or("invalid syntax".to_string())?;
+    value
+        .parse::<u64>()
+        .map_err(|_| format!("invalid count `{value}`."))
+}
+
+/// Parses the identifier of a `<command> <id>` statement
+fn parse_identifier(value: &str, key_type: KeyType) -> Result<u128, String> {
+    let data = value.split_whitespace().collect::<Vec<&str>>();
+    if data.len() != 2 {
+        return Err("invalid syntax".to_string());
+    }
+
+    key_type.parse(data[1])
+}
+
+/// Escaping rules of the formats that print values inside a line
+#[derive(Clone, Copy, PartialEq)]
+enum Escape {
+    /// Values inside a JSON string literal
+    Json,
+    /// Values that have to stay on their line and in their column
+    Line,
+}
+
+/// Escapes `value` for `style`; backslashes, tabs and line breaks are escaped like in C, quotes
+/// only in JSON. Other control characters are escaped as `\u00NN` in JSON and `\xNN` otherwise.
+///
+/// Bytes that are not valid UTF-8 are escaped as `\xNN`; JSON has no escape for them, so `None`
+/// is returned instead.
+fn escape(value: &[u8], style: Escape) -> Option<String> {
+    let mut out = String::with_capacity(value.len());
+    for chunk in value.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '\t' => out.push_str("\\t"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '"' if style == Escape::Json => out.push_str("\\\""),
+                c if c.is_control() && style == Escape::Json => {
+                    out.push_str(&format!("\\u{:04x}", c as u32))
+                }
+                c if c.is_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+                c => out.push(c),
+            }
+        }
+        if style == Escape::Json && !chunk.invalid().is_empty() {
+            return None;
+        }
+        for byte in chunk.invalid() {
+            out.push_str(&format!("\\x{byte:02x}"));
+        }
+    }
+
+    Some(out)
+}
+
+/// Escapes a value so it fits on a single line and stays in its column; see [escape]
+fn escape_value(value: &[u8]) -> String {
+    escape(value, Escape::Line).unwrap_or_default()
+}
+
+/// Encodes bytes using the standard base64 alphabet with padding
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+        out.push(ALPHABET[(n >> 18) as usize & 0x3F] as char);
+        out.push(ALPHABET[(n >> 12) as usize & 0x3F] as char);
+        out.push(if chunk.len() > 1 {
+            ALPHABET[(n >> 6) as usize & 0x3F] as char
+        } else {
+            '='
+        });
+        out.push(if chunk.len() > 2 {
+            ALPHABET[n as usize & 0x3F] as char
+        } else {
+            '='
+        });
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_rows() {
+        assert_eq!(
+            OutputFormat::Json.row(1, b"say \"hi\""),
+            r#"{"key":1,"value":"say \"hi\""}"#
+        );
+        assert_eq!(
+            OutputFormat::Json.row(2, &[0xFF, 0x00, 0x10]),
+            r#"{"key":2,"value":"/wAQ","encoding":"base64"}"#
+        );
+    }
+
+    #[test]
+    fn formats_escape_text_alike() {
+        let value = "a\\b\tc\nd\u{7}".as_bytes();
+
+        assert_eq!(OutputFormat::Tsv.row(1, value), "1\ta\\\\b\\tc\\nd\\x07");
+        assert_eq!(OutputFormat::Table.row(1, value), r"1 | a\\b\tc\nd\x07");
+        assert_eq!(
+            OutputFormat::Json.row(1, value),
+            r#"{"key":1,"value":"a\\b\tc\nd\u0007"}"#
+        );
+        assert_eq!(escape_value(&[b'a', 0xFE]), r"a\xfe");
+    }
+
+    #[test]
+    fn renders_every_format() {
+        let rows = vec![
+            (1, b"one".to_vec()),
+            (20, b"tab\there".to_vec()),
+            (300, vec![0xFF, b'a', b'\n']),
+        ];
+        let render = |format: OutputFormat| format.render(&rows).join("\n");
+
+        assert_eq!(render(OutputFormat::Plain), "one\ntab\there\n\u{FFFD}a\n");
+        assert_eq!(
+            render(OutputFormat::Json),
+            [
+                r#"{"key":1,"value":"one"}"#,
+                r#"{"key":20,"value":"tab\there"}"#,
+                r#"{"key":300,"value":"/2EK","encoding":"base64"}"#,
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            render(OutputFormat::Tsv),
+            "1\tone\n20\ttab\\there\n300\t\\xffa\\n"
+        );
+        assert_eq!(
+            render(OutputFormat::Table),
+            [
+                "key | value",
+                "----+----------",
+                "  1 | one",
+                " 20 | tab\\there",
+                "300 | \\xffa\\n",
+            ]
+            .join("\n")
+        );
+        assert!(OutputFormat::Table.render::<u64>(&[]).is_empty());
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foob"), "Zm9vYg==");
+    }
+
+    #[test]
+    fn insert_values_are_verbatim() {
+        let parse = |s: &str| -> Result<Statement, String> { s.try_into() };
+
+        assert!(matches!(
+            parse("insert 1 a  b\t c  \n"),
+            Ok(Statement::Insert(1, value)) if value == "a  b\t c  "
+        ));
+        assert!(matches!(
+            parse("insert 1 "),
+            Ok(Statement::Insert(1, value)) if value.is_empty()
+        ));
+        assert!(parse("insert 1").is_err());
+        assert!(parse("insert one value").is_err());
+
+        assert!(matches!(
+            parse("update 1 a  b "),
+            Ok(Statement::Update(1, value)) if value == "a  b "
+        ));
+        assert!(parse("update 1").is_err());
+    }
+
+    #[test]
+    fn reserved_words_as_values() {
+        let parse = |s: &str| -> Result<Statement, String> { s.try_into() };
+
+        for word in ["select", "insert", ".exit", "explain select", "use other"] {
+            assert!(matches!(
+                parse(&format!("insert 7 {word}")),
+                Ok(Statement::Insert(7, value)) if value == word
+            ));
+        }
+        assert!(parse("insert").is_err());
+        assert_eq!(
+            parse("inserted 1 value").unwrap_err(),
+            "unknown command `inserted 1 value`."
+        );
+    }
+
+    #[test]
+    fn select_clauses() {
+        let parse = |s: &str| -> Result<Statement, String> { s.try_into() };
+
+        assert!(matches!(
+            parse("select"),
+            Ok(Statement::Select {
+                offset: 0,
+                limit: None
+            })
+        ));
+        assert!(matches!(
+            parse("select limit 5"),
+            Ok(Statement::Select {
+                offset: 0,
+                limit: Some(5)
+            })
+        ));
+        assert!(matches!(
+            parse("select offset 3 limit 5"),
+            Ok(Statement::Select {
+                offset: 3,
+                limit: Some(5)
+            })
+        ));
+        assert!(parse("select limit 5 offset 3").is_err());
+        assert!(parse("select limit").is_err());
+        assert!(parse("select limit x").is_err());
+        assert!(matches!(parse("select count"), Ok(Statement::Count)));
+        assert!(parse("select count 5").is_err());
+        assert!(matches!(parse("select keys"), Ok(Statement::Keys)));
+        assert!(parse("select keys 5").is_err());
+    }
+}
@@ -1,39 +1,1044 @@
 use super::{
-    page::{CachedPage, PageType},
-    pager::Pager,
+    bloom::BloomFilter,
+    btree::{self, KeyComparator, Node, NodeResult},
+    catalog::{self, Catalog},
+    cell::{Cell, InternalCell},
+    cursor::Cursor,
+    layout::{
+        internal_max_keys, leaf_max_inline_content, CATALOG_PAGE_NUM, FORMAT_VERSION,
+        LEAF_NEXT_SIBLING_POINTER_DEFAULT,
+    },
+    oplog::{self, OpLog, Operation},
+    page::{CachedPage, PageBuilder, PageNum, PageType},
+    pager::{NewPage, Pager, PagerMetrics, RootSplit},
+    schema::{KeyType, ValueType},
+    StorageEngine,
 };
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+/// Controls when a [Table](Table) automatically flushes its contents to disk.
+///
+/// Both thresholds are checked whenever a mutation is recorded and whenever a cursor is created,
+/// so the interval also elapses while the table is only read. Callers that go idle between
+/// operations, like the REPL waiting for input, call [flush_if_due](Table::flush_if_due)
+/// themselves. A flush is performed as soon as either threshold is crossed. The default policy
+/// never flushes automatically.
+#[derive(Debug, Clone, Default)]
+pub struct FlushPolicy {
+    /// Flush after this many mutating operations
+    pub every_ops: Option<u64>,
+    /// Flush once this much time has passed since the last flush
+    pub every: Option<Duration>,
+}
+
+/// Name of the table opened by default
+pub const DEFAULT_TABLE: &str = "db";
+
+/// Largest value accepted by default; 16 MiB
+pub const DEFAULT_MAX_VALUE_SIZE: usize = 16 * 1024 * 1024;
 
 /// Table is a wrapper around B+-Trees
 ///
 /// Table wraps a B+-Tree structure and provides functionality to retrieve specific pages in the
-/// tree as well as functionality to modify the structure of the tree
+/// tree as well as functionality to modify the structure of the tree.
+///
+/// A single file can hold multiple named tables; [open](Table::open) switches the B+-Tree the
+/// Table operates on.
 pub struct Table {
     pager: Pager,
-    pub root: u64,
+    pub root: PageNum,
+    name: String,
+    schema: Option<ValueType>,
+    key_type: KeyType,
+    // `None` while an encrypted file is locked
+    catalog: Option<Catalog>,
+    max_inline_value: Option<usize>,
+    max_internal_keys: Option<usize>,
+    max_value_size: usize,
+    compression_threshold: Option<usize>,
+    prefetch_window: usize,
+    utf8_values: bool,
+    flush_policy: FlushPolicy,
+    pending_mutations: u64,
+    last_flush: Instant,
+    op_log: Option<OpLog>,
+    compare: KeyComparator,
+    // Keyed by internal page; only kept in memory. `None` unless enabled with
+    // `set_bloom_filters`
+    bloom_filters: Option<HashMap<PageNum, BloomFilter>>,
 }
 
 impl Table {
-    /// Creates a new Table wrapper on an existing/new B+-Tree structure on-disk
-    pub fn new(file_path: PathBuf) -> Self {
-        let pager = Pager::new(file_path);
+    /// Creates a new Table wrapper on an existing/new B+-Tree structure on-disk.
+    ///
+    /// Files written in an older format version are upgraded to the current one when they are
+    /// opened; files in a newer version are rejected.
+    pub fn new(file_path: PathBuf) -> Result<Self, String> {
+        Self::with_pager(Pager::new(file_path))
+    }
+
+    /// Creates a new Table wrapper on an encrypted B+-Tree structure on-disk; see
+    /// [Pager::new_encrypted]
+    pub fn new_encrypted(file_path: PathBuf, passphrase: &str) -> Result<Self, String> {
+        Self::with_pager(Pager::new_encrypted(file_path, passphrase)?)
+    }
+
+    /// Creates a new Table that only lives in memory; flushing the table is a no-op
+    pub fn in_memory() -> Self {
+        Self::with_pager(Pager::in_memory()).expect("failed to create in-memory table")
+    }
+
+    fn with_pager(mut pager: Pager) -> Result<Self, String> {
+        // Nothing can be read from encrypted files without a key
+        let catalog = if pager.is_locked() {
+            None
+        } else if pager.num_pages() == 0 {
+            let page = pager
+                .new_page(PageType::Catalog, false)
+                .map_err(|e| format!("failed to create catalog page; {e}"))?
+                .page;
+            Some(Catalog::load(page)?)
+        } else {
+            let page = pager
+                .get_page(PageNum(CATALOG_PAGE_NUM))
+                .ok_or("failed to retrieve catalog page".to_string())?;
+            let catalog = match Catalog::load(page.clone()) {
+                Ok(catalog) => catalog,
+                Err(e) if catalog::is_catalog(&page) == Ok(true) => return Err(e),
+                // Files written before the catalog existed hold a single tree rooted at the first
+                // page
+                Err(_) => Self::add_catalog(&mut pager)?,
+            };
+
+            // Pages past the stored count were allocated after the last flush; nothing references
+            // them so they can be handed out again
+            match catalog.num_pages() {
+                Some(num_pages) if num_pages <= pager.num_pages() => pager.set_num_pages(num_pages),
+                Some(num_pages) => warn!(
+                    "catalog stores {} pages but the file only holds {}",
+                    num_pages,
+                    pager.num_pages()
+                ),
+                None => (),
+            }
+            Some(catalog)
+        };
 
-        Self {
-            root: pager.root_page(),
+        let mut table = Self {
+            // Replaced by the root of the default table once it is opened
+            root: PageNum(CATALOG_PAGE_NUM),
+            name: DEFAULT_TABLE.to_string(),
+            schema: None,
+            key_type: KeyType::default(),
+            catalog,
             pager,
+            max_inline_value: None,
+            max_internal_keys: None,
+            max_value_size: DEFAULT_MAX_VALUE_SIZE,
+            compression_threshold: None,
+            prefetch_window: 1,
+            utf8_values: false,
+            flush_policy: FlushPolicy::default(),
+            pending_mutations: 0,
+            last_flush: Instant::now(),
+            op_log: None,
+            compare: btree::ascending,
+            bloom_filters: None,
+        };
+        // Locked files are opened once a key is provided
+        if !table.pager.is_locked() {
+            table.upgrade()?;
+            table.open(DEFAULT_TABLE)?;
+        }
+
+        Ok(table)
+    }
+
+    /// Moves the tree of a file written before the catalog existed out of the first page and puts
+    /// a catalog in its place; the tree becomes the default table.
+    ///
+    /// The catalog is stamped with format version 0 since the tree is still in the old format.
+    fn add_catalog(pager: &mut Pager) -> Result<Catalog, String> {
+        let RootSplit { old_root_num, page } = pager
+            .new_root(PageNum(CATALOG_PAGE_NUM), |_| {
+                PageBuilder::default().kind(&PageType::Catalog).build()
+            })
+            .map_err(|e| format!("file is not a database; {e}"))?;
+        Node::load(page)
+            .map_err(|e| format!("page {old_root_num}: {e}"))?
+            .set_is_root(true);
+
+        let page = pager
+            .get_page(PageNum(CATALOG_PAGE_NUM))
+            .ok_or("failed to retrieve catalog page".to_string())?;
+        let mut catalog = Catalog::load(page)?;
+        catalog.set_format_version(0);
+        catalog.add(DEFAULT_TABLE, old_root_num)?;
+        Ok(catalog)
+    }
+
+    /// Rewrites the pages of a file written in an older format version in the current format;
+    /// the upgraded file is flushed right away.
+    ///
+    /// Fails if the file was written in a newer format version than this one understands.
+    fn upgrade(&mut self) -> Result<(), String> {
+        let version = self.format_version();
+        if version > FORMAT_VERSION {
+            return Err(format!(
+                "file uses format version {version}; versions up to {FORMAT_VERSION} are supported"
+            ));
+        }
+        if version == FORMAT_VERSION {
+            return Ok(());
+        }
+
+        // Version 2 stores leaf content lengths as varints
+        if version < 2 {
+            let roots = self.catalog_mut()?.tables();
+            for (name, root) in roots {
+                self.upgrade_leaves(root)
+                    .map_err(|e| format!("failed to upgrade table `{name}`; {e}"))?;
+            }
+        }
+
+        // Versions 4 and 5 add the signed and wide key bits to the schema bytes; older files never
+        // set them so only the version changes. Files that do were written by binaries that
+        // don't know the bits and can't be trusted
+        let catalog = self.catalog_mut()?;
+        for (name, _) in catalog.tables() {
+            match catalog.key_type(&name)? {
+                KeyType::Signed if version < 4 => (),
+                KeyType::U128 if version < 5 => (),
+                _ => continue,
+            }
+            return Err(format!(
+                "table `{name}` uses a key type that format version {version} does not have"
+            ));
+        }
+
+        self.catalog_mut()?.set_format_version(FORMAT_VERSION);
+        self.flush()
+            .map_err(|e| format!("failed to write upgraded file; {e}"))?;
+        debug!("upgraded file from format version {version} to {FORMAT_VERSION}");
+        Ok(())
+    }
+
+    /// Rewrites the content lengths of every leaf in the tree rooted at `root`; see
+    /// [Node::upgrade_content_lengths]
+    fn upgrade_leaves(&mut self, root: PageNum) -> Result<(), String> {
+        let mut pages = vec![root];
+        while let Some(num) = pages.pop() {
+            let page = self
+                .pager
+                .get_page(num)
+                .ok_or(format!("page {num} does not exist"))?;
+            let mut node = Node::load(page).map_err(|e| format!("page {num}: {e}"))?;
+            match node.node_type() {
+                PageType::Leaf => node
+                    .upgrade_content_lengths()
+                    .map_err(|e| format!("page {num}: {e}"))?,
+                _ => {
+                    for i in 0..=node.num_cells() {
+                        let cell = node
+                            .read_cell_bytes(i)
+                            .map_err(|e| format!("page {num}: {e}"))?;
+                        pages.push(InternalCell::from_bytes(cell).pointer());
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the catalog of the file; fails while the file is locked
+    fn catalog_mut(&mut self) -> Result<&mut Catalog, String> {
+        self.check_unlocked()?;
+        self.catalog
+            .as_mut()
+            .ok_or("file has no catalog".to_string())
+    }
+
+    /// Switches to table `name`; creating it if it does not exist yet
+    pub fn open(&mut self, name: &str) -> Result<(), String> {
+        let catalog = self.catalog_mut()?;
+        let root = match catalog.find(name) {
+            Some(root) => root,
+            None => {
+                catalog::validate_name(name)?;
+                let root = self
+                    .pager
+                    .new_page(PageType::Leaf, true)
+                    .map_err(|e| format!("failed to create table `{name}`; {e}"))?
+                    .num;
+                let catalog = self.catalog_mut()?;
+                catalog.add(name, root)?;
+                root
+            }
+        };
+
+        let catalog = self.catalog_mut()?;
+        let schema = catalog.schema(name);
+        let key_type = catalog.key_type(name)?;
+
+        self.root = root;
+        self.name = name.to_string();
+        self.schema = schema;
+        self.key_type = key_type;
+        if self.bloom_filters.is_some() {
+            self.build_bloom_filters()?;
+        }
+        Ok(())
+    }
+
+    /// Switches to table `name` whose values are of type `schema`; creating it if it does not
+    /// exist yet.
+    ///
+    /// Existing tables must have been created with the same schema.
+    pub fn open_typed(&mut self, name: &str, schema: ValueType) -> Result<(), String> {
+        self.check_unlocked()?;
+        let exists = self.tables().iter().any(|table| table == name);
+        if exists {
+            let current = self.catalog.as_ref().and_then(|c| c.schema(name));
+            if current != Some(schema) {
+                return Err(match current {
+                    Some(current) => format!("table `{name}` stores {current} values"),
+                    None => format!("table `{name}` has no schema"),
+                });
+            }
+        }
+
+        if !exists {
+            let root = self
+                .pager
+                .new_page(PageType::Leaf, true)
+                .map_err(|e| format!("failed to create table `{name}`; {e}"))?
+                .num;
+            let catalog = self.catalog_mut()?;
+            catalog.add(name, root)?;
+            catalog.set_schema(name, schema)?;
+        }
+
+        self.open(name)
+    }
+
+    fn check_unlocked(&self) -> Result<(), String> {
+        if self.pager.is_locked() {
+            return Err("file is encrypted; a key is required to open it".to_string());
+        }
+        Ok(())
+    }
+
+    /// Returns whether the on-disk file is encrypted
+    pub fn is_encrypted(&self) -> bool {
+        self.pager.is_encrypted()
+    }
+
+    /// Returns the name of the table currently in use
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the type of the values stored in the current table; `None` for untyped tables
+    pub fn schema(&self) -> Option<ValueType> {
+        self.schema
+    }
+
+    /// Returns the type of the keys of the current table
+    pub fn key_type(&self) -> KeyType {
+        self.key_type
+    }
+
+    /// Returns the number of bytes every key of the current table takes up in its cells
+    pub fn key_size(&self) -> usize {
+        self.key_type.key_size()
+    }
+
+    /// Changes the type of the keys of the current table; only empty tables can change their key
+    /// type since the stored keys would change their meaning.
+    ///
+    /// Changing the key size drops the tree of the table, so removed records that are still
+    /// waiting for a vacuum aren't read with the wrong key size.
+    pub fn set_key_type(&mut self, key_type: KeyType) -> Result<(), String> {
+        if key_type == self.key_type {
+            return Ok(());
+        }
+        self.catalog_mut()?;
+        if Cursor::new(self)
+            .first()
+            .map_err(|e| e.to_string())?
+            .is_some()
+        {
+            return Err(format!(
+                "table `{}` holds records; only empty tables can change their key type",
+                self.name
+            ));
+        }
+
+        if key_type.key_size() != self.key_size() {
+            self.clear()?;
+        }
+        let name = self.name.clone();
+        self.catalog_mut()?.set_key_type(&name, key_type)?;
+        self.key_type = key_type;
+        self.record_mutation();
+        Ok(())
+    }
+
+    /// Returns the on-disk format version of the file; `0` while the file is locked
+    pub fn format_version(&self) -> u64 {
+        self.catalog.as_ref().map_or(0, Catalog::format_version)
+    }
+
+    /// Returns the names of all tables stored in the file
+    pub fn tables(&self) -> Vec<String> {
+        match self.catalog.as_ref() {
+            Some(catalog) => catalog.tables().into_iter().map(|(name, _)| name).collect(),
+            None => vec![DEFAULT_TABLE.to_string()],
+        }
+    }
+
+    /// Sets the policy used to automatically flush the table contents
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.flush_policy = policy;
+    }
+
+    /// Records a mutating operation; flushing the table if the flush policy requires it
+    pub fn record_mutation(&mut self) {
+        self.pending_mutations += 1;
+        self.flush_if_due();
+    }
+
+    /// Flushes the table if there are unflushed changes and the flush policy requires it
+    pub fn flush_if_due(&mut self) {
+        if !self.has_unflushed_changes() {
+            return;
+        }
+
+        let ops_reached = self
+            .flush_policy
+            .every_ops
+            .is_some_and(|n| self.pending_mutations >= n);
+        let time_reached = self
+            .flush_policy
+            .every
+            .is_some_and(|t| self.last_flush.elapsed() >= t);
+
+        // Mutations stay pending after a failed flush; the next flush tries again
+        if ops_reached || time_reached {
+            if let Err(e) = self.flush() {
+                warn!("failed to flush table; {e}");
+            }
+        }
+    }
+
+    /// Sets the order of the keys in the table; ascending numeric order by default.
+    ///
+    /// The order is not stored in the file, a table has to be opened with the comparator it was
+    /// written with.
+    pub fn set_comparator(&mut self, compare: KeyComparator) {
+        self.compare = compare;
+    }
+
+    /// Returns the function used to order the keys in the table
+    pub fn comparator(&self) -> KeyComparator {
+        self.compare
+    }
+
+    /// Loads a node of the current table; ordered by the tables comparator and reading keys of
+    /// the tables key size
+    pub fn load_node(&self, page: CachedPage) -> Result<Node, NodeResult> {
+        Ok(Node::load(page)?
+            .with_comparator(self.comparator())
+            .with_key_size(self.key_size())
+            .with_max_keys(self.max_internal_keys()))
+    }
+
+    /// Stores `value` under `key`
+    pub fn put(&mut self, key: u128, value: &[u8]) -> Result<(), NodeResult> {
+        Cursor::new(self).insert(key, value.to_vec())
+    }
+
+    /// Stores `value` under the next free identifier; see [Cursor::append](Cursor::append)
+    pub fn append(&mut self, value: &[u8]) -> Result<u128, NodeResult> {
+        Cursor::new(self).append(value.to_vec())
+    }
+
+    /// Retrieves the value stored under `key`
+    pub fn get_bytes(&mut self, key: u128) -> Option<Vec<u8>> {
+        Cursor::new(self).get(key)
+    }
+
+    /// Inserts every `(key, value)` pair of `rows`; returns the number of inserted records.
+    ///
+    /// Inserting stops at the first record that can't be inserted. With a `fill_factor`, leaves
+    /// filled by keys in ascending order are only filled up to that fraction of their space so
+    /// later inserts don't split them right away; see
+    /// [Cursor::with_fill_factor](Cursor::with_fill_factor).
+    pub fn bulk_insert(
+        &mut self,
+        rows: impl IntoIterator<Item = (u128, Vec<u8>)>,
+        fill_factor: Option<f64>,
+    ) -> Result<u64, NodeResult> {
+        let mut cursor = Cursor::new(self).with_fill_factor(fill_factor);
+        let mut inserted = 0;
+        for (key, value) in rows {
+            cursor.insert(key, value)?;
+            inserted += 1;
+        }
+
+        Ok(inserted)
+    }
+
+    /// Checks whether a record is stored under `key`; cheaper than [get_bytes](Table::get_bytes)
+    /// as the value is never read
+    pub fn contains_key(&mut self, key: u128) -> bool {
+        Cursor::new(self).contains_key(key)
+    }
+
+    /// Starts appending every successful mutation to the operation log at `path`.
+    ///
+    /// `None` stops logging. The log can be applied to another database with
+    /// [replay_from](Table::replay_from). Logs are written in plaintext, so encrypted files
+    /// refuse them.
+    pub fn set_op_log(&mut self, path: Option<&Path>) -> Result<(), String> {
+        if path.is_some() && self.is_encrypted() {
+            return Err(
+                "operation logs are not encrypted; refusing to log an encrypted file".into(),
+            );
+        }
+        self.op_log = path.map(OpLog::open).transpose()?;
+        Ok(())
+    }
+
+    /// Returns whether mutations are written to an operation log
+    pub fn logs_operations(&self) -> bool {
+        self.op_log.is_some()
+    }
+
+    /// Checks that mutations of the record stored under `key` can be written to the operation
+    /// log, if there is one; logs of the first version only hold 8-byte keys
+    pub fn check_logged_key(&self, key: u128) -> Result<(), NodeResult> {
+        match self.op_log.as_ref() {
+            Some(log) if !log.holds_key(key) => Err(NodeResult::KeyNotLogged),
+            _ => Ok(()),
+        }
+    }
+
+    /// Appends `op` to the operation log, if there is one
+    pub fn log_operation(&mut self, op: &Operation) {
+        if let Some(log) = self.op_log.as_mut() {
+            if let Err(e) = log.append(op) {
+                warn!("{e}");
+            }
+        }
+    }
+
+    /// Applies every operation in the log at `path` to the current table.
+    ///
+    /// Returns the number of operations applied; replaying stops at the first operation that
+    /// fails.
+    pub fn replay_from(&mut self, path: &Path) -> Result<u64, String> {
+        let file = File::open(path).map_err(|e| format!("failed to open operation log; {e}"))?;
+        let ops = oplog::read(file)?;
+
+        let mut cursor = Cursor::new(self);
+        for (n, op) in ops.iter().enumerate() {
+            match op {
+                Operation::Insert(key, value) => cursor.insert(*key, value.clone()).map(|_| ()),
+                Operation::Delete(lo, hi) => cursor.delete_range(*lo, *hi).map(|_| ()),
+            }
+            .map_err(|e| format!("failed to replay operation {n}; {e}"))?;
+        }
+
+        Ok(ops.len() as u64)
+    }
+
+    /// Sets the largest value that is stored inline in a leaf.
+    ///
+    /// Values larger than the threshold are written to overflow pages even if they would fit
+    /// in the leaf. `None` keeps as much inline as a leaf can hold.
+    pub fn set_max_inline_value(&mut self, max: Option<usize>) {
+        self.max_inline_value = max;
+    }
+
+    /// Returns the largest value that is stored inline in a leaf
+    pub fn max_inline_value(&self) -> usize {
+        let max = leaf_max_inline_content(self.key_size());
+        self.max_inline_value.unwrap_or(max).min(max)
+    }
+
+    /// Sets the largest number of cells stored in an internal node.
+    ///
+    /// Lowering the limit makes the tree grow taller with fewer records. `None` stores as many
+    /// cells as an internal node can hold; nodes always hold at least two cells.
+    pub fn set_max_internal_keys(&mut self, max: Option<usize>) {
+        self.max_internal_keys = max;
+    }
+
+    /// Returns the largest number of cells stored in an internal node
+    pub fn max_internal_keys(&self) -> usize {
+        let max = internal_max_keys(self.key_size());
+        self.max_internal_keys.unwrap_or(max).clamp(2, max)
+    }
+
+    /// Sets the largest value that can be inserted into the table
+    pub fn set_max_value_size(&mut self, max: usize) {
+        self.max_value_size = max;
+    }
+
+    /// Returns the largest value that can be inserted into the table
+    pub fn max_value_size(&self) -> usize {
+        self.max_value_size
+    }
+
+    /// Sets the size from which values are compressed before being stored.
+    ///
+    /// `None` disables compression. Values are only stored compressed if that makes them
+    /// smaller, and only when built with the `compression` feature.
+    pub fn set_compression_threshold(&mut self, threshold: Option<usize>) {
+        self.compression_threshold = threshold;
+    }
+
+    /// Returns the size from which values are compressed before being stored
+    pub fn compression_threshold(&self) -> Option<usize> {
+        self.compression_threshold
+    }
+
+    /// Sets how many leaves are read into the cache at once while scanning the leaf chain.
+    ///
+    /// A window of `1` only reads a leaf once the scan reaches it.
+    pub fn set_prefetch_window(&mut self, window: usize) {
+        self.prefetch_window = window.max(1);
+    }
+
+    /// Returns how many leaves are read into the cache at once while scanning the leaf chain
+    pub fn prefetch_window(&self) -> usize {
+        self.prefetch_window
+    }
+
+    /// Rejects inserted and updated values that are not valid UTF-8.
+    ///
+    /// Only applies to tables without a schema; their values are printed as they are stored.
+    /// Values of tables with a schema always have to match it; see
+    /// [ValueType::accepts](ValueType::accepts).
+    pub fn set_utf8_values(&mut self, enabled: bool) {
+        self.utf8_values = enabled;
+    }
+
+    /// Returns whether values that are not valid UTF-8 are rejected
+    pub fn utf8_values(&self) -> bool {
+        self.utf8_values
+    }
+
+    /// Checks that `value` may be stored in the table
+    pub fn check_value(&self, value: &[u8]) -> Result<(), NodeResult> {
+        if value.len() > self.max_value_size {
+            return Err(NodeResult::ValueTooLarge {
+                size: value.len(),
+                max: self.max_value_size,
+            });
+        }
+
+        match self.schema {
+            Some(schema) if !schema.accepts(value) => {
+                return Err(NodeResult::SchemaMismatch(schema))
+            }
+            None if self.utf8_values && std::str::from_utf8(value).is_err() => {
+                return Err(NodeResult::InvalidUtf8)
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Keeps a bloom filter of the keys under every internal node; lookups of absent keys stop at
+    /// the first node whose filter rules the key out instead of descending to a leaf.
+    ///
+    /// Filters are built from the tree when enabled and only live in memory. Every insert has to
+    /// update the filters of all internal nodes it passes, so they are disabled by default.
+    pub fn set_bloom_filters(&mut self, enabled: bool) -> Result<(), String> {
+        self.bloom_filters = None;
+        if enabled {
+            self.bloom_filters = Some(HashMap::new());
+            self.build_bloom_filters()?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether lookups check bloom filters before descending
+    pub fn has_bloom_filters(&self) -> bool {
+        self.bloom_filters.is_some()
+    }
+
+    /// Returns `false` if the filter of page `page` rules out `key`; always `true` for pages
+    /// without a filter
+    pub fn may_contain(&self, page: PageNum, key: u128) -> bool {
+        self.bloom_filters
+            .as_ref()
+            .and_then(|filters| filters.get(&page))
+            .is_none_or(|filter| filter.contains(key))
+    }
+
+    /// Adds `key` to the filters of `pages`; pages without a filter are skipped
+    pub fn add_to_bloom_filters(&mut self, pages: impl IntoIterator<Item = PageNum>, key: u128) {
+        if let Some(filters) = self.bloom_filters.as_mut() {
+            for page in pages {
+                if let Some(filter) = filters.get_mut(&page) {
+                    filter.insert(key);
+                }
+            }
+        }
+    }
+
+    /// Gives page `to` a copy of the filter of page `from`.
+    ///
+    /// Used when a node is split; the keys of the new page are a subset of the keys of the split
+    /// page, so the copy can only report more keys than the page holds.
+    pub fn copy_bloom_filter(&mut self, from: PageNum, to: PageNum) {
+        if let Some(filters) = self.bloom_filters.as_mut() {
+            if let Some(filter) = filters.get(&from).cloned() {
+                filters.insert(to, filter);
+            }
+        }
+    }
+
+    /// Builds a filter for every internal node of the current table that doesn't have one yet
+    fn build_bloom_filters(&mut self) -> Result<(), String> {
+        if self.bloom_filters.is_some() {
+            self.subtree_filter(self.root)?;
+        }
+        Ok(())
+    }
+
+    /// Returns a filter of all keys under page `page_num`; storing it for internal nodes
+    fn subtree_filter(&mut self, page_num: PageNum) -> Result<BloomFilter, String> {
+        if let Some(filter) = self
+            .bloom_filters
+            .as_ref()
+            .and_then(|filters| filters.get(&page_num))
+        {
+            return Ok(filter.clone());
         }
+
+        let page = self
+            .get_page(page_num)
+            .ok_or(format!("page {page_num} does not exist"))?;
+        let node = self
+            .load_node(page)
+            .map_err(|e| format!("page {page_num}: {e}"))?;
+        let mut filter = BloomFilter::default();
+        match node.node_type() {
+            PageType::Leaf => (0..node.num_cells()).for_each(|i| filter.insert(node.cell_key(i))),
+            _ => {
+                for i in 0..=node.num_cells() {
+                    let bytes = node
+                        .read_cell_bytes(i)
+                        .map_err(|e| format!("page {page_num}: {e}"))?;
+                    let child = InternalCell::from_bytes(bytes).pointer();
+                    filter.union(&self.subtree_filter(child)?);
+                }
+                if let Some(filters) = self.bloom_filters.as_mut() {
+                    filters.insert(page_num, filter.clone());
+                }
+            }
+        }
+
+        Ok(filter)
+    }
+
+    /// Reads pages through a memory map of the database file; see [Pager::set_mmap]
+    #[cfg(feature = "mmap")]
+    pub fn set_mmap(&mut self, enabled: bool) -> Result<(), String> {
+        self.pager.set_mmap(enabled)
+    }
+
+    /// Returns whether pages are read through a memory map of the database file
+    pub fn is_mapped(&self) -> bool {
+        self.pager.is_mapped()
     }
 
-    pub fn create_page(&mut self, kind: &PageType) -> (u64, CachedPage) {
+    /// Reads page `num` into the cache ahead of it being requested
+    pub fn prefetch_page(&mut self, num: PageNum) -> Option<CachedPage> {
+        self.pager.prefetch(num)
+    }
+
+    /// Returns whether page `num` is in the cache
+    pub fn is_cached(&self, num: PageNum) -> bool {
+        self.pager.is_cached(num)
+    }
+
+    /// Returns the lock held while a change spanning several pages is made; see
+    /// [Pager::commit_lock](Pager::commit_lock)
+    pub fn commit_lock(&self) -> Arc<Mutex<()>> {
+        self.pager.commit_lock()
+    }
+
+    /// Appends an empty page of type `kind` to the file
+    pub fn create_page(&mut self, kind: &PageType) -> std::io::Result<NewPage> {
         self.pager.new_page(kind.clone(), false)
     }
 
-    pub fn create_new_root(&mut self) -> (u64, CachedPage) {
-        self.pager.new_root()
+    /// Moves the root of the tree into a new page and replaces it with an internal node that has
+    /// the old root as left child and page `right` as right child.
+    ///
+    /// Keys up to `separator` are routed to the old root. The new root is fully linked before it
+    /// replaces the old one, so the tree is never left with a root that has no children. Returns
+    /// the page the old root contents were moved to; the root keeps its page number.
+    ///
+    /// Fails if the root page of the table can not be loaded.
+    pub fn create_new_root(
+        &mut self,
+        separator: u128,
+        right: PageNum,
+    ) -> Result<RootSplit, String> {
+        let key_size = self.key_size();
+        let split = self.pager.new_root(self.root, |left| {
+            let page = CachedPage::new(
+                PageBuilder::default()
+                    .is_root(true)
+                    .kind(&PageType::Internal)
+                    .build(),
+            );
+            let mut node = Node::load(page.clone())
+                .expect("failed to load new root")
+                .with_key_size(key_size);
+            // The first cell of an empty internal node only sets its right child; the second
+            // moves it into a cell keyed by the separator
+            node.insert_cell(InternalCell::new(separator, left))
+                .expect("failed to insert key into new internal node");
+            node.insert_cell(InternalCell::new(separator, right))
+                .expect("failed to insert right most key in internal node");
+
+            let content = page.0.read().expect("failed to retrieve read lock on page");
+            content.clone()
+        })?;
+
+        // The old root and the new root hold the same keys; a leaf root has no filter yet
+        match self
+            .bloom_filters
+            .as_ref()
+            .map(|f| f.contains_key(&self.root))
+        {
+            Some(true) => self.copy_bloom_filter(self.root, split.old_root_num),
+            // The tree is complete either way; pages without a filter are always descended into
+            Some(false) => {
+                if let Err(e) = self.build_bloom_filters() {
+                    warn!("failed to build bloom filter of new root; {e}");
+                }
+            }
+            None => (),
+        }
+
+        Ok(split)
+    }
+
+    /// Returns a read-only view of the table as it is now.
+    ///
+    /// The snapshot is an in-memory table that shares the pages of this table until either side
+    /// changes them; see [Pager::snapshot]. A scan of the snapshot sees the same records no matter
+    /// what is written to this table in the meantime. Changes made to the snapshot are never
+    /// written anywhere.
+    pub fn snapshot(&mut self) -> Result<Table, String> {
+        let mut pager = self.pager.snapshot()?;
+        let mut catalog = None;
+        if self.catalog.is_some() {
+            // Both catalogs have to be loaded again; the page they held is shared now
+            let load = |pager: &mut Pager| {
+                pager
+                    .get_page(PageNum(CATALOG_PAGE_NUM))
+                    .ok_or("failed to retrieve catalog page".to_string())
+                    .and_then(Catalog::load)
+            };
+            self.catalog = Some(load(&mut self.pager)?);
+            catalog = Some(load(&mut pager)?);
+        }
+
+        Ok(Self {
+            pager,
+            root: self.root,
+            name: self.name.clone(),
+            schema: self.schema,
+            key_type: self.key_type,
+            catalog,
+            max_inline_value: self.max_inline_value,
+            max_internal_keys: self.max_internal_keys,
+            max_value_size: self.max_value_size,
+            compression_threshold: self.compression_threshold,
+            prefetch_window: self.prefetch_window,
+            utf8_values: self.utf8_values,
+            flush_policy: FlushPolicy::default(),
+            pending_mutations: 0,
+            last_flush: Instant::now(),
+            op_log: None,
+            compare: self.compare,
+            bloom_filters: self.bloom_filters.clone(),
+        })
+    }
+
+    /// Writes a copy of the database file to a new file at `path`; returns the number of pages
+    /// copied.
+    ///
+    /// Unlike copying the file, the backup includes changes that haven't been flushed yet and is
+    /// never taken halfway through a change; see [Pager::backup_to]. The table stays usable while
+    /// the backup is written.
+    pub fn backup_to(&mut self, path: &Path) -> Result<u64, String> {
+        if let Some(catalog) = self.catalog.as_mut() {
+            catalog.set_num_pages(self.pager.num_pages());
+        }
+        self.pager.backup_to(path)
+    }
+
+    /// Writes a copy of the database file to a new file at `path` like [backup_to](Table::backup_to)
+    /// and opens the copy with the current table selected.
+    ///
+    /// The returned table is independent of this one; changes to either are never seen by the
+    /// other. Encrypted files are opened with the same key and settings that aren't stored in the
+    /// file carry over.
+    pub fn fork_to(&mut self, path: &Path) -> Result<Table, String> {
+        self.backup_to(path)?;
+        let mut fork = Self::with_pager(self.pager.open_backup(path)?)?;
+        fork.open(&self.name)?;
+
+        fork.max_inline_value = self.max_inline_value;
+        fork.max_internal_keys = self.max_internal_keys;
+        fork.max_value_size = self.max_value_size;
+        fork.compression_threshold = self.compression_threshold;
+        fork.prefetch_window = self.prefetch_window;
+        fork.utf8_values = self.utf8_values;
+        fork.flush_policy = self.flush_policy.clone();
+        fork.compare = self.compare;
+        fork.bloom_filters = self.bloom_filters.clone();
+        Ok(fork)
+    }
+
+    /// Removes every record of the current table; leaving its root as an empty leaf.
+    ///
+    /// If the current table is the only table in the file, the file is truncated right after the
+    /// root and the page cache is emptied. Otherwise the pages of the old tree stay in the file
+    /// unused since other tables may have been allocated after them.
+    pub fn clear(&mut self) -> Result<(), String> {
+        self.check_unlocked()?;
+
+        if self.tables().len() == 1 {
+            let num_pages = self.root.0 + 1;
+            if let Some(catalog) = self.catalog.as_mut() {
+                catalog.set_num_pages(num_pages);
+            }
+            self.pager.truncate(num_pages)?;
+
+            // The catalog still refers to the page that was dropped from the cache
+            if self.catalog.is_some() {
+                let page = self
+                    .pager
+                    .get_page(PageNum(CATALOG_PAGE_NUM))
+                    .ok_or("failed to retrieve catalog page".to_string())?;
+                self.catalog = Some(Catalog::load(page)?);
+            }
+        }
+
+        let root = PageBuilder::default()
+            .kind(&PageType::Leaf)
+            .is_root(true)
+            .build();
+        self.root_page()
+            .0
+            .write()
+            .expect("failed to retrieve write lock on page")
+            .0
+            .clone_from_slice(&root.0);
+
+        if self.has_bloom_filters() {
+            self.set_bloom_filters(true)?;
+        }
+        self.log_operation(&Operation::Delete(0, u128::MAX));
+        self.record_mutation();
+        Ok(())
+    }
+
+    /// Rewrites the next sibling pointer of every leaf; returns the number of leaves.
+    ///
+    /// Leaves are found in key order by following the child pointers of the internal nodes, so a
+    /// broken sibling chain can be repaired as long as the internal nodes are intact.
+    pub fn rebuild_leaf_chain(&mut self) -> Result<u64, String> {
+        let mut leaves = self.leaves_by_child_pointers()?;
+        let pages = leaves
+            .iter()
+            .map(|(page_num, _)| *page_num)
+            .collect::<Vec<_>>();
+        for (i, (_, leaf)) in leaves.iter_mut().enumerate() {
+            let next = pages
+                .get(i + 1)
+                .copied()
+                .unwrap_or(PageNum(LEAF_NEXT_SIBLING_POINTER_DEFAULT));
+            leaf.set_next_sibling(next);
+        }
+
+        self.record_mutation();
+        Ok(leaves.len() as u64)
+    }
+
+    /// Recalculates the free space pointers of every leaf from its cells; returns the number of
+    /// leaves.
+    ///
+    /// Leaves are found through the internal nodes, like [rebuild_leaf_chain](Table::rebuild_leaf_chain).
+    pub fn repair_free_space(&mut self) -> Result<u64, String> {
+        let mut leaves = self.leaves_by_child_pointers()?;
+        for (page_num, leaf) in leaves.iter_mut() {
+            leaf.recompute_free_space()
+                .map_err(|e| format!("leaf {page_num}: {e}"))?;
+        }
+
+        self.record_mutation();
+        Ok(leaves.len() as u64)
+    }
+
+    /// Loads every leaf in key order by following the child pointers of the internal nodes
+    fn leaves_by_child_pointers(&mut self) -> Result<Vec<(PageNum, Node)>, String> {
+        let mut leaves = Vec::new();
+        let mut visited = 0;
+        let mut pending = vec![self.root];
+        while let Some(page_num) = pending.pop() {
+            // Every page can be visited at most once; anything longer has to be a cycle
+            visited += 1;
+            if visited > self.page_count() {
+                return Err(format!("child pointers loop back at page {page_num}"));
+            }
+
+            let page = self
+                .get_page(page_num)
+                .ok_or(format!("page {page_num} does not exist"))?;
+            let node = self
+                .load_node(page)
+                .map_err(|e| format!("page {page_num}: {e}"))?;
+            match node.node_type() {
+                PageType::Leaf => leaves.push((page_num, node)),
+                // Children are pushed right to left so the left most child is visited first; the
+                // right child is stored past the last cell
+                _ => {
+                    for i in (0..=node.num_cells()).rev() {
+                        let bytes = node
+                            .read_cell_bytes(i)
+                            .map_err(|e| format!("page {page_num}: {e}"))?;
+                        pending.push(InternalCell::from_bytes(bytes).pointer());
+                    }
+                }
+            }
+        }
+
+        Ok(leaves)
     }
 
     /// Retrieves a particular page in the table
-    pub fn get_page(&mut self, num: u64) -> Option<CachedPage> {
+    pub fn get_page(&mut self, num: PageNum) -> Option<CachedPage> {
         self.pager.get_page(num)
     }
 
@@ -43,7 +1048,1187 @@ impl Table {
             .expect("failed to retrieve root page")
     }
 
-    pub fn flush_contents(&mut self) {
-        self.pager.flush_cache();
+    /// Returns the number of pages allocated by the table
+    pub fn page_count(&self) -> u64 {
+        self.pager.num_pages()
+    }
+
+    /// Drops pages allocated after the first `num_pages` pages that nothing links to; see
+    /// [Pager::release_pages]
+    pub fn release_pages(&mut self, num_pages: u64) -> Result<(), String> {
+        self.pager.release_pages(num_pages)
+    }
+
+    /// Returns the page cache usage counters
+    pub fn metrics(&self) -> PagerMetrics {
+        self.pager.metrics()
+    }
+
+    /// Returns the size of the on-disk file in bytes; pages that haven't been flushed are not
+    /// included
+    pub fn size_bytes(&self) -> u64 {
+        self.pager.file_len()
+    }
+
+    /// Returns whether mutations were recorded since the last successful flush
+    pub fn has_unflushed_changes(&self) -> bool {
+        self.pending_mutations > 0
+    }
+
+    /// Writes all changes to the on-disk file
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(catalog) = self.catalog.as_mut() {
+            catalog.set_num_pages(self.pager.num_pages());
+        }
+        self.pager.flush_cache()?;
+        self.pending_mutations = 0;
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+}
+
+impl StorageEngine for Table {
+    fn insert(&mut self, identifier: u128, value: Vec<u8>) -> super::Result<()> {
+        Ok(Cursor::new(self).insert(identifier, value)?)
+    }
+
+    fn update(&mut self, identifier: u128, value: Vec<u8>) -> super::Result<()> {
+        Ok(Cursor::new(self).update(identifier, value)?)
+    }
+
+    fn remove(&mut self, identifier: u128) -> super::Result<()> {
+        Ok(Cursor::new(self).remove(identifier)?)
+    }
+
+    fn get(&mut self, identifier: u128) -> super::Result<Vec<u8>> {
+        Ok(Cursor::new(self)
+            .get(identifier)
+            .ok_or(NodeResult::KeyDoesNotExist)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_fs::NamedTempFile;
+
+    use super::*;
+    use crate::{
+        calculate_offsets,
+        storage::{
+            cell::CellFlags,
+            layout::{
+                CATALOG_FORMAT_VERSION_OFFSET, CATALOG_FORMAT_VERSION_SIZE, CATALOG_MAX_TABLES,
+                CATALOG_NUM_TABLES_OFFSET, CATALOG_NUM_TABLES_SIZE, CATALOG_SCHEMAS_OFFSET,
+                CATALOG_SIGNED_KEYS_BIT, LEAF_CELL_HAS_OVERFLOW_FLAG_OFFSET,
+                LEAF_FREE_SPACE_END_OFFSET, LEAF_FREE_SPACE_END_SIZE, LEAF_FREE_SPACE_START_OFFSET,
+                LEAF_FREE_SPACE_START_SIZE, LEAF_HEADER_SIZE, LEAF_KEY_CELL_SIZE,
+                LEAF_KEY_IDENTIFIER_SIZE, LEAF_KEY_INDENTIFIER_OFFSET, LEAF_KEY_POINTER_OFFSET,
+                LEAF_KEY_POINTER_SIZE, LEAF_LEGACY_CONTENT_LEN_SIZE, LEAF_NUM_KEYS_OFFSET,
+                LEAF_NUM_KEYS_SIZE, LEGACY_PAGE_MAGIC, PAGE_MAGIC_OFFSET, PAGE_MAGIC_SIZE,
+                PAGE_SIZE,
+            },
+            page::{check_magic, Page},
+            schema,
+        },
+        validate, Cursor,
+    };
+
+    #[test]
+    fn flushes_after_n_mutations() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        table.set_flush_policy(FlushPolicy {
+            every_ops: Some(5),
+            every: None,
+        });
+
+        for i in 0..4 {
+            Cursor::new(&mut table).insert(i, b"data".to_vec()).unwrap();
+        }
+        let mut reopened = Table::new(file.path().to_path_buf()).unwrap();
+        assert_eq!(Cursor::new(&mut reopened).get(0), None);
+
+        Cursor::new(&mut table).insert(4, b"data".to_vec()).unwrap();
+        let mut reopened = Table::new(file.path().to_path_buf()).unwrap();
+        for i in 0..5 {
+            assert_eq!(Cursor::new(&mut reopened).get(i), Some(b"data".to_vec()));
+        }
+    }
+
+    #[test]
+    fn flushes_after_interval() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        table.set_flush_policy(FlushPolicy {
+            every_ops: None,
+            every: Some(Duration::ZERO),
+        });
+
+        Cursor::new(&mut table).insert(1, b"data".to_vec()).unwrap();
+        let mut reopened = Table::new(file.path().to_path_buf()).unwrap();
+        assert_eq!(Cursor::new(&mut reopened).get(1), Some(b"data".to_vec()));
+    }
+
+    #[test]
+    fn flushes_after_interval_without_mutations() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        Cursor::new(&mut table).insert(1, b"data".to_vec()).unwrap();
+        table.set_flush_policy(FlushPolicy {
+            every_ops: None,
+            every: Some(Duration::ZERO),
+        });
+
+        // Only a read happens once the interval has passed
+        assert_eq!(Cursor::new(&mut table).get(1), Some(b"data".to_vec()));
+        assert!(!table.has_unflushed_changes());
+        let mut reopened = Table::new(file.path().to_path_buf()).unwrap();
+        assert_eq!(Cursor::new(&mut reopened).get(1), Some(b"data".to_vec()));
+    }
+
+    #[test]
+    fn page_count_tracks_splits() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        // Catalog and the root of the default table
+        assert_eq!(table.page_count(), 2);
+
+        let mut key = 0;
+        while table.page_count() == 2 {
+            Cursor::new(&mut table)
+                .insert(key, b"data".to_vec())
+                .unwrap();
+            key += 1;
+        }
+        // Splitting the root allocates the new sibling and relocates the old root
+        assert_eq!(table.page_count(), 4);
+
+        while table.page_count() == 4 {
+            Cursor::new(&mut table)
+                .insert(key, b"data".to_vec())
+                .unwrap();
+            key += 1;
+        }
+        assert_eq!(table.page_count(), 5);
+
+        table.flush().unwrap();
+        assert_eq!(table.size_bytes(), 5 * PAGE_SIZE as u64);
+    }
+
+    #[test]
+    fn root_split_links_new_root() {
+        let mut table = Table::in_memory();
+        let mut key = 0;
+        while table.page_count() == 2 {
+            Cursor::new(&mut table)
+                .insert(key, b"data".to_vec())
+                .unwrap();
+            key += 1;
+        }
+
+        let root = Node::load(table.root_page()).unwrap();
+        assert_eq!(root.node_type(), PageType::Internal);
+        assert!(root.is_root());
+        assert_eq!(root.num_cells(), 1);
+
+        let left = InternalCell::from_bytes(root.read_cell_bytes(0).unwrap());
+        let right = root.right_child().unwrap();
+        for (page, keys) in [
+            (left.pointer(), 0..=left.key()),
+            (right, left.key() + 1..=key - 1),
+        ] {
+            let node = Node::load(table.get_page(page).unwrap()).unwrap();
+            assert_eq!(node.node_type(), PageType::Leaf);
+            assert!(!node.is_root());
+            let stored = (0..node.num_cells()).map(|i| node.cell_key(i));
+            assert!(stored.eq(keys));
+        }
+        assert_eq!(u128::from(validate(&mut table).unwrap().keys), key);
+    }
+
+    #[test]
+    fn root_split_promotes_left_subtree_max() {
+        let mut table = Table::in_memory();
+        // Even keys only, so there is room on both sides of the separator
+        let mut key = 1000;
+        while table.page_count() == 2 {
+            table.put(key, format!("{key}data").as_bytes()).unwrap();
+            key += 2;
+        }
+
+        let root = Node::load(table.root_page()).unwrap();
+        let left = InternalCell::from_bytes(root.read_cell_bytes(0).unwrap());
+        let leaf = Node::load(table.get_page(left.pointer()).unwrap()).unwrap();
+        assert_eq!(Some(left.key()), leaf.node_high_key());
+        assert!(left.key() > 1000);
+
+        // Keys on both sides of the separator, and below every stored key, route to the leaf
+        // that holds them
+        let new_keys = [0, 1, 999, left.key() - 1, left.key() + 1, key + 1];
+        for k in new_keys {
+            table.put(k, format!("{k}data").as_bytes()).unwrap();
+        }
+        for k in (1000..key).step_by(2).chain(new_keys) {
+            assert_eq!(table.get_bytes(k), Some(format!("{k}data").into_bytes()));
+        }
+        let leaf = Node::load(table.get_page(left.pointer()).unwrap()).unwrap();
+        assert_eq!(leaf.cell_key(0), 0);
+        assert!(leaf.check_key_exists(left.key() - 1));
+        assert!(!leaf.check_key_exists(left.key() + 1));
+        assert_eq!(
+            u128::from(validate(&mut table).unwrap().keys),
+            (key - 1000) / 2 + new_keys.len() as u128
+        );
+    }
+
+    #[test]
+    fn returned_page_numbers_match_pages() {
+        let mut table = Table::in_memory();
+        table.put(1, b"one").unwrap();
+        let root = table.root;
+
+        let new = table.create_page(&PageType::Leaf).unwrap();
+        assert_eq!(new.num, PageNum(table.page_count() - 1));
+        assert!(Arc::ptr_eq(
+            &new.page.0,
+            &table.get_page(new.num).unwrap().0
+        ));
+
+        let split = table.create_new_root(1, new.num).unwrap();
+        assert_eq!(split.old_root_num, PageNum(table.page_count() - 1));
+        assert_ne!(split.old_root_num, root);
+        assert_eq!(table.root, root);
+        let moved = table.get_page(split.old_root_num).unwrap();
+        assert!(Arc::ptr_eq(&split.page.0, &moved.0));
+
+        // The old root contents moved; the root page now links to them
+        let old_root = Node::load(moved).unwrap();
+        assert_eq!(old_root.node_type(), PageType::Leaf);
+        assert!(!old_root.is_root());
+        assert_eq!(old_root.cell_key(0), 1);
+        let root = Node::load(table.root_page()).unwrap();
+        let left = InternalCell::from_bytes(root.read_cell_bytes(0).unwrap());
+        assert_eq!(left.pointer(), split.old_root_num);
+        assert_eq!(root.right_child(), Some(new.num));
+    }
+
+    #[test]
+    fn root_splits_fail_on_invalid_roots() {
+        let mut table = Table::in_memory();
+        let right = table.create_page(&PageType::Leaf).unwrap().num;
+        let pages = table.page_count();
+
+        table.root = PageNum(999);
+        assert_eq!(
+            table.create_new_root(1, right).unwrap_err(),
+            "root page 999 does not exist"
+        );
+        table.root = PageNum(CATALOG_PAGE_NUM);
+        assert_eq!(
+            table.create_new_root(1, right).unwrap_err(),
+            "root page 0 is not a tree page"
+        );
+        assert_eq!(table.page_count(), pages);
+    }
+
+    #[test]
+    fn utf8_values_only_apply_to_text() {
+        let mut table = Table::in_memory();
+        table.set_utf8_values(true);
+        assert_eq!(table.put(1, &[0xFF, 0xFE]), Err(NodeResult::InvalidUtf8));
+        table.put(1, "text".as_bytes()).unwrap();
+        assert_eq!(
+            Cursor::new(&mut table).update(1, vec![0xFF]),
+            Err(NodeResult::InvalidUtf8)
+        );
+
+        // Int values are stored as raw bytes and decoded before they are printed
+        table.open_typed("ints", ValueType::Int).unwrap();
+        table.put(1, &(-1i64).to_be_bytes()).unwrap();
+    }
+
+    #[test]
+    fn in_memory_table() {
+        let mut table = Table::in_memory();
+        for i in 0..300 {
+            Cursor::new(&mut table)
+                .insert(i, format!("{i}data").into_bytes())
+                .unwrap();
+        }
+        table.flush().unwrap();
+
+        let rows = Cursor::new(&mut table).select().unwrap();
+        assert_eq!(rows.len(), 300);
+        assert_eq!(rows[299], (299, b"299data".to_vec()));
+        assert!(table.page_count() > 1);
+        assert_eq!(table.size_bytes(), 0);
+    }
+
+    #[test]
+    fn named_tables_are_isolated() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+
+        table.open("users").unwrap();
+        for i in 0..200 {
+            Cursor::new(&mut table)
+                .insert(i, format!("user{i}").into_bytes())
+                .unwrap();
+        }
+        table.open("orders").unwrap();
+        for i in 100..150 {
+            Cursor::new(&mut table)
+                .insert(i, format!("order{i}").into_bytes())
+                .unwrap();
+        }
+        table.flush().unwrap();
+
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        assert_eq!(table.tables(), vec!["db", "users", "orders"]);
+        assert!(Cursor::new(&mut table).select().unwrap().is_empty());
+
+        table.open("users").unwrap();
+        let users = Cursor::new(&mut table).select().unwrap();
+        assert_eq!(users.len(), 200);
+        assert_eq!(users[120], (120, b"user120".to_vec()));
+
+        table.open("orders").unwrap();
+        let orders = Cursor::new(&mut table).select().unwrap();
+        assert_eq!(orders.len(), 50);
+        assert_eq!(orders[20], (120, b"order120".to_vec()));
+    }
+
+    #[test]
+    fn invalid_table_names() {
+        let mut table = Table::in_memory();
+        assert!(table.open("").is_err());
+        assert!(table.open("two words").is_err());
+        assert!(table.open(&"x".repeat(33)).is_err());
+        assert_eq!(table.name(), DEFAULT_TABLE);
+        assert_eq!(table.page_count(), 2);
+    }
+
+    #[test]
+    fn recovers_from_partial_page() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        Cursor::new(&mut table).insert(1, b"data".to_vec()).unwrap();
+        table.flush().unwrap();
+
+        // Simulate a write that was interrupted part of the way through a new page
+        let mut contents = std::fs::read(file.path()).unwrap();
+        contents.extend_from_slice(&[0xFF; 10]);
+        std::fs::write(file.path(), contents).unwrap();
+
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        assert_eq!(table.size_bytes(), 2 * PAGE_SIZE as u64);
+        assert_eq!(Cursor::new(&mut table).get(1), Some(b"data".to_vec()));
+
+        Cursor::new(&mut table).insert(2, b"more".to_vec()).unwrap();
+        table.flush().unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        assert_eq!(Cursor::new(&mut table).select().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn replays_operation_log() {
+        let log = NamedTempFile::new("temp.log").unwrap();
+        let mut primary = Table::in_memory();
+        primary.set_op_log(Some(log.path())).unwrap();
+
+        let mut cursor = Cursor::new(&mut primary);
+        for i in 0..300 {
+            cursor.insert(i, format!("{i}data").into_bytes()).unwrap();
+        }
+        cursor.delete_range(10, 20).unwrap();
+        cursor.insert(15, vec![0xFF, 0x0]).unwrap();
+        // Failed mutations are not logged
+        assert!(cursor.insert(15, b"again".to_vec()).is_err());
+
+        let mut replica = Table::in_memory();
+        assert_eq!(replica.replay_from(log.path()), Ok(302));
+        assert_eq!(
+            Cursor::new(&mut replica).select().unwrap(),
+            Cursor::new(&mut primary).select().unwrap()
+        );
+    }
+
+    #[test]
+    fn stores_raw_bytes() {
+        let mut table = Table::in_memory();
+        let value = [0x0, 0xFF, 0xC3, 0x28, b'\n'];
+
+        table.put(1, &value).unwrap();
+        assert_eq!(table.put(1, &value), Err(NodeResult::DuplicateKey));
+        assert_eq!(table.get_bytes(1), Some(value.to_vec()));
+        assert_eq!(table.get_bytes(2), None);
+    }
+
+    #[test]
+    fn contains_key_after_splits() {
+        let mut table = Table::in_memory();
+        for i in (0..2000).step_by(2) {
+            table.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+        assert!(table.page_count() > 3);
+
+        assert!((0..2000).step_by(2).all(|i| table.contains_key(i)));
+        assert!(!(1..2000).step_by(2).any(|i| table.contains_key(i)));
+        assert!(!table.contains_key(u64::MAX.into()));
+    }
+
+    #[test]
+    fn bloom_filters_skip_absent_keys() {
+        fn page_requests(table: &Table) -> u64 {
+            let metrics = table.metrics();
+            metrics.cache_hits + metrics.cache_misses
+        }
+
+        let mut table = Table::in_memory();
+        table.set_max_internal_keys(Some(4));
+        table.set_bloom_filters(true).unwrap();
+        for i in (0..1000).step_by(2) {
+            table.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+        // Filters built from an existing tree are kept up to date by later splits
+        let mut rebuilt = Table::in_memory();
+        rebuilt.set_max_internal_keys(Some(4));
+        for i in (0..500).step_by(2) {
+            rebuilt.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+        rebuilt.set_bloom_filters(true).unwrap();
+        for i in (500..1000).step_by(2) {
+            rebuilt.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+
+        for table in [&mut table, &mut rebuilt] {
+            assert!((0..1000).step_by(2).all(|i| table.contains_key(i)));
+
+            let before = page_requests(table);
+            assert!(!(1..1000).step_by(2).any(|i| table.contains_key(i)));
+            let filtered = page_requests(table) - before;
+
+            table.set_bloom_filters(false).unwrap();
+            let before = page_requests(table);
+            assert!(!(1..1000).step_by(2).any(|i| table.contains_key(i)));
+            let unfiltered = page_requests(table) - before;
+
+            // Every lookup requests the root twice, when the cursor is created and when it is
+            // reset; only false positives of the root filter descend any further
+            assert!(filtered < 2 * 500 + 50, "{filtered} page requests");
+            assert!(unfiltered >= 4 * 500, "{unfiltered} page requests");
+        }
+    }
+
+    #[test]
+    fn snapshots_ignore_concurrent_inserts() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        for i in (0..2000).step_by(2) {
+            table.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+        table.flush().unwrap();
+        let expected = Cursor::new(&mut table).select().unwrap();
+        drop(table);
+
+        // Pages the snapshot did not read yet are read from the file while it is being written
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        let mut snapshot = table.snapshot().unwrap();
+        let (started, wait) = std::sync::mpsc::channel();
+        let reader = std::thread::spawn(move || {
+            let mut cursor = Cursor::new(&mut snapshot);
+            let mut records = Vec::new();
+            for (i, record) in cursor.iter().enumerate() {
+                records.push(record.unwrap());
+                if i == 0 {
+                    started.send(()).unwrap();
+                }
+                if i % 100 == 0 {
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+            }
+            records
+        });
+
+        // Fill the gaps and grow the tree while the scan runs; splitting most of the leaves
+        wait.recv().unwrap();
+        for i in (1..4000).step_by(2) {
+            table.put(i, format!("{i}new").as_bytes()).unwrap();
+            if i % 200 == 1 {
+                table.flush().unwrap();
+            }
+        }
+        let records = reader.join().unwrap();
+
+        assert_eq!(records, expected);
+        assert_eq!(Cursor::new(&mut table).select().unwrap().len(), 1000 + 2000);
+    }
+
+    /// Returns a root leaf holding `records` the way files before format version 2 stored them,
+    /// with fixed size content lengths
+    fn legacy_leaf(records: &[(u64, &[u8])]) -> Page {
+        let mut page = PageBuilder::default()
+            .kind(&PageType::Leaf)
+            .is_root(true)
+            .build();
+        let mut end = PAGE_SIZE;
+        for (i, (key, value)) in records.iter().enumerate() {
+            end -= LEAF_LEGACY_CONTENT_LEN_SIZE + value.len();
+            page[end..end + LEAF_LEGACY_CONTENT_LEN_SIZE]
+                .clone_from_slice(&(value.len() as u64).to_be_bytes());
+            page[end + LEAF_LEGACY_CONTENT_LEN_SIZE
+                ..end + LEAF_LEGACY_CONTENT_LEN_SIZE + value.len()]
+                .clone_from_slice(value);
+
+            let cell = LEAF_HEADER_SIZE + i * LEAF_KEY_CELL_SIZE;
+            page[cell + LEAF_CELL_HAS_OVERFLOW_FLAG_OFFSET] = CellFlags::default().to_byte();
+            let key_offset = cell + LEAF_KEY_INDENTIFIER_OFFSET;
+            page[key_offset..key_offset + LEAF_KEY_IDENTIFIER_SIZE]
+                .clone_from_slice(&key.to_be_bytes());
+            let pointer_offset = cell + LEAF_KEY_POINTER_OFFSET;
+            page[pointer_offset..pointer_offset + LEAF_KEY_POINTER_SIZE]
+                .clone_from_slice(&end.to_be_bytes());
+        }
+
+        let (start, stop) = calculate_offsets!(LEAF_NUM_KEYS_OFFSET, LEAF_NUM_KEYS_SIZE);
+        page[start..stop].clone_from_slice(&records.len().to_be_bytes());
+        let (start, stop) =
+            calculate_offsets!(LEAF_FREE_SPACE_START_OFFSET, LEAF_FREE_SPACE_START_SIZE);
+        page[start..stop].clone_from_slice(
+            &(LEAF_HEADER_SIZE + records.len() * LEAF_KEY_CELL_SIZE).to_be_bytes(),
+        );
+        let (start, stop) =
+            calculate_offsets!(LEAF_FREE_SPACE_END_OFFSET, LEAF_FREE_SPACE_END_SIZE);
+        page[start..stop].clone_from_slice(&end.to_be_bytes());
+        page
+    }
+
+    #[test]
+    fn upgrades_older_format_versions() {
+        let records: [(u64, &[u8]); 3] = [(1, b"one"), (2, b""), (3, &[0x42; 300])];
+        let leaf = legacy_leaf(&records);
+
+        // Version 0 files are a single tree rooted at the first page
+        let v0 = NamedTempFile::new("v0.db").unwrap();
+        let mut bytes = leaf.0;
+        let (start, end) = calculate_offsets!(PAGE_MAGIC_OFFSET, PAGE_MAGIC_SIZE);
+        bytes[start..end].clone_from_slice(&LEGACY_PAGE_MAGIC.to_be_bytes());
+        std::fs::write(v0.path(), bytes).unwrap();
+
+        // Version 1 files have a catalog but the same leaves
+        let v1 = NamedTempFile::new("v1.db").unwrap();
+        let catalog = CachedPage::new(PageBuilder::default().kind(&PageType::Catalog).build());
+        let mut loaded = Catalog::load(catalog.clone()).unwrap();
+        loaded.add(DEFAULT_TABLE, PageNum(1)).unwrap();
+        loaded.set_format_version(1);
+        let mut bytes = catalog.0.read().unwrap().0.to_vec();
+        bytes.extend_from_slice(&leaf.0);
+        std::fs::write(v1.path(), bytes).unwrap();
+
+        for file in [&v0, &v1] {
+            let mut table = Table::new(file.path().to_path_buf()).unwrap();
+            assert_eq!(table.format_version(), FORMAT_VERSION);
+            assert_eq!(table.tables(), vec![DEFAULT_TABLE.to_string()]);
+            for (key, value) in records {
+                assert_eq!(
+                    Cursor::new(&mut table).get(key.into()),
+                    Some(value.to_vec())
+                );
+            }
+            table.put(4, b"four").unwrap();
+            validate(&mut table).unwrap();
+            table.flush().unwrap();
+            drop(table);
+
+            // The upgrade was written back, so reopening does not convert the leaves again
+            let mut table = Table::new(file.path().to_path_buf()).unwrap();
+            assert_eq!(table.format_version(), FORMAT_VERSION);
+            assert_eq!(Cursor::new(&mut table).get(3), Some(vec![0x42; 300]));
+            assert_eq!(Cursor::new(&mut table).get(4), Some(b"four".to_vec()));
+        }
+    }
+
+    #[test]
+    fn upgrades_catalogs_without_schemas() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        table.put(1, b"data").unwrap();
+        table.flush().unwrap();
+        drop(table);
+
+        let mut bytes = std::fs::read(file.path()).unwrap();
+        let (start, end) =
+            calculate_offsets!(CATALOG_FORMAT_VERSION_OFFSET, CATALOG_FORMAT_VERSION_SIZE);
+        bytes[start..end].clone_from_slice(&2u64.to_be_bytes());
+        std::fs::write(file.path(), &bytes).unwrap();
+
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        assert_eq!(table.format_version(), FORMAT_VERSION);
+        assert_eq!(table.schema(), None);
+        assert_eq!(table.get_bytes(1), Some(b"data".to_vec()));
+        drop(table);
+
+        // Entries of catalogs with more tables would overlap the schemas; they are refused when
+        // the catalog is loaded
+        bytes[start..end].clone_from_slice(&2u64.to_be_bytes());
+        let (start, end) = calculate_offsets!(CATALOG_NUM_TABLES_OFFSET, CATALOG_NUM_TABLES_SIZE);
+        bytes[start..end].clone_from_slice(&(CATALOG_MAX_TABLES as u64 + 1).to_be_bytes());
+        std::fs::write(file.path(), &bytes).unwrap();
+        let Err(err) = Table::new(file.path().to_path_buf()) else {
+            panic!("upgraded a catalog without room for schemas");
+        };
+        assert!(err.contains("at most"));
+    }
+
+    #[test]
+    fn rejects_newer_format_versions() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        table.put(1, b"data").unwrap();
+        table.flush().unwrap();
+        drop(table);
+
+        let mut bytes = std::fs::read(file.path()).unwrap();
+        let (start, end) =
+            calculate_offsets!(CATALOG_FORMAT_VERSION_OFFSET, CATALOG_FORMAT_VERSION_SIZE);
+        bytes[start..end].clone_from_slice(&(FORMAT_VERSION + 1).to_be_bytes());
+        std::fs::write(file.path(), &bytes).unwrap();
+
+        let Err(err) = Table::new(file.path().to_path_buf()) else {
+            panic!("opened a file from a newer format version");
+        };
+        assert!(err.contains(&format!("format version {}", FORMAT_VERSION + 1)));
+    }
+
+    #[test]
+    fn rejects_catalogs_with_too_many_tables() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        Table::new(file.path().to_path_buf())
+            .unwrap()
+            .flush()
+            .unwrap();
+
+        let mut bytes = std::fs::read(file.path()).unwrap();
+        let (start, _) = calculate_offsets!(CATALOG_NUM_TABLES_OFFSET, CATALOG_NUM_TABLES_SIZE);
+        bytes[start + 2] = 0x7f;
+        std::fs::write(file.path(), &bytes).unwrap();
+
+        let Err(err) = Table::new(file.path().to_path_buf()) else {
+            panic!("opened a catalog with more tables than fit into it");
+        };
+        assert!(err.contains(&format!("at most {CATALOG_MAX_TABLES} fit")));
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_files_hide_values() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new_encrypted(file.path().into(), "secret").unwrap();
+        for key in 0..200 {
+            table
+                .put(key, format!("plaintext value {key}").as_bytes())
+                .unwrap();
+        }
+        table.flush().unwrap();
+        drop(table);
+
+        let bytes = std::fs::read(file.path()).unwrap();
+        assert!(!bytes.windows(15).any(|w| w == b"plaintext value"));
+
+        let mut table = Table::new_encrypted(file.path().into(), "secret").unwrap();
+        assert!(table.is_encrypted());
+        assert_eq!(table.get_bytes(150), Some(b"plaintext value 150".to_vec()));
+        assert_eq!(Cursor::new(&mut table).select().unwrap().len(), 200);
+
+        assert!(Table::new_encrypted(file.path().into(), "guess").is_err());
+        let mut locked = Table::new(file.path().into()).unwrap();
+        assert_eq!(
+            locked.open(DEFAULT_TABLE),
+            Err("file is encrypted; a key is required to open it".to_string())
+        );
+        assert_eq!(std::fs::read(file.path()).unwrap(), bytes);
+    }
+
+    #[test]
+    fn backs_up_unflushed_changes() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let backup = NamedTempFile::new("backup.db").unwrap();
+        let mut table = Table::new(file.path().into()).unwrap();
+        for key in 0..300 {
+            table.put(key, format!("value {key}").as_bytes()).unwrap();
+        }
+        table.flush().unwrap();
+        // Neither flushed nor part of the source file yet
+        for key in 300..400 {
+            table.put(key, format!("value {key}").as_bytes()).unwrap();
+        }
+
+        let pages = table.backup_to(backup.path()).unwrap();
+        assert_eq!(pages, table.page_count());
+        assert!(table.backup_to(backup.path()).is_err());
+        table.put(400, b"after backup").unwrap();
+
+        let mut copy = Table::new(backup.path().into()).unwrap();
+        copy.open(DEFAULT_TABLE).unwrap();
+        let records = Cursor::new(&mut copy).select().unwrap();
+        assert_eq!(records.len(), 400);
+        assert_eq!(copy.get_bytes(350), Some(b"value 350".to_vec()));
+        assert!(!copy.contains_key(400));
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn backups_keep_the_key() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let backup = NamedTempFile::new("backup.db").unwrap();
+        let mut table = Table::new_encrypted(file.path().into(), "secret").unwrap();
+        table.put(1, b"hidden").unwrap();
+        table.backup_to(backup.path()).unwrap();
+
+        let mut copy = Table::new_encrypted(backup.path().into(), "secret").unwrap();
+        assert_eq!(copy.get_bytes(1), Some(b"hidden".to_vec()));
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_files_refuse_op_logs() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let log = NamedTempFile::new("temp.log").unwrap();
+        let mut table = Table::new_encrypted(file.path().into(), "secret").unwrap();
+
+        assert!(table.set_op_log(Some(log.path())).is_err());
+        assert!(!table.logs_operations());
+        assert!(!log.path().exists());
+        table.set_op_log(None).unwrap();
+    }
+
+    #[test]
+    fn clear_truncates_the_file() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().into()).unwrap();
+        for key in 0..1000 {
+            table.put(key, format!("value {key}").as_bytes()).unwrap();
+        }
+        table.flush().unwrap();
+        assert!(table.page_count() > 2);
+
+        table.clear().unwrap();
+        assert_eq!(Cursor::new(&mut table).count(), Ok(0));
+        assert_eq!(Cursor::new(&mut table).select(), Ok(vec![]));
+        // The catalog and the root of the table are all that is left
+        assert_eq!(table.page_count(), 2);
+        table.put(7, b"again").unwrap();
+        table.flush().unwrap();
+        assert_eq!(
+            std::fs::metadata(file.path()).unwrap().len(),
+            2 * PAGE_SIZE as u64
+        );
+
+        let mut table = Table::new(file.path().into()).unwrap();
+        table.open(DEFAULT_TABLE).unwrap();
+        assert_eq!(
+            Cursor::new(&mut table).select(),
+            Ok(vec![(7, b"again".to_vec())])
+        );
+    }
+
+    #[test]
+    fn clear_keeps_other_tables() {
+        let mut table = Table::in_memory();
+        table.put(1, b"db").unwrap();
+        table.open("users").unwrap();
+        table.put(1, b"alice").unwrap();
+
+        table.clear().unwrap();
+        assert!(!table.contains_key(1));
+        table.open(DEFAULT_TABLE).unwrap();
+        assert_eq!(table.get_bytes(1), Some(b"db".to_vec()));
+    }
+
+    #[test]
+    fn storage_engine_errors_are_boxed() {
+        let mut table = Table::in_memory();
+        StorageEngine::insert(&mut table, 1, b"one".to_vec()).unwrap();
+        StorageEngine::update(&mut table, 1, b"uno".to_vec()).unwrap();
+        assert_eq!(StorageEngine::get(&mut table, 1).unwrap(), b"uno");
+
+        let e = StorageEngine::insert(&mut table, 1, b"again".to_vec()).unwrap_err();
+        assert_eq!(e.to_string(), "duplicate key");
+        assert_eq!(
+            e.downcast_ref::<NodeResult>(),
+            Some(&NodeResult::DuplicateKey)
+        );
+
+        StorageEngine::remove(&mut table, 1).unwrap();
+        let e = StorageEngine::get(&mut table, 1).unwrap_err();
+        assert_eq!(e.to_string(), "key does not exist");
+        assert!(StorageEngine::update(&mut table, 1, b"one".to_vec()).is_err());
+    }
+
+    #[test]
+    fn page_count_is_restored_from_catalog() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        for i in 0..1000 {
+            table.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+        // The root split moved the old root into a new page
+        let root = Node::load(table.root_page()).unwrap();
+        assert_eq!(root.node_type(), PageType::Internal);
+        table.flush().unwrap();
+        let pages = table.page_count();
+        let expected = Cursor::new(&mut table).select().unwrap();
+        drop(table);
+
+        // Pages allocated after the last flush are not part of the file
+        let mut bytes = std::fs::read(file.path()).unwrap();
+        bytes.extend_from_slice(&[0x0; PAGE_SIZE * 3]);
+        std::fs::write(file.path(), &bytes).unwrap();
+
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        assert_eq!(table.page_count(), pages);
+        assert_eq!(Cursor::new(&mut table).select().unwrap(), expected);
+
+        table.put(1000, b"1000data").unwrap();
+        assert!(table.page_count() <= pages + 1);
+        assert_eq!(validate(&mut table).unwrap().keys, 1001);
+    }
+
+    #[test]
+    fn schemas_are_stored_in_the_catalog() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        table.open_typed("numbers", ValueType::Int).unwrap();
+        assert_eq!(table.schema(), Some(ValueType::Int));
+        table.open(DEFAULT_TABLE).unwrap();
+        assert_eq!(table.schema(), None);
+        table.flush().unwrap();
+        drop(table);
+
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        table.open("numbers").unwrap();
+        assert_eq!(table.schema(), Some(ValueType::Int));
+        assert!(table.open_typed("numbers", ValueType::Int).is_ok());
+        assert_eq!(
+            table.open_typed("numbers", ValueType::Text),
+            Err("table `numbers` stores int values".to_string())
+        );
+        assert_eq!(
+            table.open_typed(DEFAULT_TABLE, ValueType::Int),
+            Err("table `db` has no schema".to_string())
+        );
+    }
+
+    #[test]
+    fn writes_have_to_match_the_schema() {
+        let log = NamedTempFile::new("temp.log").unwrap();
+        let mut untyped = Table::in_memory();
+        untyped.set_op_log(Some(log.path())).unwrap();
+        untyped.put(1, b"one").unwrap();
+
+        let mut table = Table::in_memory();
+        table.open_typed("numbers", ValueType::Int).unwrap();
+        let mismatch = Err(NodeResult::SchemaMismatch(ValueType::Int));
+        assert_eq!(table.put(1, b"one"), mismatch);
+        assert_eq!(table.append(b"one").map(|_| ()), mismatch);
+        assert_eq!(
+            table.bulk_insert([(1, b"one".to_vec())], None).map(|_| ()),
+            mismatch
+        );
+        assert_eq!(
+            Cursor::new(&mut table)
+                .get_or_insert(1, b"one".to_vec())
+                .map(|_| ()),
+            mismatch
+        );
+        assert!(table
+            .replay_from(log.path())
+            .unwrap_err()
+            .contains("value is not a valid int"));
+
+        table.put(1, &1i64.to_be_bytes()).unwrap();
+        assert_eq!(Cursor::new(&mut table).update(1, b"one".to_vec()), mismatch);
+        assert_eq!(Cursor::new(&mut table).rekey(1, 2), Ok(()));
+        assert_eq!(table.get_bytes(2), Some(1i64.to_be_bytes().to_vec()));
+
+        // Text has to be valid UTF-8 whether or not untyped values are checked
+        table.open_typed("names", ValueType::Text).unwrap();
+        assert!(!table.utf8_values());
+        assert_eq!(
+            table.put(1, &[0xC3, 0x28]),
+            Err(NodeResult::SchemaMismatch(ValueType::Text))
+        );
+        table.open_typed("blobs", ValueType::Blob).unwrap();
+        assert_eq!(table.put(1, &[0xC3, 0x28]), Ok(()));
+    }
+
+    #[test]
+    fn key_types_are_stored_in_the_catalog() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().into()).unwrap();
+        table.open_typed("signed", ValueType::Int).unwrap();
+        table.set_key_type(KeyType::Signed).unwrap();
+        for key in [5, -5, 0] {
+            table
+                .put(schema::encode_signed(key).into(), &key.to_be_bytes())
+                .unwrap();
+        }
+        assert!(table.set_key_type(KeyType::Unsigned).is_err());
+        table.flush().unwrap();
+
+        let mut table = Table::new(file.path().into()).unwrap();
+        assert_eq!(table.key_type(), KeyType::Unsigned);
+        table.open("signed").unwrap();
+        assert_eq!(table.key_type(), KeyType::Signed);
+        assert_eq!(table.schema(), Some(ValueType::Int));
+        let keys = Cursor::new(&mut table)
+            .select()
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| schema::decode_signed(key as u64))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, [-5, 0, 5]);
+    }
+
+    #[test]
+    fn wide_keys_need_the_current_format_version() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().into()).unwrap();
+        table.open("uuids").unwrap();
+        table.set_key_type(KeyType::U128).unwrap();
+        table.put(u128::MAX, b"data").unwrap();
+        table.flush().unwrap();
+        drop(table);
+
+        // Binaries from before wide keys stop at version 4; they would read the cells as 8-byte
+        // keys
+        let mut bytes = std::fs::read(file.path()).unwrap();
+        let (start, end) =
+            calculate_offsets!(CATALOG_FORMAT_VERSION_OFFSET, CATALOG_FORMAT_VERSION_SIZE);
+        assert_eq!(bytes[start..end], 5u64.to_be_bytes());
+
+        // The same file stamped by such a binary is rejected
+        let mut stamped = bytes.clone();
+        stamped[start..end].clone_from_slice(&4u64.to_be_bytes());
+        std::fs::write(file.path(), &stamped).unwrap();
+        let Err(err) = Table::new(file.path().into()) else {
+            panic!("opened wide keys of a file stamped with format version 4");
+        };
+        assert!(err.contains("table `uuids` uses a key type"));
+
+        // Only one key type bit may be set
+        bytes[CATALOG_SCHEMAS_OFFSET + 1] |= CATALOG_SIGNED_KEYS_BIT;
+        std::fs::write(file.path(), &bytes).unwrap();
+        let mut table = Table::new(file.path().into()).unwrap();
+        assert!(table.open("uuids").unwrap_err().contains("key type bits"));
+    }
+
+    #[test]
+    fn wide_keys_are_not_lost_by_narrow_op_logs() {
+        let log = NamedTempFile::new("temp.log").unwrap();
+        std::fs::write(log.path(), b"btree-db oplog 1\n").unwrap();
+        let mut table = Table::in_memory();
+        table.set_key_type(KeyType::U128).unwrap();
+        table.put(1 << 64, b"wide").unwrap();
+        table.set_op_log(Some(log.path())).unwrap();
+
+        let wide = 1u128 << 65;
+        assert_eq!(table.put(wide, b"data"), Err(NodeResult::KeyNotLogged));
+        assert_eq!(table.get_bytes(wide), None);
+        let mut cursor = Cursor::new(&mut table);
+        assert_eq!(
+            cursor.update(1 << 64, b"new".to_vec()),
+            Err(NodeResult::KeyNotLogged)
+        );
+        assert_eq!(cursor.remove(1 << 64), Err(NodeResult::KeyNotLogged));
+        assert_eq!(cursor.get(1 << 64), Some(b"wide".to_vec()));
+        table.put(1, b"narrow").unwrap();
+
+        let logged = oplog::read(File::open(log.path()).unwrap()).unwrap();
+        assert_eq!(logged, vec![Operation::Insert(1, b"narrow".to_vec())]);
+    }
+
+    #[test]
+    fn orders_wide_keys() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().into()).unwrap();
+        table.open("uuids").unwrap();
+        table.set_key_type(KeyType::U128).unwrap();
+
+        // UUID-like keys; enough of them to split the root a few times
+        let mut state: u64 = 0x2545F4914F6CDD1D;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let mut keys = (0..2000)
+            .map(|_| (u128::from(next()) << 64) | u128::from(next()))
+            .collect::<Vec<_>>();
+        for key in keys.iter() {
+            table.put(*key, format!("{key:032x}").as_bytes()).unwrap();
+        }
+        table.flush().unwrap();
+        keys.sort();
+
+        let mut table = Table::new(file.path().into()).unwrap();
+        table.open("uuids").unwrap();
+        assert_eq!(table.key_type(), KeyType::U128);
+        let root = table.root_page();
+        assert_eq!(
+            table.load_node(root).unwrap().node_type(),
+            PageType::Internal
+        );
+        assert_eq!(validate(&mut table).unwrap().keys, 2000);
+        let records = Cursor::new(&mut table).select().unwrap();
+        assert!(records.iter().map(|(key, _)| *key).eq(keys));
+        for (key, value) in records {
+            assert_eq!(value, format!("{key:032x}").into_bytes());
+        }
+    }
+
+    #[test]
+    fn rebuilds_broken_leaf_chain() {
+        let mut table = Table::in_memory();
+        for i in 0..2000 {
+            table.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+        let expected = Cursor::new(&mut table).select().unwrap();
+        let leaves = validate(&mut table).unwrap().leaves;
+
+        // Cut the chain after the first leaf
+        let mut node = Node::load(table.root_page()).unwrap();
+        while node.node_type() == PageType::Internal {
+            let cell = InternalCell::from_bytes(node.read_cell_bytes(0).unwrap());
+            node = Node::load(table.get_page(cell.pointer()).unwrap()).unwrap();
+        }
+        node.set_next_sibling(PageNum(LEAF_NEXT_SIBLING_POINTER_DEFAULT));
+        assert!(Cursor::new(&mut table).select().unwrap().len() < expected.len());
+
+        assert_eq!(table.rebuild_leaf_chain(), Ok(leaves));
+        assert_eq!(Cursor::new(&mut table).select().unwrap(), expected);
+    }
+
+    #[test]
+    fn repairs_corrupt_free_space() {
+        let mut table = Table::in_memory();
+        for i in (0..2000).step_by(2) {
+            table.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+        let leaves = validate(&mut table).unwrap().leaves;
+
+        // Point the free space of the first leaf over its own content
+        let mut page = table.root_page();
+        let mut node = Node::load(page.clone()).unwrap();
+        while node.node_type() == PageType::Internal {
+            let cell = InternalCell::from_bytes(node.read_cell_bytes(0).unwrap());
+            page = table.get_page(cell.pointer()).unwrap();
+            node = Node::load(page.clone()).unwrap();
+        }
+        let free_space = node.free_space();
+        {
+            let mut page = page.0.write().unwrap();
+            let (start, end) =
+                calculate_offsets!(LEAF_FREE_SPACE_START_OFFSET, LEAF_FREE_SPACE_START_SIZE);
+            page[start..end].clone_from_slice(&0u64.to_be_bytes());
+            let (start, end) =
+                calculate_offsets!(LEAF_FREE_SPACE_END_OFFSET, LEAF_FREE_SPACE_END_SIZE);
+            page[start..end].clone_from_slice(&(PAGE_SIZE as u64).to_be_bytes());
+        }
+        assert_ne!(node.free_space(), free_space);
+
+        assert_eq!(table.repair_free_space(), Ok(leaves));
+        assert_eq!(node.free_space(), free_space);
+        for i in (1..2000).step_by(2) {
+            table.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+        let records = Cursor::new(&mut table).select().unwrap();
+        assert_eq!(records.len(), 2000);
+        for (i, (key, value)) in records.into_iter().enumerate() {
+            assert_eq!(key, i as u128);
+            assert_eq!(value, format!("{i}data").into_bytes());
+        }
+        validate(&mut table).unwrap();
+    }
+
+    #[test]
+    fn bulk_insert_leaves_headroom() {
+        let rows = (0..3000).map(|i| (i, format!("{i:08}data").into_bytes()));
+        let mut table = Table::in_memory();
+        assert_eq!(table.bulk_insert(rows.clone(), Some(0.5)), Ok(3000));
+        assert_eq!(validate(&mut table).unwrap().keys, 3000);
+
+        let mut page = table.root;
+        let mut node = Node::load(table.root_page()).unwrap();
+        while node.node_type() == PageType::Internal {
+            page = InternalCell::from_bytes(node.read_cell_bytes(0).unwrap()).pointer();
+            node = Node::load(table.get_page(page).unwrap()).unwrap();
+        }
+        let mut leaves = vec![node.fill_factor()];
+        while let Some(sibling) = node.next_sibling() {
+            node = Node::load(table.get_page(sibling).unwrap()).unwrap();
+            leaves.push(node.fill_factor());
+            page = sibling;
+        }
+        assert_ne!(page, table.root);
+
+        // Every leaf but the last stops taking keys once it is half full
+        leaves.pop();
+        assert!(leaves.iter().all(|fill| (0.5..0.55).contains(fill)));
+
+        let mut packed = Table::in_memory();
+        packed.bulk_insert(rows, Some(1.0)).unwrap();
+        let packed_leaves = validate(&mut packed).unwrap().leaves;
+        assert!(packed_leaves * 2 <= leaves.len() as u64 + 2);
+    }
+
+    #[test]
+    fn reads_pages_with_legacy_magic() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        for i in 0..500 {
+            table.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+        table.flush().unwrap();
+        let expected = Cursor::new(&mut table).select().unwrap();
+        drop(table);
+
+        let mut bytes = std::fs::read(file.path()).unwrap();
+        for page in bytes.chunks_mut(PAGE_SIZE) {
+            page[PAGE_MAGIC_OFFSET..PAGE_MAGIC_OFFSET + PAGE_MAGIC_SIZE]
+                .clone_from_slice(&LEGACY_PAGE_MAGIC.to_be_bytes());
+        }
+        std::fs::write(file.path(), &bytes).unwrap();
+
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        assert_eq!(Cursor::new(&mut table).select().unwrap(), expected);
+        table.put(500, b"500data").unwrap();
+        assert!(table.contains_key(500));
+
+        // Anything else is not a page
+        bytes[..PAGE_SIZE].fill(0x0);
+        assert!(check_magic(&bytes[..PAGE_SIZE]).is_err());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mapped_scan_matches_buffered_scan() {
+        let file = NamedTempFile::new("temp.db").unwrap();
+        let mut table = Table::new(file.path().to_path_buf()).unwrap();
+        for i in 0..2000 {
+            table.put(i, format!("{i}data").as_bytes()).unwrap();
+        }
+        table.flush().unwrap();
+
+        let mut buffered = Table::new(file.path().to_path_buf()).unwrap();
+        let expected = Cursor::new(&mut buffered).select().unwrap();
+        assert_eq!(expected.len(), 2000);
+
+        let mut mapped = Table::new(file.path().to_path_buf()).unwrap();
+        mapped.set_mmap(true).unwrap();
+        assert!(mapped.is_mapped());
+        assert_eq!(Cursor::new(&mut mapped).select().unwrap(), expected);
+        assert!(Table::in_memory().set_mmap(true).is_err());
     }
 }
@@ -0,0 +1,237 @@
+use std::{cmp::Ordering, thread};
+
+use super::{
+    btree::{KeyComparator, Node},
+    cell::{Cell, InternalCell},
+    layout::{leaf_key_cell_size, LEAF_SPACE_FOR_DATA},
+    page::{CachedPage, PageNum, PageType},
+    table::Table,
+};
+
+/// Summary of a successful validation scan
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidationReport {
+    /// Number of leaves in the sibling chain
+    pub leaves: u64,
+    /// Number of keys stored in the leaves
+    pub keys: u64,
+}
+
+/// Outcome of validating a run of consecutive leaves
+#[derive(Default)]
+struct RunSummary {
+    // Page and value of the first key in the run; only known once it has been read
+    first_key: Option<(PageNum, u128)>,
+    max_key: Option<u128>,
+    report: ValidationReport,
+    // First error found in the run; the scan of the run stops there
+    error: Option<String>,
+}
+
+/// Checks that the keys in the leaf chain of the current table are sorted and that every cell's
+/// content lies within its leaf.
+pub fn validate(table: &mut Table) -> Result<ValidationReport, String> {
+    validate_parallel(table, 1)
+}
+
+/// Same checks as [validate](validate), with the leaf chain split into `threads` runs of
+/// consecutive leaves that are checked concurrently.
+///
+/// The chain is walked once up-front to collect the leaves; their pages are then shared with the
+/// worker threads. Order across runs is checked after all threads have finished, so errors are
+/// reported exactly as the serial scan would.
+pub fn validate_parallel(table: &mut Table, threads: usize) -> Result<ValidationReport, String> {
+    let leaves = collect_leaves(table)?;
+    let compare = table.comparator();
+    let key_size = table.key_size();
+    let run_len = leaves.len().div_ceil(threads.max(1)).max(1);
+
+    let runs = thread::scope(|s| {
+        let handles = leaves
+            .chunks(run_len)
+            .map(|run| s.spawn(move || validate_run(run, compare, key_size)))
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("validation thread panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    let mut report = ValidationReport::default();
+    let mut prev_max: Option<u128> = None;
+    for run in runs {
+        // The first key of a run is checked before anything that follows it in the chain
+        if let (Some(prev), Some((page_num, key))) = (prev_max, run.first_key) {
+            if compare(key, prev) != Ordering::Greater {
+                return Err(out_of_order(page_num, key, prev));
+            }
+        }
+        if let Some(e) = run.error {
+            return Err(e);
+        }
+
+        prev_max = run.max_key.or(prev_max);
+        report.leaves += run.report.leaves;
+        report.keys += run.report.keys;
+    }
+
+    Ok(report)
+}
+
+/// Walks the sibling chain from the left-most leaf of the current table
+fn collect_leaves(table: &mut Table) -> Result<Vec<(PageNum, CachedPage)>, String> {
+    let mut page_num = table.root;
+    let root = table.root_page();
+    let mut node = table.load_node(root).map_err(|e| e.to_string())?;
+
+    let mut depth = 1;
+    while node.node_type() == PageType::Internal {
+        // A path longer than the number of pages has to visit some page twice
+        if depth >= table.page_count() {
+            return Err(format!("child pointers loop back below page {page_num}"));
+        }
+        depth += 1;
+
+        let cell = InternalCell::from_bytes(node.read_cell_bytes(0).map_err(|e| e.to_string())?);
+        page_num = cell.pointer();
+        node = load_node(table, page_num)?;
+    }
+
+    let mut leaves = vec![(page_num, table.get_page(page_num).unwrap())];
+    while let Some(sibling) = node.next_sibling() {
+        // Every page can be visited at most once; anything longer has to be a cycle
+        if leaves.len() as u64 >= table.page_count() {
+            return Err(format!("sibling chain loops back at leaf {sibling}"));
+        }
+
+        node = load_node(table, sibling)?;
+        if node.node_type() != PageType::Leaf {
+            return Err(format!("sibling {sibling} is not a leaf"));
+        }
+        leaves.push((sibling, table.get_page(sibling).unwrap()));
+    }
+
+    Ok(leaves)
+}
+
+fn load_node(table: &mut Table, page_num: PageNum) -> Result<Node, String> {
+    let page = table
+        .get_page(page_num)
+        .ok_or(format!("page {page_num} does not exist"))?;
+    table
+        .load_node(page)
+        .map_err(|e| format!("page {page_num}: {e}"))
+}
+
+fn out_of_order(page_num: PageNum, key: u128, prev: u128) -> String {
+    format!("leaf {page_num} has key {key} after key {prev}; keys are out of order")
+}
+
+/// Validates a run of consecutive leaves
+fn validate_run(
+    run: &[(PageNum, CachedPage)],
+    compare: KeyComparator,
+    key_size: usize,
+) -> RunSummary {
+    let mut summary = RunSummary::default();
+    if let Err(e) = check_run(run, compare, key_size, &mut summary) {
+        summary.error = Some(e);
+    }
+
+    summary
+}
+
+fn check_run(
+    run: &[(PageNum, CachedPage)],
+    compare: KeyComparator,
+    key_size: usize,
+    summary: &mut RunSummary,
+) -> Result<(), String> {
+    for (page_num, page) in run {
+        let node = Node::load(page.clone())
+            .map_err(|e| format!("leaf {page_num}: {e}"))?
+            .with_key_size(key_size);
+        let num_cells = node.num_cells();
+        if num_cells > (LEAF_SPACE_FOR_DATA / leaf_key_cell_size(key_size)) as u64 {
+            return Err(format!("leaf {page_num} claims {num_cells} cells"));
+        }
+
+        for num in 0..num_cells {
+            node.check_cell_content(num)
+                .map_err(|e| format!("leaf {page_num}: {e}"))?;
+
+            let key = node.cell_key(num);
+            match summary.max_key {
+                Some(max) if compare(key, max) != Ordering::Greater => {
+                    return Err(out_of_order(*page_num, key, max))
+                }
+                Some(_) => (),
+                None => summary.first_key = Some((*page_num, key)),
+            }
+            summary.max_key = Some(key);
+        }
+
+        summary.report.leaves += 1;
+        summary.report.keys += num_cells;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        calculate_offsets,
+        storage::layout::{
+            LEAF_HEADER_SIZE, LEAF_KEY_IDENTIFIER_SIZE, LEAF_KEY_INDENTIFIER_OFFSET,
+        },
+        Cursor,
+    };
+
+    fn large_table() -> Table {
+        let mut table = Table::in_memory();
+        for i in 0..5000 {
+            Cursor::new(&mut table)
+                .insert(i, format!("{i}data").into_bytes())
+                .unwrap();
+        }
+
+        table
+    }
+
+    #[test]
+    fn parallel_scan_matches_serial() {
+        let mut table = large_table();
+
+        let serial = validate(&mut table).unwrap();
+        assert_eq!(serial.keys, 5000);
+        assert!(serial.leaves > 1);
+        for threads in [2, 3, 8, 1000] {
+            assert_eq!(validate_parallel(&mut table, threads), Ok(serial.clone()));
+        }
+    }
+
+    #[test]
+    fn parallel_scan_reports_unsorted_keys() {
+        let mut table = large_table();
+        let leaves = collect_leaves(&mut table).unwrap();
+
+        // Give the first key of a leaf in the middle of the chain a key smaller than its
+        // predecessor so the error crosses a run boundary
+        let (_, page) = &leaves[leaves.len() / 2];
+        let page = Arc::clone(&page.0);
+        let pos = LEAF_HEADER_SIZE + LEAF_KEY_INDENTIFIER_OFFSET;
+        let (start, end) = calculate_offsets!(pos, LEAF_KEY_IDENTIFIER_SIZE);
+        page.write().unwrap()[start..end].clone_from_slice(&0u64.to_be_bytes());
+
+        let serial = validate(&mut table);
+        assert!(serial.is_err());
+        for threads in [2, 4, 16] {
+            assert_eq!(validate_parallel(&mut table, threads), serial);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+/// Longest LEB128 encoding of a `u64`
+pub const MAX_SIZE: usize = 10;
+
+/// Encodes `value` as an unsigned LEB128 varint; seven bits per byte, lowest bits first
+pub fn encode(mut value: u64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(encoded_len(value));
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return out;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Returns the number of bytes [encode](encode) uses for `value`
+pub fn encoded_len(value: u64) -> usize {
+    (64 - value.leading_zeros() as usize).div_ceil(7).max(1)
+}
+
+/// Decodes the varint at the start of `bytes`; returns the value and the number of bytes it
+/// takes up. `None` if `bytes` ends before the varint does or it doesn't fit a `u64`.
+pub fn decode(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().take(MAX_SIZE).enumerate() {
+        let bits = (*byte & 0x7F) as u64;
+        // The last byte of a u64 only has a single bit left
+        if i == MAX_SIZE - 1 && bits > 1 {
+            return None;
+        }
+        value |= bits << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        for value in [0, 1, 127, 128, 300, 16383, 16384, u32::MAX as u64, u64::MAX] {
+            let bytes = encode(value);
+            assert_eq!(bytes.len(), encoded_len(value));
+            assert_eq!(decode(&bytes), Some((value, bytes.len())));
+        }
+        assert_eq!(encode(300), vec![0xAC, 0x02]);
+        assert_eq!(encode(u64::MAX).len(), MAX_SIZE);
+    }
+
+    #[test]
+    fn rejects_truncated_and_oversized_input() {
+        assert_eq!(decode(&[]), None);
+        assert_eq!(decode(&[0x80, 0x80]), None);
+        assert_eq!(decode(&[0xFF; MAX_SIZE]), None);
+        // Trailing bytes are not part of the varint
+        assert_eq!(decode(&[0x05, 0xFF]), Some((5, 1)));
+    }
+}
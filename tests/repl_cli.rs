@@ -1,8 +1,9 @@
 use assert_cmd::prelude::*;
 use assert_fs::{prelude::*, NamedTempFile};
+use btree_db::{start_repl, OutputFormat, DEFAULT_MAX_LINE_LENGTH, IN_MEMORY_PATH};
 use predicates::prelude::*;
 use std::{
-    io::Write,
+    io::{BufRead, BufReader, Write},
     process::{Command, Stdio},
 };
 
@@ -41,6 +42,85 @@ fn inserts_data() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn preserves_value_whitespace() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"insert 1 a  b   c \n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".mode json\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"get 1\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#"{"key":1,"value":"a  b   c "}"#));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn inserts_with_auto_keys() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    for entry in ["first", "second", "third"] {
+        cmd.stdin
+            .as_mut()
+            .unwrap()
+            .write_all(format!("insert auto {entry}\n").as_bytes())?;
+    }
+    cmd.stdin.as_mut().unwrap().write_all(b"get 3\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "db > 1\ndb > 2\ndb > 3\ndb > third\n",
+        ));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn stores_reserved_words_as_values() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"insert 1 select\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"insert 2 insert\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 3 .exit\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"get 3\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "db > select\ninsert\n.exit\ndb > .exit\ndb > ",
+        ));
+
+    file.close()?;
+    Ok(())
+}
+
 #[test]
 fn persists_data() -> Result<()> {
     let file = assert_fs::NamedTempFile::new("temp.db")?;
@@ -133,7 +213,7 @@ fn multi_level_trees_support() -> Result<()> {
     file.touch()?;
     let mut cmd = test_cmd(&file)?;
 
-    for i in 1..140 {
+    for i in 1..200 {
         cmd.stdin
             .as_mut()
             .unwrap()
@@ -152,15 +232,14 @@ fn multi_level_trees_support() -> Result<()> {
             "split node was root; creating new root",
         ));
 
-    for i in 1..140 {
+    for i in 1..200 {
         output
             .clone()
             .assert()
             .stdout(predicate::str::contains(format!("{i}name")));
     }
 
-    let expected_format = (1..140)
-        .into_iter()
+    let expected_format = (1..200)
         .map(|i| format!("{i}name"))
         .collect::<Vec<String>>();
     output
@@ -241,7 +320,6 @@ fn multi_level_trees_format() -> Result<()> {
     }
 
     let expected_format = (1..280)
-        .into_iter()
         .map(|i| format!("{i}name"))
         .collect::<Vec<String>>();
     output
@@ -252,3 +330,1279 @@ fn multi_level_trees_format() -> Result<()> {
     file.close()?;
     Ok(())
 }
+
+#[test]
+fn gets_single_record() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    for i in 0..10 {
+        cmd.stdin
+            .as_mut()
+            .unwrap()
+            .write_all(format!("insert {i} {i}data\n").as_bytes())?;
+    }
+    cmd.stdin.as_mut().unwrap().write_all(b"get 3\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"get 0\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"get 42\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("3data"))
+        .stdout(predicate::str::contains("0data"))
+        .stdout(predicate::str::contains("5data").not())
+        .stdout(predicate::str::contains("not found"));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn checks_record_exists() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    for i in 0..10 {
+        cmd.stdin
+            .as_mut()
+            .unwrap()
+            .write_all(format!("insert {i} {i}data\n").as_bytes())?;
+    }
+    cmd.stdin.as_mut().unwrap().write_all(b"exists 4\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"exists 99\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("true"))
+        .stdout(predicate::str::contains("false"));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn selects_tsv_and_table_rows() -> Result<()> {
+    for (format, expected) in [
+        ("tsv", "1\tone\n20\ttab\\there\n"),
+        (
+            "table",
+            "key | value\n----+----------\n  1 | one\n 20 | tab\\there\n",
+        ),
+    ] {
+        let file = assert_fs::NamedTempFile::new("temp.db")?;
+        file.touch()?;
+        let mut cmd = Command::cargo_bin("btree-db")?
+            .arg("-f")
+            .arg(file.path())
+            .arg("--output")
+            .arg(format)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        cmd.stdin
+            .as_mut()
+            .unwrap()
+            .write_all(b"insert 20 tab\there\n")?;
+        cmd.stdin.as_mut().unwrap().write_all(b"insert 1 one\n")?;
+        cmd.stdin.as_mut().unwrap().write_all(b"select\n")?;
+        cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+        cmd.wait_with_output()?
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(expected));
+        file.close()?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn changes_settings() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 1 one\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b".set output json\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".get output\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b".set timer maybe\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b".set colour red\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".get\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("db > json\n"))
+        .stdout(predicate::str::contains(r#"{"key":1,"value":"one"}"#))
+        .stdout(predicate::str::contains(
+            "error: invalid setting `maybe`; expected `on` or `off`.",
+        ))
+        .stdout(predicate::str::contains("error: unknown setting `colour`"))
+        .stdout(predicate::str::contains(
+            "output = json\nprefetch_window = 1\n",
+        ));
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn selects_rows_by_value() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    for (id, value) in [(1, "apple"), (2, "pear"), (3, "apple"), (4, "apple pie")] {
+        cmd.stdin
+            .as_mut()
+            .unwrap()
+            .write_all(format!("insert {id} {value}\n").as_bytes())?;
+    }
+    cmd.stdin.as_mut().unwrap().write_all(b".mode tsv\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"select where value = apple\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"select where value = plum\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    let output = cmd.wait_with_output()?;
+    output.clone().assert().success();
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.ends_with("db > 1\tapple\n3\tapple\ndb > db > "));
+    assert!(!stdout.contains("apple pie"));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn selects_json_rows() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = Command::cargo_bin("btree-db")?
+        .arg("-f")
+        .arg(file.path())
+        .arg("--output")
+        .arg("json")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"insert 1 hello \"world\"\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 2 bye\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    let output = cmd.wait_with_output()?;
+    output.clone().assert().success();
+
+    let rows = String::from_utf8(output.stdout)?
+        .lines()
+        .filter_map(|l| l.split_once('{').map(|(_, row)| format!("{{{row}")))
+        .map(|row| serde_json::from_str::<serde_json::Value>(&row))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0]["key"], 1);
+    assert_eq!(rows[0]["value"], "hello \"world\"");
+    assert_eq!(rows[1]["key"], 2);
+    assert_eq!(rows[1]["value"], "bye");
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn mode_command_switches_output() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 1 hello\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".mode json\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#"{"key":1,"value":"hello"}"#));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn in_memory_database() -> Result<()> {
+    let dir = assert_fs::TempDir::new()?;
+    let mut cmd = Command::cargo_bin("btree-db")?
+        .current_dir(dir.path())
+        .arg("-f")
+        .arg(":memory:")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"insert 1 hello world!\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("hello world!"));
+    assert_eq!(std::fs::read_dir(dir.path())?.count(), 0);
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn switches_between_tables() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"insert 1 default-row\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"use other\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"insert 1 other-row\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".tables\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    let output = cmd.wait_with_output()?;
+    let stdout = String::from_utf8(output.stdout)?;
+    let (_, after_use) = stdout.split_once("other-row").unwrap();
+    assert!(!after_use.contains("default-row"));
+    assert!(after_use.contains("db\n"));
+    assert!(after_use.contains("other\n"));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn validates_table() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    for i in 0..300 {
+        cmd.stdin
+            .as_mut()
+            .unwrap()
+            .write_all(format!("insert {i} {i}data\n").as_bytes())?;
+    }
+    cmd.stdin.as_mut().unwrap().write_all(b".validate\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("ok: 300 keys in"));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn deletes_key_range() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    for i in 0..30 {
+        cmd.stdin
+            .as_mut()
+            .unwrap()
+            .write_all(format!("insert {i} {i}data\n").as_bytes())?;
+    }
+    cmd.stdin.as_mut().unwrap().write_all(b"delete 10 20\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"delete 25\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    let expected = (0..10)
+        .chain(21..25)
+        .chain(26..30)
+        .map(|i| format!("{i}data"))
+        .collect::<Vec<String>>();
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("db > deleted 11\n"))
+        .stdout(predicate::str::contains("db > deleted 1\n"))
+        .stdout(predicate::str::contains(expected.join("\n")));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn rekeys_records() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 3 three\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 5 five\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"rekey 3 7\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"rekey 5 7\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"get 7\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"get 3\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("error: key 7 already exists"))
+        .stdout(predicate::str::contains("db > three\ndb > not found"));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn counts_records() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    for i in 0..50 {
+        cmd.stdin
+            .as_mut()
+            .unwrap()
+            .write_all(format!("insert {i} {i}data\n").as_bytes())?;
+    }
+    cmd.stdin.as_mut().unwrap().write_all(b"select count\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("db > 50\n"));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn selects_keys_without_values() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    for i in (0..10).rev() {
+        cmd.stdin
+            .as_mut()
+            .unwrap()
+            .write_all(format!("insert {i} value{i}\n").as_bytes())?;
+    }
+    cmd.stdin.as_mut().unwrap().write_all(b"select keys\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "db > 0\n1\n2\n3\n4\n5\n6\n7\n8\n9\ndb > ",
+        ))
+        .stdout(predicate::str::contains("value").not());
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn prints_key_histogram() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    for i in (0..30).chain([99]) {
+        cmd.stdin
+            .as_mut()
+            .unwrap()
+            .write_all(format!("insert {i} value{i}\n").as_bytes())?;
+    }
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b".histogram 4\n.histogram 0\n.histogram 18446744073709551615\n.exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "db > 0..=24: 25\n25..=49: 5\n50..=74: 0\n75..=99: 1\n",
+        ))
+        .stdout(predicate::str::contains(
+            "error: invalid bucket count `0`; expected 1 to 1024.",
+        ))
+        .stdout(predicate::str::contains(
+            "error: invalid bucket count `18446744073709551615`; expected 1 to 1024.",
+        ));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn selects_min_and_max() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin.as_mut().unwrap().write_all(b"select min\n")?;
+    for i in [7, 3, 42, 10] {
+        cmd.stdin
+            .as_mut()
+            .unwrap()
+            .write_all(format!("insert {i} {i}data\n").as_bytes())?;
+    }
+    cmd.stdin.as_mut().unwrap().write_all(b"select min\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select max\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("db > not found\n"))
+        .stdout(predicate::str::contains("db > 3data\ndb > 42data\n"));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn init_refuses_to_clobber() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 1 one\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+    cmd.wait_with_output()?.assert().success();
+
+    Command::cargo_bin("btree-db")?
+        .arg("-f")
+        .arg(file.path())
+        .arg("--init")
+        .stdin(Stdio::null())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("refusing to initialize"));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn refuses_to_open_missing_files() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+
+    Command::cargo_bin("btree-db")?
+        .arg("-f")
+        .arg(file.path())
+        .stdin(Stdio::null())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("use --init to create it"));
+    file.assert(predicate::path::missing());
+
+    let mut cmd = Command::cargo_bin("btree-db")?
+        .arg("-f")
+        .arg(file.path())
+        .arg("--init")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 1 one\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("db > one"));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn verbose_flag_enables_debug_logs() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+
+    for (args, logs) in [(vec![], false), (vec!["-vv"], true)] {
+        let mut cmd = Command::cargo_bin("btree-db")?
+            .arg("-f")
+            .arg(file.path())
+            .args(args)
+            .env_remove("RUST_LOG")
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        cmd.stdin.as_mut().unwrap().write_all(b"insert 1 one\n")?;
+        cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+        let output = cmd.wait_with_output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stderr)?.contains("DEBUG"), logs);
+    }
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn prints_version() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin.as_mut().unwrap().write_all(b".version\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "btree-db {}",
+            env!("CARGO_PKG_VERSION")
+        )))
+        .stdout(predicate::str::contains("format version 5 (current 5)"));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn ignores_blank_lines() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin.as_mut().unwrap().write_all(b"\n   \n\t\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("error").not());
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn prints_help() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin.as_mut().unwrap().write_all(b".help\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("insert <id> <value>"))
+        .stdout(predicate::str::contains("select"))
+        .stdout(predicate::str::contains(".exit"));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn selects_with_limit() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    for i in 0..100 {
+        cmd.stdin
+            .as_mut()
+            .unwrap()
+            .write_all(format!("insert {i} {i}data\n").as_bytes())?;
+    }
+    cmd.stdin.as_mut().unwrap().write_all(b"select limit 5\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"select offset 50 limit 2\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    let output = cmd.wait_with_output()?;
+    let stdout = String::from_utf8(output.stdout)?;
+    let rows = stdout
+        .split("db > ")
+        .map(|s| s.lines().collect::<Vec<&str>>())
+        .filter(|lines| !lines.is_empty())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rows,
+        vec![
+            vec!["0data", "1data", "2data", "3data", "4data"],
+            vec!["50data", "51data"]
+        ]
+    );
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn dumps_and_imports_records() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let dump = assert_fs::NamedTempFile::new("temp.dump")?;
+    let mut cmd = test_cmd(&file)?;
+
+    for i in 0..50 {
+        cmd.stdin
+            .as_mut()
+            .unwrap()
+            .write_all(format!("insert {i} {i} data\n").as_bytes())?;
+    }
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(format!(".dump {}\n", dump.path().display()).as_bytes())?;
+    cmd.stdin.as_mut().unwrap().write_all(b"use copy\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(format!(".import {}\n", dump.path().display()).as_bytes())?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    let expected = (0..50)
+        .map(|i| format!("{i} data"))
+        .collect::<Vec<String>>();
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("dumped 50 records"))
+        .stdout(predicate::str::contains("imported 50 records"))
+        .stdout(predicate::str::contains(expected.join("\n")));
+
+    dump.close()?;
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn imports_csv() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let csv = assert_fs::NamedTempFile::new("temp.csv")?;
+    csv.write_str("1,one\n2,two\nbad line\n3,three\n")?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(format!(".import csv {}\n", csv.path().display()).as_bytes())?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "skipped line 3: expected `id,value`",
+        ))
+        .stdout(predicate::str::contains("imported 3 records"))
+        .stdout(predicate::str::contains("one\ntwo\nthree"));
+
+    csv.close()?;
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn utf8_values_setting_rejects_binary_values() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let dump = assert_fs::NamedTempFile::new("binary.dump")?;
+    let mut records = b"btree-db dump 1\n".to_vec();
+    records.extend(1u64.to_be_bytes());
+    records.extend(2u64.to_be_bytes());
+    records.extend([0xFF, 0xFE]);
+    dump.write_binary(&records)?;
+    let mut cmd = test_cmd(&file)?;
+
+    let import = format!(".import {}\n", dump.path().display());
+    let stdin = cmd.stdin.as_mut().unwrap();
+    stdin.write_all(b".set utf8_values on\n")?;
+    stdin.write_all(import.as_bytes())?;
+    stdin.write_all(b"select count\n")?;
+    stdin.write_all(b".set utf8_values off\n")?;
+    stdin.write_all(import.as_bytes())?;
+    stdin.write_all(b"select count\n")?;
+    stdin.write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "error: failed to import record 1; value is not valid UTF-8\ndb > 0\n",
+        ))
+        .stdout(predicate::str::contains("imported 1 records\ndb > 1\n"));
+
+    dump.close()?;
+    file.close()?;
+    Ok(())
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn encrypts_database_file() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let run = |key: &[&str], input: &[u8]| -> Result<std::process::Output> {
+        let mut cmd = Command::cargo_bin("btree-db")?
+            .arg("-f")
+            .arg(file.path())
+            .args(key)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        // Runs with a wrong or missing key may exit before reading their input
+        let _ = cmd.stdin.as_mut().unwrap().write_all(input);
+        Ok(cmd.wait_with_output()?)
+    };
+
+    run(&["--key", "secret"], b"insert 1 hidden value\n.exit\n")?
+        .assert()
+        .success();
+    let bytes = std::fs::read(file.path())?;
+    assert!(!bytes.windows(12).any(|w| w == b"hidden value"));
+
+    run(&["--key", "secret"], b"select\n.exit\n")?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("hidden value"));
+    run(&["--key", "guess"], b"select\n.exit\n")?
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("error: invalid key"));
+    run(&[], b"select\n.exit\n")?
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "error: file is encrypted; a key is required to open it",
+        ));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn backs_up_database() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let backup = assert_fs::NamedTempFile::new("backup.db")?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 1 one\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(format!(".backup {}\n", backup.path().display()).as_bytes())?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(format!(".backup {}\n", backup.path().display()).as_bytes())?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("db > backed up 2 pages\n"))
+        .stdout(predicate::str::contains("db > error: failed to create"));
+
+    let mut cmd = test_cmd(&backup)?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n.exit\n")?;
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("one\n"));
+
+    backup.close()?;
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn clones_leave_the_original_unchanged() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let clone = assert_fs::NamedTempFile::new("clone.db")?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"insert 1 one\ninsert 2 two\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(format!(".clone --switch {}\n", clone.path().display()).as_bytes())?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"delete 1\ninsert 3 three\nselect\n.exit\n")?;
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "db > cloned 2 pages; now using {}\n",
+            clone.path().display()
+        )))
+        .stdout(predicate::str::contains("db > two\nthree\n"));
+
+    let mut cmd = test_cmd(&file)?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n.exit\n")?;
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("db > one\ntwo\n"))
+        .stdout(predicate::str::contains("three").not());
+
+    clone.close()?;
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn updates_records_in_child_leaves() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    // Enough records to split the root; the updated keys live in leaves below it
+    for i in 0..500 {
+        cmd.stdin
+            .as_mut()
+            .unwrap()
+            .write_all(format!("insert {i} value{i}\n").as_bytes())?;
+    }
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"update 10 changed\nupdate 499 last\nget 10\nget 499\nget 11\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"update 500 missing\nselect count\n.validate\n.exit\n")?;
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "db > db > db > changed\ndb > last\ndb > value11\n",
+        ))
+        .stdout(predicate::str::contains(
+            "db > error: key does not exist\ndb > 500\n",
+        ))
+        .stdout(predicate::str::contains("db > ok: 500 keys"));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn clears_table_after_confirmation() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"insert 1 one\n.clear\nselect count\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b".clear confirm\nselect count\n.exit\n")?;
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "db > this removes every record of table `db`; run `.clear confirm` to continue\n",
+        ))
+        .stdout(predicate::str::contains("db > 1\n"))
+        .stdout(predicate::str::contains("db > db > 0\n"));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn opens_named_table() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+
+    let mut cmd = Command::cargo_bin("btree-db")?
+        .arg("users")
+        .arg("-f")
+        .arg(file.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 1 alice\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("users > "));
+
+    // The record is only visible from the named table
+    let mut cmd = test_cmd(&file)?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"use users\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("db > db > users > alice\n"));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn times_statements() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"insert 1 untimed\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".timer on\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 2 timed\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".timer off\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"insert 3 untimed\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"time: [0-9.]+[µnm]?s\n")?.count(1));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn validates_typed_values() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"use numbers int\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 1 42\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 2 foo\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "numbers > error: `foo` is not an int\nnumbers > 42\n",
+        ));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn orders_signed_keys() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"use ledger signed\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 5 five\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"insert -5 minus five\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 0 zero\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".mode tsv\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"get -5\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"use ledger unsigned\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "ledger > -5\tminus five\n0\tzero\n5\tfive\nledger > -5\tminus five\n",
+        ))
+        .stdout(predicate::str::contains(
+            "error: table `ledger` holds records; only empty tables can change their key type",
+        ));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn orders_u128_keys() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"use ids text u128\n")?;
+    // f47ac10b-58cc-4372-a567-0e02b2c3d479, 6ba7b810-9dad-11d1-80b4-00c04fd430c8 and 0xff
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"insert 324969006592305634633390616021200786553 f47ac10b\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"insert 143098242404177361603877621312831893704 6ba7b810\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 255 ff\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".mode tsv\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "ids > 255\tff\n\
+             143098242404177361603877621312831893704\t6ba7b810\n\
+             324969006592305634633390616021200786553\tf47ac10b\n",
+        ));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn rejects_long_lines() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    let mut line = b"insert 1 ".to_vec();
+    line.resize(line.len() + 8 * 1024 * 1024, b'x');
+    line.push(b'\n');
+    cmd.stdin.as_mut().unwrap().write_all(&line)?;
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 2 short\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "db > error: input is longer than 1048576 bytes\ndb > db > short\n",
+        ));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn explains_statements() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin.as_mut().unwrap().write_all(b"insert 1 one\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"explain insert 2 two\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"explain select\n")?;
+    cmd.stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"explain delete 1\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select count\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "db > page 1 (leaf)\nsplit: no\ndb > page 1 (leaf)\n",
+        ))
+        .stdout(predicate::str::contains(
+            "db > error: only insert, get, exists and select can be explained\ndb > 1\n",
+        ));
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn rejects_unknown_meta_commands() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    cmd.stdin.as_mut().unwrap().write_all(b".nope\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".timer maybe\n")?;
+    cmd.stdin.as_mut().unwrap().write_all(b".exit\n")?;
+
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "db > error: unknown meta command `.nope`. Try `.help` for a list of commands.\n",
+        ))
+        .stdout(predicate::str::contains(
+            "db > error: invalid timer setting `maybe`.\n",
+        ))
+        .stdout(predicate::str::contains("unknown command").not());
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn meta_commands_honor_autoflush() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let mut cmd = test_cmd(&file)?;
+
+    let stdin = cmd.stdin.as_mut().unwrap();
+    stdin.write_all(b".set autoflush off\ninsert 1 one\n.tables\n")?;
+    stdin.flush()?;
+    // Wait until `.tables` ran before killing the session
+    let mut stdout = BufReader::new(cmd.stdout.take().unwrap());
+    let mut printed = String::new();
+    while !printed.ends_with("db\n") {
+        if stdout.read_line(&mut printed)? == 0 {
+            break;
+        }
+    }
+    cmd.kill()?;
+    cmd.wait()?;
+
+    let mut cmd = test_cmd(&file)?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n.exit\n")?;
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("one").not());
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn meta_commands_autoflush_their_changes() -> Result<()> {
+    let file = assert_fs::NamedTempFile::new("temp.db")?;
+    file.touch()?;
+    let csv = assert_fs::NamedTempFile::new("temp.csv")?;
+    csv.write_str("1,one\n2,two\n")?;
+    let mut cmd = test_cmd(&file)?;
+
+    let stdin = cmd.stdin.as_mut().unwrap();
+    stdin.write_all(format!(".import csv {}\n", csv.path().display()).as_bytes())?;
+    stdin.flush()?;
+    // The next prompt is printed once the import was flushed; kill the session right there
+    let mut stdout = BufReader::new(cmd.stdout.take().unwrap());
+    let mut printed = Vec::new();
+    while !printed.ends_with(b"imported 2 records\ndb > ") {
+        if stdout.read_until(b' ', &mut printed)? == 0 {
+            break;
+        }
+    }
+    cmd.kill()?;
+    cmd.wait()?;
+
+    let mut cmd = test_cmd(&file)?;
+    cmd.stdin.as_mut().unwrap().write_all(b"select\n.exit\n")?;
+    cmd.wait_with_output()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("one\ntwo"));
+
+    csv.close()?;
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn runs_in_process() {
+    let script = b"insert 1 one\ninsert 2 two\nselect\nexists 3\n.exit\nselect\n";
+    let mut output = Vec::new();
+    start_repl(
+        "db".to_string(),
+        IN_MEMORY_PATH.into(),
+        None,
+        OutputFormat::Plain,
+        DEFAULT_MAX_LINE_LENGTH,
+        &script[..],
+        &mut output,
+    );
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "db > db > db > one\ntwo\ndb > false\ndb > "
+    );
+}